@@ -0,0 +1,30 @@
+import * as anchor from "@coral-xyz/anchor";
+
+// Where `EscrowState::tag` sits in the account data; matches
+// `ESCROW_TAG_OFFSET` in the program.
+export const ESCROW_TAG_OFFSET = 8 + 1149;
+
+// Pads a short category name such as "art" to the 8-byte tag code.
+export function listingTag(name: string): number[] {
+  const bytes = Buffer.from(anchor.utils.bytes.utf8.encode(name));
+  if (bytes.length > 8) {
+    throw new Error(`tag ${name} is longer than 8 bytes`);
+  }
+  return [...bytes, ...new Array(8 - bytes.length).fill(0)];
+}
+
+// Fetches the open listings filed under `name`, letting the RPC node filter
+// them instead of fetching every listing.
+export async function fetchListingsByTag(
+  program: anchor.Program,
+  name: string
+) {
+  return program.account.escrowState.all([
+    {
+      memcmp: {
+        offset: ESCROW_TAG_OFFSET,
+        bytes: anchor.utils.bytes.bs58.encode(listingTag(name))
+      }
+    }
+  ]);
+}
@@ -0,0 +1,139 @@
+import * as anchor from "@coral-xyz/anchor";
+import {
+  AccountMeta,
+  AddressLookupTableAccount,
+  AddressLookupTableProgram,
+  Connection,
+  Keypair,
+  PublicKey,
+  SystemProgram,
+  Transaction,
+  TransactionInstruction,
+  TransactionMessage,
+  VersionedTransaction,
+  sendAndConfirmTransaction
+} from "@solana/web3.js";
+import {
+  ASSOCIATED_TOKEN_PROGRAM_ID,
+  NATIVE_MINT,
+  TOKEN_PROGRAM_ID
+} from "@solana/spl-token";
+
+// Accounts every exchange touches regardless of the escrow being settled. Vault
+// authorities are per escrow, so they are not worth caching here.
+export function staticAccounts(
+  programId: PublicKey,
+  treasury: PublicKey
+): PublicKey[] {
+  const [config] = PublicKey.findProgramAddressSync(
+    [Buffer.from(anchor.utils.bytes.utf8.encode("config"))],
+    programId
+  );
+  return [
+    programId,
+    TOKEN_PROGRAM_ID,
+    ASSOCIATED_TOKEN_PROGRAM_ID,
+    SystemProgram.programId,
+    NATIVE_MINT,
+    config,
+    treasury
+  ];
+}
+
+export async function createEscrowLookupTable(
+  connection: Connection,
+  payer: Keypair,
+  programId: PublicKey,
+  treasury: PublicKey
+): Promise<PublicKey> {
+  const slot = await connection.getSlot("finalized");
+  const [createIx, lookupTable] = AddressLookupTableProgram.createLookupTable({
+    authority: payer.publicKey,
+    payer: payer.publicKey,
+    recentSlot: slot
+  });
+  await sendAndConfirmTransaction(
+    connection,
+    new Transaction().add(createIx),
+    [payer]
+  );
+  await extendEscrowLookupTable(
+    connection,
+    payer,
+    lookupTable,
+    staticAccounts(programId, treasury)
+  );
+  return lookupTable;
+}
+
+export async function extendEscrowLookupTable(
+  connection: Connection,
+  payer: Keypair,
+  lookupTable: PublicKey,
+  addresses: PublicKey[]
+): Promise<void> {
+  const existing = await connection.getAddressLookupTable(lookupTable);
+  const known = existing.value ? existing.value.state.addresses : [];
+  const missing = addresses.filter(
+    (address) => !known.some((key) => key.equals(address))
+  );
+  if (missing.length == 0) {
+    return;
+  }
+
+  const extendIx = AddressLookupTableProgram.extendLookupTable({
+    authority: payer.publicKey,
+    payer: payer.publicKey,
+    lookupTable,
+    addresses: missing
+  });
+  await sendAndConfirmTransaction(
+    connection,
+    new Transaction().add(extendIx),
+    [payer]
+  );
+}
+
+export async function buildV0Transaction(
+  connection: Connection,
+  payer: PublicKey,
+  instructions: TransactionInstruction[],
+  lookupTables: AddressLookupTableAccount[]
+): Promise<VersionedTransaction> {
+  const { blockhash } = await connection.getLatestBlockhash();
+  const message = new TransactionMessage({
+    payerKey: payer,
+    recentBlockhash: blockhash,
+    instructions
+  }).compileToV0Message(lookupTables);
+  return new VersionedTransaction(message);
+}
+
+// Builds an unsigned v0 exchange transaction resolving the static accounts
+// through `lookupTable`; the taker still has to sign it. Accounts only some
+// listings need, such as a donation recipient, go in `remainingAccounts`.
+export async function buildExchangeTransaction(
+  program: anchor.Program,
+  lookupTable: PublicKey,
+  accounts: Record<string, PublicKey | null>,
+  preInstructions: TransactionInstruction[] = [],
+  remainingAccounts: AccountMeta[] = []
+): Promise<VersionedTransaction> {
+  const connection = program.provider.connection;
+  const table = await connection.getAddressLookupTable(lookupTable);
+  if (!table.value) {
+    throw new Error(`lookup table ${lookupTable.toBase58()} not found`);
+  }
+
+  const exchangeIx = await program.methods
+    .exchange(null, null, null, null)
+    .accounts(accounts)
+    .remainingAccounts(remainingAccounts)
+    .instruction();
+  return buildV0Transaction(
+    connection,
+    accounts.taker,
+    [...preInstructions, exchangeIx],
+    [table.value]
+  );
+}
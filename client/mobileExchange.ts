@@ -0,0 +1,72 @@
+import * as anchor from "@coral-xyz/anchor";
+import {
+  AccountMeta,
+  PACKET_DATA_SIZE,
+  PublicKey,
+  SYSVAR_INSTRUCTIONS_PUBKEY,
+  Transaction,
+  TransactionInstruction
+} from "@solana/web3.js";
+import { staticAccounts } from "./lookupTable";
+
+export const MEMO_PROGRAM_ID = new PublicKey(
+  "MemoSq4gqABAXKb96qQbMTvfVTyxcLtuDZhjssjQEFo"
+);
+export const TOKEN_METADATA_PROGRAM_ID = new PublicKey(
+  "metaqbxxUerdq28cj1RjAWkegKJZHgjJvh7RNVEQ7LR8s"
+);
+
+// What a lookup table for mobile wallets should hold: the accounts of every
+// exchange, plus the programs a collection or private listing pulls in.
+export function mobileLookupPreset(
+  programId: PublicKey,
+  treasury: PublicKey
+): PublicKey[] {
+  return [
+    ...staticAccounts(programId, treasury),
+    TOKEN_METADATA_PROGRAM_ID,
+    MEMO_PROGRAM_ID,
+    SYSVAR_INSTRUCTIONS_PUBKEY
+  ];
+}
+
+// Size of `transaction` once signed, without needing the signatures.
+export function signedSize(transaction: Transaction): number {
+  const signers = transaction.compileMessage().header.numRequiredSignatures;
+  const message = transaction.serializeMessage();
+  // A one-byte signature count, then 64 bytes per signature.
+  return 1 + signers * 64 + message.length;
+}
+
+// Builds an unsigned legacy exchange for wallets that cannot sign v0
+// transactions yet. Optional accounts left `null` are passed as the program
+// id, which the transaction already holds, so they add one byte each rather
+// than a 32-byte key. Whatever the listing needs beyond a plain fill, such as
+// its donation recipient, goes in `remainingAccounts`: pass only those.
+export async function buildLegacyExchangeTransaction(
+  program: anchor.Program,
+  accounts: Record<string, PublicKey | null>,
+  preInstructions: TransactionInstruction[] = [],
+  remainingAccounts: AccountMeta[] = []
+): Promise<Transaction> {
+  const connection = program.provider.connection;
+  const exchangeIx = await program.methods
+    .exchange(null, null, null, null)
+    .accounts(accounts)
+    .remainingAccounts(remainingAccounts)
+    .instruction();
+  const { blockhash } = await connection.getLatestBlockhash();
+  const transaction = new Transaction({
+    feePayer: accounts.taker,
+    recentBlockhash: blockhash
+  }).add(...preInstructions, exchangeIx);
+
+  const size = signedSize(transaction);
+  if (size > PACKET_DATA_SIZE) {
+    throw new Error(
+      `exchange is ${size} bytes, over the ${PACKET_DATA_SIZE}-byte legacy limit; ` +
+        "drop pre-instructions or use buildExchangeTransaction with a lookup table"
+    );
+  }
+  return transaction;
+}
@@ -0,0 +1,63 @@
+import * as anchor from "@coral-xyz/anchor";
+import { PublicKey } from "@solana/web3.js";
+import { getAssociatedTokenAddressSync } from "@solana/spl-token";
+
+export type ReserveProof = {
+  escrows: number;
+  hash: number[];
+};
+
+// The account holding an escrow's NFT: its vault, or the seller's own token
+// account for a delegated listing.
+export function custodyAccount(
+  programId: PublicKey,
+  escrowState: PublicKey,
+  state: any
+): PublicKey {
+  if (state.delegated) {
+    return state.initializerDepositTokenAccount;
+  }
+  const [vaultAuthority] = PublicKey.findProgramAddressSync(
+    [
+      Buffer.from(anchor.utils.bytes.utf8.encode("authority")),
+      escrowState.toBuffer()
+    ],
+    programId
+  );
+  return getAssociatedTokenAddressSync(
+    state.initializerDepositMintAccount,
+    vaultAuthority,
+    true
+  );
+}
+
+// Simulates `prove_reserves` over `escrows`; it fails unless every one of
+// them still has its NFT in custody. Keep the list short enough for a
+// single transaction and call it in batches for the whole program.
+export async function proveReserves(
+  program: anchor.Program,
+  escrows: PublicKey[]
+): Promise<ReserveProof> {
+  const states = await program.account.escrowState.fetchMultiple(escrows);
+  const remainingAccounts = escrows.flatMap((escrowState, index) => {
+    const state = states[index];
+    if (!state) {
+      throw new Error(`escrow ${escrowState.toBase58()} not found`);
+    }
+    return [
+      { pubkey: escrowState, isSigner: false, isWritable: false },
+      {
+        pubkey: custodyAccount(program.programId, escrowState, state),
+        isSigner: false,
+        isWritable: false
+      }
+    ];
+  });
+
+  const proof = await program.methods
+    .proveReserves()
+    .accounts({})
+    .remainingAccounts(remainingAccounts)
+    .view();
+  return proof as ReserveProof;
+}
@@ -0,0 +1,51 @@
+import * as anchor from "@coral-xyz/anchor";
+import { PublicKey } from "@solana/web3.js";
+
+// Matches `QUOTE_ROUTE_VERSION` in the program.
+export const QUOTE_ROUTE_VERSION = 1;
+
+export type QuoteRoute = {
+  version: number;
+  paymentMint: PublicKey;
+  price: anchor.BN;
+  total: anchor.BN;
+  payouts: { recipient: PublicKey; amount: anchor.BN }[];
+};
+
+// Simulates `get_quote_route` for `escrowState`: what `taker` would pay and
+// who it goes to. Refuses a layout newer than this client understands.
+export async function fetchQuoteRoute(
+  program: anchor.Program,
+  escrowState: PublicKey,
+  taker: PublicKey
+): Promise<QuoteRoute> {
+  const state = await program.account.escrowState.fetch(escrowState);
+  const [config] = PublicKey.findProgramAddressSync(
+    [Buffer.from(anchor.utils.bytes.utf8.encode("config"))],
+    program.programId
+  );
+  const [collectionFeeOverride] = PublicKey.findProgramAddressSync(
+    [
+      Buffer.from(anchor.utils.bytes.utf8.encode("fee_override")),
+      (state as any).collection.toBuffer()
+    ],
+    program.programId
+  );
+  const route = (await program.methods
+    .getQuoteRoute()
+    .accounts({
+      taker,
+      escrowState,
+      config,
+      discountTokenAccount: null,
+      collectionFeeOverride,
+      priceFeed: (state as any).priceUsdCents.isZero()
+        ? null
+        : (state as any).priceFeed
+    })
+    .view()) as QuoteRoute;
+  if (route.version > QUOTE_ROUTE_VERSION) {
+    throw new Error(`unsupported quote route version ${route.version}`);
+  }
+  return route;
+}
@@ -0,0 +1,479 @@
+//! Seeds a local validator with wallets, NFT mints and escrows in a handful of
+//! states so frontends can be developed against realistic data.
+//!
+//! Start a validator with the program deployed (`anchor localnet` does both),
+//! then from this directory:
+//! `cargo run -- [rpc_url] [payer_keypair]`.
+//!
+//! Everything created, including the wallet keypairs, is printed as JSON on
+//! stdout. Running it again adds a fresh set of escrows next to the old ones.
+
+use std::collections::BTreeMap;
+
+use anchor_lang::prelude::Pubkey;
+use anchor_lang::solana_program::{instruction::Instruction, system_instruction, system_program};
+use anchor_lang::{AccountDeserialize, InstructionData, ToAccountMetas};
+use anchor_spl::associated_token::get_associated_token_address;
+use anchor_spl::token::spl_token;
+use serde_json::{json, Value};
+use solana_client::rpc_client::RpcClient;
+use solana_nft_escrow::{accounts, instruction, Config, ListingCounter};
+use solana_sdk::{
+    commitment_config::CommitmentConfig,
+    native_token::LAMPORTS_PER_SOL,
+    program_pack::Pack,
+    signature::{read_keypair_file, Keypair, Signature, Signer},
+    transaction::Transaction,
+};
+use spl_associated_token_account::instruction::create_associated_token_account;
+
+const DEFAULT_RPC_URL: &str = "http://127.0.0.1:8899";
+const MAKER_FEE_BPS: u16 = 150;
+const TAKER_FEE_BPS: u16 = 100;
+
+type Error = Box<dyn std::error::Error>;
+
+fn pda(seeds: &[&[u8]]) -> Pubkey {
+    Pubkey::find_program_address(seeds, &solana_nft_escrow::ID).0
+}
+
+fn escrow_state_key(initializer: &Pubkey, nonce: u64) -> Pubkey {
+    pda(&[b"state", initializer.as_ref(), &nonce.to_le_bytes()])
+}
+
+fn vault_authority_key(escrow_state: &Pubkey) -> Pubkey {
+    pda(&[b"authority", escrow_state.as_ref()])
+}
+
+fn vault_key(escrow_state: &Pubkey, mint: &Pubkey) -> Pubkey {
+    get_associated_token_address(&vault_authority_key(escrow_state), mint)
+}
+
+/// A listing to create, and what to do with it afterwards.
+struct Listing {
+    name: &'static str,
+    nft: &'static str,
+    receive_nft: &'static str,
+    taker: &'static str,
+    taker_amount: u64,
+    donation: Option<(&'static str, u16)>,
+    outcome: Outcome,
+}
+
+#[derive(Clone, Copy)]
+enum Outcome {
+    Open,
+    Exchanged,
+    Cancelled,
+}
+
+impl Outcome {
+    fn as_str(self) -> &'static str {
+        match self {
+            Outcome::Open => "open",
+            Outcome::Exchanged => "exchanged",
+            Outcome::Cancelled => "cancelled",
+        }
+    }
+}
+
+/// NFTs minted up front, by name and holder.
+const NFTS: &[(&str, &str)] = &[
+    ("alice_nft_1", "alice"),
+    ("alice_nft_2", "alice"),
+    ("alice_nft_3", "alice"),
+    ("alice_nft_4", "alice"),
+    ("bob_nft_1", "bob"),
+    ("bob_nft_2", "bob"),
+    ("carol_nft_1", "carol"),
+];
+
+const LISTINGS: &[Listing] = &[
+    Listing {
+        name: "open",
+        nft: "alice_nft_1",
+        receive_nft: "bob_nft_1",
+        taker: "bob",
+        taker_amount: LAMPORTS_PER_SOL / 2,
+        donation: None,
+        outcome: Outcome::Open,
+    },
+    Listing {
+        name: "open_with_donation",
+        nft: "alice_nft_2",
+        receive_nft: "bob_nft_2",
+        taker: "bob",
+        taker_amount: LAMPORTS_PER_SOL,
+        donation: Some(("carol", 500)),
+        outcome: Outcome::Open,
+    },
+    Listing {
+        name: "exchanged",
+        nft: "alice_nft_3",
+        receive_nft: "carol_nft_1",
+        taker: "carol",
+        taker_amount: LAMPORTS_PER_SOL / 4,
+        donation: None,
+        outcome: Outcome::Exchanged,
+    },
+    Listing {
+        name: "cancelled",
+        nft: "alice_nft_4",
+        receive_nft: "bob_nft_1",
+        taker: "bob",
+        taker_amount: LAMPORTS_PER_SOL / 2,
+        donation: None,
+        outcome: Outcome::Cancelled,
+    },
+];
+
+struct Fixtures {
+    rpc: RpcClient,
+    payer: Keypair,
+    treasury: Pubkey,
+    wallets: BTreeMap<&'static str, Keypair>,
+    mints: BTreeMap<&'static str, Pubkey>,
+}
+
+impl Fixtures {
+    fn send(&self, instructions: &[Instruction], signers: &[&Keypair]) -> Result<Signature, Error> {
+        let mut all_signers = vec![&self.payer];
+        all_signers.extend_from_slice(signers);
+        let tx = Transaction::new_signed_with_payer(
+            instructions,
+            Some(&self.payer.pubkey()),
+            &all_signers,
+            self.rpc.get_latest_blockhash()?,
+        );
+        Ok(self.rpc.send_and_confirm_transaction(&tx)?)
+    }
+
+    fn airdrop(&self, to: &Pubkey, lamports: u64) -> Result<(), Error> {
+        let signature = self.rpc.request_airdrop(to, lamports)?;
+        self.rpc.poll_for_signature(&signature)?;
+        Ok(())
+    }
+
+    fn wallet(&self, name: &str) -> &Keypair {
+        &self.wallets[name]
+    }
+
+    /// Reuses the existing fee config, or creates one with the payer as admin
+    /// and a freshly funded treasury.
+    fn ensure_config(rpc: &RpcClient, payer: &Keypair) -> Result<Pubkey, Error> {
+        let config = pda(&[b"config"]);
+        if let Ok(account) = rpc.get_account(&config) {
+            let config = Config::try_deserialize(&mut account.data.as_slice())?;
+            return Ok(config.treasury);
+        }
+
+        let treasury = Keypair::new().pubkey();
+        let signature = rpc.request_airdrop(&treasury, LAMPORTS_PER_SOL)?;
+        rpc.poll_for_signature(&signature)?;
+        let ix = Instruction {
+            program_id: solana_nft_escrow::ID,
+            accounts: accounts::InitializeConfig {
+                admin: payer.pubkey(),
+                config,
+                treasury,
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            data: instruction::InitializeConfig {
+                maker_fee_bps: MAKER_FEE_BPS,
+                taker_fee_bps: TAKER_FEE_BPS,
+                referral_share_bps: 0,
+            }
+            .data(),
+        };
+        let tx = Transaction::new_signed_with_payer(
+            &[ix],
+            Some(&payer.pubkey()),
+            &[payer],
+            rpc.get_latest_blockhash()?,
+        );
+        rpc.send_and_confirm_transaction(&tx)?;
+        Ok(treasury)
+    }
+
+    fn mint_nft(&self, owner: &Pubkey) -> Result<Pubkey, Error> {
+        let mint = Keypair::new();
+        let payer = self.payer.pubkey();
+        let rent = self
+            .rpc
+            .get_minimum_balance_for_rent_exemption(spl_token::state::Mint::LEN)?;
+        let ata = get_associated_token_address(owner, &mint.pubkey());
+        let instructions = [
+            system_instruction::create_account(
+                &payer,
+                &mint.pubkey(),
+                rent,
+                spl_token::state::Mint::LEN as u64,
+                &spl_token::ID,
+            ),
+            spl_token::instruction::initialize_mint2(
+                &spl_token::ID,
+                &mint.pubkey(),
+                &payer,
+                None,
+                0,
+            )?,
+            create_associated_token_account(&payer, owner, &mint.pubkey(), &spl_token::ID),
+            spl_token::instruction::mint_to(&spl_token::ID, &mint.pubkey(), &ata, &payer, &[], 1)?,
+        ];
+        self.send(&instructions, &[&mint])?;
+        Ok(mint.pubkey())
+    }
+
+    fn next_nonce(&self, initializer: &Pubkey) -> Result<u64, Error> {
+        let listing_counter = pda(&[b"listing_counter", initializer.as_ref()]);
+        match self.rpc.get_account(&listing_counter) {
+            Ok(account) => Ok(ListingCounter::try_deserialize(&mut account.data.as_slice())?.count),
+            Err(_) => Ok(0),
+        }
+    }
+
+    fn initialize(&self, initializer: &Keypair, listing: &Listing) -> Result<Pubkey, Error> {
+        let initializer_key = initializer.pubkey();
+        let mint = self.mints[listing.nft];
+        let escrow_state = escrow_state_key(&initializer_key, self.next_nonce(&initializer_key)?);
+        let (donation_recipient, donation_bps) = match listing.donation {
+            Some((recipient, bps)) => (self.wallet(recipient).pubkey(), bps),
+            None => (Pubkey::default(), 0),
+        };
+        let ix = Instruction {
+            program_id: solana_nft_escrow::ID,
+            accounts: accounts::Initialize {
+                initializer: initializer_key,
+                rent_payer: initializer_key,
+                mint,
+                vault_authority: vault_authority_key(&escrow_state),
+                vault: vault_key(&escrow_state, &mint),
+                taker_key: self.wallet(listing.taker).pubkey(),
+                initializer_deposit_token_account: get_associated_token_address(
+                    &initializer_key,
+                    &mint,
+                ),
+                initializer_receive_mint_account: self.mints[listing.receive_nft],
+                config: pda(&[b"config"]),
+                listing_counter: pda(&[b"listing_counter", initializer_key.as_ref()]),
+                escrow_state,
+                nft_metadata: solana_nft_escrow::metadata_address(&mint),
+                collection_floor: solana_nft_escrow::collection_floor_address(
+                    &Pubkey::default(),
+                    0,
+                ),
+                wallet_activity: None,
+                system_program: system_program::ID,
+                token_program: spl_token::ID,
+                associated_token_program: spl_associated_token_account::ID,
+            }
+            .to_account_metas(None),
+            data: instruction::Initialize {
+                initializer_amount: 0,
+                taker_amount: listing.taker_amount,
+                donation_recipient,
+                donation_bps,
+                price_usd_cents: 0,
+                price_feed: Pubkey::default(),
+                settlement_delay_secs: 0,
+                note: String::new(),
+            }
+            .data(),
+        };
+        self.send(&[ix], &[initializer])?;
+        Ok(escrow_state)
+    }
+
+    fn exchange(
+        &self,
+        initializer: &Pubkey,
+        escrow_state: Pubkey,
+        listing: &Listing,
+    ) -> Result<Signature, Error> {
+        let taker = self.wallet(listing.taker);
+        let mint = self.mints[listing.nft];
+        let receive_mint = self.mints[listing.receive_nft];
+        let ix = Instruction {
+            program_id: solana_nft_escrow::ID,
+            accounts: accounts::Exchange {
+                taker: taker.pubkey(),
+                initializer_deposit_token_mint: mint,
+                taker_deposit_token_mint: receive_mint,
+                taker_deposit_token_account: get_associated_token_address(
+                    &taker.pubkey(),
+                    &receive_mint,
+                ),
+                taker_receive_token_account: get_associated_token_address(&taker.pubkey(), &mint),
+                initializer: *initializer,
+                initializer_receive_token_account: get_associated_token_address(
+                    initializer,
+                    &receive_mint,
+                ),
+                escrow_state,
+                vault: Some(vault_key(&escrow_state, &mint)),
+                vault_authority: vault_authority_key(&escrow_state),
+                config: pda(&[b"config"]),
+                treasury: self.treasury,
+                referrer: None,
+                discount_token_account: None,
+                collection_fee_override: solana_nft_escrow::collection_fee_override_address(
+                    &Pubkey::default(),
+                    0,
+                ),
+                collection_treasury: solana_nft_escrow::collection_treasury_address(
+                    &Pubkey::default(),
+                    0,
+                ),
+                taker_activity: None,
+                audit_trail: None,
+                token_program: spl_token::ID,
+                associated_token_program: spl_associated_token_account::ID,
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            data: instruction::Exchange {
+                memo: None,
+                terms_hash: None,
+                expected_price: None,
+                expected_mint: None,
+            }
+            .data(),
+        };
+        self.send(&[ix], &[taker])
+    }
+
+    fn cancel(
+        &self,
+        initializer: &Keypair,
+        escrow_state: Pubkey,
+        listing: &Listing,
+    ) -> Result<Signature, Error> {
+        let mint = self.mints[listing.nft];
+        let ix = Instruction {
+            program_id: solana_nft_escrow::ID,
+            accounts: accounts::Cancel {
+                initializer: initializer.pubkey(),
+                mint,
+                vault: Some(vault_key(&escrow_state, &mint)),
+                closed_vault: None,
+                vault_authority: vault_authority_key(&escrow_state),
+                initializer_deposit_token_account: get_associated_token_address(
+                    &initializer.pubkey(),
+                    &mint,
+                ),
+                escrow_state,
+                nft_edition: None,
+                token_metadata_program: None,
+                audit_trail: None,
+                memo_program: None,
+                token_program: spl_token::ID,
+            }
+            .to_account_metas(None),
+            data: instruction::Cancel { memo: None }.data(),
+        };
+        self.send(&[ix], &[initializer])
+    }
+
+    fn seed_listing(&self, listing: &Listing) -> Result<Value, Error> {
+        let initializer = self.wallet("alice");
+        let escrow_state = self.initialize(initializer, listing)?;
+        let signature = match listing.outcome {
+            Outcome::Open => None,
+            Outcome::Exchanged => {
+                Some(self.exchange(&initializer.pubkey(), escrow_state, listing)?)
+            }
+            Outcome::Cancelled => Some(self.cancel(initializer, escrow_state, listing)?),
+        };
+        Ok(json!({
+            "name": listing.name,
+            "status": listing.outcome.as_str(),
+            "escrowState": escrow_state.to_string(),
+            "vault": vault_key(&escrow_state, &self.mints[listing.nft]).to_string(),
+            "initializer": initializer.pubkey().to_string(),
+            "taker": self.wallet(listing.taker).pubkey().to_string(),
+            "mint": self.mints[listing.nft].to_string(),
+            "receiveMint": self.mints[listing.receive_nft].to_string(),
+            "takerAmount": listing.taker_amount,
+            "signature": signature.map(|signature| signature.to_string()),
+        }))
+    }
+}
+
+fn main() -> Result<(), Error> {
+    let mut args = std::env::args().skip(1);
+    let rpc_url = args.next().unwrap_or_else(|| DEFAULT_RPC_URL.to_string());
+    let payer_path = args.next().unwrap_or_else(|| {
+        format!(
+            "{}/.config/solana/id.json",
+            std::env::var("HOME").unwrap_or_default()
+        )
+    });
+
+    let rpc = RpcClient::new_with_commitment(rpc_url, CommitmentConfig::confirmed());
+    let payer = read_keypair_file(&payer_path)
+        .map_err(|err| format!("failed to read payer keypair {payer_path}: {err}"))?;
+    if !rpc
+        .get_account(&solana_nft_escrow::ID)
+        .map(|account| account.executable)
+        .unwrap_or(false)
+    {
+        return Err(format!("program {} is not deployed", solana_nft_escrow::ID).into());
+    }
+    if rpc.get_balance(&payer.pubkey())? < LAMPORTS_PER_SOL {
+        let signature = rpc.request_airdrop(&payer.pubkey(), 10 * LAMPORTS_PER_SOL)?;
+        rpc.poll_for_signature(&signature)?;
+    }
+
+    let treasury = Fixtures::ensure_config(&rpc, &payer)?;
+    let mut fixtures = Fixtures {
+        rpc,
+        payer,
+        treasury,
+        wallets: BTreeMap::new(),
+        mints: BTreeMap::new(),
+    };
+    for name in ["alice", "bob", "carol"] {
+        let wallet = Keypair::new();
+        fixtures.airdrop(&wallet.pubkey(), 10 * LAMPORTS_PER_SOL)?;
+        fixtures.wallets.insert(name, wallet);
+    }
+    for &(nft, holder) in NFTS {
+        let mint = fixtures.mint_nft(&fixtures.wallet(holder).pubkey())?;
+        fixtures.mints.insert(nft, mint);
+    }
+
+    let escrows = LISTINGS
+        .iter()
+        .map(|listing| fixtures.seed_listing(listing))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let wallets: BTreeMap<_, _> = fixtures
+        .wallets
+        .iter()
+        .map(|(name, wallet)| {
+            (
+                *name,
+                json!({
+                    "pubkey": wallet.pubkey().to_string(),
+                    "secretKey": wallet.to_bytes().to_vec(),
+                }),
+            )
+        })
+        .collect();
+    let mints: BTreeMap<_, _> = fixtures
+        .mints
+        .iter()
+        .map(|(name, mint)| (*name, mint.to_string()))
+        .collect();
+    let output = json!({
+        "programId": solana_nft_escrow::ID.to_string(),
+        "config": pda(&[b"config"]).to_string(),
+        "treasury": fixtures.treasury.to_string(),
+        "wallets": wallets,
+        "mints": mints,
+        "escrows": escrows,
+    });
+    println!("{}", serde_json::to_string_pretty(&output)?);
+    Ok(())
+}
@@ -0,0 +1,572 @@
+//! Drives random sequences of `initialize`, `exchange` and `cancel` (plus raw
+//! instructions with mutated data and permuted accounts) against the BPF build
+//! and checks that no run panics the program, leaks lamports, duplicates an
+//! NFT or releases a vault to someone it shouldn't.
+//!
+//! Build the program with `anchor build`, then from this directory:
+//! `BPF_OUT_DIR=../target/deploy cargo hfuzz run escrow_instructions`.
+
+use std::collections::HashMap;
+
+use anchor_lang::prelude::Pubkey;
+use anchor_lang::solana_program::{
+    instruction::{AccountMeta, Instruction},
+    system_instruction, system_program,
+};
+use anchor_lang::{AccountDeserialize, Discriminator, InstructionData, ToAccountMetas};
+use anchor_spl::associated_token::get_associated_token_address;
+use anchor_spl::token::spl_token;
+use arbitrary::Arbitrary;
+use honggfuzz::fuzz;
+use solana_nft_escrow::{accounts, instruction, EscrowState, ListingCounter};
+use solana_program_test::{ProgramTest, ProgramTestContext};
+use solana_sdk::{
+    account::Account,
+    instruction::InstructionError,
+    program_pack::Pack,
+    signature::{Keypair, Signer},
+    transaction::{Transaction, TransactionError},
+};
+use spl_associated_token_account::instruction::create_associated_token_account;
+
+const WALLETS: usize = 3;
+const MINTS: usize = 3;
+const MAX_INSTRUCTIONS: usize = 8;
+
+#[derive(Arbitrary, Debug)]
+enum FuzzInstruction {
+    Initialize {
+        initializer: u8,
+        taker: u8,
+        deposit_mint: u8,
+        receive_mint: u8,
+        initializer_amount: u32,
+        taker_amount: u32,
+        donation_bps: u16,
+    },
+    Exchange {
+        taker: u8,
+        initializer: u8,
+        nonce: u8,
+        deposit_mint: u8,
+        receive_mint: u8,
+    },
+    Cancel {
+        initializer: u8,
+        nonce: u8,
+        mint: u8,
+    },
+    Mutated {
+        instruction: u8,
+        signer: u8,
+        data: Vec<u8>,
+        accounts: Vec<u8>,
+    },
+}
+
+struct Env {
+    ctx: ProgramTestContext,
+    wallets: Vec<Keypair>,
+    mints: Vec<Pubkey>,
+    treasury: Pubkey,
+    /// Every account an instruction can reach; lamports and tokens are
+    /// conserved across this set.
+    pool: Vec<Pubkey>,
+}
+
+fn pda(seeds: &[&[u8]]) -> Pubkey {
+    Pubkey::find_program_address(seeds, &solana_nft_escrow::ID).0
+}
+
+fn escrow_state_key(initializer: &Pubkey, nonce: u64) -> Pubkey {
+    pda(&[b"state", initializer.as_ref(), &nonce.to_le_bytes()])
+}
+
+fn vault_authority_key(escrow_state: &Pubkey) -> Pubkey {
+    pda(&[b"authority", escrow_state.as_ref()])
+}
+
+fn vault_key(escrow_state: &Pubkey, mint: &Pubkey) -> Pubkey {
+    get_associated_token_address(&vault_authority_key(escrow_state), mint)
+}
+
+impl Env {
+    async fn new() -> Self {
+        let wallets: Vec<Keypair> = (0..WALLETS).map(|_| Keypair::new()).collect();
+        let treasury = Pubkey::new_unique();
+
+        let mut program_test = ProgramTest::new("solana_nft_escrow", solana_nft_escrow::ID, None);
+        program_test.prefer_bpf(true);
+        for wallet in &wallets {
+            program_test.add_account(
+                wallet.pubkey(),
+                Account::new(10_000_000_000, 0, &system_program::ID),
+            );
+        }
+        let ctx = program_test.start_with_context().await;
+        let mut env = Env {
+            ctx,
+            wallets,
+            mints: vec![],
+            treasury,
+            pool: vec![],
+        };
+
+        for _ in 0..MINTS {
+            let mint = env.create_mint().await;
+            env.mints.push(mint);
+        }
+        let payer = env.ctx.payer.pubkey();
+        let mut instructions = vec![];
+        for wallet in &env.wallets {
+            for mint in &env.mints {
+                let ata = get_associated_token_address(&wallet.pubkey(), mint);
+                instructions.push(create_associated_token_account(
+                    &payer,
+                    &wallet.pubkey(),
+                    mint,
+                    &spl_token::ID,
+                ));
+                instructions.push(
+                    spl_token::instruction::mint_to(&spl_token::ID, mint, &ata, &payer, &[], 1)
+                        .unwrap(),
+                );
+            }
+        }
+        env.process(&instructions, &[]).await.1.unwrap();
+
+        let initialize_config = Instruction {
+            program_id: solana_nft_escrow::ID,
+            accounts: accounts::InitializeConfig {
+                admin: payer,
+                config: pda(&[b"config"]),
+                treasury,
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            data: instruction::InitializeConfig {
+                maker_fee_bps: 150,
+                taker_fee_bps: 100,
+                referral_share_bps: 0,
+            }
+            .data(),
+        };
+        env.process(&[initialize_config], &[]).await.1.unwrap();
+
+        env.pool = env.reachable_accounts();
+        env
+    }
+
+    fn reachable_accounts(&self) -> Vec<Pubkey> {
+        let mut pool = vec![
+            self.ctx.payer.pubkey(),
+            self.treasury,
+            pda(&[b"config"]),
+            system_program::ID,
+            spl_token::ID,
+            spl_associated_token_account::ID,
+        ];
+        pool.extend(self.mints.iter().copied());
+        for wallet in &self.wallets {
+            let wallet = wallet.pubkey();
+            pool.push(wallet);
+            pool.push(pda(&[b"listing_counter", wallet.as_ref()]));
+            pool.extend(
+                self.mints
+                    .iter()
+                    .map(|mint| get_associated_token_address(&wallet, mint)),
+            );
+            for nonce in 0..MAX_INSTRUCTIONS as u64 {
+                let escrow_state = escrow_state_key(&wallet, nonce);
+                pool.push(escrow_state);
+                pool.push(vault_authority_key(&escrow_state));
+                pool.extend(self.mints.iter().map(|mint| vault_key(&escrow_state, mint)));
+            }
+        }
+        pool
+    }
+
+    /// Returns the transaction fee alongside the outcome.
+    async fn process(
+        &mut self,
+        instructions: &[Instruction],
+        signers: &[&Keypair],
+    ) -> (u64, Result<(), TransactionError>) {
+        let blockhash = self.ctx.get_new_latest_blockhash().await.unwrap();
+        let mut all_signers = vec![&self.ctx.payer];
+        all_signers.extend_from_slice(signers);
+        let tx = Transaction::new_signed_with_payer(
+            instructions,
+            Some(&self.ctx.payer.pubkey()),
+            &all_signers,
+            blockhash,
+        );
+        let fee = self
+            .ctx
+            .banks_client
+            .get_fee_for_message(tx.message().clone())
+            .await
+            .unwrap()
+            .unwrap_or_default();
+        let result = self
+            .ctx
+            .banks_client
+            .process_transaction(tx)
+            .await
+            .map_err(|err| err.unwrap());
+        (fee, result)
+    }
+
+    async fn create_mint(&mut self) -> Pubkey {
+        let mint = Keypair::new();
+        let rent = self.ctx.banks_client.get_rent().await.unwrap();
+        let payer = self.ctx.payer.pubkey();
+        let instructions = [
+            system_instruction::create_account(
+                &payer,
+                &mint.pubkey(),
+                rent.minimum_balance(spl_token::state::Mint::LEN),
+                spl_token::state::Mint::LEN as u64,
+                &spl_token::ID,
+            ),
+            spl_token::instruction::initialize_mint2(
+                &spl_token::ID,
+                &mint.pubkey(),
+                &payer,
+                None,
+                0,
+            )
+            .unwrap(),
+        ];
+        self.process(&instructions, &[&mint]).await.1.unwrap();
+        mint.pubkey()
+    }
+
+    async fn snapshot(&mut self) -> Snapshot {
+        let mut snapshot = Snapshot::default();
+        for key in self.pool.clone() {
+            let Some(account) = self.ctx.banks_client.get_account(key).await.unwrap() else {
+                continue;
+            };
+            snapshot.lamports += account.lamports as u128;
+            if account.owner == spl_token::ID {
+                if let Ok(token) = spl_token::state::Account::unpack(&account.data) {
+                    snapshot.tokens.insert(key, (token.mint, token.amount));
+                }
+            } else if account.owner == solana_nft_escrow::ID {
+                if let Ok(state) = EscrowState::try_deserialize(&mut account.data.as_slice()) {
+                    snapshot.escrows.insert(key, state);
+                }
+            }
+        }
+        snapshot
+    }
+
+    fn wallet(&self, index: u8) -> &Keypair {
+        &self.wallets[index as usize % WALLETS]
+    }
+
+    fn mint(&self, index: u8) -> Pubkey {
+        self.mints[index as usize % MINTS]
+    }
+
+    async fn next_nonce(&mut self, listing_counter: Pubkey) -> u64 {
+        match self
+            .ctx
+            .banks_client
+            .get_account(listing_counter)
+            .await
+            .unwrap()
+        {
+            Some(account) => ListingCounter::try_deserialize(&mut account.data.as_slice())
+                .map(|counter| counter.count)
+                .unwrap_or_default(),
+            None => 0,
+        }
+    }
+
+    async fn build(&mut self, fuzz_instruction: &FuzzInstruction) -> (Instruction, Keypair) {
+        match *fuzz_instruction {
+            FuzzInstruction::Initialize {
+                initializer,
+                taker,
+                deposit_mint,
+                receive_mint,
+                initializer_amount,
+                taker_amount,
+                donation_bps,
+            } => {
+                let signer = self.wallet(initializer).insecure_clone();
+                let initializer = signer.pubkey();
+                let mint = self.mint(deposit_mint);
+                let listing_counter = pda(&[b"listing_counter", initializer.as_ref()]);
+                let nonce = self.next_nonce(listing_counter).await;
+                let escrow_state = escrow_state_key(&initializer, nonce);
+                let ix = Instruction {
+                    program_id: solana_nft_escrow::ID,
+                    accounts: accounts::Initialize {
+                        initializer,
+                        rent_payer: initializer,
+                        mint,
+                        vault_authority: vault_authority_key(&escrow_state),
+                        vault: vault_key(&escrow_state, &mint),
+                        taker_key: self.wallet(taker).pubkey(),
+                        initializer_deposit_token_account: get_associated_token_address(
+                            &initializer,
+                            &mint,
+                        ),
+                        initializer_receive_mint_account: self.mint(receive_mint),
+                        config: pda(&[b"config"]),
+                        listing_counter,
+                        escrow_state,
+                        nft_metadata: solana_nft_escrow::metadata_address(&mint),
+                        collection_floor: solana_nft_escrow::collection_floor_address(
+                            &Pubkey::default(),
+                            0,
+                        ),
+                        wallet_activity: None,
+                        system_program: system_program::ID,
+                        token_program: spl_token::ID,
+                        associated_token_program: spl_associated_token_account::ID,
+                    }
+                    .to_account_metas(None),
+                    data: instruction::Initialize {
+                        initializer_amount: initializer_amount as u64,
+                        taker_amount: taker_amount as u64,
+                        donation_recipient: self.treasury,
+                        donation_bps,
+                        price_usd_cents: 0,
+                        price_feed: Pubkey::default(),
+                        settlement_delay_secs: 0,
+                        note: String::new(),
+                    }
+                    .data(),
+                };
+                (ix, signer.insecure_clone())
+            }
+            FuzzInstruction::Exchange {
+                taker,
+                initializer,
+                nonce,
+                deposit_mint,
+                receive_mint,
+            } => {
+                let signer = self.wallet(taker);
+                let taker = signer.pubkey();
+                let initializer = self.wallet(initializer).pubkey();
+                let escrow_state = escrow_state_key(&initializer, nonce as u64);
+                let deposit_mint = self.mint(deposit_mint);
+                let receive_mint = self.mint(receive_mint);
+                let mut ix = Instruction {
+                    program_id: solana_nft_escrow::ID,
+                    accounts: accounts::Exchange {
+                        taker,
+                        initializer_deposit_token_mint: deposit_mint,
+                        taker_deposit_token_mint: receive_mint,
+                        taker_deposit_token_account: get_associated_token_address(
+                            &taker,
+                            &receive_mint,
+                        ),
+                        taker_receive_token_account: get_associated_token_address(
+                            &taker,
+                            &deposit_mint,
+                        ),
+                        initializer,
+                        initializer_receive_token_account: get_associated_token_address(
+                            &initializer,
+                            &receive_mint,
+                        ),
+                        escrow_state,
+                        vault: Some(vault_key(&escrow_state, &deposit_mint)),
+                        vault_authority: vault_authority_key(&escrow_state),
+                        config: pda(&[b"config"]),
+                        treasury: self.treasury,
+                        referrer: None,
+                        discount_token_account: None,
+                        collection_fee_override: solana_nft_escrow::collection_fee_override_address(
+                            &Pubkey::default(),
+                            0,
+                        ),
+                        collection_treasury: solana_nft_escrow::collection_treasury_address(
+                            &Pubkey::default(),
+                            0,
+                        ),
+                        taker_activity: None,
+                        audit_trail: None,
+                        token_program: spl_token::ID,
+                        associated_token_program: spl_associated_token_account::ID,
+                        system_program: system_program::ID,
+                    }
+                    .to_account_metas(None),
+                    data: instruction::Exchange {
+                        memo: None,
+                        terms_hash: None,
+                        expected_price: None,
+                        expected_mint: None,
+                    }
+                    .data(),
+                };
+                // Listings made by the harness donate to the treasury.
+                ix.accounts.push(AccountMeta::new(self.treasury, false));
+                (ix, signer.insecure_clone())
+            }
+            FuzzInstruction::Cancel {
+                initializer,
+                nonce,
+                mint,
+            } => {
+                let signer = self.wallet(initializer);
+                let initializer = signer.pubkey();
+                let escrow_state = escrow_state_key(&initializer, nonce as u64);
+                let mint = self.mint(mint);
+                let ix = Instruction {
+                    program_id: solana_nft_escrow::ID,
+                    accounts: accounts::Cancel {
+                        initializer,
+                        mint,
+                        vault: Some(vault_key(&escrow_state, &mint)),
+                        closed_vault: None,
+                        vault_authority: vault_authority_key(&escrow_state),
+                        initializer_deposit_token_account: get_associated_token_address(
+                            &initializer,
+                            &mint,
+                        ),
+                        escrow_state,
+                        nft_edition: None,
+                        token_metadata_program: None,
+                        audit_trail: None,
+                        memo_program: None,
+                        token_program: spl_token::ID,
+                    }
+                    .to_account_metas(None),
+                    data: instruction::Cancel { memo: None }.data(),
+                };
+                (ix, signer.insecure_clone())
+            }
+            FuzzInstruction::Mutated {
+                instruction,
+                signer,
+                ref data,
+                ref accounts,
+            } => {
+                let signer = self.wallet(signer);
+                let discriminator = match instruction % 3 {
+                    0 => instruction::Initialize::discriminator(),
+                    1 => instruction::Exchange::discriminator(),
+                    _ => instruction::Cancel::discriminator(),
+                };
+                let mut ix_data = discriminator.to_vec();
+                ix_data.extend_from_slice(data);
+                let metas = accounts
+                    .iter()
+                    .map(|index| {
+                        let key = self.pool[*index as usize % self.pool.len()];
+                        AccountMeta {
+                            pubkey: key,
+                            is_signer: key == signer.pubkey(),
+                            is_writable: true,
+                        }
+                    })
+                    .collect();
+                let ix = Instruction {
+                    program_id: solana_nft_escrow::ID,
+                    accounts: metas,
+                    data: ix_data,
+                };
+                (ix, signer.insecure_clone())
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct Snapshot {
+    lamports: u128,
+    /// Token account to (mint, amount).
+    tokens: HashMap<Pubkey, (Pubkey, u64)>,
+    escrows: HashMap<Pubkey, EscrowState>,
+}
+
+fn check_invariants(
+    before: &Snapshot,
+    after: &Snapshot,
+    spent: &[u128],
+    signer: &Pubkey,
+    env: &Env,
+) {
+    // The only lamports allowed to leave the pool are the transaction fee.
+    assert!(
+        spent.contains(&(before.lamports - after.lamports)),
+        "lamports leaked"
+    );
+
+    // Every NFT exists exactly once across wallets and vaults.
+    for mint in &env.mints {
+        let held: u64 = after
+            .tokens
+            .values()
+            .filter(|(token_mint, _)| token_mint == mint)
+            .map(|(_, amount)| amount)
+            .sum();
+        assert_eq!(held, WALLETS as u64, "supply of {mint} changed");
+    }
+
+    // The state and its vault always close together, and a closed escrow
+    // either returned the NFT to its initializer or paid them.
+    for (key, state) in &before.escrows {
+        if after.escrows.contains_key(key) {
+            continue;
+        }
+        let vault = vault_key(key, &state.initializer_deposit_mint_account);
+        assert!(
+            !after.tokens.contains_key(&vault),
+            "vault outlived its escrow"
+        );
+
+        let amount =
+            |snapshot: &Snapshot, key: &Pubkey| snapshot.tokens.get(key).map(|(_, amount)| *amount);
+        let refunded = amount(after, &state.initializer_deposit_token_account)
+            > amount(before, &state.initializer_deposit_token_account);
+        let paid = amount(after, &state.initializer_receive_token_account)
+            > amount(before, &state.initializer_receive_token_account);
+        assert!(refunded || paid, "escrow {key} released without settlement");
+        if refunded && !paid {
+            assert_eq!(*signer, state.initializer_key, "cancelled by a stranger");
+        }
+    }
+}
+
+async fn run(input: Vec<FuzzInstruction>) {
+    let mut env = Env::new().await;
+    for fuzz_instruction in input.iter().take(MAX_INSTRUCTIONS) {
+        let (ix, signer) = env.build(fuzz_instruction).await;
+        let before = env.snapshot().await;
+        // Mutated account lists may leave the wallet out entirely.
+        let signs = ix
+            .accounts
+            .iter()
+            .any(|meta| meta.pubkey == signer.pubkey());
+        let signers: &[&Keypair] = if signs { &[&signer] } else { &[] };
+        let (fee, result) = env.process(&[ix], signers).await;
+        let spent = match result {
+            Ok(()) => vec![fee as u128],
+            Err(TransactionError::InstructionError(
+                _,
+                InstructionError::ProgramFailedToComplete,
+            )) => panic!("program panicked on {fuzz_instruction:?}"),
+            // Rejected transactions pay their fee unless they never executed.
+            Err(_) => vec![0, fee as u128],
+        };
+        let after = env.snapshot().await;
+        check_invariants(&before, &after, &spent, &signer.pubkey(), &env);
+    }
+}
+
+fn main() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    loop {
+        fuzz!(|input: Vec<FuzzInstruction>| {
+            runtime.block_on(run(input));
+        });
+    }
+}
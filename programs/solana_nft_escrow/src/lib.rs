@@ -1,370 +1,11031 @@
 use anchor_lang::prelude::*;
+use anchor_lang::solana_program::sysvar::instructions::{
+    self as instructions_sysvar, load_current_index_checked, load_instruction_at_checked,
+};
 use anchor_lang::solana_program::{
-    program::invoke, program::invoke_signed, system_instruction::transfer,
+    ed25519_program, hash, instruction::Instruction, keccak, native_token::LAMPORTS_PER_SOL,
+    program::invoke, program::invoke_signed, program_option::COption, system_instruction::transfer,
+};
+use anchor_lang::InstructionData;
+use anchor_spl::associated_token::{get_associated_token_address, AssociatedToken};
+use anchor_spl::memo::{self, BuildMemo, Memo};
+use anchor_spl::metadata::mpl_token_metadata::state::TokenStandard;
+use anchor_spl::metadata::{
+    self, FreezeDelegatedAccount, MasterEditionAccount, Metadata, MetadataAccount,
+    MintNewEditionFromMasterEditionViaToken, ThawDelegatedAccount,
+};
+use anchor_spl::token::spl_token::native_mint::{
+    DECIMALS as NATIVE_MINT_DECIMALS, ID as NATIVE_MINT,
+};
+use anchor_spl::token::{
+    self, Approve, Burn, CloseAccount, Mint, MintTo, Revoke, SyncNative, Token, TokenAccount,
+    TransferChecked,
 };
-use anchor_spl::associated_token::AssociatedToken;
-use anchor_spl::token::{self, CloseAccount, Mint, Token, TokenAccount, TransferChecked};
+use pyth_sdk_solana::load_price_feed_from_account_info;
 
 declare_id!("DGEX1Zf94mjrPHNLiutYTdwfdBBvsXk8BBHF2kFeBPyy");
 
+/// Denominator for every basis-point value stored in the config.
+pub const BPS_DENOMINATOR: u64 = 10_000;
+pub const SECONDS_PER_YEAR: u64 = 31_536_000;
+/// Most assets either side of a `SwapState` can offer.
+pub const MAX_SWAP_ASSETS: usize = 4;
+/// Most parties and most deposits a `DealRoom` holds.
+pub const MAX_DEAL_PARTIES: usize = 4;
+pub const MAX_DEAL_DEPOSITS: usize = 8;
+/// Most asks and most bids an `OrderBook` lists at once.
+pub const MAX_BOOK_ORDERS: usize = 32;
+/// What a `Session` key may do on its owner's behalf.
+pub const SESSION_SCOPE_LIST: u8 = 1;
+pub const SESSION_SCOPE_CANCEL: u8 = 2;
+/// Where `EscrowState::tag` sits in the account data, for memcmp filters.
+pub const ESCROW_TAG_OFFSET: usize = 8 + 1149;
+/// Longest note a seller can attach to a listing, in bytes.
+pub const MAX_NOTE_LEN: usize = 64;
+/// Longest `terms_uri` a listing can reference, in bytes.
+pub const MAX_TERMS_URI_LEN: usize = 128;
+/// Longest memo `exchange` and `cancel` forward to the memo program.
+pub const MAX_MEMO_LEN: usize = 256;
+/// Actions an `AuditTrail` folds into its hash.
+pub const AUDIT_OPENED: u8 = 0;
+pub const AUDIT_EXCHANGED: u8 = 1;
+pub const AUDIT_CANCELLED: u8 = 2;
+/// Layout version of the `QuoteRoute` returned by `get_quote_route`.
+pub const QUOTE_ROUTE_VERSION: u8 = 1;
+
 #[program]
 pub mod anchor_escrow {
     use super::*;
 
     const AUTHORITY_SEED: &[u8] = b"authority";
 
+    pub fn initialize_config(
+        ctx: Context<InitializeConfig>,
+        maker_fee_bps: u16,
+        taker_fee_bps: u16,
+        referral_share_bps: u16,
+    ) -> Result<()> {
+        require_valid_bps(&[maker_fee_bps, taker_fee_bps, referral_share_bps])?;
+
+        let config = &mut ctx.accounts.config;
+        config.admin = *ctx.accounts.admin.key;
+        config.treasury = *ctx.accounts.treasury.key;
+        config.maker_fee_bps = maker_fee_bps;
+        config.taker_fee_bps = taker_fee_bps;
+        config.referral_share_bps = referral_share_bps;
+        config.bump = *ctx.bumps.get("config").unwrap();
+
+        Ok(())
+    }
+
+    /// Opens an isolated market with its own admin, treasury and fees.
+    /// Escrows listed in it are only settled against its config.
+    pub fn create_market(
+        ctx: Context<CreateMarket>,
+        market_id: u64,
+        maker_fee_bps: u16,
+        taker_fee_bps: u16,
+        referral_share_bps: u16,
+    ) -> Result<()> {
+        require!(market_id != 0, EscrowError::InvalidMarket);
+        require_valid_bps(&[maker_fee_bps, taker_fee_bps, referral_share_bps])?;
+
+        let config = &mut ctx.accounts.config;
+        config.admin = *ctx.accounts.admin.key;
+        config.treasury = *ctx.accounts.treasury.key;
+        config.maker_fee_bps = maker_fee_bps;
+        config.taker_fee_bps = taker_fee_bps;
+        config.referral_share_bps = referral_share_bps;
+        config.bump = *ctx.bumps.get("config").unwrap();
+        config.market_id = market_id;
+
+        Ok(())
+    }
+
+    pub fn update_config(
+        ctx: Context<UpdateConfig>,
+        maker_fee_bps: u16,
+        taker_fee_bps: u16,
+        referral_share_bps: u16,
+    ) -> Result<()> {
+        require_valid_bps(&[maker_fee_bps, taker_fee_bps, referral_share_bps])?;
+
+        let config = &mut ctx.accounts.config;
+        config.treasury = *ctx.accounts.treasury.key;
+        config.maker_fee_bps = maker_fee_bps;
+        config.taker_fee_bps = taker_fee_bps;
+        config.referral_share_bps = referral_share_bps;
+
+        Ok(())
+    }
+
+    pub fn set_fee_discount(
+        ctx: Context<AdminConfig>,
+        discount_mint: Pubkey,
+        discount_min_amount: u64,
+        discount_maker_fee_bps: u16,
+        discount_taker_fee_bps: u16,
+    ) -> Result<()> {
+        require_valid_bps(&[discount_maker_fee_bps, discount_taker_fee_bps])?;
+
+        let config = &mut ctx.accounts.config;
+        config.discount_mint = discount_mint;
+        config.discount_min_amount = discount_min_amount;
+        config.discount_maker_fee_bps = discount_maker_fee_bps;
+        config.discount_taker_fee_bps = discount_taker_fee_bps;
+
+        Ok(())
+    }
+
+    pub fn set_rewards(
+        ctx: Context<SetRewards>,
+        rewards_per_sol: u64,
+        rewards_paused: bool,
+    ) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        config.rewards_mint = ctx.accounts.rewards_mint.key();
+        config.rewards_per_sol = rewards_per_sol;
+        config.rewards_paused = rewards_paused;
+        config.rewards_authority_bump = *ctx.bumps.get("rewards_authority").unwrap();
+
+        Ok(())
+    }
+
+    pub fn set_rewards_paused(ctx: Context<AdminConfig>, rewards_paused: bool) -> Result<()> {
+        ctx.accounts.config.rewards_paused = rewards_paused;
+        Ok(())
+    }
+
+    pub fn set_collection_fee_override(
+        ctx: Context<SetCollectionFeeOverride>,
+        maker_fee_bps: u16,
+        taker_fee_bps: u16,
+    ) -> Result<()> {
+        require_valid_bps(&[maker_fee_bps, taker_fee_bps])?;
+
+        let fee_override = &mut ctx.accounts.collection_fee_override;
+        fee_override.collection_mint = ctx.accounts.collection_mint.key();
+        fee_override.maker_fee_bps = maker_fee_bps;
+        fee_override.taker_fee_bps = taker_fee_bps;
+        fee_override.bump = *ctx.bumps.get("collection_fee_override").unwrap();
+
+        Ok(())
+    }
+
+    pub fn remove_collection_fee_override(
+        _ctx: Context<RemoveCollectionFeeOverride>,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn register_collection_treasury(
+        ctx: Context<RegisterCollectionTreasury>,
+        share_bps: u16,
+    ) -> Result<()> {
+        require_valid_bps(&[share_bps])?;
+
+        let collection_treasury = &mut ctx.accounts.collection_treasury;
+        collection_treasury.collection_mint = ctx.accounts.collection_mint.key();
+        collection_treasury.treasury = ctx.accounts.dao_treasury.key();
+        collection_treasury.share_bps = share_bps;
+        collection_treasury.bump = *ctx.bumps.get("collection_treasury").unwrap();
+
+        Ok(())
+    }
+
+    pub fn remove_collection_treasury(_ctx: Context<RemoveCollectionTreasury>) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn set_oracle_params(
+        ctx: Context<AdminConfig>,
+        oracle_max_age_secs: u64,
+        oracle_max_conf_bps: u16,
+        oracle_max_twap_deviation_bps: u16,
+    ) -> Result<()> {
+        require_valid_bps(&[oracle_max_conf_bps, oracle_max_twap_deviation_bps])?;
+
+        let config = &mut ctx.accounts.config;
+        config.oracle_max_age_secs = oracle_max_age_secs;
+        config.oracle_max_conf_bps = oracle_max_conf_bps;
+        config.oracle_max_twap_deviation_bps = oracle_max_twap_deviation_bps;
+
+        Ok(())
+    }
+
+    pub fn set_reservation_params(
+        ctx: Context<AdminConfig>,
+        reservation_deposit_bps: u16,
+        reservation_window_secs: i64,
+    ) -> Result<()> {
+        require_valid_bps(&[reservation_deposit_bps])?;
+        require!(
+            reservation_window_secs >= 0,
+            EscrowError::InvalidReservationWindow
+        );
+
+        let config = &mut ctx.accounts.config;
+        config.reservation_deposit_bps = reservation_deposit_bps;
+        config.reservation_window_secs = reservation_window_secs;
+
+        Ok(())
+    }
+
+    /// Sets the treasury's share of each printed edition's price, taken in
+    /// place of the maker fee; zero keeps the maker fee.
+    pub fn set_edition_treasury_share(
+        ctx: Context<AdminConfig>,
+        edition_treasury_bps: u16,
+    ) -> Result<()> {
+        require_valid_bps(&[edition_treasury_bps])?;
+        ctx.accounts.config.edition_treasury_bps = edition_treasury_bps;
+        Ok(())
+    }
+
+    /// Sets the seller's share of the surplus when `match_escrows` fills a
+    /// listing from an offer above its price; the buyer keeps the rest.
+    pub fn set_match_surplus_share(
+        ctx: Context<AdminConfig>,
+        match_surplus_seller_bps: u16,
+    ) -> Result<()> {
+        require_valid_bps(&[match_surplus_seller_bps])?;
+        ctx.accounts.config.match_surplus_seller_bps = match_surplus_seller_bps;
+        Ok(())
+    }
+
+    /// Sets the tip `crank_match` pays its caller out of the spread.
+    pub fn set_crank_tip(ctx: Context<AdminConfig>, crank_tip_bps: u16) -> Result<()> {
+        require_valid_bps(&[crank_tip_bps])?;
+        ctx.accounts.config.crank_tip_bps = crank_tip_bps;
+        Ok(())
+    }
+
+    /// Sets how long a new listing waits before it can be bought, so bots
+    /// can't sweep an underpriced listing the moment it appears.
+    pub fn set_min_listing_age(ctx: Context<AdminConfig>, min_listing_age_secs: i64) -> Result<()> {
+        require!(min_listing_age_secs >= 0, EscrowError::InvalidMinListingAge);
+        ctx.accounts.config.min_listing_age_secs = min_listing_age_secs;
+        Ok(())
+    }
+
+    /// Caps how many listings and fills one wallet makes per window of
+    /// `window_secs`; a zero cap leaves that side unlimited.
+    pub fn set_rate_limits(
+        ctx: Context<AdminConfig>,
+        window_secs: i64,
+        max_listings_per_window: u16,
+        max_fills_per_window: u16,
+    ) -> Result<()> {
+        require!(
+            window_secs > 0 || (max_listings_per_window == 0 && max_fills_per_window == 0),
+            EscrowError::InvalidRateLimit
+        );
+        let config = &mut ctx.accounts.config;
+        config.rate_limit_window_secs = window_secs;
+        config.max_listings_per_window = max_listings_per_window;
+        config.max_fills_per_window = max_fills_per_window;
+        Ok(())
+    }
+
+    /// Caps how far ahead `extend_expiry` may push a listing's expiry; zero
+    /// leaves it unbounded.
+    pub fn set_max_listing_duration(
+        ctx: Context<AdminConfig>,
+        max_listing_duration_secs: i64,
+    ) -> Result<()> {
+        require!(
+            max_listing_duration_secs >= 0,
+            EscrowError::InvalidListingDuration
+        );
+        ctx.accounts.config.max_listing_duration_secs = max_listing_duration_secs;
+        Ok(())
+    }
+
+    /// Refuses fixed-price listings against a collection floor published
+    /// longer than `max_floor_age_secs` ago; zero accepts any age.
+    pub fn set_max_floor_age(ctx: Context<AdminConfig>, max_floor_age_secs: i64) -> Result<()> {
+        require!(max_floor_age_secs >= 0, EscrowError::InvalidFloorAge);
+        ctx.accounts.config.max_floor_age_secs = max_floor_age_secs;
+        Ok(())
+    }
+
+    /// Opens the account a wallet's listings and fills are counted in while
+    /// the market is rate limited.
+    pub fn open_wallet_activity(ctx: Context<OpenWalletActivity>) -> Result<()> {
+        let wallet_activity = &mut ctx.accounts.wallet_activity;
+        wallet_activity.wallet = ctx.accounts.wallet.key();
+        wallet_activity.bump = *ctx.bumps.get("wallet_activity").unwrap();
+        Ok(())
+    }
+
+    pub fn set_collection_floor_authority(
+        ctx: Context<SetCollectionFloorAuthority>,
+        max_below_floor_bps: u16,
+    ) -> Result<()> {
+        require_valid_bps(&[max_below_floor_bps])?;
+
+        let collection_floor = &mut ctx.accounts.collection_floor;
+        collection_floor.collection_mint = ctx.accounts.collection_mint.key();
+        collection_floor.floor_authority = ctx.accounts.floor_authority.key();
+        collection_floor.max_below_floor_bps = max_below_floor_bps;
+        collection_floor.bump = *ctx.bumps.get("collection_floor").unwrap();
+
+        Ok(())
+    }
+
+    pub fn update_collection_floor(
+        ctx: Context<UpdateCollectionFloor>,
+        floor_lamports: u64,
+    ) -> Result<()> {
+        let collection_floor = &mut ctx.accounts.collection_floor;
+        collection_floor.floor_lamports = floor_lamports;
+        collection_floor.updated_at = Clock::get()?.unix_timestamp;
+
+        Ok(())
+    }
+
+    pub fn set_self_trade_prevention(
+        ctx: Context<AdminConfig>,
+        prevent_self_trade: bool,
+    ) -> Result<()> {
+        ctx.accounts.config.prevent_self_trade = prevent_self_trade;
+        Ok(())
+    }
+
     pub fn initialize(
         ctx: Context<Initialize>,
-        random_seed: u64,
         initializer_amount: u64,
         taker_amount: u64,
-    ) -> Result<()> {
-        ctx.accounts.escrow_state.initializer_key = *ctx.accounts.initializer.key;
-        ctx.accounts.escrow_state.initializer_deposit_token_account = *ctx
-            .accounts
-            .initializer_deposit_token_account
-            .to_account_info()
-            .key;
-        ctx.accounts.escrow_state.initializer_receive_token_account = *ctx
-            .accounts
-            .initializer_receive_token_account
-            .to_account_info()
-            .key;
-        ctx.accounts.escrow_state.initializer_deposit_mint_account =
-            *ctx.accounts.mint.to_account_info().key;
-        ctx.accounts.escrow_state.initializer_receive_mint_account = *ctx
-            .accounts
-            .initializer_receive_mint_account
-            .to_account_info()
-            .key;
-        ctx.accounts.escrow_state.taker_key = *ctx.accounts.taker_key.key;
-        ctx.accounts.escrow_state.initializer_amount = initializer_amount;
-        ctx.accounts.escrow_state.taker_amount = taker_amount;
-        ctx.accounts.escrow_state.random_seed = random_seed;
+        donation_recipient: Pubkey,
+        donation_bps: u16,
+        price_usd_cents: u64,
+        price_feed: Pubkey,
+        settlement_delay_secs: i64,
+        note: String,
+    ) -> Result<EscrowAddresses> {
+        require_valid_bps(&[donation_bps])?;
+        require!(
+            donation_bps == 0 || donation_recipient != Pubkey::default(),
+            EscrowError::MissingDonationRecipient
+        );
+        require!(
+            price_usd_cents == 0 || price_feed != Pubkey::default(),
+            EscrowError::MissingPriceFeed
+        );
+        require!(
+            settlement_delay_secs >= 0,
+            EscrowError::InvalidSettlementDelay
+        );
+
+        let collection = require_listable(
+            &ctx.accounts.nft_metadata,
+            &ctx.accounts.collection_floor,
+            &ctx.accounts.config,
+            price_usd_cents,
+            taker_amount,
+        )?;
 
-        let (_vault_authority, vault_authority_bump) =
-            Pubkey::find_program_address(&[AUTHORITY_SEED], ctx.program_id);
-        ctx.accounts.escrow_state.vault_authority_bump = vault_authority_bump;
+        let nonce = ctx.accounts.listing_counter.take_nonce(
+            ctx.accounts.initializer.key(),
+            *ctx.bumps.get("listing_counter").unwrap(),
+        )?;
+        let now = Clock::get()?.unix_timestamp;
+        ctx.accounts.escrow_state.open(
+            NewListing {
+                nonce,
+                initializer: ctx.accounts.initializer.key(),
+                taker: ctx.accounts.taker_key.key(),
+                deposit_token_account: ctx.accounts.initializer_deposit_token_account.key(),
+                deposit_mint: ctx.accounts.mint.key(),
+                receive_mint: ctx.accounts.initializer_receive_mint_account.key(),
+                taker_amount,
+                vault_authority_bump: *ctx.bumps.get("vault_authority").unwrap(),
+                // Whatever the vault holds before the deposit is its
+                // rent-exempt reserve.
+                vault_rent_lamports: ctx.accounts.vault.to_account_info().lamports(),
+                market_id: ctx.accounts.config.market_id,
+                rent_payer: ctx.accounts.rent_payer.key(),
+                collection,
+                note,
+            },
+            now,
+        )?;
+        let escrow_state = &mut ctx.accounts.escrow_state;
+        escrow_state.initializer_amount = initializer_amount;
+        escrow_state.donation_recipient = donation_recipient;
+        escrow_state.donation_bps = donation_bps;
+        escrow_state.price_usd_cents = price_usd_cents;
+        escrow_state.price_feed = price_feed;
+        escrow_state.settlement_delay_secs = settlement_delay_secs;
+        escrow_state.delegated = false;
+        record_wallet_activity(
+            &mut ctx.accounts.wallet_activity,
+            &ctx.accounts.config,
+            now,
+            true,
+        )?;
 
         token::transfer_checked(
             ctx.accounts.into_transfer_to_pda_context(),
-            // .with_signer(&[&authority_seeds[..]]),
-            // ctx.accounts.escrow_state.initializer_amount,
             1 as u64,
             ctx.accounts.mint.decimals,
         )?;
 
-        // token::transfer(
-        //     CpiContext::new(
-        //         ctx.accounts.token_program.to_account_info(),
-        //         token::Transfer {
-        //             from: ctx
-        //                 .accounts
-        //                 .initializer_deposit_token_account
-        //                 .to_account_info(),
-        //             authority: ctx.accounts.initializer.to_account_info(),
-        //             to: ctx.accounts.vault.to_account_info(),
-        //         },
-        //     ),
-        //     1 as u64,
-        // )?;
-
-        let ix = transfer(
-            &ctx.accounts.initializer.key(),
-            &ctx.accounts.vault.key(),
+        // Skipped when there is no deposit, so a sponsored seller signs
+        // without being debited and need not be a system-owned wallet.
+        transfer_lamports(
+            &ctx.accounts.initializer.to_account_info(),
+            &ctx.accounts.vault.to_account_info(),
             ctx.accounts.escrow_state.initializer_amount,
-        );
-        invoke(
-            &ix,
-            &[
-                ctx.accounts.initializer.to_account_info(),
-                ctx.accounts.vault.to_account_info(),
-            ],
         )?;
 
-        Ok(())
+        // Returned to the caller as return data so CPI callers and simulating
+        // clients don't have to re-derive the PDAs.
+        Ok(EscrowAddresses {
+            escrow_state: ctx.accounts.escrow_state.key(),
+            vault: ctx.accounts.vault.key(),
+            nonce: ctx.accounts.escrow_state.nonce,
+        })
     }
 
-    pub fn cancel(ctx: Context<Cancel>) -> Result<()> {
+    /// Lists an NFT without moving it: the escrow's vault authority becomes the
+    /// token delegate and freezes the seller's token account, so the seller
+    /// keeps holding it until it is sold or the listing is cancelled.
+    pub fn initialize_delegated(
+        ctx: Context<InitializeDelegated>,
+        taker_amount: u64,
+        donation_recipient: Pubkey,
+        donation_bps: u16,
+        price_usd_cents: u64,
+        price_feed: Pubkey,
+        note: String,
+    ) -> Result<EscrowAddresses> {
+        require_valid_bps(&[donation_bps])?;
+        require!(
+            donation_bps == 0 || donation_recipient != Pubkey::default(),
+            EscrowError::MissingDonationRecipient
+        );
+        require!(
+            price_usd_cents == 0 || price_feed != Pubkey::default(),
+            EscrowError::MissingPriceFeed
+        );
+        let collection = require_listable(
+            &ctx.accounts.nft_metadata,
+            &ctx.accounts.collection_floor,
+            &ctx.accounts.config,
+            price_usd_cents,
+            taker_amount,
+        )?;
+
+        let nonce = ctx.accounts.listing_counter.take_nonce(
+            ctx.accounts.initializer.key(),
+            *ctx.bumps.get("listing_counter").unwrap(),
+        )?;
+        let now = Clock::get()?.unix_timestamp;
+        ctx.accounts.escrow_state.open(
+            NewListing {
+                nonce,
+                initializer: ctx.accounts.initializer.key(),
+                taker: ctx.accounts.taker_key.key(),
+                deposit_token_account: ctx.accounts.initializer_deposit_token_account.key(),
+                deposit_mint: ctx.accounts.mint.key(),
+                receive_mint: ctx.accounts.initializer_receive_mint_account.key(),
+                taker_amount,
+                vault_authority_bump: *ctx.bumps.get("vault_authority").unwrap(),
+                vault_rent_lamports: 0,
+                market_id: ctx.accounts.config.market_id,
+                rent_payer: ctx.accounts.rent_payer.key(),
+                collection,
+                note,
+            },
+            now,
+        )?;
+        let escrow_state = &mut ctx.accounts.escrow_state;
+        escrow_state.initializer_amount = 0;
+        escrow_state.donation_recipient = donation_recipient;
+        escrow_state.donation_bps = donation_bps;
+        escrow_state.price_usd_cents = price_usd_cents;
+        escrow_state.price_feed = price_feed;
+        escrow_state.delegated = true;
+        record_wallet_activity(
+            &mut ctx.accounts.wallet_activity,
+            &ctx.accounts.config,
+            now,
+            true,
+        )?;
+
+        token::approve(ctx.accounts.into_approve_context(), 1)?;
+
+        let escrow_key = ctx.accounts.escrow_state.key();
         let authority_seeds = &[
             &AUTHORITY_SEED[..],
+            escrow_key.as_ref(),
             &[ctx.accounts.escrow_state.vault_authority_bump],
         ];
-
-        token::transfer_checked(
+        metadata::freeze_delegated_account(
             ctx.accounts
-                .into_transfer_to_initializer_context()
+                .into_freeze_context()
                 .with_signer(&[&authority_seeds[..]]),
-            // ctx.accounts.escrow_state.initializer_amount,
-            1 as u64,
-            ctx.accounts.mint.decimals,
         )?;
 
-        // let ix = transfer(
-        //     &ctx.accounts.vault.key(),
-        //     &ctx.accounts.initializer.key(),
-        //     ctx.accounts.vault.to_account_info().lamports(),
-        // );
-        // invoke(
-        //     &ix,
-        //     &[
-        //         ctx.accounts.vault.to_account_info(),
-        //         ctx.accounts.initializer.to_account_info(),
-        //     ],
-        // )?;
+        Ok(EscrowAddresses {
+            escrow_state: escrow_key,
+            vault: ctx.accounts.initializer_deposit_token_account.key(),
+            nonce,
+        })
+    }
 
-        token::close_account(
-            ctx.accounts
-                .into_close_context()
-                .with_signer(&[&authority_seeds[..]]),
+    /// CPI entrypoint for programs embedding the escrow: the integrator
+    /// signs with its `[b"escrow_integrator"]` PDA, picks the escrow's
+    /// address through `namespace` and takes `integrator_fee_bps` of the
+    /// seller's proceeds when the listing sells through `exchange`.
+    pub fn initialize_for_integrator(
+        ctx: Context<InitializeForIntegrator>,
+        namespace: [u8; 32],
+        taker_amount: u64,
+        integrator_fee_bps: u16,
+    ) -> Result<EscrowAddresses> {
+        require_valid_bps(&[integrator_fee_bps])?;
+        let collection = require_listable(
+            &ctx.accounts.nft_metadata,
+            &ctx.accounts.collection_floor,
+            &ctx.accounts.config,
+            0,
+            taker_amount,
         )?;
 
-        Ok(())
+        let now = Clock::get()?.unix_timestamp;
+        // The namespace, not a listing counter, tells integrated escrows apart.
+        ctx.accounts.escrow_state.open(
+            NewListing {
+                nonce: 0,
+                initializer: ctx.accounts.initializer.key(),
+                taker: ctx.accounts.taker_key.key(),
+                deposit_token_account: ctx.accounts.initializer_deposit_token_account.key(),
+                deposit_mint: ctx.accounts.mint.key(),
+                receive_mint: ctx.accounts.initializer_receive_mint_account.key(),
+                taker_amount,
+                vault_authority_bump: *ctx.bumps.get("vault_authority").unwrap(),
+                vault_rent_lamports: ctx.accounts.vault.to_account_info().lamports(),
+                market_id: ctx.accounts.config.market_id,
+                rent_payer: ctx.accounts.initializer.key(),
+                collection,
+                note: String::new(),
+            },
+            now,
+        )?;
+        let escrow_state = &mut ctx.accounts.escrow_state;
+        escrow_state.integrator = ctx.accounts.integrator_program.key();
+        escrow_state.integrator_namespace = namespace;
+        escrow_state.integrator_fee_recipient = ctx.accounts.integrator_fee_recipient.key();
+        escrow_state.integrator_fee_bps = integrator_fee_bps;
+        record_wallet_activity(
+            &mut ctx.accounts.wallet_activity,
+            &ctx.accounts.config,
+            now,
+            true,
+        )?;
+
+        token::transfer_checked(
+            ctx.accounts.into_transfer_to_pda_context(),
+            1,
+            ctx.accounts.mint.decimals,
+        )?;
+
+        Ok(EscrowAddresses {
+            escrow_state: ctx.accounts.escrow_state.key(),
+            vault: ctx.accounts.vault.key(),
+            nonce: 0,
+        })
     }
 
-    pub fn exchange(ctx: Context<Exchange>) -> Result<()> {
+    /// Returns the NFT to the initializer and the rent to whoever paid it. A
+    /// `memo`, when given, is attached to the transaction through the memo
+    /// program.
+    pub fn cancel<'info>(
+        ctx: Context<'_, '_, '_, 'info, Cancel<'info>>,
+        memo: Option<String>,
+    ) -> Result<()> {
+        // The taker of an HTLC may already have paid on the other chain.
+        require!(
+            ctx.accounts
+                .escrow_state
+                .htlc_expired(Clock::get()?.unix_timestamp)
+                || !ctx.accounts.escrow_state.is_htlc(),
+            EscrowError::HtlcLocked
+        );
+        record_audit(
+            &mut ctx.accounts.audit_trail,
+            &ctx.accounts.escrow_state,
+            AUDIT_CANCELLED,
+            &ctx.accounts.initializer.key(),
+            0,
+        )?;
+        forward_memo(&ctx.accounts.memo_program, memo)?;
+
+        let escrow_key = ctx.accounts.escrow_state.key();
         let authority_seeds = &[
             &AUTHORITY_SEED[..],
+            escrow_key.as_ref(),
             &[ctx.accounts.escrow_state.vault_authority_bump],
         ];
 
-        token::transfer_checked(
-            ctx.accounts.into_transfer_to_initializer_context(),
-            // .with_signer(&[&authority_seeds[..]]),
-            // ctx.accounts.escrow_state.taker_amount,
-            1 as u64,
-            ctx.accounts.taker_deposit_token_mint.decimals,
-        )?;
+        let escrow_cancelled = |refunded_amount, refunded_rent| EscrowCancelled {
+            escrow_state: escrow_key,
+            initializer: ctx.accounts.initializer.key(),
+            refunded_amount,
+            refunded_rent,
+        };
 
-        let ix1 = transfer(
-            &ctx.accounts.taker.key(),
-            &ctx.accounts.initializer.key(),
-            ctx.accounts.escrow_state.taker_amount,
-        );
-        invoke(
-            &ix1,
-            &[
-                ctx.accounts.taker.to_account_info(),
-                ctx.accounts.initializer.to_account_info(),
-            ],
-        )?;
+        // A delegated listing never left the seller's wallet; releasing it is
+        // just undoing the freeze and the delegation.
+        if ctx.accounts.escrow_state.delegated {
+            emit!(escrow_cancelled(1, 0));
+            thaw_delegated(
+                &ctx.accounts.token_metadata_program,
+                &ctx.accounts.nft_edition,
+                &ctx.accounts.vault_authority,
+                ctx.accounts
+                    .initializer_deposit_token_account
+                    .to_account_info(),
+                ctx.accounts.mint.to_account_info(),
+                ctx.accounts.token_program.to_account_info(),
+                &authority_seeds[..],
+            )?;
+            token::revoke(ctx.accounts.into_revoke_context())?;
+            return close_escrow(
+                &ctx.accounts.escrow_state,
+                &ctx.accounts.initializer.to_account_info(),
+                ctx.remaining_accounts,
+            );
+        }
 
-        token::transfer_checked(
-            ctx.accounts
-                .into_transfer_to_taker_context()
-                .with_signer(&[&authority_seeds[..]]),
-            // ctx.accounts.escrow_state.initializer_amount,
-            1 as u64,
-            ctx.accounts.initializer_deposit_token_mint.decimals,
-        )?;
-
-        // let ix = transfer(
-        //     &ctx.accounts.vault.key(),
-        //     &ctx.accounts.taker.key(),
-        //     // ctx.accounts.escrow_state.initializer_amount,
-        //     ctx.accounts.vault.to_account_info().lamports(),
-        // );
-        // invoke(
-        //     &ix,
-        //     &[
-        //         ctx.accounts.vault.to_account_info(),
-        //         ctx.accounts.taker.to_account_info(),
-        //     ],
-        // )?;
-
-        // let temp = [&authority_seeds[..]];
-        // let cpi_context = CpiContext::new_with_signer(
-        //     ctx.accounts.system_program.to_account_info(),
-        //     anchor_lang::system_program::Transfer {
-        //         from: ctx.accounts.vault.to_account_info(),
-        //         to: ctx.accounts.taker.to_account_info().clone(),
-        //     },
-        //     &temp,
-        // );
-
-        // anchor_lang::system_program::transfer(cpi_context, 1)?;
+        // A vault already torn down, e.g. by an admin action, has nothing
+        // left to return; the listing still closes rather than being stuck.
+        let Some(vault) = &ctx.accounts.vault else {
+            let closed_vault = ctx
+                .accounts
+                .closed_vault
+                .as_ref()
+                .ok_or(EscrowError::MissingVault)?;
+            require!(closed_vault.data_is_empty(), EscrowError::VaultNotClosed);
+            emit!(escrow_cancelled(0, 0));
+            // Whoever tore the vault down already has its rent.
+            ctx.accounts.escrow_state.vault_rent_lamports = 0;
+            return close_escrow(
+                &ctx.accounts.escrow_state,
+                &ctx.accounts.initializer.to_account_info(),
+                ctx.remaining_accounts,
+            );
+        };
+        let refunded_amount = vault.amount;
+        let refunded_rent = vault.to_account_info().lamports();
+
+        // A drained vault is only closed.
+        if refunded_amount > 0 {
+            token::transfer_checked(
+                ctx.accounts
+                    .into_transfer_to_initializer_context()?
+                    .with_signer(&[&authority_seeds[..]]),
+                refunded_amount,
+                ctx.accounts.mint.decimals,
+            )?;
+        }
 
+        // Closing the vault into the escrow state lets `close_escrow` split
+        // the seller's deposit from the sponsor's rent.
         token::close_account(
             ctx.accounts
-                .into_close_context()
+                .into_close_context()?
                 .with_signer(&[&authority_seeds[..]]),
         )?;
+        close_escrow(
+            &ctx.accounts.escrow_state,
+            &ctx.accounts.initializer.to_account_info(),
+            ctx.remaining_accounts,
+        )?;
 
+        emit!(escrow_cancelled(refunded_amount, refunded_rent));
         Ok(())
     }
-}
 
-#[derive(Accounts)]
-#[instruction(escrow_seed: u64, initializer_amount: u64, taker_amount: u64)]
-pub struct Initialize<'info> {
-    /// CHECK: This is not dangerous because we don't read or write from this account
-    // #[account(mut)]
-    #[account(mut, constraint = initializer.lamports() >= initializer_amount)]
-    pub initializer: Signer<'info>,
-    pub mint: Account<'info, Mint>,
+    pub fn get_quote(ctx: Context<GetQuote>) -> Result<Quote> {
+        quote_escrow(
+            &ctx.accounts.config,
+            &ctx.accounts.escrow_state,
+            &ctx.accounts.discount_token_account,
+            &ctx.accounts.collection_fee_override,
+            &ctx.accounts.price_feed,
+        )
+    }
 
-    /// CHECK: This is not dangerous because we don't read or write from this account
-    #[account(
-        seeds = [b"authority".as_ref()],
-        bump,
-    )]
-    pub vault_authority: AccountInfo<'info>,
+    /// `get_quote` for aggregators: the total and where `exchange` pays it,
+    /// in a layout that only changes along with `QUOTE_ROUTE_VERSION`. A
+    /// referrer's or collection DAO's share comes out of the treasury payout
+    /// and depends on the accounts passed to `exchange`.
+    pub fn get_quote_route(ctx: Context<GetQuote>) -> Result<QuoteRoute> {
+        let Quote {
+            price,
+            maker_fee,
+            taker_fee,
+            total,
+        } = quote_escrow(
+            &ctx.accounts.config,
+            &ctx.accounts.escrow_state,
+            &ctx.accounts.discount_token_account,
+            &ctx.accounts.collection_fee_override,
+            &ctx.accounts.price_feed,
+        )?;
+        let escrow_state = &ctx.accounts.escrow_state;
+        let proceeds = checked_sub(price, maker_fee)?;
+        let donation = bps_of(proceeds, escrow_state.donation_bps)?;
+        let integrator_fee = bps_of(proceeds, escrow_state.integrator_fee_bps)?;
+        let seller_proceeds = checked_sub(checked_sub(proceeds, donation)?, integrator_fee)?;
+        let payouts = [
+            (escrow_state.initializer_key, seller_proceeds),
+            (escrow_state.donation_recipient, donation),
+            (escrow_state.integrator_fee_recipient, integrator_fee),
+            (
+                ctx.accounts.config.treasury,
+                checked_add(maker_fee, taker_fee)?,
+            ),
+        ]
+        .into_iter()
+        .filter(|(_, amount)| *amount > 0)
+        .map(|(recipient, amount)| Payout { recipient, amount })
+        .collect();
 
-    #[account(
-        init,
-        payer = initializer,
-        associated_token::mint = mint,
-        associated_token::authority = vault_authority
-    )]
-    pub vault: Box<Account<'info, TokenAccount>>,
+        Ok(QuoteRoute {
+            version: QUOTE_ROUTE_VERSION,
+            payment_mint: NATIVE_MINT,
+            price,
+            total,
+            payouts,
+        })
+    }
 
-    /// CHECK: This is not dangerous because we don't read or write from this account
-    #[account(mut, constraint = taker_key.lamports() >= taker_amount)]
-    pub taker_key: AccountInfo<'info>,
+    /// Locks the listing to the taker and escrows the quoted payment with it,
+    /// so a deal can be coordinated and finalized later without the listing
+    /// being taken by someone else in the meantime.
+    pub fn reserve(ctx: Context<Reserve>) -> Result<()> {
+        require!(
+            !ctx.accounts.config.prevent_self_trade
+                || ctx.accounts.taker.key() != ctx.accounts.escrow_state.initializer_key,
+            EscrowError::SelfTrade
+        );
 
-    #[account(mut, constraint = &initializer_deposit_token_account.owner == initializer.key)]
-    pub initializer_deposit_token_account: Account<'info, TokenAccount>,
+        let Quote { price, total, .. } = quote_escrow(
+            &ctx.accounts.config,
+            &ctx.accounts.escrow_state,
+            &ctx.accounts.discount_token_account,
+            &ctx.accounts.collection_fee_override,
+            &ctx.accounts.price_feed,
+        )?;
+        // On top of the payment the taker puts down a deposit that the seller
+        // keeps if the reservation is not finalized in time.
+        let deposit = bps_of(price, ctx.accounts.config.reservation_deposit_bps)?;
+        transfer_lamports(
+            &ctx.accounts.taker.to_account_info(),
+            &ctx.accounts.escrow_state.to_account_info(),
+            checked_add(total, deposit)?,
+        )?;
 
-    #[account(mut, constraint = &initializer_receive_token_account.owner == initializer.key)]
-    pub initializer_receive_token_account: Account<'info, TokenAccount>,
+        let window = ctx.accounts.config.reservation_window_secs;
+        let escrow_state = &mut ctx.accounts.escrow_state;
+        escrow_state.reserved_by = ctx.accounts.taker.key();
+        escrow_state.reserved_amount = total;
+        escrow_state.reservation_deposit = deposit;
+        escrow_state.reserved_until = if window > 0 {
+            Clock::get()?
+                .unix_timestamp
+                .checked_add(window)
+                .ok_or(EscrowError::ArithmeticOverflow)?
+        } else {
+            0
+        };
 
-    // #[account(constraint = initializer_receive_mint_account.to_account_info().owner == taker_key.key)]
-    pub initializer_receive_mint_account: Account<'info, Mint>,
+        Ok(())
+    }
 
-    #[account(
-        init,
-        seeds = [b"state".as_ref(), &escrow_seed.to_le_bytes()],
-        bump,
-        payer = initializer,
-        space = EscrowState::space()
-    )]
-    pub escrow_state: Box<Account<'info, EscrowState>>,
+    /// Completes a reserved exchange. The payment and deposit are handed back
+    /// to the taker first and the exchange then charges the current quote, so
+    /// wSOL payments and oracle-priced listings settle exactly as they would
+    /// without a reservation. Since that quote may have moved since the
+    /// reservation, `terms_hash`, `expected_price` and `expected_mint` are
+    /// checked as in `exchange`.
+    pub fn finalize<'info>(
+        ctx: Context<'_, '_, '_, 'info, Exchange<'info>>,
+        terms_hash: Option<[u8; 32]>,
+        expected_price: Option<u64>,
+        expected_mint: Option<Pubkey>,
+    ) -> Result<()> {
+        require_keys_eq!(
+            ctx.accounts.escrow_state.reserved_by,
+            ctx.accounts.taker.key(),
+            EscrowError::ReservationMismatch
+        );
+        require!(
+            !ctx.accounts
+                .escrow_state
+                .reservation_expired(Clock::get()?.unix_timestamp),
+            EscrowError::ReservationExpired
+        );
+        ctx.accounts.release_reservation()?;
+        ctx.accounts.fill(
+            ctx.remaining_accounts,
+            None,
+            None,
+            terms_hash,
+            expected_price,
+            expected_mint,
+        )
+    }
 
-    /// CHECK: This is not dangerous because we don't read or write from this account
-    pub system_program: Program<'info, System>,
+    /// Drops a reservation and refunds the taker's payment. Either side may
+    /// walk away until the deal is finalized, but the deposit only goes back
+    /// to the taker when the seller backs out of a live reservation; a taker
+    /// who walks away or lets the window lapse forfeits it to the seller.
+    pub fn abort(ctx: Context<Abort>) -> Result<()> {
+        let escrow_state = &ctx.accounts.escrow_state;
+        let seller_backed_out = ctx.accounts.signer.key() == escrow_state.initializer_key
+            && !escrow_state.reservation_expired(Clock::get()?.unix_timestamp);
+        let deposit_recipient = if seller_backed_out {
+            &ctx.accounts.taker
+        } else {
+            &ctx.accounts.initializer
+        };
 
-    pub rent: Sysvar<'info, Rent>,
+        let escrow_info = escrow_state.to_account_info();
+        move_program_lamports(
+            &escrow_info,
+            &ctx.accounts.taker,
+            escrow_state.reserved_amount,
+        )?;
+        move_program_lamports(
+            &escrow_info,
+            deposit_recipient,
+            escrow_state.reservation_deposit,
+        )?;
 
-    /// CHECK: This is not dangerous because we don't read or write from this account
-    pub token_program: Program<'info, Token>,
+        let escrow_state = &mut ctx.accounts.escrow_state;
+        escrow_state.reserved_by = Pubkey::default();
+        escrow_state.reserved_amount = 0;
+        escrow_state.reservation_deposit = 0;
+        escrow_state.reserved_until = 0;
 
-    /// CHECK: This is not dangerous because we don't read or write from this account
-    pub associated_token_program: Program<'info, AssociatedToken>,
-}
+        Ok(())
+    }
 
-#[derive(Accounts)]
-pub struct Cancel<'info> {
-    /// CHECK: This is not dangerous because we don't read or write from this account
-    #[account(mut)]
-    pub initializer: Signer<'info>,
-    pub mint: Account<'info, Mint>,
-    #[account(mut)]
-    pub vault: Account<'info, TokenAccount>,
-    /// CHECK: This is not dangerous because we don't read or write from this account
-    #[account(
-        seeds = [b"authority".as_ref()],
-        bump,
-    )]
-    pub vault_authority: AccountInfo<'info>,
-    #[account(mut)]
-    pub initializer_deposit_token_account: Account<'info, TokenAccount>,
+    /// Swaps the NFT for the listing price. A `memo`, when given, is attached
+    /// to the transaction through the memo program. A listing with terms
+    /// only fills for a taker passing their `terms_hash`. A fill signed
+    /// long before it lands, e.g. with a durable nonce, can pin the NFT with
+    /// `expected_mint` and cap the price with `expected_price`.
+    pub fn exchange<'info>(
+        ctx: Context<'_, '_, '_, 'info, Exchange<'info>>,
+        memo: Option<String>,
+        terms_hash: Option<[u8; 32]>,
+        expected_price: Option<u64>,
+        expected_mint: Option<Pubkey>,
+    ) -> Result<()> {
+        ctx.accounts.fill(
+            ctx.remaining_accounts,
+            None,
+            memo,
+            terms_hash,
+            expected_price,
+            expected_mint,
+        )
+    }
+
+    /// `exchange` for a listing that prints editions: the taker gets the
+    /// next numbered edition, minted into their `new_edition_mint`, and the
+    /// master edition stays listed for the next print.
+    pub fn exchange_edition<'info>(
+        ctx: Context<'_, '_, '_, 'info, ExchangeEdition<'info>>,
+        memo: Option<String>,
+        terms_hash: Option<[u8; 32]>,
+        expected_price: Option<u64>,
+        expected_mint: Option<Pubkey>,
+    ) -> Result<()> {
+        require!(
+            ctx.accounts.exchange.escrow_state.is_printing_editions(),
+            EscrowError::NotPrintingEditions
+        );
+        ctx.accounts.exchange.fill(
+            ctx.remaining_accounts,
+            Some(&ctx.accounts.print),
+            memo,
+            terms_hash,
+            expected_price,
+            expected_mint,
+        )
+    }
+
+    /// `exchange` paid by a swap router earlier in the same transaction, e.g.
+    /// one turning the taker's BONK into wSOL: the payment must already sit
+    /// in the taker's wSOL account, covering the quoted total, so nothing is
+    /// topped up from the taker's lamports if the swap came up short.
+    pub fn exchange_via_router<'info>(
+        ctx: Context<'_, '_, '_, 'info, Exchange<'info>>,
+        terms_hash: Option<[u8; 32]>,
+        expected_price: Option<u64>,
+    ) -> Result<()> {
+        let taker_wsol_account = ctx
+            .accounts
+            .taker_wsol_account(ctx.remaining_accounts)
+            .ok_or(EscrowError::MissingRouterPayment)?;
+        let delivered = token_balance(taker_wsol_account)?;
+        let Quote { total, .. } = quote_escrow(
+            &ctx.accounts.config,
+            &ctx.accounts.escrow_state,
+            &ctx.accounts.discount_token_account,
+            &ctx.accounts.collection_fee_override,
+            &remaining_account(
+                ctx.remaining_accounts,
+                &ctx.accounts.escrow_state.price_feed,
+            )
+            .cloned(),
+        )?;
+        require!(delivered >= total, EscrowError::RouterPaymentShort);
+        ctx.accounts.fill(
+            ctx.remaining_accounts,
+            None,
+            None,
+            terms_hash,
+            expected_price,
+            None,
+        )
+    }
+
+    /// Completes a delayed exchange once the cooling-off window has passed:
+    /// the NFT goes to the taker and the held token and proceeds to the seller.
+    pub fn settle<'info>(ctx: Context<'_, '_, '_, 'info, Settle<'info>>) -> Result<()> {
+        let escrow_state = &ctx.accounts.escrow_state;
+        require!(
+            escrow_state.is_settlement_party(ctx.accounts.authority.key()),
+            EscrowError::NotSettlementParty
+        );
+        require!(!escrow_state.disputed, EscrowError::SettlementDisputed);
+        require!(
+            Clock::get()?.unix_timestamp >= escrow_state.settle_after,
+            EscrowError::SettlementWindowOpen
+        );
+
+        ctx.accounts.release()?;
+        close_escrow(
+            &ctx.accounts.escrow_state,
+            &ctx.accounts.initializer,
+            ctx.remaining_accounts,
+        )
+    }
+
+    /// Freezes a delayed exchange before its window passes until the config
+    /// admin rules on it.
+    pub fn dispute(ctx: Context<Dispute>) -> Result<()> {
+        let escrow_state = &mut ctx.accounts.escrow_state;
+        require!(
+            escrow_state.is_settlement_party(ctx.accounts.authority.key()),
+            EscrowError::NotSettlementParty
+        );
+        require!(!escrow_state.disputed, EscrowError::SettlementDisputed);
+        require!(
+            Clock::get()?.unix_timestamp < escrow_state.settle_after,
+            EscrowError::SettlementWindowClosed
+        );
+
+        escrow_state.disputed = true;
+        Ok(())
+    }
+
+    /// Settles a disputed exchange either way. A refund returns the NFT, the
+    /// taker's token and the held proceeds; fees and donations already paid
+    /// at exchange time are not clawed back.
+    pub fn resolve_dispute<'info>(
+        ctx: Context<'_, '_, '_, 'info, Settle<'info>>,
+        refund: bool,
+    ) -> Result<()> {
+        require!(ctx.accounts.escrow_state.disputed, EscrowError::NotDisputed);
+        require_keys_eq!(
+            ctx.accounts.authority.key(),
+            ctx.accounts.config.admin,
+            EscrowError::NotDisputeResolver
+        );
+
+        if refund {
+            ctx.accounts.refund()?;
+        } else {
+            ctx.accounts.release()?;
+        }
+        close_escrow(
+            &ctx.accounts.escrow_state,
+            &ctx.accounts.initializer,
+            ctx.remaining_accounts,
+        )
+    }
+
+    /// Lets takers buy the listing in `installments` payments, each due
+    /// `interval_secs` after the previous one. A taker who misses a deadline
+    /// forfeits `forfeit_bps` of what they paid to the seller. Zero
+    /// installments withdraws the offer.
+    pub fn offer_payment_plan(
+        ctx: Context<OfferPaymentPlan>,
+        installments: u8,
+        interval_secs: i64,
+        forfeit_bps: u16,
+    ) -> Result<()> {
+        require!(
+            installments == 0 || (installments >= 2 && interval_secs > 0),
+            EscrowError::InvalidPaymentPlan
+        );
+        require_valid_bps(&[forfeit_bps])?;
+
+        let escrow_state = &mut ctx.accounts.escrow_state;
+        escrow_state.plan_installments = installments;
+        escrow_state.plan_interval_secs = interval_secs;
+        escrow_state.plan_forfeit_bps = forfeit_bps;
+        Ok(())
+    }
+
+    /// Locks the listing to the taker and takes the first installment of the
+    /// current quote. Later installments go through `pay_installment` and the
+    /// last one through `complete_payment_plan`.
+    pub fn start_payment_plan(ctx: Context<StartPaymentPlan>) -> Result<()> {
+        require!(
+            !ctx.accounts.config.prevent_self_trade
+                || ctx.accounts.taker.key() != ctx.accounts.escrow_state.initializer_key,
+            EscrowError::SelfTrade
+        );
+
+        let Quote { total, .. } = quote_escrow(
+            &ctx.accounts.config,
+            &ctx.accounts.escrow_state,
+            &ctx.accounts.discount_token_account,
+            &ctx.accounts.collection_fee_override,
+            &ctx.accounts.price_feed,
+        )?;
+        let installments = ctx.accounts.escrow_state.plan_installments;
+        require!(installments > 0, EscrowError::PaymentPlanNotOffered);
+        let interval_secs = ctx.accounts.escrow_state.plan_interval_secs;
+        let installment_amount = total / installments as u64;
+        transfer_lamports(
+            &ctx.accounts.taker.to_account_info(),
+            &ctx.accounts.payment_plan.to_account_info(),
+            installment_amount,
+        )?;
+
+        let payment_plan = &mut ctx.accounts.payment_plan;
+        payment_plan.escrow_state = ctx.accounts.escrow_state.key();
+        payment_plan.taker = ctx.accounts.taker.key();
+        payment_plan.installments = installments;
+        payment_plan.installments_paid = 1;
+        payment_plan.installment_amount = installment_amount;
+        payment_plan.paid = installment_amount;
+        payment_plan.interval_secs = interval_secs;
+        payment_plan.next_due = Clock::get()?
+            .unix_timestamp
+            .checked_add(interval_secs)
+            .ok_or(EscrowError::ArithmeticOverflow)?;
+        payment_plan.bump = *ctx.bumps.get("payment_plan").unwrap();
+
+        ctx.accounts.escrow_state.plan_taker = ctx.accounts.taker.key();
+        Ok(())
+    }
+
+    /// Pays one of the installments before the last.
+    pub fn pay_installment(ctx: Context<PayInstallment>) -> Result<()> {
+        let payment_plan = &ctx.accounts.payment_plan;
+        require!(
+            payment_plan.installments_paid + 1 < payment_plan.installments,
+            EscrowError::FinalInstallmentRequired
+        );
+        require!(
+            !payment_plan.overdue(Clock::get()?.unix_timestamp),
+            EscrowError::InstallmentOverdue
+        );
+        transfer_lamports(
+            &ctx.accounts.taker.to_account_info(),
+            &payment_plan.to_account_info(),
+            payment_plan.installment_amount,
+        )?;
+
+        let payment_plan = &mut ctx.accounts.payment_plan;
+        payment_plan.installments_paid += 1;
+        payment_plan.paid = checked_add(payment_plan.paid, payment_plan.installment_amount)?;
+        payment_plan.next_due = payment_plan
+            .next_due
+            .checked_add(payment_plan.interval_secs)
+            .ok_or(EscrowError::ArithmeticOverflow)?;
+        Ok(())
+    }
+
+    /// Pays the final installment and releases the NFT. Like `finalize`, the
+    /// installments are handed back to the taker first and the exchange then
+    /// charges the current quote, so the taker covers whatever is left. The
+    /// taker's expectations are checked against that quote as in `finalize`.
+    pub fn complete_payment_plan<'info>(
+        ctx: Context<'_, '_, '_, 'info, CompletePaymentPlan<'info>>,
+        terms_hash: Option<[u8; 32]>,
+        expected_price: Option<u64>,
+        expected_mint: Option<Pubkey>,
+    ) -> Result<()> {
+        let payment_plan = &ctx.accounts.payment_plan;
+        let exchange = &mut ctx.accounts.exchange;
+        require_keys_eq!(
+            payment_plan.taker,
+            exchange.taker.key(),
+            EscrowError::PaymentPlanMismatch
+        );
+        require!(
+            payment_plan.installments_paid + 1 == payment_plan.installments,
+            EscrowError::InstallmentsOutstanding
+        );
+        require!(
+            !payment_plan.overdue(Clock::get()?.unix_timestamp),
+            EscrowError::InstallmentOverdue
+        );
+        move_program_lamports(
+            &payment_plan.to_account_info(),
+            &exchange.taker.to_account_info(),
+            payment_plan.paid,
+        )?;
+        exchange.escrow_state.plan_taker = Pubkey::default();
+        exchange.fill(
+            ctx.remaining_accounts,
+            None,
+            None,
+            terms_hash,
+            expected_price,
+            expected_mint,
+        )?;
+        payment_plan.close(exchange.taker.to_account_info())
+    }
+
+    /// Lets a renter pay `payment` every `period_secs` to use the NFT, with
+    /// the payments counting towards buying it outright once they reach
+    /// `target_total`. Zero `payment` withdraws the offer.
+    pub fn offer_rent_to_own(
+        ctx: Context<OfferRentToOwn>,
+        payment: u64,
+        period_secs: i64,
+        target_total: u64,
+    ) -> Result<()> {
+        require!(
+            payment == 0 || (period_secs > 0 && target_total > payment),
+            EscrowError::InvalidRentToOwn
+        );
+
+        let escrow_state = &mut ctx.accounts.escrow_state;
+        escrow_state.rent_to_own_payment = payment;
+        escrow_state.rent_to_own_period_secs = period_secs;
+        escrow_state.rent_to_own_target = target_total;
+        Ok(())
+    }
+
+    /// Pays the first rent and hands the NFT to the renter, frozen in their
+    /// wallet with the vault authority as delegate so it can be used but not
+    /// moved until it is paid off or repossessed.
+    pub fn start_rent_to_own(ctx: Context<StartRentToOwn>) -> Result<()> {
+        require!(
+            !ctx.accounts.config.prevent_self_trade
+                || ctx.accounts.renter.key() != ctx.accounts.escrow_state.initializer_key,
+            EscrowError::SelfTrade
+        );
+
+        let payment = ctx.accounts.escrow_state.rent_to_own_payment;
+        transfer_lamports(
+            &ctx.accounts.renter.to_account_info(),
+            &ctx.accounts.initializer,
+            payment,
+        )?;
+
+        let escrow_key = ctx.accounts.escrow_state.key();
+        let authority_seeds = &[
+            &AUTHORITY_SEED[..],
+            escrow_key.as_ref(),
+            &[ctx.accounts.escrow_state.vault_authority_bump],
+        ];
+        token::transfer_checked(
+            ctx.accounts
+                .into_transfer_to_renter_context()
+                .with_signer(&[&authority_seeds[..]]),
+            1,
+            ctx.accounts.mint.decimals,
+        )?;
+        token::close_account(
+            ctx.accounts
+                .into_close_context()
+                .with_signer(&[&authority_seeds[..]]),
+        )?;
+        token::approve(ctx.accounts.into_approve_context(), 1)?;
+        freeze_token_account(
+            &ctx.accounts.token_metadata_program,
+            &ctx.accounts.nft_edition,
+            &ctx.accounts.vault_authority,
+            ctx.accounts.renter_token_account.to_account_info(),
+            ctx.accounts.mint.to_account_info(),
+            ctx.accounts.token_program.to_account_info(),
+            &authority_seeds[..],
+        )?;
+
+        let period_secs = ctx.accounts.escrow_state.rent_to_own_period_secs;
+        let rent_to_own = &mut ctx.accounts.rent_to_own;
+        rent_to_own.escrow_state = escrow_key;
+        rent_to_own.renter = ctx.accounts.renter.key();
+        rent_to_own.renter_token_account = ctx.accounts.renter_token_account.key();
+        rent_to_own.paid = payment;
+        rent_to_own.paid_until = Clock::get()?
+            .unix_timestamp
+            .checked_add(period_secs)
+            .ok_or(EscrowError::ArithmeticOverflow)?;
+        rent_to_own.bump = *ctx.bumps.get("rent_to_own").unwrap();
+
+        ctx.accounts.escrow_state.rent_to_own_renter = ctx.accounts.renter.key();
+        Ok(())
+    }
+
+    /// Pays the next period's rent. The payment that reaches the target only
+    /// covers what is left of it and makes the renter the full owner.
+    pub fn pay_rent<'info>(ctx: Context<'_, '_, '_, 'info, PayRent<'info>>) -> Result<()> {
+        require!(
+            !ctx.accounts
+                .rent_to_own
+                .lapsed(Clock::get()?.unix_timestamp),
+            EscrowError::RentLapsed
+        );
+
+        let escrow_state = &ctx.accounts.escrow_state;
+        let remaining = checked_sub(
+            escrow_state.rent_to_own_target,
+            ctx.accounts.rent_to_own.paid,
+        )?;
+        let payment = escrow_state.rent_to_own_payment.min(remaining);
+        transfer_lamports(
+            &ctx.accounts.renter.to_account_info(),
+            &ctx.accounts.initializer,
+            payment,
+        )?;
+
+        let period_secs = escrow_state.rent_to_own_period_secs;
+        let rent_to_own = &mut ctx.accounts.rent_to_own;
+        rent_to_own.paid = checked_add(rent_to_own.paid, payment)?;
+        rent_to_own.paid_until = rent_to_own
+            .paid_until
+            .checked_add(period_secs)
+            .ok_or(EscrowError::ArithmeticOverflow)?;
+        if payment < remaining {
+            return Ok(());
+        }
+
+        // Paid off: lift the freeze and the delegation and end the listing.
+        let escrow_key = ctx.accounts.escrow_state.key();
+        let authority_seeds = &[
+            &AUTHORITY_SEED[..],
+            escrow_key.as_ref(),
+            &[ctx.accounts.escrow_state.vault_authority_bump],
+        ];
+        thaw_token_account(
+            &ctx.accounts.token_metadata_program,
+            &ctx.accounts.nft_edition,
+            &ctx.accounts.vault_authority,
+            ctx.accounts.renter_token_account.to_account_info(),
+            ctx.accounts.mint.to_account_info(),
+            ctx.accounts.token_program.to_account_info(),
+            &authority_seeds[..],
+        )?;
+        token::revoke(ctx.accounts.into_revoke_context())?;
+
+        ctx.accounts
+            .rent_to_own
+            .close(ctx.accounts.renter.to_account_info())?;
+        close_escrow(
+            &ctx.accounts.escrow_state,
+            &ctx.accounts.initializer,
+            ctx.remaining_accounts,
+        )
+    }
+
+    /// Takes the NFT back from a renter whose paid period has ended. The
+    /// seller keeps the rent already paid and the listing is closed.
+    pub fn repossess<'info>(ctx: Context<'_, '_, '_, 'info, Repossess<'info>>) -> Result<()> {
+        require!(
+            ctx.accounts
+                .rent_to_own
+                .lapsed(Clock::get()?.unix_timestamp),
+            EscrowError::RentNotLapsed
+        );
+
+        let escrow_key = ctx.accounts.escrow_state.key();
+        let authority_seeds = &[
+            &AUTHORITY_SEED[..],
+            escrow_key.as_ref(),
+            &[ctx.accounts.escrow_state.vault_authority_bump],
+        ];
+        thaw_token_account(
+            &ctx.accounts.token_metadata_program,
+            &ctx.accounts.nft_edition,
+            &ctx.accounts.vault_authority,
+            ctx.accounts.renter_token_account.to_account_info(),
+            ctx.accounts.mint.to_account_info(),
+            ctx.accounts.token_program.to_account_info(),
+            &authority_seeds[..],
+        )?;
+        // Still the delegate, so the vault authority can move it back.
+        token::transfer_checked(
+            ctx.accounts
+                .into_transfer_to_initializer_context()
+                .with_signer(&[&authority_seeds[..]]),
+            1,
+            ctx.accounts.mint.decimals,
+        )?;
+        close_escrow(
+            &ctx.accounts.escrow_state,
+            &ctx.accounts.initializer.to_account_info(),
+            ctx.remaining_accounts,
+        )
+    }
+
+    /// Lets a buyer take the NFT right away and pay for it in `installments`
+    /// payments `interval_secs` apart, against collateral of
+    /// `collateral_bps` of the price. Only over-collateralized terms are
+    /// accepted. Zero installments withdraws the offer.
+    pub fn offer_bnpl(
+        ctx: Context<OfferBnpl>,
+        installments: u8,
+        interval_secs: i64,
+        collateral_bps: u16,
+    ) -> Result<()> {
+        require!(
+            installments == 0
+                || (installments >= 2
+                    && interval_secs > 0
+                    && collateral_bps as u64 > BPS_DENOMINATOR),
+            EscrowError::InvalidBnplTerms
+        );
+
+        let escrow_state = &mut ctx.accounts.escrow_state;
+        escrow_state.bnpl_installments = installments;
+        escrow_state.bnpl_interval_secs = interval_secs;
+        escrow_state.bnpl_collateral_bps = collateral_bps;
+        Ok(())
+    }
+
+    /// Posts the collateral and the first installment of the current quote
+    /// and delivers the NFT, frozen in the buyer's wallet with the vault
+    /// authority as delegate until it is paid off or clawed back.
+    pub fn buy_now_pay_later(ctx: Context<StartBnpl>) -> Result<()> {
+        require!(
+            !ctx.accounts.config.prevent_self_trade
+                || ctx.accounts.buyer.key() != ctx.accounts.escrow_state.initializer_key,
+            EscrowError::SelfTrade
+        );
+
+        let Quote {
+            price,
+            maker_fee,
+            taker_fee,
+            total,
+        } = quote_escrow(
+            &ctx.accounts.config,
+            &ctx.accounts.escrow_state,
+            &ctx.accounts.discount_token_account,
+            &ctx.accounts.collection_fee_override,
+            &ctx.accounts.price_feed,
+        )?;
+        let escrow_state = &ctx.accounts.escrow_state;
+        let collateral = bps_of(price, escrow_state.bnpl_collateral_bps)?;
+        let installments = escrow_state.bnpl_installments;
+        require!(installments > 0, EscrowError::BnplNotOffered);
+        let interval_secs = escrow_state.bnpl_interval_secs;
+        let installment_amount = total / installments as u64;
+        transfer_lamports(
+            &ctx.accounts.buyer.to_account_info(),
+            &ctx.accounts.bnpl_purchase.to_account_info(),
+            checked_add(collateral, installment_amount)?,
+        )?;
+
+        let escrow_key = ctx.accounts.escrow_state.key();
+        let authority_seeds = &[
+            &AUTHORITY_SEED[..],
+            escrow_key.as_ref(),
+            &[ctx.accounts.escrow_state.vault_authority_bump],
+        ];
+        token::transfer_checked(
+            ctx.accounts
+                .into_transfer_to_buyer_context()
+                .with_signer(&[&authority_seeds[..]]),
+            1,
+            ctx.accounts.mint.decimals,
+        )?;
+        token::close_account(
+            ctx.accounts
+                .into_close_context()
+                .with_signer(&[&authority_seeds[..]]),
+        )?;
+        token::approve(ctx.accounts.into_approve_context(), 1)?;
+        freeze_token_account(
+            &ctx.accounts.token_metadata_program,
+            &ctx.accounts.nft_edition,
+            &ctx.accounts.vault_authority,
+            ctx.accounts.buyer_token_account.to_account_info(),
+            ctx.accounts.mint.to_account_info(),
+            ctx.accounts.token_program.to_account_info(),
+            &authority_seeds[..],
+        )?;
+
+        let bnpl_purchase = &mut ctx.accounts.bnpl_purchase;
+        bnpl_purchase.escrow_state = escrow_key;
+        bnpl_purchase.buyer = ctx.accounts.buyer.key();
+        bnpl_purchase.buyer_token_account = ctx.accounts.buyer_token_account.key();
+        bnpl_purchase.total = total;
+        bnpl_purchase.fees = checked_add(maker_fee, taker_fee)?;
+        bnpl_purchase.collateral = collateral;
+        bnpl_purchase.installments = installments;
+        bnpl_purchase.installments_paid = 1;
+        bnpl_purchase.installment_amount = installment_amount;
+        bnpl_purchase.paid = installment_amount;
+        bnpl_purchase.interval_secs = interval_secs;
+        bnpl_purchase.next_due = Clock::get()?
+            .unix_timestamp
+            .checked_add(interval_secs)
+            .ok_or(EscrowError::ArithmeticOverflow)?;
+        bnpl_purchase.bump = *ctx.bumps.get("bnpl_purchase").unwrap();
+
+        ctx.accounts.escrow_state.bnpl_buyer = ctx.accounts.buyer.key();
+        Ok(())
+    }
+
+    /// Pays the next installment. The last one covers the rest of the total,
+    /// after which the fees and proceeds are paid out, the collateral is
+    /// returned and the NFT is thawed for good.
+    pub fn pay_bnpl_installment<'info>(
+        ctx: Context<'_, '_, '_, 'info, PayBnpl<'info>>,
+    ) -> Result<()> {
+        let bnpl_purchase = &ctx.accounts.bnpl_purchase;
+        require!(
+            !bnpl_purchase.overdue(Clock::get()?.unix_timestamp),
+            EscrowError::InstallmentOverdue
+        );
+        let final_installment = bnpl_purchase.installments_paid + 1 == bnpl_purchase.installments;
+        let amount = if final_installment {
+            checked_sub(bnpl_purchase.total, bnpl_purchase.paid)?
+        } else {
+            bnpl_purchase.installment_amount
+        };
+        transfer_lamports(
+            &ctx.accounts.buyer.to_account_info(),
+            &bnpl_purchase.to_account_info(),
+            amount,
+        )?;
+
+        let bnpl_purchase = &mut ctx.accounts.bnpl_purchase;
+        bnpl_purchase.installments_paid += 1;
+        bnpl_purchase.paid = checked_add(bnpl_purchase.paid, amount)?;
+        bnpl_purchase.next_due = bnpl_purchase
+            .next_due
+            .checked_add(bnpl_purchase.interval_secs)
+            .ok_or(EscrowError::ArithmeticOverflow)?;
+        if !final_installment {
+            return Ok(());
+        }
+
+        let bnpl_info = ctx.accounts.bnpl_purchase.to_account_info();
+        let fees = ctx.accounts.bnpl_purchase.fees;
+        move_program_lamports(&bnpl_info, &ctx.accounts.treasury, fees)?;
+        move_program_lamports(
+            &bnpl_info,
+            &ctx.accounts.initializer,
+            checked_sub(ctx.accounts.bnpl_purchase.total, fees)?,
+        )?;
+
+        let escrow_key = ctx.accounts.escrow_state.key();
+        let authority_seeds = &[
+            &AUTHORITY_SEED[..],
+            escrow_key.as_ref(),
+            &[ctx.accounts.escrow_state.vault_authority_bump],
+        ];
+        thaw_token_account(
+            &ctx.accounts.token_metadata_program,
+            &ctx.accounts.nft_edition,
+            &ctx.accounts.vault_authority,
+            ctx.accounts.buyer_token_account.to_account_info(),
+            ctx.accounts.mint.to_account_info(),
+            ctx.accounts.token_program.to_account_info(),
+            &authority_seeds[..],
+        )?;
+        token::revoke(ctx.accounts.into_revoke_context())?;
+
+        // What is left is the collateral and the account's rent.
+        ctx.accounts
+            .bnpl_purchase
+            .close(ctx.accounts.buyer.to_account_info())?;
+        close_escrow(
+            &ctx.accounts.escrow_state,
+            &ctx.accounts.initializer,
+            ctx.remaining_accounts,
+        )
+    }
+
+    /// Claws the NFT back from a buyer who missed an installment. The seller
+    /// keeps the installments paid and the collateral, and no fees are due
+    /// since the sale never completed.
+    pub fn seize_collateral<'info>(
+        ctx: Context<'_, '_, '_, 'info, SeizeCollateral<'info>>,
+    ) -> Result<()> {
+        require!(
+            ctx.accounts
+                .bnpl_purchase
+                .overdue(Clock::get()?.unix_timestamp),
+            EscrowError::InstallmentNotOverdue
+        );
+
+        let escrow_key = ctx.accounts.escrow_state.key();
+        let authority_seeds = &[
+            &AUTHORITY_SEED[..],
+            escrow_key.as_ref(),
+            &[ctx.accounts.escrow_state.vault_authority_bump],
+        ];
+        thaw_token_account(
+            &ctx.accounts.token_metadata_program,
+            &ctx.accounts.nft_edition,
+            &ctx.accounts.vault_authority,
+            ctx.accounts.buyer_token_account.to_account_info(),
+            ctx.accounts.mint.to_account_info(),
+            ctx.accounts.token_program.to_account_info(),
+            &authority_seeds[..],
+        )?;
+        token::transfer_checked(
+            ctx.accounts
+                .into_transfer_to_initializer_context()
+                .with_signer(&[&authority_seeds[..]]),
+            1,
+            ctx.accounts.mint.decimals,
+        )?;
+        close_escrow(
+            &ctx.accounts.escrow_state,
+            &ctx.accounts.initializer.to_account_info(),
+            ctx.remaining_accounts,
+        )
+    }
+
+    /// Ends a payment plan whose installment is overdue. The seller keeps the
+    /// forfeited share of the installments; closing the plan hands the rest
+    /// back to the taker along with its rent. The listing is open again, to
+    /// be taken by someone else or cancelled to get the NFT back.
+    pub fn reclaim_payment_plan(ctx: Context<ReclaimPaymentPlan>) -> Result<()> {
+        let payment_plan = &ctx.accounts.payment_plan;
+        require!(
+            payment_plan.overdue(Clock::get()?.unix_timestamp),
+            EscrowError::InstallmentNotOverdue
+        );
+        let forfeit = bps_of(
+            payment_plan.paid,
+            ctx.accounts.escrow_state.plan_forfeit_bps,
+        )?;
+        move_program_lamports(
+            &payment_plan.to_account_info(),
+            &ctx.accounts.initializer,
+            forfeit,
+        )?;
+
+        ctx.accounts.escrow_state.plan_taker = Pubkey::default();
+        Ok(())
+    }
+
+    /// Deposits an NFT to be rented out for `duration_secs` at a time. Each
+    /// renter pays `rent_lamports` to the owner and posts
+    /// `collateral_lamports`, which the owner keeps if the NFT is not
+    /// returned in time.
+    pub fn list_for_rent(
+        ctx: Context<ListForRent>,
+        rent_lamports: u64,
+        collateral_lamports: u64,
+        duration_secs: i64,
+    ) -> Result<()> {
+        require!(
+            collateral_lamports > 0 && duration_secs > 0,
+            EscrowError::InvalidRentalTerms
+        );
+
+        let rental_listing = &mut ctx.accounts.rental_listing;
+        rental_listing.owner = ctx.accounts.owner.key();
+        rental_listing.mint = ctx.accounts.mint.key();
+        rental_listing.rent_lamports = rent_lamports;
+        rental_listing.collateral_lamports = collateral_lamports;
+        rental_listing.duration_secs = duration_secs;
+        rental_listing.renter = Pubkey::default();
+        rental_listing.rented_until = 0;
+        rental_listing.vault_authority_bump = *ctx.bumps.get("vault_authority").unwrap();
+        rental_listing.bump = *ctx.bumps.get("rental_listing").unwrap();
+
+        token::transfer_checked(
+            ctx.accounts.into_transfer_to_vault_context(),
+            1,
+            ctx.accounts.mint.decimals,
+        )
+    }
+
+    /// Pays the rent, posts the collateral and takes the NFT for the rental
+    /// period. The NFT is handed over outright; the collateral is what
+    /// secures its return.
+    pub fn rent(ctx: Context<StartRental>) -> Result<()> {
+        require!(
+            ctx.accounts.renter.key() != ctx.accounts.rental_listing.owner,
+            EscrowError::SelfTrade
+        );
+
+        let rental_listing = &ctx.accounts.rental_listing;
+        transfer_lamports(
+            &ctx.accounts.renter.to_account_info(),
+            &ctx.accounts.owner,
+            rental_listing.rent_lamports,
+        )?;
+        transfer_lamports(
+            &ctx.accounts.renter.to_account_info(),
+            &rental_listing.to_account_info(),
+            rental_listing.collateral_lamports,
+        )?;
+
+        let rental_key = rental_listing.key();
+        let authority_seeds = &[
+            &AUTHORITY_SEED[..],
+            rental_key.as_ref(),
+            &[rental_listing.vault_authority_bump],
+        ];
+        token::transfer_checked(
+            ctx.accounts
+                .into_transfer_to_renter_context()
+                .with_signer(&[&authority_seeds[..]]),
+            1,
+            ctx.accounts.mint.decimals,
+        )?;
+
+        let duration_secs = ctx.accounts.rental_listing.duration_secs;
+        let rental_listing = &mut ctx.accounts.rental_listing;
+        rental_listing.renter = ctx.accounts.renter.key();
+        rental_listing.rented_until = Clock::get()?
+            .unix_timestamp
+            .checked_add(duration_secs)
+            .ok_or(EscrowError::ArithmeticOverflow)?;
+        Ok(())
+    }
+
+    /// Hands the NFT back and refunds the collateral. A late renter may still
+    /// return it as long as the owner has not claimed the collateral.
+    pub fn return_rental(ctx: Context<ReturnRental>) -> Result<()> {
+        token::transfer_checked(
+            ctx.accounts.into_transfer_to_vault_context(),
+            1,
+            ctx.accounts.mint.decimals,
+        )?;
+        move_program_lamports(
+            &ctx.accounts.rental_listing.to_account_info(),
+            &ctx.accounts.renter.to_account_info(),
+            ctx.accounts.rental_listing.collateral_lamports,
+        )?;
+
+        let rental_listing = &mut ctx.accounts.rental_listing;
+        rental_listing.renter = Pubkey::default();
+        rental_listing.rented_until = 0;
+        Ok(())
+    }
+
+    /// Keeps the collateral of a rental that was not returned in time. The
+    /// NFT stays with the renter and the listing is closed.
+    pub fn claim_collateral(ctx: Context<ClaimCollateral>) -> Result<()> {
+        require!(
+            Clock::get()?.unix_timestamp > ctx.accounts.rental_listing.rented_until,
+            EscrowError::RentalNotOverdue
+        );
+
+        let rental_key = ctx.accounts.rental_listing.key();
+        let authority_seeds = &[
+            &AUTHORITY_SEED[..],
+            rental_key.as_ref(),
+            &[ctx.accounts.rental_listing.vault_authority_bump],
+        ];
+        token::close_account(
+            ctx.accounts
+                .into_close_context()
+                .with_signer(&[&authority_seeds[..]]),
+        )
+    }
+
+    /// Takes a listed NFT back while it is not rented out.
+    pub fn delist_rental(ctx: Context<DelistRental>) -> Result<()> {
+        let rental_key = ctx.accounts.rental_listing.key();
+        let authority_seeds = &[
+            &AUTHORITY_SEED[..],
+            rental_key.as_ref(),
+            &[ctx.accounts.rental_listing.vault_authority_bump],
+        ];
+        token::transfer_checked(
+            ctx.accounts
+                .into_transfer_to_owner_context()
+                .with_signer(&[&authority_seeds[..]]),
+            1,
+            ctx.accounts.mint.decimals,
+        )?;
+        token::close_account(
+            ctx.accounts
+                .into_close_context()
+                .with_signer(&[&authority_seeds[..]]),
+        )
+    }
+
+    /// Streams the seller's proceeds over `duration_secs` after the exchange
+    /// instead of paying them out at once, with nothing claimable before
+    /// `cliff_secs`. Zero `duration_secs` pays out at exchange as usual.
+    pub fn configure_vesting(
+        ctx: Context<ConfigureVesting>,
+        duration_secs: i64,
+        cliff_secs: i64,
+    ) -> Result<()> {
+        require!(
+            duration_secs >= 0 && (0..=duration_secs).contains(&cliff_secs),
+            EscrowError::InvalidVestingSchedule
+        );
+        require!(
+            duration_secs == 0 || ctx.accounts.escrow_state.settlement_delay_secs == 0,
+            EscrowError::VestingWithDelayedSettlement
+        );
+        require!(
+            duration_secs == 0 || !ctx.accounts.escrow_state.is_printing_editions(),
+            EscrowError::EditionPrintingConflict
+        );
+
+        let escrow_state = &mut ctx.accounts.escrow_state;
+        escrow_state.vesting_duration_secs = duration_secs;
+        escrow_state.vesting_cliff_secs = cliff_secs;
+        Ok(())
+    }
+
+    /// Pays the seller whatever has vested since the last claim. The escrow
+    /// state closes with the final claim.
+    pub fn claim_vested<'info>(ctx: Context<'_, '_, '_, 'info, ClaimVested<'info>>) -> Result<()> {
+        let escrow_state = &ctx.accounts.escrow_state;
+        let vested = escrow_state.vested_amount(Clock::get()?.unix_timestamp)?;
+        let claimable = checked_sub(vested, escrow_state.vesting_claimed)?;
+        move_program_lamports(
+            &escrow_state.to_account_info(),
+            &ctx.accounts.initializer.to_account_info(),
+            claimable,
+        )?;
+
+        let escrow_state = &mut ctx.accounts.escrow_state;
+        escrow_state.vesting_claimed = vested;
+        if vested == escrow_state.vesting_total {
+            return close_escrow(
+                &ctx.accounts.escrow_state,
+                &ctx.accounts.initializer.to_account_info(),
+                ctx.remaining_accounts,
+            );
+        }
+        Ok(())
+    }
+
+    /// Turns a vaulted master edition listing into an edition sale: each
+    /// `exchange` prints the next numbered edition to the taker, up to
+    /// `max_prints` editions. The first print costs `price_per_print`
+    /// lamports and every print raises the price along a bonding curve, by
+    /// `price_growth_bps` of the current price plus `price_step` lamports.
+    /// Zero `max_prints` sells the master edition itself as usual.
+    pub fn configure_edition_printing(
+        ctx: Context<ConfigureEditionPrinting>,
+        price_per_print: u64,
+        max_prints: u64,
+        price_step: u64,
+        price_growth_bps: u16,
+    ) -> Result<()> {
+        require!(
+            max_prints == 0 || price_per_print > 0,
+            EscrowError::InvalidEditionPrinting
+        );
+        let escrow_state = &ctx.accounts.escrow_state;
+        require!(
+            max_prints == 0
+                || (escrow_state.settlement_delay_secs == 0
+                    && escrow_state.vesting_duration_secs == 0),
+            EscrowError::EditionPrintingConflict
+        );
+
+        let escrow_state = &mut ctx.accounts.escrow_state;
+        escrow_state.edition_price = price_per_print;
+        escrow_state.edition_max_prints = max_prints;
+        escrow_state.edition_price_step = price_step;
+        escrow_state.edition_price_growth_bps = price_growth_bps;
+        Ok(())
+    }
+
+    /// Restricts the listing to takers holding at least `min_amount` of
+    /// `gate_mint`, either in a token account or deposited as governing
+    /// tokens in a Realms DAO run by `governance_program`. A default
+    /// `gate_mint` opens the listing to everyone; a default
+    /// `governance_program` only accepts token accounts.
+    pub fn set_purchase_gate(
+        ctx: Context<SetPurchaseGate>,
+        gate_mint: Pubkey,
+        min_amount: u64,
+        governance_program: Pubkey,
+    ) -> Result<()> {
+        let escrow_state = &mut ctx.accounts.escrow_state;
+        escrow_state.gate_mint = gate_mint;
+        escrow_state.gate_min_amount = min_amount;
+        escrow_state.gate_governance_program = governance_program;
+        Ok(())
+    }
+
+    /// Makes the listing fill only for a taker holding an allowance signed by
+    /// the initializer, so a privately negotiated price can't be sniped.
+    pub fn set_private_fills(ctx: Context<SetPrivateFills>, enabled: bool) -> Result<()> {
+        ctx.accounts.escrow_state.private_fills = enabled;
+        Ok(())
+    }
+
+    /// Replaces the human-readable note frontends show with the listing.
+    pub fn update_terms(ctx: Context<UpdateTerms>, note: String) -> Result<()> {
+        require!(note.len() <= MAX_NOTE_LEN, EscrowError::NoteTooLong);
+        ctx.accounts.escrow_state.note = note;
+        Ok(())
+    }
+
+    /// Attaches an off-chain agreement at `terms_uri`, committed to by
+    /// `terms_hash`; `exchange` then needs the taker to pass the same hash.
+    /// Unlike the note, terms are set once, so an acknowledgment always
+    /// refers to what was listed.
+    pub fn set_terms(
+        ctx: Context<SetTerms>,
+        terms_uri: String,
+        terms_hash: [u8; 32],
+    ) -> Result<()> {
+        require!(
+            terms_uri.len() <= MAX_TERMS_URI_LEN,
+            EscrowError::TermsUriTooLong
+        );
+        require!(terms_hash != [0; 32], EscrowError::InvalidTerms);
+        let escrow_state = &mut ctx.accounts.escrow_state;
+        require!(!escrow_state.has_terms(), EscrowError::TermsAlreadySet);
+        escrow_state.terms_uri = terms_uri;
+        escrow_state.terms_hash = terms_hash;
+        Ok(())
+    }
+
+    /// Files the listing under a category code such as `b"art\0\0\0\0\0"`,
+    /// so frontends can find it with a memcmp filter on `ESCROW_TAG_OFFSET`.
+    pub fn set_listing_tag(ctx: Context<SetListingTag>, tag: [u8; 8]) -> Result<()> {
+        ctx.accounts.escrow_state.tag = tag;
+        Ok(())
+    }
+
+    /// Turns a zero-price listing into a gift that only `recipient` can
+    /// claim. With a non-zero `claim_deadline` the gift goes back to the
+    /// initializer if it is still unclaimed at that time.
+    pub fn offer_gift(
+        ctx: Context<OfferGift>,
+        recipient: Pubkey,
+        claim_deadline: i64,
+    ) -> Result<()> {
+        require!(
+            recipient != Pubkey::default()
+                && (claim_deadline == 0 || claim_deadline > Clock::get()?.unix_timestamp),
+            EscrowError::InvalidGift
+        );
+
+        let escrow_state = &mut ctx.accounts.escrow_state;
+        escrow_state.gift_recipient = recipient;
+        escrow_state.gift_claim_deadline = claim_deadline;
+        Ok(())
+    }
+
+    /// Hands a gift and the initializer's lamport deposit to its recipient.
+    pub fn claim_gift<'info>(ctx: Context<'_, '_, '_, 'info, ClaimGift<'info>>) -> Result<()> {
+        let escrow_state = &ctx.accounts.escrow_state;
+        require!(
+            !escrow_state.gift_expired(Clock::get()?.unix_timestamp),
+            EscrowError::GiftExpired
+        );
+
+        let escrow_key = escrow_state.key();
+        let authority_seeds = &[
+            &AUTHORITY_SEED[..],
+            escrow_key.as_ref(),
+            &[escrow_state.vault_authority_bump],
+        ];
+        token::transfer_checked(
+            ctx.accounts
+                .into_transfer_to_recipient_context()
+                .with_signer(&[&authority_seeds[..]]),
+            1,
+            ctx.accounts.mint.decimals,
+        )?;
+        // As in `exchange`, the deposit goes to the recipient while the vault
+        // rent follows the state rent back to whoever paid it.
+        token::close_account(
+            ctx.accounts
+                .into_close_context()
+                .with_signer(&[&authority_seeds[..]]),
+        )?;
+        move_program_lamports(
+            &ctx.accounts.escrow_state.to_account_info(),
+            &ctx.accounts.recipient.to_account_info(),
+            ctx.accounts.escrow_state.initializer_amount,
+        )?;
+        close_escrow(
+            &ctx.accounts.escrow_state,
+            &ctx.accounts.initializer,
+            ctx.remaining_accounts,
+        )
+    }
+
+    /// Sends an unclaimed gift back to the initializer once its claim
+    /// deadline has passed. Anyone may call it.
+    pub fn return_gift<'info>(ctx: Context<'_, '_, '_, 'info, ReturnGift<'info>>) -> Result<()> {
+        require!(
+            ctx.accounts
+                .escrow_state
+                .gift_expired(Clock::get()?.unix_timestamp),
+            EscrowError::GiftNotExpired
+        );
+
+        let escrow_key = ctx.accounts.escrow_state.key();
+        let authority_seeds = &[
+            &AUTHORITY_SEED[..],
+            escrow_key.as_ref(),
+            &[ctx.accounts.escrow_state.vault_authority_bump],
+        ];
+        token::transfer_checked(
+            ctx.accounts
+                .into_transfer_to_initializer_context()
+                .with_signer(&[&authority_seeds[..]]),
+            1,
+            ctx.accounts.mint.decimals,
+        )?;
+        token::close_account(
+            ctx.accounts
+                .into_close_context()
+                .with_signer(&[&authority_seeds[..]]),
+        )?;
+        close_escrow(
+            &ctx.accounts.escrow_state,
+            &ctx.accounts.initializer,
+            ctx.remaining_accounts,
+        )
+    }
+
+    /// Locks the listing behind `hash_lock`, the SHA-256 of a secret, for a
+    /// swap against an asset on another chain. Until `timeout` only `taker`
+    /// can take the NFT and only by revealing the secret through
+    /// `exchange_with_preimage`; the seller can cancel once it has passed.
+    pub fn offer_htlc(
+        ctx: Context<OfferHtlc>,
+        taker: Pubkey,
+        hash_lock: [u8; 32],
+        timeout: i64,
+    ) -> Result<()> {
+        require!(
+            taker != Pubkey::default() && timeout > Clock::get()?.unix_timestamp,
+            EscrowError::InvalidHtlc
+        );
+
+        let escrow_state = &mut ctx.accounts.escrow_state;
+        escrow_state.htlc_taker = taker;
+        escrow_state.htlc_hash = hash_lock;
+        escrow_state.htlc_timeout = timeout;
+        Ok(())
+    }
+
+    /// Hands an HTLC listing and the initializer's lamport deposit to its
+    /// taker in exchange for the secret, which the seller then reads from
+    /// this transaction to claim the other side of the swap.
+    pub fn exchange_with_preimage<'info>(
+        ctx: Context<'_, '_, '_, 'info, ExchangeWithPreimage<'info>>,
+        preimage: Vec<u8>,
+    ) -> Result<()> {
+        let escrow_state = &ctx.accounts.escrow_state;
+        require!(
+            !escrow_state.htlc_expired(Clock::get()?.unix_timestamp),
+            EscrowError::HtlcExpired
+        );
+        require!(
+            hash::hash(&preimage).to_bytes() == escrow_state.htlc_hash,
+            EscrowError::InvalidPreimage
+        );
+
+        let escrow_key = escrow_state.key();
+        let authority_seeds = &[
+            &AUTHORITY_SEED[..],
+            escrow_key.as_ref(),
+            &[escrow_state.vault_authority_bump],
+        ];
+        token::transfer_checked(
+            ctx.accounts
+                .into_transfer_to_taker_context()
+                .with_signer(&[&authority_seeds[..]]),
+            1,
+            ctx.accounts.mint.decimals,
+        )?;
+        token::close_account(
+            ctx.accounts
+                .into_close_context()
+                .with_signer(&[&authority_seeds[..]]),
+        )?;
+        move_program_lamports(
+            &ctx.accounts.escrow_state.to_account_info(),
+            &ctx.accounts.taker.to_account_info(),
+            ctx.accounts.escrow_state.initializer_amount,
+        )?;
+        close_escrow(
+            &ctx.accounts.escrow_state,
+            &ctx.accounts.initializer,
+            ctx.remaining_accounts,
+        )
+    }
+
+    /// Sells the listing for a payment made on another chain. The paying
+    /// contract there, `emitter_address` on Wormhole chain `emitter_chain`,
+    /// publishes a message naming this escrow, a Solana recipient and the
+    /// amount paid, which `exchange_with_vaa` accepts once `wormhole_program`
+    /// has verified it.
+    pub fn accept_wormhole_payment(
+        ctx: Context<AcceptWormholePayment>,
+        wormhole_program: Pubkey,
+        emitter_chain: u16,
+        emitter_address: [u8; 32],
+        amount: u64,
+    ) -> Result<()> {
+        require!(
+            wormhole_program != Pubkey::default() && emitter_address != [0; 32] && amount > 0,
+            EscrowError::InvalidWormholeTerms
+        );
+
+        let escrow_state = &mut ctx.accounts.escrow_state;
+        escrow_state.wormhole_program = wormhole_program;
+        escrow_state.wormhole_emitter_chain = emitter_chain;
+        escrow_state.wormhole_emitter = emitter_address;
+        escrow_state.wormhole_amount = amount;
+        Ok(())
+    }
+
+    /// Releases the NFT and the initializer's lamport deposit to the
+    /// recipient named in a verified payment message. Anyone may relay it.
+    pub fn exchange_with_vaa<'info>(
+        ctx: Context<'_, '_, '_, 'info, ExchangeWithVaa<'info>>,
+    ) -> Result<()> {
+        let escrow_state = &ctx.accounts.escrow_state;
+        let (recipient, amount) = wormhole_payment(
+            &ctx.accounts.posted_vaa,
+            escrow_state.wormhole_emitter_chain,
+            escrow_state.wormhole_emitter,
+            escrow_state.key(),
+        )?;
+        require_keys_eq!(
+            recipient,
+            ctx.accounts.recipient.key(),
+            EscrowError::WormholeRecipientMismatch
+        );
+        require!(
+            amount >= escrow_state.wormhole_amount,
+            EscrowError::WormholeUnderpaid
+        );
+
+        let escrow_key = escrow_state.key();
+        let authority_seeds = &[
+            &AUTHORITY_SEED[..],
+            escrow_key.as_ref(),
+            &[escrow_state.vault_authority_bump],
+        ];
+        token::transfer_checked(
+            ctx.accounts
+                .into_transfer_to_recipient_context()
+                .with_signer(&[&authority_seeds[..]]),
+            1,
+            ctx.accounts.mint.decimals,
+        )?;
+        token::close_account(
+            ctx.accounts
+                .into_close_context()
+                .with_signer(&[&authority_seeds[..]]),
+        )?;
+        move_program_lamports(
+            &ctx.accounts.escrow_state.to_account_info(),
+            &ctx.accounts.recipient.to_account_info(),
+            ctx.accounts.escrow_state.initializer_amount,
+        )?;
+        close_escrow(
+            &ctx.accounts.escrow_state,
+            &ctx.accounts.initializer,
+            ctx.remaining_accounts,
+        )
+    }
+
+    /// Makes the listing one leg of a three-way trade: its NFT goes to
+    /// `recipient` when `partner`, the leg listed by that recipient, settles
+    /// with it in `settle_ring`. The last recipient of the ring pays each
+    /// leg's `taker_amount` to its initializer.
+    pub fn link_ring(ctx: Context<LinkRing>, recipient: Pubkey, partner: Pubkey) -> Result<()> {
+        require!(
+            recipient != Pubkey::default() && partner != ctx.accounts.escrow_state.key(),
+            EscrowError::InvalidRing
+        );
+
+        let escrow_state = &mut ctx.accounts.escrow_state;
+        escrow_state.ring_recipient = recipient;
+        escrow_state.ring_partner = partner;
+        Ok(())
+    }
+
+    /// Settles a ring at once: A's NFT goes to B, B's NFT goes to the payer
+    /// C, and C pays both asking prices plus the taker fee. Both escrows
+    /// close to their initializers with their lamport deposits.
+    pub fn settle_ring<'info>(ctx: Context<'_, '_, '_, 'info, SettleRing<'info>>) -> Result<()> {
+        let (_, taker_fee_bps) = ctx.accounts.config.fee_bps(None, false);
+        let price_a = ctx.accounts.escrow_a.taker_amount;
+        let price_b = ctx.accounts.escrow_b.taker_amount;
+        transfer_lamports(
+            &ctx.accounts.payer.to_account_info(),
+            &ctx.accounts.initializer_a,
+            price_a,
+        )?;
+        transfer_lamports(
+            &ctx.accounts.payer.to_account_info(),
+            &ctx.accounts.initializer_b,
+            price_b,
+        )?;
+        transfer_lamports(
+            &ctx.accounts.payer.to_account_info(),
+            &ctx.accounts.treasury,
+            bps_of(checked_add(price_a, price_b)?, taker_fee_bps)?,
+        )?;
+
+        for (escrow_state, vault_authority, vault, mint, destination, initializer) in [
+            (
+                &ctx.accounts.escrow_a,
+                &ctx.accounts.vault_authority_a,
+                &ctx.accounts.vault_a,
+                &ctx.accounts.mint_a,
+                ctx.accounts.initializer_b_token_account.to_account_info(),
+                &ctx.accounts.initializer_a,
+            ),
+            (
+                &ctx.accounts.escrow_b,
+                &ctx.accounts.vault_authority_b,
+                &ctx.accounts.vault_b,
+                &ctx.accounts.mint_b,
+                ctx.accounts.payer_token_account.to_account_info(),
+                &ctx.accounts.initializer_b,
+            ),
+        ] {
+            let escrow_key = escrow_state.key();
+            let authority_seeds = &[
+                &AUTHORITY_SEED[..],
+                escrow_key.as_ref(),
+                &[escrow_state.vault_authority_bump],
+            ];
+            token::transfer_checked(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    TransferChecked {
+                        from: vault.to_account_info(),
+                        mint: mint.to_account_info(),
+                        to: destination,
+                        authority: vault_authority.clone(),
+                    },
+                    &[&authority_seeds[..]],
+                ),
+                1,
+                mint.decimals,
+            )?;
+            token::close_account(CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                CloseAccount {
+                    account: vault.to_account_info(),
+                    destination: escrow_state.to_account_info(),
+                    authority: vault_authority.clone(),
+                },
+                &[&authority_seeds[..]],
+            ))?;
+            close_escrow(escrow_state, initializer, ctx.remaining_accounts)?;
+        }
+        Ok(())
+    }
+
+    /// Proposes a bundle swap: the maker's `maker_assets` for `taker`'s
+    /// `taker_assets`, each any token amount (an NFT is an amount of one).
+    /// Both parties deposit everything before `execute_swap`.
+    pub fn create_swap(
+        ctx: Context<CreateSwap>,
+        swap_id: u64,
+        taker: Pubkey,
+        maker_assets: Vec<SwapAsset>,
+        taker_assets: Vec<SwapAsset>,
+    ) -> Result<()> {
+        let sides = [&maker_assets, &taker_assets];
+        require!(
+            sides
+                .iter()
+                .all(|assets| !assets.is_empty() && assets.len() <= MAX_SWAP_ASSETS),
+            EscrowError::InvalidSwapAssets
+        );
+        // Every asset has its own vault for the mint, shared by both sides.
+        let mints: Vec<Pubkey> = sides
+            .iter()
+            .flat_map(|assets| assets.iter())
+            .map(|asset| asset.mint)
+            .collect();
+        require!(
+            sides
+                .iter()
+                .flat_map(|assets| assets.iter())
+                .all(|asset| asset.amount > 0)
+                && mints
+                    .iter()
+                    .enumerate()
+                    .all(|(i, mint)| !mints[..i].contains(mint)),
+            EscrowError::InvalidSwapAssets
+        );
+
+        let swap_state = &mut ctx.accounts.swap_state;
+        swap_state.maker = ctx.accounts.maker.key();
+        swap_state.taker = taker;
+        swap_state.swap_id = swap_id;
+        swap_state.maker_assets = maker_assets
+            .into_iter()
+            .map(|asset| SwapAsset {
+                deposited: false,
+                ..asset
+            })
+            .collect();
+        swap_state.taker_assets = taker_assets
+            .into_iter()
+            .map(|asset| SwapAsset {
+                deposited: false,
+                ..asset
+            })
+            .collect();
+        swap_state.vault_authority_bump = *ctx.bumps.get("vault_authority").unwrap();
+        swap_state.bump = *ctx.bumps.get("swap_state").unwrap();
+        Ok(())
+    }
+
+    /// Deposits the signer's asset at `index` of their side of the swap.
+    pub fn deposit_swap_asset(ctx: Context<DepositSwapAsset>, index: u8) -> Result<()> {
+        let party = ctx.accounts.party.key();
+        let swap_state = &mut ctx.accounts.swap_state;
+        let assets = if party == swap_state.maker {
+            &mut swap_state.maker_assets
+        } else if party == swap_state.taker {
+            &mut swap_state.taker_assets
+        } else {
+            return err!(EscrowError::NotSwapParty);
+        };
+        let asset = assets
+            .get_mut(index as usize)
+            .ok_or(EscrowError::InvalidSwapAssets)?;
+        require_keys_eq!(
+            asset.mint,
+            ctx.accounts.mint.key(),
+            EscrowError::EscrowMintMismatch
+        );
+        require!(!asset.deposited, EscrowError::SwapAssetDeposited);
+        asset.deposited = true;
+        let amount = asset.amount;
+
+        token::transfer_checked(
+            ctx.accounts.into_transfer_to_vault_context(),
+            amount,
+            ctx.accounts.mint.decimals,
+        )
+    }
+
+    /// Swaps both bundles once everything is deposited. The remaining
+    /// accounts hold every asset's mint, vault and the receiving party's
+    /// token account.
+    pub fn execute_swap<'info>(ctx: Context<'_, '_, '_, 'info, ExecuteSwap<'info>>) -> Result<()> {
+        let swap_state = &ctx.accounts.swap_state;
+        require!(
+            swap_state
+                .maker_assets
+                .iter()
+                .chain(swap_state.taker_assets.iter())
+                .all(|asset| asset.deposited),
+            EscrowError::SwapNotFunded
+        );
+
+        let swap_key = swap_state.key();
+        let authority_seeds = &[
+            &AUTHORITY_SEED[..],
+            swap_key.as_ref(),
+            &[swap_state.vault_authority_bump],
+        ];
+        for (assets, depositor, recipient) in [
+            (
+                &swap_state.maker_assets,
+                &ctx.accounts.maker,
+                ctx.accounts.taker.key,
+            ),
+            (
+                &swap_state.taker_assets,
+                &ctx.accounts.taker,
+                ctx.accounts.maker.key,
+            ),
+        ] {
+            for asset in assets {
+                release_vault_tokens(
+                    &ctx.accounts.token_program.to_account_info(),
+                    &ctx.accounts.vault_authority,
+                    &authority_seeds[..],
+                    ctx.remaining_accounts,
+                    asset.mint,
+                    asset.amount,
+                    recipient,
+                    depositor,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Calls off a swap before it executes, returning every deposit. The
+    /// remaining accounts hold each deposited asset's mint, vault and the
+    /// depositor's token account.
+    pub fn cancel_swap<'info>(ctx: Context<'_, '_, '_, 'info, ExecuteSwap<'info>>) -> Result<()> {
+        let swap_state = &ctx.accounts.swap_state;
+        let swap_key = swap_state.key();
+        let authority_seeds = &[
+            &AUTHORITY_SEED[..],
+            swap_key.as_ref(),
+            &[swap_state.vault_authority_bump],
+        ];
+        for (assets, depositor) in [
+            (&swap_state.maker_assets, &ctx.accounts.maker),
+            (&swap_state.taker_assets, &ctx.accounts.taker),
+        ] {
+            for asset in assets.iter().filter(|asset| asset.deposited) {
+                release_vault_tokens(
+                    &ctx.accounts.token_program.to_account_info(),
+                    &ctx.accounts.vault_authority,
+                    &authority_seeds[..],
+                    ctx.remaining_accounts,
+                    asset.mint,
+                    asset.amount,
+                    depositor.key,
+                    depositor,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Places a standing bid of `max_price` lamports, held in the offer
+    /// account, for `mint` or for any NFT of the verified `collection`. The
+    /// bid stops filling after `expires_at`, unless that is zero.
+    pub fn place_buy_offer(
+        ctx: Context<PlaceBuyOffer>,
+        offer_id: u64,
+        mint: Pubkey,
+        collection: Pubkey,
+        max_price: u64,
+        expires_at: i64,
+    ) -> Result<()> {
+        require!(
+            (mint == Pubkey::default()) != (collection == Pubkey::default())
+                && max_price > 0
+                && (expires_at == 0 || expires_at > Clock::get()?.unix_timestamp),
+            EscrowError::InvalidBuyOffer
+        );
+        transfer_lamports(
+            &ctx.accounts.buyer.to_account_info(),
+            &ctx.accounts.buy_offer.to_account_info(),
+            max_price,
+        )?;
+
+        let buy_offer = &mut ctx.accounts.buy_offer;
+        buy_offer.buyer = ctx.accounts.buyer.key();
+        buy_offer.offer_id = offer_id;
+        buy_offer.mint = mint;
+        buy_offer.collection = collection;
+        buy_offer.max_price = max_price;
+        buy_offer.expires_at = expires_at;
+        buy_offer.bump = *ctx.bumps.get("buy_offer").unwrap();
+        Ok(())
+    }
+
+    /// Withdraws an unfilled buy offer and its lamports.
+    pub fn cancel_buy_offer(_ctx: Context<CancelBuyOffer>) -> Result<()> {
+        Ok(())
+    }
+
+    /// Closes expired buy offers, refunding each bid and its rent to the
+    /// buyer. Anyone may crank it, passing (buy offer, buyer) pairs as
+    /// remaining accounts.
+    pub fn sweep_expired_offers<'info>(
+        ctx: Context<'_, '_, '_, 'info, SweepExpiredOffers>,
+    ) -> Result<()> {
+        require!(
+            ctx.remaining_accounts.len() % 2 == 0,
+            EscrowError::InvalidSweepAccounts
+        );
+        let now = Clock::get()?.unix_timestamp;
+        for pair in ctx.remaining_accounts.chunks(2) {
+            let buy_offer = Account::<BuyOffer>::try_from(&pair[0])?;
+            require_keys_eq!(
+                buy_offer.buyer,
+                pair[1].key(),
+                EscrowError::InvalidSweepAccounts
+            );
+            require!(buy_offer.is_expired(now), EscrowError::BuyOfferNotExpired);
+            buy_offer.close(pair[1].clone())?;
+        }
+        Ok(())
+    }
+
+    /// Fills a listing from a buy offer at or above its quoted total. Anyone
+    /// may match them. The listing is paid as in `exchange`, the seller gets
+    /// the configured share of the surplus, and the rest goes back to the
+    /// buyer when the offer closes.
+    pub fn match_escrows<'info>(
+        ctx: Context<'_, '_, '_, 'info, MatchEscrows<'info>>,
+    ) -> Result<()> {
+        ctx.accounts.fill(0, ctx.remaining_accounts)
+    }
+
+    /// Crosses the best bid and the best ask of a collection's book when
+    /// the bid covers the ask's quoted total. Anyone may crank it and earns
+    /// the configured tip out of the spread, ahead of the seller's share.
+    pub fn crank_match<'info>(ctx: Context<'_, '_, '_, 'info, CrankMatch<'info>>) -> Result<()> {
+        let order_book = &mut ctx.accounts.order_book;
+        let best_ask = order_book
+            .asks
+            .iter()
+            .min_by_key(|order| order.price)
+            .ok_or(EscrowError::OrderBookEmpty)?;
+        let best_bid = order_book
+            .bids
+            .iter()
+            .max_by_key(|order| order.price)
+            .ok_or(EscrowError::OrderBookEmpty)?;
+        require_keys_eq!(
+            best_ask.account,
+            ctx.accounts.fill.escrow_state.key(),
+            EscrowError::NotBestOrder
+        );
+        require_keys_eq!(
+            best_bid.account,
+            ctx.accounts.fill.buy_offer.key(),
+            EscrowError::NotBestOrder
+        );
+        let (ask, bid) = (best_ask.account, best_bid.account);
+        order_book.asks.retain(|order| order.account != ask);
+        order_book.bids.retain(|order| order.account != bid);
+
+        let crank_tip_bps = ctx.accounts.fill.config.crank_tip_bps;
+        ctx.accounts
+            .fill
+            .fill(crank_tip_bps, ctx.remaining_accounts)
+    }
+
+    /// Opens the order book of a verified collection. Anyone may pay for it.
+    pub fn create_order_book(ctx: Context<CreateOrderBook>, collection: Pubkey) -> Result<()> {
+        require!(
+            collection != Pubkey::default(),
+            EscrowError::CollectionMismatch
+        );
+        let order_book = &mut ctx.accounts.order_book;
+        order_book.collection = collection;
+        order_book.bump = *ctx.bumps.get("order_book").unwrap();
+        Ok(())
+    }
+
+    /// Lists a plain fixed-price listing of the collection in its book.
+    pub fn post_ask(ctx: Context<PostAsk>) -> Result<()> {
+        let escrow_state = &ctx.accounts.escrow_state;
+        require_collection_member(
+            &ctx.accounts.nft_metadata,
+            escrow_state.initializer_deposit_mint_account,
+            ctx.accounts.order_book.collection,
+        )?;
+        let order = BookOrder {
+            account: escrow_state.key(),
+            price: escrow_state.taker_amount,
+        };
+        ctx.accounts.order_book.post(true, order)
+    }
+
+    /// Lists a collection buy offer in the collection's book.
+    pub fn post_bid(ctx: Context<PostBid>) -> Result<()> {
+        let order = BookOrder {
+            account: ctx.accounts.buy_offer.key(),
+            price: ctx.accounts.buy_offer.max_price,
+        };
+        ctx.accounts.order_book.post(false, order)
+    }
+
+    /// Drops the book's orders whose listing or offer has closed. Anyone may
+    /// crank it, passing the closed accounts as remaining accounts; orders
+    /// whose account is not passed are kept.
+    pub fn compact_order_book(ctx: Context<CompactOrderBook>) -> Result<()> {
+        let closed: Vec<Pubkey> = ctx
+            .remaining_accounts
+            .iter()
+            .filter(|account| account.data_is_empty() || account.owner != &crate::ID)
+            .map(|account| account.key())
+            .collect();
+        let order_book = &mut ctx.accounts.order_book;
+        order_book
+            .asks
+            .retain(|order| !closed.contains(&order.account));
+        order_book
+            .bids
+            .retain(|order| !closed.contains(&order.account));
+        Ok(())
+    }
+
+    /// Opens an OTC deal room between `parties`. Each deposits what they
+    /// bring, naming which party receives it, and the deal executes once
+    /// every party has approved the room's current deposits.
+    pub fn create_deal_room(
+        ctx: Context<CreateDealRoom>,
+        deal_id: u64,
+        parties: Vec<Pubkey>,
+    ) -> Result<()> {
+        require!(
+            (2..=MAX_DEAL_PARTIES).contains(&parties.len())
+                && parties
+                    .iter()
+                    .enumerate()
+                    .all(|(i, party)| !parties[..i].contains(party)),
+            EscrowError::InvalidDealParties
+        );
+
+        let deal_room = &mut ctx.accounts.deal_room;
+        deal_room.creator = ctx.accounts.creator.key();
+        deal_room.deal_id = deal_id;
+        deal_room.approved = vec![false; parties.len()];
+        deal_room.parties = parties;
+        deal_room.deposits = vec![];
+        deal_room.vault_authority_bump = *ctx.bumps.get("vault_authority").unwrap();
+        deal_room.bump = *ctx.bumps.get("deal_room").unwrap();
+        Ok(())
+    }
+
+    /// Deposits `amount` of a mint for `recipient`. Every approval is
+    /// withdrawn, since it covered the deposits before this one.
+    pub fn deposit_to_deal(
+        ctx: Context<DepositToDeal>,
+        amount: u64,
+        recipient: Pubkey,
+    ) -> Result<()> {
+        let deal_room = &mut ctx.accounts.deal_room;
+        deal_room.party_index(ctx.accounts.depositor.key())?;
+        deal_room.party_index(recipient)?;
+        require!(amount > 0, EscrowError::InvalidDealDeposit);
+        // Each deposit has its own vault for the mint.
+        let mint = ctx.accounts.mint.key();
+        require!(
+            !deal_room
+                .deposits
+                .iter()
+                .any(|deposit| deposit.mint == mint),
+            EscrowError::DealMintTaken
+        );
+        require!(
+            deal_room.deposits.len() < MAX_DEAL_DEPOSITS,
+            EscrowError::DealRoomFull
+        );
+        deal_room.deposits.push(DealDeposit {
+            depositor: ctx.accounts.depositor.key(),
+            mint,
+            amount,
+            recipient,
+        });
+        deal_room
+            .approved
+            .iter_mut()
+            .for_each(|approved| *approved = false);
+
+        token::transfer_checked(
+            ctx.accounts.into_transfer_to_vault_context(),
+            amount,
+            ctx.accounts.mint.decimals,
+        )
+    }
+
+    /// Takes a deposit back before the deal executes, withdrawing every
+    /// approval.
+    pub fn withdraw_from_deal(ctx: Context<WithdrawFromDeal>) -> Result<()> {
+        let mint = ctx.accounts.mint.key();
+        let deal_room = &mut ctx.accounts.deal_room;
+        let index = deal_room
+            .deposits
+            .iter()
+            .position(|deposit| {
+                deposit.mint == mint && deposit.depositor == ctx.accounts.depositor.key()
+            })
+            .ok_or(EscrowError::NotDealDeposit)?;
+        let deposit = deal_room.deposits.remove(index);
+        deal_room
+            .approved
+            .iter_mut()
+            .for_each(|approved| *approved = false);
+
+        let deal_key = deal_room.key();
+        let authority_seeds = &[
+            &AUTHORITY_SEED[..],
+            deal_key.as_ref(),
+            &[deal_room.vault_authority_bump],
+        ];
+        token::transfer_checked(
+            ctx.accounts
+                .into_transfer_to_depositor_context()
+                .with_signer(&[&authority_seeds[..]]),
+            deposit.amount,
+            ctx.accounts.mint.decimals,
+        )?;
+        token::close_account(
+            ctx.accounts
+                .into_close_context()
+                .with_signer(&[&authority_seeds[..]]),
+        )
+    }
+
+    /// Approves the deal room's current deposits.
+    pub fn approve_deal(ctx: Context<ApproveDeal>) -> Result<()> {
+        let deal_room = &mut ctx.accounts.deal_room;
+        let index = deal_room.party_index(ctx.accounts.party.key())?;
+        deal_room.approved[index] = true;
+        Ok(())
+    }
+
+    /// Hands every deposit to its recipient once all parties approve. Anyone
+    /// may call it. The remaining accounts hold each deposit's mint, vault,
+    /// recipient token account and depositor, who gets the vault rent back.
+    pub fn execute_deal<'info>(ctx: Context<'_, '_, '_, 'info, ExecuteDeal<'info>>) -> Result<()> {
+        let deal_room = &ctx.accounts.deal_room;
+        require!(
+            deal_room.approved.iter().all(|approved| *approved),
+            EscrowError::DealNotApproved
+        );
+
+        let deal_key = deal_room.key();
+        let authority_seeds = &[
+            &AUTHORITY_SEED[..],
+            deal_key.as_ref(),
+            &[deal_room.vault_authority_bump],
+        ];
+        for deposit in &deal_room.deposits {
+            let depositor = ctx
+                .remaining_accounts
+                .iter()
+                .find(|account| account.key == &deposit.depositor)
+                .ok_or(EscrowError::MissingVaultAccount)?;
+            release_vault_tokens(
+                &ctx.accounts.token_program.to_account_info(),
+                &ctx.accounts.vault_authority,
+                &authority_seeds[..],
+                ctx.remaining_accounts,
+                deposit.mint,
+                deposit.amount,
+                &deposit.recipient,
+                depositor,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Sells an NFT on an order its seller signed off-chain, so there is no
+    /// listing transaction or escrow rent. The ed25519 program instruction
+    /// right before this one must verify the seller's signature over
+    /// `signed_order_message`, and the NFT moves through the `order_delegate`
+    /// PDA the seller approved on their token account.
+    pub fn fill_signed_order(
+        ctx: Context<FillSignedOrder>,
+        price: u64,
+        expiry: i64,
+        nonce: u64,
+    ) -> Result<()> {
+        require!(
+            Clock::get()?.unix_timestamp <= expiry,
+            EscrowError::SignedOrderExpired
+        );
+        let message = signed_order_message(
+            &ctx.accounts.seller.key(),
+            &ctx.accounts.mint.key(),
+            price,
+            expiry,
+            nonce,
+        );
+        verify_ed25519_signature(
+            &ctx.accounts.instructions,
+            &ctx.accounts.seller.key(),
+            &message,
+        )?;
+        // The marker's address is taken, so the order can't be filled twice.
+        ctx.accounts.filled_order.bump = *ctx.bumps.get("filled_order").unwrap();
+
+        let (maker_fee_bps, taker_fee_bps) = ctx.accounts.config.fee_bps(None, false);
+        let maker_fee = bps_of(price, maker_fee_bps)?;
+        let taker_fee = bps_of(price, taker_fee_bps)?;
+        transfer_lamports(
+            &ctx.accounts.buyer.to_account_info(),
+            &ctx.accounts.seller,
+            checked_sub(price, maker_fee)?,
+        )?;
+        transfer_lamports(
+            &ctx.accounts.buyer.to_account_info(),
+            &ctx.accounts.treasury,
+            checked_add(maker_fee, taker_fee)?,
+        )?;
+
+        let delegate_seeds = &[
+            b"order_delegate".as_ref(),
+            &[*ctx.bumps.get("order_delegate").unwrap()],
+        ];
+        token::transfer_checked(
+            ctx.accounts
+                .into_transfer_to_buyer_context()
+                .with_signer(&[&delegate_seeds[..]]),
+            1,
+            ctx.accounts.mint.decimals,
+        )
+    }
+
+    /// Voids a signed order before anyone fills it by taking its marker.
+    pub fn cancel_signed_order(ctx: Context<CancelSignedOrder>, _nonce: u64) -> Result<()> {
+        ctx.accounts.filled_order.bump = *ctx.bumps.get("filled_order").unwrap();
+        Ok(())
+    }
+
+    /// Authorizes `session_key` to list and cancel for the owner until
+    /// `expires_at`, within `scope`. To list an NFT the key moves it as
+    /// delegate, so the owner approves the session PDA on its token account.
+    pub fn create_session(
+        ctx: Context<CreateSession>,
+        session_key: Pubkey,
+        scope: u8,
+        expires_at: i64,
+    ) -> Result<()> {
+        require!(
+            scope != 0
+                && scope & !(SESSION_SCOPE_LIST | SESSION_SCOPE_CANCEL) == 0
+                && expires_at > Clock::get()?.unix_timestamp,
+            EscrowError::InvalidSession
+        );
+
+        let session = &mut ctx.accounts.session;
+        session.owner = ctx.accounts.owner.key();
+        session.session_key = session_key;
+        session.scope = scope;
+        session.expires_at = expires_at;
+        session.bump = *ctx.bumps.get("session").unwrap();
+
+        Ok(())
+    }
+
+    pub fn revoke_session(_ctx: Context<RevokeSession>) -> Result<()> {
+        Ok(())
+    }
+
+    /// Lists the owner's NFT for `taker_amount` lamports, signed by a session
+    /// key instead of the owner's wallet. The session key pays the rent out
+    /// of what the owner funded it with, so the rent goes back to the owner.
+    pub fn list_with_session(
+        ctx: Context<ListWithSession>,
+        taker_amount: u64,
+        note: String,
+    ) -> Result<EscrowAddresses> {
+        let now = Clock::get()?.unix_timestamp;
+        ctx.accounts
+            .session
+            .require_scope(SESSION_SCOPE_LIST, now)?;
+        record_wallet_activity(
+            &mut ctx.accounts.wallet_activity,
+            &ctx.accounts.config,
+            now,
+            true,
+        )?;
+        let collection = require_listable(
+            &ctx.accounts.nft_metadata,
+            &ctx.accounts.collection_floor,
+            &ctx.accounts.config,
+            0,
+            taker_amount,
+        )?;
+
+        let nonce = ctx.accounts.listing_counter.take_nonce(
+            ctx.accounts.owner.key(),
+            *ctx.bumps.get("listing_counter").unwrap(),
+        )?;
+        ctx.accounts.escrow_state.open(
+            NewListing {
+                nonce,
+                initializer: ctx.accounts.owner.key(),
+                taker: ctx.accounts.taker_key.key(),
+                deposit_token_account: ctx.accounts.owner_deposit_token_account.key(),
+                deposit_mint: ctx.accounts.mint.key(),
+                receive_mint: ctx.accounts.initializer_receive_mint_account.key(),
+                taker_amount,
+                vault_authority_bump: *ctx.bumps.get("vault_authority").unwrap(),
+                vault_rent_lamports: ctx.accounts.vault.to_account_info().lamports(),
+                market_id: ctx.accounts.config.market_id,
+                rent_payer: ctx.accounts.owner.key(),
+                collection,
+                note,
+            },
+            now,
+        )?;
+
+        let owner_key = ctx.accounts.owner.key();
+        let session_key = ctx.accounts.session_key.key();
+        let session_seeds = &[
+            b"session".as_ref(),
+            owner_key.as_ref(),
+            session_key.as_ref(),
+            &[ctx.accounts.session.bump],
+        ];
+        token::transfer_checked(
+            ctx.accounts
+                .into_transfer_to_pda_context()
+                .with_signer(&[&session_seeds[..]]),
+            1,
+            ctx.accounts.mint.decimals,
+        )?;
+
+        Ok(EscrowAddresses {
+            escrow_state: ctx.accounts.escrow_state.key(),
+            vault: ctx.accounts.vault.key(),
+            nonce,
+        })
+    }
+
+    /// Cancels one of the owner's plain listings through a session key,
+    /// returning the NFT and the deposit to the owner and the rent to whoever
+    /// paid it.
+    pub fn cancel_with_session<'info>(
+        ctx: Context<'_, '_, '_, 'info, CancelWithSession<'info>>,
+    ) -> Result<()> {
+        ctx.accounts
+            .session
+            .require_scope(SESSION_SCOPE_CANCEL, Clock::get()?.unix_timestamp)?;
+
+        let escrow_key = ctx.accounts.escrow_state.key();
+        let authority_seeds = &[
+            &AUTHORITY_SEED[..],
+            escrow_key.as_ref(),
+            &[ctx.accounts.escrow_state.vault_authority_bump],
+        ];
+        token::transfer_checked(
+            ctx.accounts
+                .into_transfer_to_owner_context()
+                .with_signer(&[&authority_seeds[..]]),
+            1,
+            ctx.accounts.mint.decimals,
+        )?;
+        token::close_account(
+            ctx.accounts
+                .into_close_context()
+                .with_signer(&[&authority_seeds[..]]),
+        )?;
+        close_escrow(
+            &ctx.accounts.escrow_state,
+            &ctx.accounts.owner,
+            ctx.remaining_accounts,
+        )
+    }
+
+    /// Starts a tamper-evident history for the escrow. From then on its
+    /// exchange and cancellation each fold into the trail's hash, and the
+    /// trail outlives the escrow so the history can still be checked after.
+    pub fn open_audit_trail(ctx: Context<OpenAuditTrail>) -> Result<()> {
+        let escrow_state = &mut ctx.accounts.escrow_state;
+        escrow_state.audited = true;
+
+        let audit_trail = &mut ctx.accounts.audit_trail;
+        audit_trail.escrow_state = escrow_state.key();
+        audit_trail.initializer = escrow_state.initializer_key;
+        audit_trail.bump = *ctx.bumps.get("audit_trail").unwrap();
+        audit_trail.record(
+            AUDIT_OPENED,
+            &escrow_state.initializer_key,
+            escrow_state.taker_amount,
+            Clock::get()?.slot,
+        )
+    }
+
+    pub fn close_audit_trail(_ctx: Context<CloseAuditTrail>) -> Result<()> {
+        Ok(())
+    }
+
+    /// Checks that every escrow passed in still has its NFT in custody and
+    /// returns a hash over what was checked, so a third party can attest
+    /// the program's reserves. Remaining accounts come in pairs: the escrow
+    /// state, then its vault, or the seller's token account for a delegated
+    /// listing.
+    pub fn prove_reserves<'info>(
+        ctx: Context<'_, '_, '_, 'info, ProveReserves>,
+    ) -> Result<ReserveProof> {
+        require!(
+            ctx.remaining_accounts.len() % 2 == 0,
+            EscrowError::InvalidReserveAccounts
+        );
+
+        let mut proof = ReserveProof {
+            escrows: 0,
+            hash: [0; 32],
+        };
+        for pair in ctx.remaining_accounts.chunks(2) {
+            let escrow_state = Account::<EscrowState>::try_from(&pair[0])?;
+            let custody = Account::<TokenAccount>::try_from(&pair[1])?;
+            let vault_authority = Pubkey::create_program_address(
+                &[
+                    AUTHORITY_SEED,
+                    escrow_state.key().as_ref(),
+                    &[escrow_state.vault_authority_bump],
+                ],
+                &crate::ID,
+            )
+            .map_err(|_| error!(EscrowError::InvalidReserveAccounts))?;
+            let mint = escrow_state.initializer_deposit_mint_account;
+
+            let held = if escrow_state.delegated {
+                custody.key() == escrow_state.initializer_deposit_token_account
+                    && custody.delegate == COption::Some(vault_authority)
+                    && custody.delegated_amount >= 1
+            } else {
+                custody.key() == get_associated_token_address(&vault_authority, &mint)
+            };
+            require!(
+                held && custody.mint == mint && custody.amount >= 1,
+                EscrowError::ReserveShortfall
+            );
+
+            proof.hash = keccak::hashv(&[
+                &proof.hash,
+                escrow_state.key().as_ref(),
+                mint.as_ref(),
+                &custody.amount.to_le_bytes(),
+            ])
+            .0;
+            proof.escrows += 1;
+        }
+        Ok(proof)
+    }
+
+    /// Saves default terms under `template_id` so a store can list many
+    /// NFTs alike through `initialize_from_template`.
+    pub fn create_listing_template(
+        ctx: Context<CreateListingTemplate>,
+        template_id: u64,
+        taker_amount: u64,
+        donation_recipient: Pubkey,
+        donation_bps: u16,
+        settlement_delay_secs: i64,
+        tag: [u8; 8],
+        note: String,
+    ) -> Result<()> {
+        require_valid_bps(&[donation_bps])?;
+        require!(
+            donation_bps == 0 || donation_recipient != Pubkey::default(),
+            EscrowError::MissingDonationRecipient
+        );
+        require!(
+            settlement_delay_secs >= 0,
+            EscrowError::InvalidSettlementDelay
+        );
+        require!(note.len() <= MAX_NOTE_LEN, EscrowError::NoteTooLong);
+
+        let template = &mut ctx.accounts.template;
+        template.owner = ctx.accounts.owner.key();
+        template.template_id = template_id;
+        template.taker_amount = taker_amount;
+        template.donation_recipient = donation_recipient;
+        template.donation_bps = donation_bps;
+        template.settlement_delay_secs = settlement_delay_secs;
+        template.tag = tag;
+        template.note = note;
+        template.bump = *ctx.bumps.get("template").unwrap();
+        Ok(())
+    }
+
+    pub fn close_listing_template(_ctx: Context<CloseListingTemplate>) -> Result<()> {
+        Ok(())
+    }
+
+    /// Lists an NFT on the terms of one of the initializer's templates
+    /// instead of passing them in each time.
+    pub fn initialize_from_template(
+        ctx: Context<InitializeFromTemplate>,
+    ) -> Result<EscrowAddresses> {
+        let template = &ctx.accounts.template;
+        let collection = require_listable(
+            &ctx.accounts.nft_metadata,
+            &ctx.accounts.collection_floor,
+            &ctx.accounts.config,
+            0,
+            template.taker_amount,
+        )?;
+
+        let nonce = ctx.accounts.listing_counter.take_nonce(
+            ctx.accounts.initializer.key(),
+            *ctx.bumps.get("listing_counter").unwrap(),
+        )?;
+        let now = Clock::get()?.unix_timestamp;
+        ctx.accounts.escrow_state.open(
+            NewListing {
+                nonce,
+                initializer: ctx.accounts.initializer.key(),
+                taker: ctx.accounts.taker_key.key(),
+                deposit_token_account: ctx.accounts.initializer_deposit_token_account.key(),
+                deposit_mint: ctx.accounts.mint.key(),
+                receive_mint: ctx.accounts.initializer_receive_mint_account.key(),
+                taker_amount: template.taker_amount,
+                vault_authority_bump: *ctx.bumps.get("vault_authority").unwrap(),
+                vault_rent_lamports: ctx.accounts.vault.to_account_info().lamports(),
+                market_id: ctx.accounts.config.market_id,
+                rent_payer: ctx.accounts.rent_payer.key(),
+                collection,
+                note: template.note.clone(),
+            },
+            now,
+        )?;
+        let escrow_state = &mut ctx.accounts.escrow_state;
+        escrow_state.donation_recipient = template.donation_recipient;
+        escrow_state.donation_bps = template.donation_bps;
+        escrow_state.settlement_delay_secs = template.settlement_delay_secs;
+        escrow_state.tag = template.tag;
+        record_wallet_activity(
+            &mut ctx.accounts.wallet_activity,
+            &ctx.accounts.config,
+            now,
+            true,
+        )?;
+
+        token::transfer_checked(
+            ctx.accounts.into_transfer_to_pda_context(),
+            1,
+            ctx.accounts.mint.decimals,
+        )?;
+
+        Ok(EscrowAddresses {
+            escrow_state: ctx.accounts.escrow_state.key(),
+            vault: ctx.accounts.vault.key(),
+            nonce,
+        })
+    }
+
+    /// First half of a listing split over two small transactions for wallets
+    /// on constrained transports: creates the escrow and its empty vault.
+    /// The listing fills only once `deposit` has moved the NFT in.
+    pub fn create_listing(
+        ctx: Context<CreateListing>,
+        taker_amount: u64,
+        note: String,
+    ) -> Result<EscrowAddresses> {
+        let collection = require_listable(
+            &ctx.accounts.nft_metadata,
+            &ctx.accounts.collection_floor,
+            &ctx.accounts.config,
+            0,
+            taker_amount,
+        )?;
+
+        let nonce = ctx.accounts.listing_counter.take_nonce(
+            ctx.accounts.initializer.key(),
+            *ctx.bumps.get("listing_counter").unwrap(),
+        )?;
+        let now = Clock::get()?.unix_timestamp;
+        ctx.accounts.escrow_state.open(
+            NewListing {
+                nonce,
+                initializer: ctx.accounts.initializer.key(),
+                taker: ctx.accounts.taker_key.key(),
+                deposit_token_account: ctx.accounts.initializer_deposit_token_account.key(),
+                deposit_mint: ctx.accounts.mint.key(),
+                receive_mint: ctx.accounts.initializer_receive_mint_account.key(),
+                taker_amount,
+                vault_authority_bump: *ctx.bumps.get("vault_authority").unwrap(),
+                vault_rent_lamports: ctx.accounts.vault.to_account_info().lamports(),
+                market_id: ctx.accounts.config.market_id,
+                rent_payer: ctx.accounts.rent_payer.key(),
+                collection,
+                note,
+            },
+            now,
+        )?;
+        ctx.accounts.escrow_state.awaiting_deposit = true;
+        record_wallet_activity(
+            &mut ctx.accounts.wallet_activity,
+            &ctx.accounts.config,
+            now,
+            true,
+        )?;
+
+        Ok(EscrowAddresses {
+            escrow_state: ctx.accounts.escrow_state.key(),
+            vault: ctx.accounts.vault.key(),
+            nonce,
+        })
+    }
+
+    /// Second half of `create_listing`: moves the NFT into the vault and
+    /// activates the listing. Its age counts from here.
+    pub fn deposit(ctx: Context<Deposit>) -> Result<()> {
+        token::transfer_checked(
+            ctx.accounts.into_transfer_to_pda_context(),
+            1,
+            ctx.accounts.mint.decimals,
+        )?;
+        let escrow_state = &mut ctx.accounts.escrow_state;
+        escrow_state.awaiting_deposit = false;
+        escrow_state.created_at = Clock::get()?.unix_timestamp;
+        Ok(())
+    }
+
+    /// Hands the listing to `new_initializer`, e.g. when a business rotates
+    /// its operational keys. Both wallets sign, and from then on the
+    /// proceeds, and the NFT on `cancel`, go to the new wallet's token
+    /// accounts for the listing's mints. Rent the old wallet put up follows
+    /// the listing, while a sponsor keeps its claim to the rent it paid.
+    /// Delegated listings are refused, since their NFT stays frozen in the
+    /// old wallet's token account.
+    pub fn transfer_listing(ctx: Context<TransferListing>) -> Result<()> {
+        let new_initializer = ctx.accounts.new_initializer.key();
+        let escrow_state = &mut ctx.accounts.escrow_state;
+        if escrow_state.rent_payer == escrow_state.initializer_key {
+            escrow_state.rent_payer = new_initializer;
+        }
+        escrow_state.initializer_key = new_initializer;
+        escrow_state.initializer_deposit_token_account = get_associated_token_address(
+            &new_initializer,
+            &escrow_state.initializer_deposit_mint_account,
+        );
+        escrow_state.initializer_receive_token_account = get_associated_token_address(
+            &new_initializer,
+            &escrow_state.initializer_receive_mint_account,
+        );
+        Ok(())
+    }
+
+    /// Points the listing at another taker wallet, e.g. when the buyer it
+    /// was negotiated with switched wallets, without relisting.
+    pub fn reassign_taker(ctx: Context<ReassignTaker>) -> Result<()> {
+        ctx.accounts.escrow_state.taker_key = ctx.accounts.new_taker.key();
+        Ok(())
+    }
+
+    /// Moves the listing's expiry out to `expires_at` instead of cancelling
+    /// and relisting; a listing that never expired gets its first expiry
+    /// this way. The config bounds how far ahead it may go.
+    pub fn extend_expiry(ctx: Context<ExtendExpiry>, expires_at: i64) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let escrow_state = &mut ctx.accounts.escrow_state;
+        require!(
+            expires_at > now
+                && (escrow_state.expires_at == 0 || expires_at > escrow_state.expires_at),
+            EscrowError::InvalidExpiry
+        );
+        let max_listing_duration_secs = ctx.accounts.config.max_listing_duration_secs;
+        if max_listing_duration_secs > 0 {
+            let latest = now
+                .checked_add(max_listing_duration_secs)
+                .ok_or(EscrowError::ArithmeticOverflow)?;
+            require!(expires_at <= latest, EscrowError::ExpiryTooFar);
+        }
+        escrow_state.expires_at = expires_at;
+        Ok(())
+    }
+
+    /// Opts the listing into being relisted once it expires: a keeper may then
+    /// call `relist_decayed` to drop the price by `decay_bps` and keep it up
+    /// for another `window_secs`. Zero `decay_bps` opts back out.
+    pub fn set_relist_decay(
+        ctx: Context<SetRelistDecay>,
+        decay_bps: u16,
+        window_secs: i64,
+    ) -> Result<()> {
+        let max_listing_duration_secs = ctx.accounts.config.max_listing_duration_secs;
+        require!(
+            (decay_bps as u64) < BPS_DENOMINATOR
+                && (decay_bps == 0 || window_secs > 0)
+                && (max_listing_duration_secs == 0 || window_secs <= max_listing_duration_secs),
+            EscrowError::InvalidRelistDecay
+        );
+        let escrow_state = &mut ctx.accounts.escrow_state;
+        escrow_state.relist_decay_bps = decay_bps;
+        escrow_state.relist_window_secs = window_secs;
+        Ok(())
+    }
+
+    /// Permissionless: re-activates an expired listing that opted in through
+    /// `set_relist_decay`, at the decayed price, instead of it sitting idle
+    /// until the seller cancels.
+    pub fn relist_decayed(ctx: Context<RelistDecayed>) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let escrow_state = &mut ctx.accounts.escrow_state;
+        require!(
+            escrow_state.relist_decay_bps > 0,
+            EscrowError::RelistNotEnabled
+        );
+        require!(escrow_state.is_expired(now), EscrowError::ListingNotExpired);
+        let decay_bps = escrow_state.relist_decay_bps;
+        escrow_state.taker_amount = checked_sub(
+            escrow_state.taker_amount,
+            bps_of(escrow_state.taker_amount, decay_bps)?,
+        )?;
+        escrow_state.price_usd_cents = checked_sub(
+            escrow_state.price_usd_cents,
+            bps_of(escrow_state.price_usd_cents, decay_bps)?,
+        )?;
+        escrow_state.expires_at = now
+            .checked_add(escrow_state.relist_window_secs)
+            .ok_or(EscrowError::ArithmeticOverflow)?;
+
+        emit!(ListingRelisted {
+            escrow_state: escrow_state.key(),
+            taker_amount: escrow_state.taker_amount,
+            price_usd_cents: escrow_state.price_usd_cents,
+            expires_at: escrow_state.expires_at,
+        });
+        Ok(())
+    }
+
+    /// Permissionless: returns an expired listing's NFT to the seller and its
+    /// rent to whoever paid it.
+    /// Before the expiry it does nothing, so an automation thread such as a
+    /// Clockwork one can call it on a schedule; the keeper only signs and
+    /// pays no rent.
+    pub fn cancel_expired<'info>(
+        ctx: Context<'_, '_, '_, 'info, CancelExpired<'info>>,
+    ) -> Result<()> {
+        if !ctx
+            .accounts
+            .escrow_state
+            .is_expired(Clock::get()?.unix_timestamp)
+        {
+            return Ok(());
+        }
+        record_audit(
+            &mut ctx.accounts.audit_trail,
+            &ctx.accounts.escrow_state,
+            AUDIT_CANCELLED,
+            &ctx.accounts.keeper.key(),
+            0,
+        )?;
+
+        let escrow_key = ctx.accounts.escrow_state.key();
+        let authority_seeds = &[
+            &AUTHORITY_SEED[..],
+            escrow_key.as_ref(),
+            &[ctx.accounts.escrow_state.vault_authority_bump],
+        ];
+        let refunded_amount = ctx.accounts.vault.amount;
+        let refunded_rent = ctx.accounts.vault.to_account_info().lamports();
+        if refunded_amount > 0 {
+            token::transfer_checked(
+                ctx.accounts
+                    .into_transfer_to_initializer_context()
+                    .with_signer(&[&authority_seeds[..]]),
+                refunded_amount,
+                ctx.accounts.mint.decimals,
+            )?;
+        }
+        token::close_account(
+            ctx.accounts
+                .into_close_context()
+                .with_signer(&[&authority_seeds[..]]),
+        )?;
+        close_escrow(
+            &ctx.accounts.escrow_state,
+            &ctx.accounts.initializer,
+            ctx.remaining_accounts,
+        )?;
+
+        emit!(EscrowCancelled {
+            escrow_state: escrow_key,
+            initializer: ctx.accounts.initializer.key(),
+            refunded_amount,
+            refunded_rent,
+        });
+        Ok(())
+    }
+
+    /// Opens an airdrop of NFTs held in program custody. Each leaf of
+    /// `merkle_root` is `keccak(claimant, mint)` and lets that claimant take
+    /// that NFT. After `expires_at`, or never if zero, the project may take
+    /// unclaimed NFTs back.
+    pub fn create_airdrop(
+        ctx: Context<CreateAirdrop>,
+        merkle_root: [u8; 32],
+        expires_at: i64,
+    ) -> Result<()> {
+        let airdrop = &mut ctx.accounts.airdrop;
+        airdrop.project = ctx.accounts.project.key();
+        airdrop.merkle_root = merkle_root;
+        airdrop.expires_at = expires_at;
+        airdrop.nft_count = 0;
+        airdrop.vault_authority_bump = *ctx.bumps.get("vault_authority").unwrap();
+        airdrop.bump = *ctx.bumps.get("airdrop").unwrap();
+        Ok(())
+    }
+
+    /// Puts one NFT of the airdrop into custody.
+    pub fn deposit_airdrop_nft(ctx: Context<DepositAirdropNft>) -> Result<()> {
+        token::transfer_checked(
+            ctx.accounts.into_transfer_to_vault_context(),
+            1,
+            ctx.accounts.mint.decimals,
+        )?;
+
+        let airdrop = &mut ctx.accounts.airdrop;
+        airdrop.nft_count = checked_add(airdrop.nft_count, 1)?;
+        Ok(())
+    }
+
+    /// Releases the NFT named by the claimant's leaf. The emptied vault's
+    /// rent goes to the claimant, covering their token account, and since
+    /// the vault is gone the leaf cannot be claimed twice.
+    pub fn claim_airdrop(ctx: Context<ClaimAirdrop>, proof: Vec<[u8; 32]>) -> Result<()> {
+        let airdrop = &ctx.accounts.airdrop;
+        let leaf = keccak::hashv(&[
+            ctx.accounts.claimant.key().as_ref(),
+            ctx.accounts.mint.key().as_ref(),
+        ]);
+        require!(
+            verify_merkle_proof(&proof, airdrop.merkle_root, leaf.0),
+            EscrowError::InvalidMerkleProof
+        );
+
+        let airdrop_key = airdrop.key();
+        let authority_seeds = &[
+            &AUTHORITY_SEED[..],
+            airdrop_key.as_ref(),
+            &[airdrop.vault_authority_bump],
+        ];
+        token::transfer_checked(
+            ctx.accounts
+                .into_transfer_to_claimant_context()
+                .with_signer(&[&authority_seeds[..]]),
+            1,
+            ctx.accounts.mint.decimals,
+        )?;
+        token::close_account(
+            ctx.accounts
+                .into_close_context()
+                .with_signer(&[&authority_seeds[..]]),
+        )?;
+
+        let airdrop = &mut ctx.accounts.airdrop;
+        airdrop.nft_count = checked_sub(airdrop.nft_count, 1)?;
+        Ok(())
+    }
+
+    /// Takes an unclaimed NFT back once the airdrop has expired.
+    pub fn reclaim_airdrop_nft(ctx: Context<ReclaimAirdropNft>) -> Result<()> {
+        let airdrop = &ctx.accounts.airdrop;
+        require!(
+            airdrop.expires_at > 0 && Clock::get()?.unix_timestamp > airdrop.expires_at,
+            EscrowError::AirdropNotExpired
+        );
+
+        let airdrop_key = airdrop.key();
+        let authority_seeds = &[
+            &AUTHORITY_SEED[..],
+            airdrop_key.as_ref(),
+            &[airdrop.vault_authority_bump],
+        ];
+        token::transfer_checked(
+            ctx.accounts
+                .into_transfer_to_project_context()
+                .with_signer(&[&authority_seeds[..]]),
+            1,
+            ctx.accounts.mint.decimals,
+        )?;
+        token::close_account(
+            ctx.accounts
+                .into_close_context()
+                .with_signer(&[&authority_seeds[..]]),
+        )?;
+
+        let airdrop = &mut ctx.accounts.airdrop;
+        airdrop.nft_count = checked_sub(airdrop.nft_count, 1)?;
+        Ok(())
+    }
+
+    /// Closes an airdrop with nothing left in custody.
+    pub fn close_airdrop(_ctx: Context<CloseAirdrop>) -> Result<()> {
+        Ok(())
+    }
+
+    /// Asks for a SOL loan against the escrowed NFT: `principal` lamports at
+    /// `apr_bps` simple yearly interest, due `duration_secs` after funding.
+    /// Zero `principal` withdraws the request.
+    pub fn request_loan(
+        ctx: Context<RequestLoan>,
+        principal: u64,
+        apr_bps: u16,
+        duration_secs: i64,
+    ) -> Result<()> {
+        require!(
+            principal == 0 || duration_secs > 0,
+            EscrowError::InvalidLoanTerms
+        );
+
+        let escrow_state = &mut ctx.accounts.escrow_state;
+        escrow_state.loan_principal = principal;
+        escrow_state.loan_apr_bps = apr_bps;
+        escrow_state.loan_duration_secs = duration_secs;
+        Ok(())
+    }
+
+    /// Lends the requested principal to the seller. The NFT stays in the vault
+    /// and the listing is locked until the loan is repaid or liquidated.
+    pub fn fund_loan(ctx: Context<FundLoan>) -> Result<()> {
+        let escrow_state = &ctx.accounts.escrow_state;
+        transfer_lamports(
+            &ctx.accounts.lender.to_account_info(),
+            &ctx.accounts.borrower,
+            escrow_state.loan_principal,
+        )?;
+
+        let now = Clock::get()?.unix_timestamp;
+        let loan_state = &mut ctx.accounts.loan_state;
+        loan_state.escrow_state = escrow_state.key();
+        loan_state.borrower = escrow_state.initializer_key;
+        loan_state.lender = ctx.accounts.lender.key();
+        loan_state.principal = escrow_state.loan_principal;
+        loan_state.apr_bps = escrow_state.loan_apr_bps;
+        loan_state.started_at = now;
+        loan_state.due_at = now
+            .checked_add(escrow_state.loan_duration_secs)
+            .ok_or(EscrowError::ArithmeticOverflow)?;
+        loan_state.bump = *ctx.bumps.get("loan_state").unwrap();
+
+        ctx.accounts.escrow_state.loan_lender = ctx.accounts.lender.key();
+        Ok(())
+    }
+
+    /// Pays back the principal and the interest accrued so far. A late
+    /// borrower may still repay as long as the lender has not liquidated.
+    pub fn repay(ctx: Context<Repay>) -> Result<()> {
+        let loan_state = &ctx.accounts.loan_state;
+        let interest = loan_state.accrued_interest(Clock::get()?.unix_timestamp)?;
+        transfer_lamports(
+            &ctx.accounts.borrower.to_account_info(),
+            &ctx.accounts.lender,
+            checked_add(loan_state.principal, interest)?,
+        )?;
+
+        let escrow_state = &mut ctx.accounts.escrow_state;
+        escrow_state.loan_lender = Pubkey::default();
+        escrow_state.loan_principal = 0;
+        Ok(())
+    }
+
+    /// Takes the NFT for the lender once the loan is overdue. The listing is
+    /// closed: its SOL deposit goes back to the borrower and its rent to
+    /// whoever paid it.
+    pub fn liquidate<'info>(ctx: Context<'_, '_, '_, 'info, Liquidate<'info>>) -> Result<()> {
+        require!(
+            Clock::get()?.unix_timestamp > ctx.accounts.loan_state.due_at,
+            EscrowError::LoanNotDue
+        );
+
+        let escrow_key = ctx.accounts.escrow_state.key();
+        let authority_seeds = &[
+            &AUTHORITY_SEED[..],
+            escrow_key.as_ref(),
+            &[ctx.accounts.escrow_state.vault_authority_bump],
+        ];
+        token::transfer_checked(
+            ctx.accounts
+                .into_transfer_to_lender_context()
+                .with_signer(&[&authority_seeds[..]]),
+            1,
+            ctx.accounts.mint.decimals,
+        )?;
+        token::close_account(
+            ctx.accounts
+                .into_close_context()
+                .with_signer(&[&authority_seeds[..]]),
+        )?;
+        close_escrow(
+            &ctx.accounts.escrow_state,
+            &ctx.accounts.borrower,
+            ctx.remaining_accounts,
+        )
+    }
+
+    /// Opens a subscription to an edition stocked in a vault: `stock` copies
+    /// of `mint` are deposited and subscribers get one per `period_secs` for
+    /// `price` lamports each.
+    pub fn create_subscription_listing(
+        ctx: Context<CreateSubscriptionListing>,
+        price: u64,
+        period_secs: i64,
+        stock: u64,
+    ) -> Result<()> {
+        require!(
+            price > 0 && period_secs > 0 && stock > 0,
+            EscrowError::InvalidSubscriptionTerms
+        );
+
+        let subscription_listing = &mut ctx.accounts.subscription_listing;
+        subscription_listing.seller = ctx.accounts.seller.key();
+        subscription_listing.mint = ctx.accounts.mint.key();
+        subscription_listing.price = price;
+        subscription_listing.period_secs = period_secs;
+        subscription_listing.vault_authority_bump = *ctx.bumps.get("vault_authority").unwrap();
+        subscription_listing.bump = *ctx.bumps.get("subscription_listing").unwrap();
+
+        token::transfer_checked(
+            ctx.accounts.into_transfer_to_vault_context(),
+            stock,
+            ctx.accounts.mint.decimals,
+        )
+    }
+
+    /// Starts a subscription and delivers the first copy.
+    pub fn subscribe(ctx: Context<Subscribe>) -> Result<()> {
+        let subscription = &mut ctx.accounts.subscription;
+        require!(
+            subscription.subscriber == Pubkey::default(),
+            EscrowError::AlreadySubscribed
+        );
+        subscription.subscription_listing = ctx.accounts.subscription_listing.key();
+        subscription.subscriber = ctx.accounts.subscriber.key();
+        subscription.deliveries = 0;
+        subscription.bump = *ctx.bumps.get("subscription").unwrap();
+
+        ctx.accounts.deliver()
+    }
+
+    /// Pays for and delivers the next period's copy once the current period
+    /// has passed.
+    pub fn renew_subscription(ctx: Context<Subscribe>) -> Result<()> {
+        require!(
+            Clock::get()?.unix_timestamp >= ctx.accounts.subscription.next_delivery,
+            EscrowError::SubscriptionPeriodNotElapsed
+        );
+        ctx.accounts.deliver()
+    }
+
+    /// Ends a subscription; nothing further is charged or delivered.
+    pub fn cancel_subscription(_ctx: Context<CancelSubscription>) -> Result<()> {
+        Ok(())
+    }
+
+    /// Withdraws the remaining stock and closes the subscription listing.
+    /// Existing subscriptions simply stop renewing.
+    pub fn close_subscription_listing(ctx: Context<CloseSubscriptionListing>) -> Result<()> {
+        let listing_key = ctx.accounts.subscription_listing.key();
+        let authority_seeds = &[
+            &AUTHORITY_SEED[..],
+            listing_key.as_ref(),
+            &[ctx.accounts.subscription_listing.vault_authority_bump],
+        ];
+        token::transfer_checked(
+            ctx.accounts
+                .into_transfer_to_seller_context()
+                .with_signer(&[&authority_seeds[..]]),
+            ctx.accounts.vault.amount,
+            ctx.accounts.mint.decimals,
+        )?;
+        token::close_account(
+            ctx.accounts
+                .into_close_context()
+                .with_signer(&[&authority_seeds[..]]),
+        )
+    }
+
+    /// Opens a two-sided pool for `collection_mint`: the owner stocks it with
+    /// NFTs and SOL, and traders buy from or sell to it at any time. Each
+    /// trade moves `spot_price` by `delta` lamports, and `spread_bps` is the
+    /// pool's margin over spot on both sides.
+    pub fn create_nft_pool(
+        ctx: Context<CreateNftPool>,
+        spot_price: u64,
+        delta: u64,
+        spread_bps: u16,
+    ) -> Result<()> {
+        require_valid_bps(&[spread_bps])?;
+
+        let nft_pool = &mut ctx.accounts.nft_pool;
+        nft_pool.owner = ctx.accounts.owner.key();
+        nft_pool.collection_mint = ctx.accounts.collection_mint.key();
+        nft_pool.spot_price = spot_price;
+        nft_pool.delta = delta;
+        nft_pool.spread_bps = spread_bps;
+        nft_pool.sol_reserve = 0;
+        nft_pool.nft_count = 0;
+        nft_pool.vault_authority_bump = *ctx.bumps.get("vault_authority").unwrap();
+        nft_pool.bump = *ctx.bumps.get("nft_pool").unwrap();
+        Ok(())
+    }
+
+    /// Reprices the pool.
+    pub fn update_nft_pool(
+        ctx: Context<UpdateNftPool>,
+        spot_price: u64,
+        delta: u64,
+        spread_bps: u16,
+    ) -> Result<()> {
+        require_valid_bps(&[spread_bps])?;
+
+        let nft_pool = &mut ctx.accounts.nft_pool;
+        nft_pool.spot_price = spot_price;
+        nft_pool.delta = delta;
+        nft_pool.spread_bps = spread_bps;
+        Ok(())
+    }
+
+    /// Adds a verified member of the pool's collection to its inventory.
+    pub fn deposit_pool_nft(ctx: Context<DepositPoolNft>) -> Result<()> {
+        token::transfer_checked(
+            ctx.accounts.into_transfer_to_vault_context(),
+            1,
+            ctx.accounts.mint.decimals,
+        )?;
+
+        let nft_pool = &mut ctx.accounts.nft_pool;
+        nft_pool.nft_count = checked_add(nft_pool.nft_count, 1)?;
+        Ok(())
+    }
+
+    /// Takes an NFT out of the pool's inventory.
+    pub fn withdraw_pool_nft(ctx: Context<WithdrawPoolNft>) -> Result<()> {
+        let pool_key = ctx.accounts.nft_pool.key();
+        let authority_seeds = &[
+            &AUTHORITY_SEED[..],
+            pool_key.as_ref(),
+            &[ctx.accounts.nft_pool.vault_authority_bump],
+        ];
+        token::transfer_checked(
+            ctx.accounts
+                .into_transfer_to_owner_context()
+                .with_signer(&[&authority_seeds[..]]),
+            1,
+            ctx.accounts.mint.decimals,
+        )?;
+        token::close_account(
+            ctx.accounts
+                .into_close_context()
+                .with_signer(&[&authority_seeds[..]]),
+        )?;
+
+        let nft_pool = &mut ctx.accounts.nft_pool;
+        nft_pool.nft_count = checked_sub(nft_pool.nft_count, 1)?;
+        Ok(())
+    }
+
+    /// Adds `amount` lamports to the SOL the pool buys NFTs with.
+    pub fn deposit_pool_sol(ctx: Context<PoolSol>, amount: u64) -> Result<()> {
+        transfer_lamports(
+            &ctx.accounts.owner.to_account_info(),
+            &ctx.accounts.nft_pool.to_account_info(),
+            amount,
+        )?;
+
+        let nft_pool = &mut ctx.accounts.nft_pool;
+        nft_pool.sol_reserve = checked_add(nft_pool.sol_reserve, amount)?;
+        Ok(())
+    }
+
+    /// Withdraws `amount` lamports of the pool's SOL, including what it has
+    /// earned from sales.
+    pub fn withdraw_pool_sol(ctx: Context<PoolSol>, amount: u64) -> Result<()> {
+        let nft_pool = &mut ctx.accounts.nft_pool;
+        nft_pool.sol_reserve = checked_sub(nft_pool.sol_reserve, amount)
+            .map_err(|_| error!(EscrowError::InsufficientPoolLiquidity))?;
+        move_program_lamports(
+            &ctx.accounts.nft_pool.to_account_info(),
+            &ctx.accounts.owner.to_account_info(),
+            amount,
+        )
+    }
+
+    /// Buys an NFT from the pool at its buy quote plus the taker fee, paying
+    /// at most `max_price` in total. Spot rises by `delta` afterwards.
+    pub fn buy_from_pool(ctx: Context<BuyFromPool>, max_price: u64) -> Result<()> {
+        let nft_pool = &ctx.accounts.nft_pool;
+        require!(
+            ctx.accounts.buyer.key() != nft_pool.owner,
+            EscrowError::SelfTrade
+        );
+        let price = nft_pool.buy_price()?;
+        let (_, taker_fee_bps) = ctx.accounts.config.fee_bps(None, false);
+        let taker_fee = bps_of(price, taker_fee_bps)?;
+        require!(
+            checked_add(price, taker_fee)? <= max_price,
+            EscrowError::PoolPriceMoved
+        );
+
+        transfer_lamports(
+            &ctx.accounts.buyer.to_account_info(),
+            &nft_pool.to_account_info(),
+            price,
+        )?;
+        transfer_lamports(
+            &ctx.accounts.buyer.to_account_info(),
+            &ctx.accounts.treasury,
+            taker_fee,
+        )?;
+
+        let pool_key = nft_pool.key();
+        let authority_seeds = &[
+            &AUTHORITY_SEED[..],
+            pool_key.as_ref(),
+            &[nft_pool.vault_authority_bump],
+        ];
+        token::transfer_checked(
+            ctx.accounts
+                .into_transfer_to_buyer_context()
+                .with_signer(&[&authority_seeds[..]]),
+            1,
+            ctx.accounts.mint.decimals,
+        )?;
+        token::close_account(
+            ctx.accounts
+                .into_close_context()
+                .with_signer(&[&authority_seeds[..]]),
+        )?;
+
+        let nft_pool = &mut ctx.accounts.nft_pool;
+        nft_pool.sol_reserve = checked_add(nft_pool.sol_reserve, price)?;
+        nft_pool.nft_count = checked_sub(nft_pool.nft_count, 1)?;
+        nft_pool.spot_price = checked_add(nft_pool.spot_price, nft_pool.delta)?;
+        Ok(())
+    }
+
+    /// Sells a verified member of the collection to the pool at its sell
+    /// quote less the taker fee, receiving at least `min_price`. Spot falls
+    /// by `delta` afterwards.
+    pub fn sell_to_pool(ctx: Context<SellToPool>, min_price: u64) -> Result<()> {
+        let nft_pool = &ctx.accounts.nft_pool;
+        require!(
+            ctx.accounts.seller.key() != nft_pool.owner,
+            EscrowError::SelfTrade
+        );
+        let price = nft_pool.sell_price()?;
+        require!(
+            price <= nft_pool.sol_reserve,
+            EscrowError::InsufficientPoolLiquidity
+        );
+        let (_, taker_fee_bps) = ctx.accounts.config.fee_bps(None, false);
+        let taker_fee = bps_of(price, taker_fee_bps)?;
+        let payout = checked_sub(price, taker_fee)?;
+        require!(payout >= min_price, EscrowError::PoolPriceMoved);
+
+        token::transfer_checked(
+            ctx.accounts.into_transfer_to_vault_context(),
+            1,
+            ctx.accounts.mint.decimals,
+        )?;
+        move_program_lamports(
+            &nft_pool.to_account_info(),
+            &ctx.accounts.seller.to_account_info(),
+            payout,
+        )?;
+        move_program_lamports(
+            &nft_pool.to_account_info(),
+            &ctx.accounts.treasury,
+            taker_fee,
+        )?;
+
+        let nft_pool = &mut ctx.accounts.nft_pool;
+        nft_pool.sol_reserve = checked_sub(nft_pool.sol_reserve, price)?;
+        nft_pool.nft_count = checked_add(nft_pool.nft_count, 1)?;
+        nft_pool.spot_price = nft_pool.spot_price.saturating_sub(nft_pool.delta);
+        Ok(())
+    }
+
+    /// Closes an empty pool and returns its remaining SOL to the owner.
+    pub fn close_nft_pool(_ctx: Context<CloseNftPool>) -> Result<()> {
+        Ok(())
+    }
+
+    /// Locks an NFT and mints `fraction_supply` fraction tokens for it to the
+    /// depositor. Anyone may later buy the NFT out for `reserve_price`
+    /// lamports, which fraction holders then redeem pro rata.
+    pub fn fractionalize(
+        ctx: Context<Fractionalize>,
+        fraction_supply: u64,
+        reserve_price: u64,
+    ) -> Result<()> {
+        require!(
+            fraction_supply > 0 && reserve_price > 0,
+            EscrowError::InvalidFractionTerms
+        );
+
+        let fraction_vault = &mut ctx.accounts.fraction_vault;
+        fraction_vault.depositor = ctx.accounts.depositor.key();
+        fraction_vault.mint = ctx.accounts.mint.key();
+        fraction_vault.fraction_mint = ctx.accounts.fraction_mint.key();
+        fraction_vault.fraction_supply = fraction_supply;
+        fraction_vault.reserve_price = reserve_price;
+        fraction_vault.bought_out_by = Pubkey::default();
+        fraction_vault.fractions_outstanding = fraction_supply;
+        fraction_vault.buyout_remaining = 0;
+        fraction_vault.vault_authority_bump = *ctx.bumps.get("vault_authority").unwrap();
+        fraction_vault.bump = *ctx.bumps.get("fraction_vault").unwrap();
+
+        token::transfer_checked(
+            ctx.accounts.into_transfer_to_vault_context(),
+            1,
+            ctx.accounts.mint.decimals,
+        )?;
+
+        let vault_key = ctx.accounts.fraction_vault.key();
+        let authority_seeds = &[
+            &AUTHORITY_SEED[..],
+            vault_key.as_ref(),
+            &[ctx.accounts.fraction_vault.vault_authority_bump],
+        ];
+        token::mint_to(
+            ctx.accounts
+                .into_mint_fractions_context()
+                .with_signer(&[&authority_seeds[..]]),
+            fraction_supply,
+        )
+    }
+
+    /// Buys the locked NFT out at the reserve price plus the taker fee. The
+    /// price stays in the fraction vault for holders to redeem.
+    pub fn buyout(ctx: Context<Buyout>) -> Result<()> {
+        let fraction_vault = &ctx.accounts.fraction_vault;
+        let reserve_price = fraction_vault.reserve_price;
+        let (_, taker_fee_bps) = ctx.accounts.config.fee_bps(None, false);
+        transfer_lamports(
+            &ctx.accounts.buyer.to_account_info(),
+            &fraction_vault.to_account_info(),
+            reserve_price,
+        )?;
+        transfer_lamports(
+            &ctx.accounts.buyer.to_account_info(),
+            &ctx.accounts.treasury,
+            bps_of(reserve_price, taker_fee_bps)?,
+        )?;
+
+        let vault_key = fraction_vault.key();
+        let authority_seeds = &[
+            &AUTHORITY_SEED[..],
+            vault_key.as_ref(),
+            &[fraction_vault.vault_authority_bump],
+        ];
+        token::transfer_checked(
+            ctx.accounts
+                .into_transfer_to_buyer_context()
+                .with_signer(&[&authority_seeds[..]]),
+            1,
+            ctx.accounts.mint.decimals,
+        )?;
+        token::close_account(
+            ctx.accounts
+                .into_close_context()
+                .with_signer(&[&authority_seeds[..]]),
+        )?;
+
+        let fraction_vault = &mut ctx.accounts.fraction_vault;
+        fraction_vault.bought_out_by = ctx.accounts.buyer.key();
+        fraction_vault.buyout_remaining = reserve_price;
+        Ok(())
+    }
+
+    /// Opens a crowdfunded purchase of a listing. Contributions collect in a
+    /// custody PDA until they cover the quoted total, and the custody PDA
+    /// takes the NFT when the purchase executes.
+    pub fn create_purchase_pool(ctx: Context<CreatePurchasePool>) -> Result<()> {
+        let Quote { total, .. } = quote_escrow(
+            &ctx.accounts.config,
+            &ctx.accounts.escrow_state,
+            &None,
+            &ctx.accounts.collection_fee_override,
+            &None,
+        )?;
+
+        let purchase_pool = &mut ctx.accounts.purchase_pool;
+        purchase_pool.escrow_state = ctx.accounts.escrow_state.key();
+        purchase_pool.target = total;
+        purchase_pool.raised = 0;
+        purchase_pool.executed = false;
+        purchase_pool.custody_bump = *ctx.bumps.get("custody").unwrap();
+        purchase_pool.bump = *ctx.bumps.get("purchase_pool").unwrap();
+        Ok(())
+    }
+
+    /// Adds up to `amount` lamports towards the purchase; only the remaining
+    /// shortfall is taken.
+    pub fn contribute(ctx: Context<Contribute>, amount: u64) -> Result<()> {
+        let purchase_pool = &ctx.accounts.purchase_pool;
+        let amount = amount.min(checked_sub(purchase_pool.target, purchase_pool.raised)?);
+        require!(amount > 0, EscrowError::InvalidContribution);
+        transfer_lamports(
+            &ctx.accounts.contributor.to_account_info(),
+            &ctx.accounts.custody,
+            amount,
+        )?;
+
+        let contribution = &mut ctx.accounts.contribution;
+        contribution.purchase_pool = ctx.accounts.purchase_pool.key();
+        contribution.contributor = ctx.accounts.contributor.key();
+        contribution.amount = checked_add(contribution.amount, amount)?;
+        contribution.bump = *ctx.bumps.get("contribution").unwrap();
+
+        let purchase_pool = &mut ctx.accounts.purchase_pool;
+        purchase_pool.raised = checked_add(purchase_pool.raised, amount)?;
+        Ok(())
+    }
+
+    /// Takes a contribution back before the purchase executes.
+    pub fn withdraw_contribution(ctx: Context<WithdrawContribution>) -> Result<()> {
+        let amount = ctx.accounts.contribution.amount;
+        let pool_key = ctx.accounts.purchase_pool.key();
+        let custody_seeds = &[
+            b"custody".as_ref(),
+            pool_key.as_ref(),
+            &[ctx.accounts.purchase_pool.custody_bump],
+        ];
+        invoke_signed(
+            &transfer(
+                ctx.accounts.custody.key,
+                ctx.accounts.contributor.key,
+                amount,
+            ),
+            &[
+                ctx.accounts.custody.clone(),
+                ctx.accounts.contributor.to_account_info(),
+            ],
+            &[&custody_seeds[..]],
+        )?;
+
+        let purchase_pool = &mut ctx.accounts.purchase_pool;
+        purchase_pool.raised = checked_sub(purchase_pool.raised, amount)?;
+        Ok(())
+    }
+
+    /// Buys the listing once the pool is fully funded, by running `exchange`
+    /// with the custody PDA as taker. Anyone may trigger it; they only pay
+    /// for the token accounts the exchange needs. The custody PDA must
+    /// already hold the listing's receive token.
+    pub fn execute_group_purchase<'info>(
+        ctx: Context<'_, '_, '_, 'info, ExecuteGroupPurchase<'info>>,
+    ) -> Result<()> {
+        // Read without holding on to it: the nested exchange closes it.
+        let escrow_state = Account::<EscrowState>::try_from(&ctx.accounts.escrow_state)?;
+        let Quote { total, .. } = quote_escrow(
+            &ctx.accounts.config,
+            &escrow_state,
+            &None,
+            &ctx.accounts.collection_fee_override,
+            &None,
+        )?;
+        require!(
+            total == ctx.accounts.purchase_pool.target,
+            EscrowError::GroupPurchasePriceChanged
+        );
+
+        ctx.accounts.exchange_for_custody()?;
+        ctx.accounts.purchase_pool.executed = true;
+        Ok(())
+    }
+
+    /// Burns the holder's fractions for their share of the buyout price. The
+    /// fraction vault closes to the depositor once every fraction is redeemed.
+    pub fn redeem_fractions(ctx: Context<RedeemFractions>) -> Result<()> {
+        let amount = ctx.accounts.holder_fraction_account.amount;
+        require!(amount > 0, EscrowError::NoFractionsHeld);
+        token::burn(ctx.accounts.into_burn_context(), amount)?;
+
+        let fraction_vault = &ctx.accounts.fraction_vault;
+        let payout = mul_div(
+            fraction_vault.buyout_remaining,
+            amount,
+            fraction_vault.fractions_outstanding,
+        )?;
+        move_program_lamports(
+            &fraction_vault.to_account_info(),
+            &ctx.accounts.holder.to_account_info(),
+            payout,
+        )?;
+
+        let fraction_vault = &mut ctx.accounts.fraction_vault;
+        fraction_vault.buyout_remaining = checked_sub(fraction_vault.buyout_remaining, payout)?;
+        fraction_vault.fractions_outstanding =
+            checked_sub(fraction_vault.fractions_outstanding, amount)?;
+        if fraction_vault.fractions_outstanding == 0 {
+            ctx.accounts
+                .fraction_vault
+                .close(ctx.accounts.depositor.to_account_info())?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct InitializeConfig<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    #[account(
+        init,
+        seeds = [b"config".as_ref()],
+        bump,
+        payer = admin,
+        space = Config::space()
+    )]
+    pub config: Account<'info, Config>,
+    /// CHECK: This is not dangerous because we only record its address as the fee recipient
+    pub treasury: AccountInfo<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct CreateMarket<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    #[account(
+        init,
+        seeds = [b"config".as_ref(), market_id.to_le_bytes().as_ref()],
+        bump,
+        payer = admin,
+        space = Config::space()
+    )]
+    pub config: Account<'info, Config>,
+    /// CHECK: This is not dangerous because we only record its address as the fee recipient
+    pub treasury: AccountInfo<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct OpenWalletActivity<'info> {
+    #[account(mut)]
+    pub wallet: Signer<'info>,
+    #[account(seeds = [b"config".as_ref(), &market_seed(config.market_id)], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+    #[account(
+        init,
+        seeds = [
+            b"wallet_activity".as_ref(),
+            wallet.key().as_ref(),
+            &market_seed(config.market_id)
+        ],
+        bump,
+        payer = wallet,
+        space = WalletActivity::space()
+    )]
+    pub wallet_activity: Account<'info, WalletActivity>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateConfig<'info> {
+    pub admin: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"config".as_ref(), &market_seed(config.market_id)],
+        bump = config.bump,
+        has_one = admin
+    )]
+    pub config: Account<'info, Config>,
+    /// CHECK: This is not dangerous because we only record its address as the fee recipient
+    pub treasury: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AdminConfig<'info> {
+    pub admin: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"config".as_ref(), &market_seed(config.market_id)],
+        bump = config.bump,
+        has_one = admin
+    )]
+    pub config: Account<'info, Config>,
+}
+
+#[derive(Accounts)]
+pub struct SetRewards<'info> {
+    pub admin: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"config".as_ref(), &market_seed(config.market_id)],
+        bump = config.bump,
+        has_one = admin
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        constraint = rewards_mint.mint_authority == COption::Some(rewards_authority.key()) @ EscrowError::RewardsMintAuthorityMismatch
+    )]
+    pub rewards_mint: Account<'info, Mint>,
+    /// CHECK: This is not dangerous because it is only used as the PDA signer for rewards
+    #[account(seeds = [b"rewards".as_ref(), &market_seed(config.market_id)], bump)]
+    pub rewards_authority: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetCollectionFeeOverride<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    #[account(
+        seeds = [b"config".as_ref(), &market_seed(config.market_id)],
+        bump = config.bump,
+        has_one = admin
+    )]
+    pub config: Account<'info, Config>,
+    pub collection_mint: Account<'info, Mint>,
+    #[account(
+        init_if_needed,
+        seeds = [
+            b"fee_override".as_ref(),
+            collection_mint.key().as_ref(),
+            &market_seed(config.market_id)
+        ],
+        bump,
+        payer = admin,
+        space = CollectionFeeOverride::space()
+    )]
+    pub collection_fee_override: Account<'info, CollectionFeeOverride>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RemoveCollectionFeeOverride<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    #[account(
+        seeds = [b"config".as_ref(), &market_seed(config.market_id)],
+        bump = config.bump,
+        has_one = admin
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        mut,
+        seeds = [
+            b"fee_override".as_ref(),
+            collection_fee_override.collection_mint.as_ref(),
+            &market_seed(config.market_id)
+        ],
+        bump = collection_fee_override.bump,
+        close = admin
+    )]
+    pub collection_fee_override: Account<'info, CollectionFeeOverride>,
+}
+
+#[derive(Accounts)]
+pub struct RegisterCollectionTreasury<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    #[account(
+        seeds = [b"config".as_ref(), &market_seed(config.market_id)],
+        bump = config.bump,
+        has_one = admin
+    )]
+    pub config: Account<'info, Config>,
+    pub collection_mint: Account<'info, Mint>,
+    /// CHECK: This is not dangerous because we only record its address as the DAO fee recipient
+    pub dao_treasury: AccountInfo<'info>,
+    #[account(
+        init_if_needed,
+        seeds = [
+            b"collection_treasury".as_ref(),
+            collection_mint.key().as_ref(),
+            &market_seed(config.market_id)
+        ],
+        bump,
+        payer = admin,
+        space = CollectionTreasury::space()
+    )]
+    pub collection_treasury: Account<'info, CollectionTreasury>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RemoveCollectionTreasury<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    #[account(
+        seeds = [b"config".as_ref(), &market_seed(config.market_id)],
+        bump = config.bump,
+        has_one = admin
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        mut,
+        seeds = [
+            b"collection_treasury".as_ref(),
+            collection_treasury.collection_mint.as_ref(),
+            &market_seed(config.market_id)
+        ],
+        bump = collection_treasury.bump,
+        close = admin
+    )]
+    pub collection_treasury: Account<'info, CollectionTreasury>,
+}
+
+#[derive(Accounts)]
+pub struct SetCollectionFloorAuthority<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    #[account(
+        seeds = [b"config".as_ref(), &market_seed(config.market_id)],
+        bump = config.bump,
+        has_one = admin
+    )]
+    pub config: Account<'info, Config>,
+    pub collection_mint: Account<'info, Mint>,
+    /// CHECK: This is not dangerous because we only record it as the key allowed to publish floors
+    pub floor_authority: AccountInfo<'info>,
+    #[account(
+        init_if_needed,
+        seeds = [
+            b"collection_floor".as_ref(),
+            collection_mint.key().as_ref(),
+            &market_seed(config.market_id)
+        ],
+        bump,
+        payer = admin,
+        space = CollectionFloor::space()
+    )]
+    pub collection_floor: Account<'info, CollectionFloor>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateCollectionFloor<'info> {
+    pub floor_authority: Signer<'info>,
+    /// Config of the market the floor belongs to.
+    #[account(seeds = [b"config".as_ref(), &market_seed(config.market_id)], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+    #[account(
+        mut,
+        seeds = [
+            b"collection_floor".as_ref(),
+            collection_floor.collection_mint.as_ref(),
+            &market_seed(config.market_id)
+        ],
+        bump = collection_floor.bump,
+        has_one = floor_authority
+    )]
+    pub collection_floor: Account<'info, CollectionFloor>,
+}
+
+#[derive(Accounts)]
+#[instruction(initializer_amount: u64)]
+pub struct Initialize<'info> {
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account(mut, constraint = initializer.lamports() >= initializer_amount)]
+    pub initializer: Signer<'info>,
+    /// Pays the rent of the listing's accounts: the initializer, or a relayer
+    /// sponsoring the listing so the seller parts with nothing but the NFT.
+    #[account(mut)]
+    pub rent_payer: Signer<'info>,
+    #[account(constraint = !vault_freezable(&mint) @ EscrowError::MintCanFreezeVault)]
+    pub mint: Account<'info, Mint>,
+
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    /// Each escrow gets its own vault authority so one escrow's signer can
+    /// never move tokens out of another escrow's vault.
+    #[account(
+        seeds = [b"authority".as_ref(), escrow_state.key().as_ref()],
+        bump,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+
+    #[account(
+        init,
+        payer = rent_payer,
+        associated_token::mint = mint,
+        associated_token::authority = vault_authority
+    )]
+    pub vault: Box<Account<'info, TokenAccount>>,
+
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account(mut)]
+    pub taker_key: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        constraint = &initializer_deposit_token_account.owner == initializer.key,
+        constraint = !initializer_deposit_token_account.is_frozen() @ EscrowError::DepositAccountFrozen
+    )]
+    pub initializer_deposit_token_account: Account<'info, TokenAccount>,
+
+    pub initializer_receive_mint_account: Account<'info, Mint>,
+
+    /// Config of the market to list in.
+    #[account(seeds = [b"config".as_ref(), &market_seed(config.market_id)], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    #[account(
+        init_if_needed,
+        seeds = [
+            b"listing_counter".as_ref(),
+            initializer.key().as_ref(),
+            &market_seed(config.market_id)
+        ],
+        bump,
+        payer = rent_payer,
+        space = ListingCounter::space()
+    )]
+    pub listing_counter: Box<Account<'info, ListingCounter>>,
+
+    #[account(
+        init,
+        seeds = [
+            b"state".as_ref(),
+            initializer.key().as_ref(),
+            &listing_counter.count.to_le_bytes(),
+            &market_seed(config.market_id)
+        ],
+        bump,
+        payer = rent_payer,
+        space = EscrowState::space()
+    )]
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+
+    /// CHECK: This is not dangerous because it is the mint's Token Metadata account, only read once it exists
+    #[account(address = metadata_address(&mint.key()) @ EscrowError::NftMetadataMismatch)]
+    pub nft_metadata: AccountInfo<'info>,
+
+    /// CHECK: This is not dangerous because `require_listable` checks it is the floor of the NFT's collection, only read once initialized
+    pub collection_floor: AccountInfo<'info>,
+
+    /// Required while the config limits how many listings a wallet makes.
+    #[account(
+        mut,
+        seeds = [
+            b"wallet_activity".as_ref(),
+            initializer.key().as_ref(),
+            &market_seed(config.market_id)
+        ],
+        bump = wallet_activity.bump
+    )]
+    pub wallet_activity: Option<Box<Account<'info, WalletActivity>>>,
+
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    pub system_program: Program<'info, System>,
+
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    pub token_program: Program<'info, Token>,
+
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeDelegated<'info> {
+    #[account(mut)]
+    pub initializer: Signer<'info>,
+    /// Pays the rent of the listing's accounts, as in `initialize`.
+    #[account(mut)]
+    pub rent_payer: Signer<'info>,
+    #[account(constraint = !vault_freezable(&mint) @ EscrowError::MintCanFreezeVault)]
+    pub mint: Account<'info, Mint>,
+
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    /// Becomes the delegate of the seller's token account and freezes it.
+    #[account(
+        seeds = [b"authority".as_ref(), escrow_state.key().as_ref()],
+        bump,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    pub taker_key: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        constraint = &initializer_deposit_token_account.owner == initializer.key,
+        constraint = initializer_deposit_token_account.mint == mint.key() @ EscrowError::EscrowMintMismatch,
+        constraint = initializer_deposit_token_account.amount == 1,
+        constraint = !initializer_deposit_token_account.is_frozen() @ EscrowError::DepositAccountFrozen
+    )]
+    pub initializer_deposit_token_account: Account<'info, TokenAccount>,
+
+    pub initializer_receive_mint_account: Account<'info, Mint>,
+
+    /// Config of the market to list in.
+    #[account(seeds = [b"config".as_ref(), &market_seed(config.market_id)], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    #[account(
+        init_if_needed,
+        seeds = [
+            b"listing_counter".as_ref(),
+            initializer.key().as_ref(),
+            &market_seed(config.market_id)
+        ],
+        bump,
+        payer = rent_payer,
+        space = ListingCounter::space()
+    )]
+    pub listing_counter: Box<Account<'info, ListingCounter>>,
+
+    #[account(
+        init,
+        seeds = [
+            b"state".as_ref(),
+            initializer.key().as_ref(),
+            &listing_counter.count.to_le_bytes(),
+            &market_seed(config.market_id)
+        ],
+        bump,
+        payer = rent_payer,
+        space = EscrowState::space()
+    )]
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+
+    /// CHECK: This is not dangerous because the token metadata program checks it is the mint's edition
+    pub nft_edition: AccountInfo<'info>,
+
+    /// CHECK: This is not dangerous because it is the mint's Token Metadata account, only read once it exists
+    #[account(address = metadata_address(&mint.key()) @ EscrowError::NftMetadataMismatch)]
+    pub nft_metadata: AccountInfo<'info>,
+
+    /// CHECK: This is not dangerous because `require_listable` checks it is the floor of the NFT's collection, only read once initialized
+    pub collection_floor: AccountInfo<'info>,
+
+    /// Required while the config limits how many listings a wallet makes.
+    #[account(
+        mut,
+        seeds = [
+            b"wallet_activity".as_ref(),
+            initializer.key().as_ref(),
+            &market_seed(config.market_id)
+        ],
+        bump = wallet_activity.bump
+    )]
+    pub wallet_activity: Option<Box<Account<'info, WalletActivity>>>,
+
+    pub token_metadata_program: Program<'info, Metadata>,
+
+    pub system_program: Program<'info, System>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+#[instruction(namespace: [u8; 32])]
+pub struct InitializeForIntegrator<'info> {
+    #[account(mut)]
+    pub initializer: Signer<'info>,
+    /// CHECK: This is not dangerous because only its PDA signer is trusted
+    #[account(executable)]
+    pub integrator_program: AccountInfo<'info>,
+    /// Proves the call comes from `integrator_program`.
+    #[account(
+        seeds = [b"escrow_integrator".as_ref()],
+        bump,
+        seeds::program = integrator_program.key()
+    )]
+    pub integrator_authority: Signer<'info>,
+    /// CHECK: This is not dangerous because we only record its address as the fee recipient
+    pub integrator_fee_recipient: AccountInfo<'info>,
+    #[account(constraint = !vault_freezable(&mint) @ EscrowError::MintCanFreezeVault)]
+    pub mint: Account<'info, Mint>,
+
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account(
+        seeds = [b"authority".as_ref(), escrow_state.key().as_ref()],
+        bump,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+
+    #[account(
+        init,
+        payer = initializer,
+        associated_token::mint = mint,
+        associated_token::authority = vault_authority
+    )]
+    pub vault: Box<Account<'info, TokenAccount>>,
+
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    pub taker_key: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        constraint = &initializer_deposit_token_account.owner == initializer.key,
+        constraint = !initializer_deposit_token_account.is_frozen() @ EscrowError::DepositAccountFrozen
+    )]
+    pub initializer_deposit_token_account: Account<'info, TokenAccount>,
+
+    pub initializer_receive_mint_account: Account<'info, Mint>,
+
+    /// Config of the market to list in.
+    #[account(seeds = [b"config".as_ref(), &market_seed(config.market_id)], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    #[account(
+        init,
+        seeds = [
+            b"integrated".as_ref(),
+            integrator_program.key().as_ref(),
+            namespace.as_ref(),
+            &market_seed(config.market_id)
+        ],
+        bump,
+        payer = initializer,
+        space = EscrowState::space()
+    )]
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+
+    /// CHECK: This is not dangerous because it is the mint's Token Metadata account, only read once it exists
+    #[account(address = metadata_address(&mint.key()) @ EscrowError::NftMetadataMismatch)]
+    pub nft_metadata: AccountInfo<'info>,
+
+    /// CHECK: This is not dangerous because `require_listable` checks it is the floor of the NFT's collection, only read once initialized
+    pub collection_floor: AccountInfo<'info>,
+
+    /// Required while the config limits how many listings a wallet makes.
+    #[account(
+        mut,
+        seeds = [
+            b"wallet_activity".as_ref(),
+            initializer.key().as_ref(),
+            &market_seed(config.market_id)
+        ],
+        bump = wallet_activity.bump
+    )]
+    pub wallet_activity: Option<Box<Account<'info, WalletActivity>>>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+pub struct Cancel<'info> {
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account(mut)]
+    pub initializer: Signer<'info>,
+    #[account(
+        constraint = mint.key() == escrow_state.initializer_deposit_mint_account @ EscrowError::EscrowMintMismatch
+    )]
+    pub mint: Account<'info, Mint>,
+    /// Not used by delegated listings, which have no vault.
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = vault_authority
+    )]
+    pub vault: Option<Account<'info, TokenAccount>>,
+    /// CHECK: This is not dangerous because it is checked to be the vault's address and only read for being closed
+    /// Passed instead of `vault` once the vault has been closed.
+    #[account(address = get_associated_token_address(vault_authority.key, &mint.key()))]
+    pub closed_vault: Option<UncheckedAccount<'info>>,
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account(
+        seeds = [b"authority".as_ref(), escrow_state.key().as_ref()],
+        bump = escrow_state.vault_authority_bump,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+    #[account(mut)]
+    pub initializer_deposit_token_account: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        constraint = escrow_state.initializer_key == *initializer.key,
+        constraint = escrow_state.initializer_deposit_token_account == *initializer_deposit_token_account.to_account_info().key,
+        constraint = !escrow_state.is_reserved() @ EscrowError::EscrowReserved,
+        constraint = !escrow_state.is_settling() @ EscrowError::EscrowSettling,
+        constraint = !escrow_state.in_payment_plan() @ EscrowError::PaymentPlanActive,
+        constraint = !escrow_state.in_rent_to_own() @ EscrowError::RentToOwnActive,
+        constraint = !escrow_state.on_loan() @ EscrowError::EscrowOnLoan,
+        constraint = !escrow_state.is_vesting() @ EscrowError::EscrowVesting,
+        constraint = !escrow_state.in_bnpl() @ EscrowError::BnplActive,
+        constraint = !escrow_state.paid_through_wormhole() @ EscrowError::WormholeEscrow
+    )]
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+    /// CHECK: This is not dangerous because the token metadata program checks it is the mint's edition
+    pub nft_edition: Option<AccountInfo<'info>>,
+    pub token_metadata_program: Option<Program<'info, Metadata>>,
+    /// Required once the escrow has an audit trail.
+    #[account(
+        mut,
+        seeds = [b"audit".as_ref(), escrow_state.key().as_ref()],
+        bump = audit_trail.bump
+    )]
+    pub audit_trail: Option<Box<Account<'info, AuditTrail>>>,
+    /// Required to attach a memo.
+    pub memo_program: Option<Program<'info, Memo>>,
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct GetQuote<'info> {
+    /// CHECK: This is not dangerous because it is only compared with the discount account owner
+    pub taker: AccountInfo<'info>,
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+    #[account(
+        seeds = [b"config".as_ref(), &market_seed(config.market_id)],
+        bump = config.bump,
+        constraint = config.market_id == escrow_state.market_id @ EscrowError::MarketMismatch
+    )]
+    pub config: Box<Account<'info, Config>>,
+    #[account(
+        constraint = discount_token_account.owner == taker.key() @ EscrowError::DiscountAccountOwnerMismatch,
+        constraint = discount_token_account.mint == config.discount_mint @ EscrowError::DiscountAccountMintMismatch
+    )]
+    pub discount_token_account: Option<Box<Account<'info, TokenAccount>>>,
+    /// CHECK: This is not dangerous because it is the fee override PDA of the listing's collection, only read once initialized
+    #[account(address = collection_fee_override_address(&escrow_state.collection, config.market_id) @ EscrowError::FeeOverrideMismatch)]
+    pub collection_fee_override: AccountInfo<'info>,
+    /// CHECK: This is not dangerous because it is checked against the escrow's price feed and parsed by the Pyth SDK
+    #[account(address = escrow_state.price_feed @ EscrowError::PriceFeedMismatch)]
+    pub price_feed: Option<AccountInfo<'info>>,
+}
+
+#[derive(Accounts)]
+pub struct Reserve<'info> {
+    #[account(mut)]
+    pub taker: Signer<'info>,
+    #[account(
+        mut,
+        constraint = !escrow_state.is_reserved() @ EscrowError::EscrowReserved,
+        constraint = !escrow_state.is_settling() @ EscrowError::EscrowSettling,
+        constraint = !escrow_state.in_payment_plan() @ EscrowError::PaymentPlanActive,
+        constraint = !escrow_state.in_rent_to_own() @ EscrowError::RentToOwnActive,
+        constraint = !escrow_state.on_loan() @ EscrowError::EscrowOnLoan,
+        constraint = !escrow_state.is_vesting() @ EscrowError::EscrowVesting,
+        constraint = !escrow_state.in_bnpl() @ EscrowError::BnplActive,
+        constraint = !escrow_state.is_printing_editions() @ EscrowError::PrintingEditions,
+        constraint = !escrow_state.is_gift() @ EscrowError::GiftEscrow,
+        constraint = !escrow_state.is_htlc() @ EscrowError::HtlcEscrow,
+        constraint = !escrow_state.paid_through_wormhole() @ EscrowError::WormholeEscrow,
+        constraint = !escrow_state.in_ring() @ EscrowError::RingLeg,
+        constraint = !escrow_state.fills_privately() @ EscrowError::PrivateFillsOnly,
+        constraint = !escrow_state.has_terms() @ EscrowError::TermsNotAcknowledged,
+        constraint = !escrow_state.awaiting_deposit @ EscrowError::ListingNotDeposited
+    )]
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+    #[account(
+        seeds = [b"config".as_ref(), &market_seed(config.market_id)],
+        bump = config.bump,
+        constraint = config.market_id == escrow_state.market_id @ EscrowError::MarketMismatch
+    )]
+    pub config: Box<Account<'info, Config>>,
+    #[account(
+        constraint = discount_token_account.owner == taker.key() @ EscrowError::DiscountAccountOwnerMismatch,
+        constraint = discount_token_account.mint == config.discount_mint @ EscrowError::DiscountAccountMintMismatch
+    )]
+    pub discount_token_account: Option<Box<Account<'info, TokenAccount>>>,
+    /// CHECK: This is not dangerous because it is the fee override PDA of the listing's collection, only read once initialized
+    #[account(address = collection_fee_override_address(&escrow_state.collection, config.market_id) @ EscrowError::FeeOverrideMismatch)]
+    pub collection_fee_override: AccountInfo<'info>,
+    /// CHECK: This is not dangerous because it is checked against the escrow's price feed and parsed by the Pyth SDK
+    #[account(address = escrow_state.price_feed @ EscrowError::PriceFeedMismatch)]
+    pub price_feed: Option<AccountInfo<'info>>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct Abort<'info> {
+    #[account(
+        constraint = signer.key() == escrow_state.reserved_by
+            || signer.key() == escrow_state.initializer_key @ EscrowError::AbortNotAllowed
+    )]
+    pub signer: Signer<'info>,
+    /// CHECK: This is not dangerous because it is checked against the reservation and only receives lamports
+    #[account(mut, address = escrow_state.reserved_by @ EscrowError::ReservationMismatch)]
+    pub taker: AccountInfo<'info>,
+    /// CHECK: This is not dangerous because it is checked against the escrow and only receives lamports
+    #[account(mut, address = escrow_state.initializer_key)]
+    pub initializer: AccountInfo<'info>,
+    #[account(
+        mut,
+        constraint = escrow_state.is_reserved() @ EscrowError::NotReserved
+    )]
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+}
+
+/// Accounts of a plain fill. Whatever only some listings or takers need is
+/// passed after them as remaining accounts and looked up by address: the
+/// donation and integrator fee recipients, the DAO treasury, the price feed,
+/// the memo program, the rewards mint and authority with both parties'
+/// rewards ATAs, the settlement vault, a delegated listing's token account,
+/// edition and the token metadata program, the taker's gate ATA or token
+/// owner record, and, to pay in wSOL, the taker's wSOL ATA, the native mint
+/// and every recipient's wSOL ATA.
+#[derive(Accounts)]
+pub struct Exchange<'info> {
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account(mut)]
+    pub taker: Signer<'info>,
+    #[account(
+        mut,
+        constraint = initializer_deposit_token_mint.key() == escrow_state.initializer_deposit_mint_account @ EscrowError::EscrowMintMismatch
+    )]
+    pub initializer_deposit_token_mint: Account<'info, Mint>,
+    #[account(
+        mut,
+        constraint = taker_deposit_token_mint.key() == escrow_state.initializer_receive_mint_account @ EscrowError::EscrowMintMismatch
+    )]
+    pub taker_deposit_token_mint: Account<'info, Mint>,
+    #[account(mut, constraint = &taker_deposit_token_account.owner == taker.key)]
+    pub taker_deposit_token_account: Box<Account<'info, TokenAccount>>,
+    #[account(
+        init_if_needed,
+        payer = taker,
+        associated_token::mint = initializer_deposit_token_mint,
+        associated_token::authority = taker
+    )]
+    pub taker_receive_token_account: Box<Account<'info, TokenAccount>>,
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account(mut)]
+    pub initializer: AccountInfo<'info>,
+    #[account(
+        init_if_needed,
+        payer = taker,
+        associated_token::mint = taker_deposit_token_mint,
+        associated_token::authority = initializer
+    )]
+    pub initializer_receive_token_account: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        constraint = escrow_state.initializer_receive_token_account == *initializer_receive_token_account.to_account_info().key,
+        constraint = escrow_state.initializer_key == *initializer.key,
+        // Closed by the handler, since a delayed settlement keeps it open.
+    )]
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+    /// Not used by delegated listings, which have no vault.
+    #[account(
+        mut,
+        associated_token::mint = initializer_deposit_token_mint,
+        associated_token::authority = vault_authority
+    )]
+    pub vault: Option<Box<Account<'info, TokenAccount>>>,
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account(
+        seeds = [b"authority".as_ref(), escrow_state.key().as_ref()],
+        bump = escrow_state.vault_authority_bump,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+    #[account(
+        seeds = [b"config".as_ref(), &market_seed(config.market_id)],
+        bump = config.bump,
+        constraint = config.market_id == escrow_state.market_id @ EscrowError::MarketMismatch
+    )]
+    pub config: Box<Account<'info, Config>>,
+    /// CHECK: This is not dangerous because it is checked against the config treasury
+    #[account(mut, address = config.treasury)]
+    pub treasury: AccountInfo<'info>,
+    /// CHECK: This is not dangerous because it only receives lamports
+    #[account(mut)]
+    pub referrer: Option<AccountInfo<'info>>,
+    #[account(
+        constraint = discount_token_account.owner == taker.key() @ EscrowError::DiscountAccountOwnerMismatch,
+        constraint = discount_token_account.mint == config.discount_mint @ EscrowError::DiscountAccountMintMismatch
+    )]
+    pub discount_token_account: Option<Box<Account<'info, TokenAccount>>>,
+    /// CHECK: This is not dangerous because it is the fee override PDA of the listing's collection, only read once initialized
+    #[account(address = collection_fee_override_address(&escrow_state.collection, config.market_id) @ EscrowError::FeeOverrideMismatch)]
+    pub collection_fee_override: AccountInfo<'info>,
+    /// CHECK: This is not dangerous because it is the DAO treasury entry of the listing's collection, only read once initialized
+    #[account(address = collection_treasury_address(&escrow_state.collection, config.market_id) @ EscrowError::CollectionTreasuryMismatch)]
+    pub collection_treasury: AccountInfo<'info>,
+    /// Required while the config limits how many fills a wallet makes.
+    #[account(
+        mut,
+        seeds = [
+            b"wallet_activity".as_ref(),
+            taker.key().as_ref(),
+            &market_seed(config.market_id)
+        ],
+        bump = taker_activity.bump
+    )]
+    pub taker_activity: Option<Box<Account<'info, WalletActivity>>>,
+    /// Required once the escrow has an audit trail.
+    #[account(
+        mut,
+        seeds = [b"audit".as_ref(), escrow_state.key().as_ref()],
+        bump = audit_trail.bump
+    )]
+    pub audit_trail: Option<Box<Account<'info, AuditTrail>>>,
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CompletePaymentPlan<'info> {
+    pub exchange: Exchange<'info>,
+    /// The taker's installments, closed back to them once the NFT is released.
+    #[account(
+        mut,
+        seeds = [b"payment_plan".as_ref(), exchange.escrow_state.key().as_ref()],
+        bump = payment_plan.bump
+    )]
+    pub payment_plan: Box<Account<'info, PaymentPlan>>,
+}
+
+#[derive(Accounts)]
+pub struct ExchangeEdition<'info> {
+    pub exchange: Exchange<'info>,
+    pub print: PrintEdition<'info>,
+}
+
+/// What the token metadata program needs to print an edition of the vaulted
+/// master edition, which checks them against it.
+#[derive(Accounts)]
+pub struct PrintEdition<'info> {
+    #[account(mut)]
+    pub master_edition: Box<Account<'info, MasterEditionAccount>>,
+    pub nft_metadata: Box<Account<'info, MetadataAccount>>,
+    /// CHECK: This is not dangerous because the token metadata program creates it
+    #[account(mut)]
+    pub new_edition_metadata: AccountInfo<'info>,
+    /// CHECK: This is not dangerous because the token metadata program creates it
+    #[account(mut)]
+    pub new_edition: AccountInfo<'info>,
+    /// The taker's freshly minted edition token, with the taker as mint authority.
+    #[account(mut)]
+    pub new_edition_mint: Box<Account<'info, Mint>>,
+    /// CHECK: This is not dangerous because the token metadata program checks it against the edition number
+    #[account(mut)]
+    pub edition_marker: AccountInfo<'info>,
+    pub token_metadata_program: Program<'info, Metadata>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct Settle<'info> {
+    pub authority: Signer<'info>,
+    /// CHECK: This is not dangerous because it is checked against the escrow and only receives lamports
+    #[account(mut, address = escrow_state.initializer_key)]
+    pub initializer: AccountInfo<'info>,
+    /// CHECK: This is not dangerous because it is checked against the escrow and only receives lamports
+    #[account(mut, address = escrow_state.pending_taker)]
+    pub taker: AccountInfo<'info>,
+    #[account(address = escrow_state.initializer_deposit_mint_account @ EscrowError::EscrowMintMismatch)]
+    pub initializer_deposit_token_mint: Box<Account<'info, Mint>>,
+    #[account(address = escrow_state.initializer_receive_mint_account @ EscrowError::EscrowMintMismatch)]
+    pub taker_deposit_token_mint: Box<Account<'info, Mint>>,
+    #[account(
+        mut,
+        associated_token::mint = initializer_deposit_token_mint,
+        associated_token::authority = vault_authority
+    )]
+    pub vault: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        associated_token::mint = taker_deposit_token_mint,
+        associated_token::authority = vault_authority
+    )]
+    pub settlement_vault: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        associated_token::mint = initializer_deposit_token_mint,
+        associated_token::authority = taker
+    )]
+    pub taker_receive_token_account: Box<Account<'info, TokenAccount>>,
+    #[account(mut, address = escrow_state.initializer_receive_token_account)]
+    pub initializer_receive_token_account: Box<Account<'info, TokenAccount>>,
+    /// Refund destinations.
+    #[account(mut, address = escrow_state.initializer_deposit_token_account)]
+    pub initializer_deposit_token_account: Box<Account<'info, TokenAccount>>,
+    #[account(mut, address = escrow_state.pending_taker_deposit_token_account)]
+    pub taker_deposit_token_account: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        constraint = escrow_state.is_settling() @ EscrowError::NotSettling
+    )]
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account(
+        seeds = [b"authority".as_ref(), escrow_state.key().as_ref()],
+        bump = escrow_state.vault_authority_bump,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+    #[account(
+        seeds = [b"config".as_ref(), &market_seed(config.market_id)],
+        bump = config.bump,
+        constraint = config.market_id == escrow_state.market_id @ EscrowError::MarketMismatch
+    )]
+    pub config: Box<Account<'info, Config>>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct Dispute<'info> {
+    pub authority: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_state.is_settling() @ EscrowError::NotSettling
+    )]
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+}
+
+#[derive(Accounts)]
+pub struct OfferPaymentPlan<'info> {
+    pub initializer: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_state.initializer_key == *initializer.key,
+        constraint = !escrow_state.in_payment_plan() @ EscrowError::PaymentPlanActive
+    )]
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+}
+
+#[derive(Accounts)]
+pub struct StartPaymentPlan<'info> {
+    #[account(mut)]
+    pub taker: Signer<'info>,
+    #[account(
+        mut,
+        constraint = !escrow_state.is_reserved() @ EscrowError::EscrowReserved,
+        constraint = !escrow_state.is_settling() @ EscrowError::EscrowSettling,
+        constraint = !escrow_state.in_payment_plan() @ EscrowError::PaymentPlanActive,
+        constraint = !escrow_state.in_rent_to_own() @ EscrowError::RentToOwnActive,
+        constraint = !escrow_state.on_loan() @ EscrowError::EscrowOnLoan,
+        constraint = !escrow_state.is_vesting() @ EscrowError::EscrowVesting,
+        constraint = !escrow_state.in_bnpl() @ EscrowError::BnplActive,
+        constraint = !escrow_state.is_printing_editions() @ EscrowError::PrintingEditions,
+        constraint = !escrow_state.is_gift() @ EscrowError::GiftEscrow,
+        constraint = !escrow_state.is_htlc() @ EscrowError::HtlcEscrow,
+        constraint = !escrow_state.paid_through_wormhole() @ EscrowError::WormholeEscrow,
+        constraint = !escrow_state.in_ring() @ EscrowError::RingLeg,
+        constraint = !escrow_state.fills_privately() @ EscrowError::PrivateFillsOnly,
+        constraint = !escrow_state.has_terms() @ EscrowError::TermsNotAcknowledged,
+        constraint = !escrow_state.awaiting_deposit @ EscrowError::ListingNotDeposited
+    )]
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+    #[account(
+        init,
+        payer = taker,
+        space = PaymentPlan::space(),
+        seeds = [b"payment_plan".as_ref(), escrow_state.key().as_ref()],
+        bump
+    )]
+    pub payment_plan: Box<Account<'info, PaymentPlan>>,
+    #[account(
+        seeds = [b"config".as_ref(), &market_seed(config.market_id)],
+        bump = config.bump,
+        constraint = config.market_id == escrow_state.market_id @ EscrowError::MarketMismatch
+    )]
+    pub config: Box<Account<'info, Config>>,
+    #[account(
+        constraint = discount_token_account.owner == taker.key() @ EscrowError::DiscountAccountOwnerMismatch,
+        constraint = discount_token_account.mint == config.discount_mint @ EscrowError::DiscountAccountMintMismatch
+    )]
+    pub discount_token_account: Option<Box<Account<'info, TokenAccount>>>,
+    /// CHECK: This is not dangerous because it is the fee override PDA of the listing's collection, only read once initialized
+    #[account(address = collection_fee_override_address(&escrow_state.collection, config.market_id) @ EscrowError::FeeOverrideMismatch)]
+    pub collection_fee_override: AccountInfo<'info>,
+    /// CHECK: This is not dangerous because it is checked against the escrow's price feed and parsed by the Pyth SDK
+    #[account(address = escrow_state.price_feed @ EscrowError::PriceFeedMismatch)]
+    pub price_feed: Option<AccountInfo<'info>>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct PayInstallment<'info> {
+    #[account(mut)]
+    pub taker: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"payment_plan".as_ref(), payment_plan.escrow_state.as_ref()],
+        bump = payment_plan.bump,
+        constraint = payment_plan.taker == taker.key() @ EscrowError::PaymentPlanMismatch
+    )]
+    pub payment_plan: Box<Account<'info, PaymentPlan>>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct OfferRentToOwn<'info> {
+    pub initializer: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_state.initializer_key == *initializer.key,
+        constraint = !escrow_state.delegated @ EscrowError::RentToOwnDelegated,
+        constraint = !escrow_state.in_rent_to_own() @ EscrowError::RentToOwnActive
+    )]
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+}
+
+#[derive(Accounts)]
+pub struct StartRentToOwn<'info> {
+    #[account(mut)]
+    pub renter: Signer<'info>,
+    /// CHECK: This is not dangerous because it is checked against the escrow and only receives lamports
+    #[account(mut, address = escrow_state.initializer_key)]
+    pub initializer: AccountInfo<'info>,
+    #[account(
+        constraint = mint.key() == escrow_state.initializer_deposit_mint_account @ EscrowError::EscrowMintMismatch
+    )]
+    pub mint: Box<Account<'info, Mint>>,
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = vault_authority
+    )]
+    pub vault: Box<Account<'info, TokenAccount>>,
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account(
+        seeds = [b"authority".as_ref(), escrow_state.key().as_ref()],
+        bump = escrow_state.vault_authority_bump,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+    #[account(
+        init_if_needed,
+        payer = renter,
+        associated_token::mint = mint,
+        associated_token::authority = renter
+    )]
+    pub renter_token_account: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        constraint = escrow_state.rent_to_own_payment > 0 @ EscrowError::RentToOwnNotOffered,
+        constraint = !escrow_state.is_reserved() @ EscrowError::EscrowReserved,
+        constraint = !escrow_state.is_settling() @ EscrowError::EscrowSettling,
+        constraint = !escrow_state.in_payment_plan() @ EscrowError::PaymentPlanActive,
+        constraint = !escrow_state.in_rent_to_own() @ EscrowError::RentToOwnActive,
+        constraint = !escrow_state.on_loan() @ EscrowError::EscrowOnLoan,
+        constraint = !escrow_state.is_vesting() @ EscrowError::EscrowVesting,
+        constraint = !escrow_state.in_bnpl() @ EscrowError::BnplActive,
+        constraint = !escrow_state.is_printing_editions() @ EscrowError::PrintingEditions,
+        constraint = !escrow_state.is_gift() @ EscrowError::GiftEscrow,
+        constraint = !escrow_state.is_htlc() @ EscrowError::HtlcEscrow,
+        constraint = !escrow_state.paid_through_wormhole() @ EscrowError::WormholeEscrow,
+        constraint = !escrow_state.in_ring() @ EscrowError::RingLeg,
+        constraint = !escrow_state.fills_privately() @ EscrowError::PrivateFillsOnly,
+        constraint = !escrow_state.has_terms() @ EscrowError::TermsNotAcknowledged,
+        constraint = !escrow_state.awaiting_deposit @ EscrowError::ListingNotDeposited
+    )]
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+    #[account(
+        init,
+        payer = renter,
+        space = RentToOwn::space(),
+        seeds = [b"rent_to_own".as_ref(), escrow_state.key().as_ref()],
+        bump
+    )]
+    pub rent_to_own: Box<Account<'info, RentToOwn>>,
+    #[account(
+        seeds = [b"config".as_ref(), &market_seed(config.market_id)],
+        bump = config.bump,
+        constraint = config.market_id == escrow_state.market_id @ EscrowError::MarketMismatch
+    )]
+    pub config: Box<Account<'info, Config>>,
+    /// CHECK: This is not dangerous because the token metadata program checks it is the mint's edition
+    pub nft_edition: AccountInfo<'info>,
+    pub token_metadata_program: Program<'info, Metadata>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct PayRent<'info> {
+    #[account(mut)]
+    pub renter: Signer<'info>,
+    /// CHECK: This is not dangerous because it is checked against the escrow and only receives lamports
+    #[account(mut, address = escrow_state.initializer_key)]
+    pub initializer: AccountInfo<'info>,
+    #[account(
+        constraint = mint.key() == escrow_state.initializer_deposit_mint_account @ EscrowError::EscrowMintMismatch
+    )]
+    pub mint: Box<Account<'info, Mint>>,
+    #[account(mut, address = rent_to_own.renter_token_account)]
+    pub renter_token_account: Box<Account<'info, TokenAccount>>,
+    #[account(mut)]
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+    #[account(
+        mut,
+        seeds = [b"rent_to_own".as_ref(), escrow_state.key().as_ref()],
+        bump = rent_to_own.bump,
+        constraint = rent_to_own.renter == renter.key() @ EscrowError::RentToOwnMismatch
+    )]
+    pub rent_to_own: Box<Account<'info, RentToOwn>>,
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account(
+        seeds = [b"authority".as_ref(), escrow_state.key().as_ref()],
+        bump = escrow_state.vault_authority_bump,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+    /// CHECK: This is not dangerous because the token metadata program checks it is the mint's edition
+    pub nft_edition: AccountInfo<'info>,
+    pub token_metadata_program: Program<'info, Metadata>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct Repossess<'info> {
+    #[account(mut, address = escrow_state.initializer_key)]
+    pub initializer: Signer<'info>,
+    /// CHECK: This is not dangerous because it is checked against the rental and only receives lamports
+    #[account(mut, address = rent_to_own.renter @ EscrowError::RentToOwnMismatch)]
+    pub renter: AccountInfo<'info>,
+    #[account(
+        constraint = mint.key() == escrow_state.initializer_deposit_mint_account @ EscrowError::EscrowMintMismatch
+    )]
+    pub mint: Box<Account<'info, Mint>>,
+    #[account(mut, address = rent_to_own.renter_token_account)]
+    pub renter_token_account: Box<Account<'info, TokenAccount>>,
+    #[account(mut, address = escrow_state.initializer_deposit_token_account)]
+    pub initializer_deposit_token_account: Box<Account<'info, TokenAccount>>,
+    #[account(mut)]
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+    #[account(
+        mut,
+        seeds = [b"rent_to_own".as_ref(), escrow_state.key().as_ref()],
+        bump = rent_to_own.bump,
+        close = renter
+    )]
+    pub rent_to_own: Box<Account<'info, RentToOwn>>,
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account(
+        seeds = [b"authority".as_ref(), escrow_state.key().as_ref()],
+        bump = escrow_state.vault_authority_bump,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+    /// CHECK: This is not dangerous because the token metadata program checks it is the mint's edition
+    pub nft_edition: AccountInfo<'info>,
+    pub token_metadata_program: Program<'info, Metadata>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ConfigureVesting<'info> {
+    pub initializer: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_state.initializer_key == *initializer.key,
+        constraint = !escrow_state.is_vesting() @ EscrowError::EscrowVesting
+    )]
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimVested<'info> {
+    #[account(mut)]
+    pub initializer: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_state.initializer_key == *initializer.key,
+        constraint = escrow_state.is_vesting() @ EscrowError::NotVesting
+    )]
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+}
+
+#[derive(Accounts)]
+pub struct ConfigureEditionPrinting<'info> {
+    pub initializer: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_state.initializer_key == *initializer.key,
+        constraint = !escrow_state.delegated @ EscrowError::EditionPrintingDelegated,
+        constraint = !escrow_state.awaiting_deposit @ EscrowError::ListingNotDeposited
+    )]
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+}
+
+#[derive(Accounts)]
+pub struct SetPrivateFills<'info> {
+    pub initializer: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_state.initializer_key == *initializer.key
+    )]
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateTerms<'info> {
+    pub initializer: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_state.initializer_key == *initializer.key
+    )]
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+}
+
+#[derive(Accounts)]
+pub struct SetTerms<'info> {
+    pub initializer: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_state.initializer_key == *initializer.key
+    )]
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+}
+
+#[derive(Accounts)]
+pub struct SetListingTag<'info> {
+    pub initializer: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_state.initializer_key == *initializer.key
+    )]
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+}
+
+#[derive(Accounts)]
+pub struct SetPurchaseGate<'info> {
+    pub initializer: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_state.initializer_key == *initializer.key
+    )]
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+}
+
+#[derive(Accounts)]
+pub struct OfferGift<'info> {
+    pub initializer: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_state.initializer_key == *initializer.key,
+        constraint = escrow_state.taker_amount == 0 && escrow_state.price_usd_cents == 0 @ EscrowError::InvalidGift,
+        constraint = !escrow_state.delegated @ EscrowError::GiftDelegated,
+        constraint = !escrow_state.is_htlc() @ EscrowError::HtlcEscrow,
+        constraint = !escrow_state.paid_through_wormhole() @ EscrowError::WormholeEscrow,
+        constraint = !escrow_state.in_ring() @ EscrowError::RingLeg,
+        constraint = !escrow_state.is_reserved() @ EscrowError::EscrowReserved,
+        constraint = !escrow_state.is_settling() @ EscrowError::EscrowSettling,
+        constraint = !escrow_state.in_payment_plan() @ EscrowError::PaymentPlanActive,
+        constraint = !escrow_state.in_rent_to_own() @ EscrowError::RentToOwnActive,
+        constraint = !escrow_state.on_loan() @ EscrowError::EscrowOnLoan,
+        constraint = !escrow_state.is_vesting() @ EscrowError::EscrowVesting,
+        constraint = !escrow_state.in_bnpl() @ EscrowError::BnplActive,
+        constraint = !escrow_state.is_printing_editions() @ EscrowError::PrintingEditions,
+        constraint = !escrow_state.awaiting_deposit @ EscrowError::ListingNotDeposited
+    )]
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimGift<'info> {
+    #[account(mut, address = escrow_state.gift_recipient @ EscrowError::NotGiftRecipient)]
+    pub recipient: Signer<'info>,
+    /// CHECK: This is not dangerous because it is checked against the escrow and only receives rent
+    #[account(mut, address = escrow_state.initializer_key)]
+    pub initializer: AccountInfo<'info>,
+    #[account(address = escrow_state.initializer_deposit_mint_account @ EscrowError::EscrowMintMismatch)]
+    pub mint: Box<Account<'info, Mint>>,
+    #[account(
+        mut,
+        constraint = escrow_state.is_gift() @ EscrowError::NotGift,
+        constraint = !escrow_state.is_reserved() @ EscrowError::EscrowReserved,
+        constraint = !escrow_state.is_settling() @ EscrowError::EscrowSettling,
+        constraint = !escrow_state.in_payment_plan() @ EscrowError::PaymentPlanActive,
+        constraint = !escrow_state.in_rent_to_own() @ EscrowError::RentToOwnActive,
+        constraint = !escrow_state.on_loan() @ EscrowError::EscrowOnLoan,
+        constraint = !escrow_state.is_vesting() @ EscrowError::EscrowVesting,
+        constraint = !escrow_state.in_bnpl() @ EscrowError::BnplActive,
+        constraint = !escrow_state.is_printing_editions() @ EscrowError::PrintingEditions,
+        constraint = !escrow_state.awaiting_deposit @ EscrowError::ListingNotDeposited
+    )]
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account(
+        seeds = [b"authority".as_ref(), escrow_state.key().as_ref()],
+        bump = escrow_state.vault_authority_bump,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = vault_authority
+    )]
+    pub vault: Box<Account<'info, TokenAccount>>,
+    #[account(
+        init_if_needed,
+        payer = recipient,
+        associated_token::mint = mint,
+        associated_token::authority = recipient
+    )]
+    pub recipient_token_account: Box<Account<'info, TokenAccount>>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+pub struct ReturnGift<'info> {
+    /// CHECK: This is not dangerous because it is checked against the escrow and only receives the gift back
+    #[account(mut, address = escrow_state.initializer_key)]
+    pub initializer: AccountInfo<'info>,
+    #[account(address = escrow_state.initializer_deposit_mint_account @ EscrowError::EscrowMintMismatch)]
+    pub mint: Box<Account<'info, Mint>>,
+    #[account(mut, address = escrow_state.initializer_deposit_token_account)]
+    pub initializer_deposit_token_account: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        constraint = escrow_state.is_gift() @ EscrowError::NotGift
+    )]
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account(
+        seeds = [b"authority".as_ref(), escrow_state.key().as_ref()],
+        bump = escrow_state.vault_authority_bump,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = vault_authority
+    )]
+    pub vault: Box<Account<'info, TokenAccount>>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct RequestLoan<'info> {
+    pub initializer: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_state.initializer_key == *initializer.key,
+        constraint = !escrow_state.delegated @ EscrowError::DelegatedLoan,
+        constraint = !escrow_state.on_loan() @ EscrowError::EscrowOnLoan,
+        constraint = !escrow_state.awaiting_deposit @ EscrowError::ListingNotDeposited
+    )]
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+}
+
+#[derive(Accounts)]
+pub struct FundLoan<'info> {
+    #[account(mut)]
+    pub lender: Signer<'info>,
+    /// CHECK: This is not dangerous because it is checked against the escrow and only receives lamports
+    #[account(mut, address = escrow_state.initializer_key)]
+    pub borrower: AccountInfo<'info>,
+    #[account(
+        mut,
+        constraint = escrow_state.loan_principal > 0 @ EscrowError::LoanNotRequested,
+        constraint = !escrow_state.is_reserved() @ EscrowError::EscrowReserved,
+        constraint = !escrow_state.is_settling() @ EscrowError::EscrowSettling,
+        constraint = !escrow_state.in_payment_plan() @ EscrowError::PaymentPlanActive,
+        constraint = !escrow_state.in_rent_to_own() @ EscrowError::RentToOwnActive,
+        constraint = !escrow_state.on_loan() @ EscrowError::EscrowOnLoan,
+        constraint = !escrow_state.is_vesting() @ EscrowError::EscrowVesting,
+        constraint = !escrow_state.in_bnpl() @ EscrowError::BnplActive,
+        constraint = !escrow_state.is_printing_editions() @ EscrowError::PrintingEditions,
+        constraint = !escrow_state.is_gift() @ EscrowError::GiftEscrow,
+        constraint = !escrow_state.is_htlc() @ EscrowError::HtlcEscrow,
+        constraint = !escrow_state.paid_through_wormhole() @ EscrowError::WormholeEscrow,
+        constraint = !escrow_state.in_ring() @ EscrowError::RingLeg,
+        constraint = !escrow_state.awaiting_deposit @ EscrowError::ListingNotDeposited
+    )]
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+    #[account(
+        init,
+        payer = lender,
+        space = LoanState::space(),
+        seeds = [b"loan".as_ref(), escrow_state.key().as_ref()],
+        bump
+    )]
+    pub loan_state: Box<Account<'info, LoanState>>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct Repay<'info> {
+    #[account(mut, address = loan_state.borrower)]
+    pub borrower: Signer<'info>,
+    /// CHECK: This is not dangerous because it is checked against the loan and only receives lamports
+    #[account(mut, address = loan_state.lender)]
+    pub lender: AccountInfo<'info>,
+    #[account(mut)]
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+    #[account(
+        mut,
+        seeds = [b"loan".as_ref(), escrow_state.key().as_ref()],
+        bump = loan_state.bump,
+        close = lender
+    )]
+    pub loan_state: Box<Account<'info, LoanState>>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct Liquidate<'info> {
+    #[account(mut, address = loan_state.lender)]
+    pub lender: Signer<'info>,
+    /// CHECK: This is not dangerous because it is checked against the loan and only receives lamports
+    #[account(mut, address = loan_state.borrower)]
+    pub borrower: AccountInfo<'info>,
+    #[account(
+        constraint = mint.key() == escrow_state.initializer_deposit_mint_account @ EscrowError::EscrowMintMismatch
+    )]
+    pub mint: Box<Account<'info, Mint>>,
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = vault_authority
+    )]
+    pub vault: Box<Account<'info, TokenAccount>>,
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account(
+        seeds = [b"authority".as_ref(), escrow_state.key().as_ref()],
+        bump = escrow_state.vault_authority_bump,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+    #[account(
+        init_if_needed,
+        payer = lender,
+        associated_token::mint = mint,
+        associated_token::authority = lender
+    )]
+    pub lender_token_account: Box<Account<'info, TokenAccount>>,
+    #[account(mut)]
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+    #[account(
+        mut,
+        seeds = [b"loan".as_ref(), escrow_state.key().as_ref()],
+        bump = loan_state.bump,
+        close = lender
+    )]
+    pub loan_state: Box<Account<'info, LoanState>>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct OfferBnpl<'info> {
+    pub initializer: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_state.initializer_key == *initializer.key,
+        constraint = !escrow_state.delegated @ EscrowError::BnplDelegated,
+        constraint = !escrow_state.in_bnpl() @ EscrowError::BnplActive,
+        constraint = !escrow_state.is_printing_editions() @ EscrowError::PrintingEditions,
+        constraint = !escrow_state.is_gift() @ EscrowError::GiftEscrow,
+        constraint = !escrow_state.is_htlc() @ EscrowError::HtlcEscrow,
+        constraint = !escrow_state.paid_through_wormhole() @ EscrowError::WormholeEscrow,
+        constraint = !escrow_state.in_ring() @ EscrowError::RingLeg
+    )]
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+}
+
+#[derive(Accounts)]
+pub struct StartBnpl<'info> {
+    #[account(mut)]
+    pub buyer: Signer<'info>,
+    #[account(
+        constraint = mint.key() == escrow_state.initializer_deposit_mint_account @ EscrowError::EscrowMintMismatch
+    )]
+    pub mint: Box<Account<'info, Mint>>,
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = vault_authority
+    )]
+    pub vault: Box<Account<'info, TokenAccount>>,
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account(
+        seeds = [b"authority".as_ref(), escrow_state.key().as_ref()],
+        bump = escrow_state.vault_authority_bump,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+    #[account(
+        init_if_needed,
+        payer = buyer,
+        associated_token::mint = mint,
+        associated_token::authority = buyer
+    )]
+    pub buyer_token_account: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        constraint = !escrow_state.is_reserved() @ EscrowError::EscrowReserved,
+        constraint = !escrow_state.is_settling() @ EscrowError::EscrowSettling,
+        constraint = !escrow_state.in_payment_plan() @ EscrowError::PaymentPlanActive,
+        constraint = !escrow_state.in_rent_to_own() @ EscrowError::RentToOwnActive,
+        constraint = !escrow_state.on_loan() @ EscrowError::EscrowOnLoan,
+        constraint = !escrow_state.is_vesting() @ EscrowError::EscrowVesting,
+        constraint = !escrow_state.in_bnpl() @ EscrowError::BnplActive,
+        constraint = !escrow_state.is_printing_editions() @ EscrowError::PrintingEditions,
+        constraint = !escrow_state.is_gift() @ EscrowError::GiftEscrow,
+        constraint = !escrow_state.is_htlc() @ EscrowError::HtlcEscrow,
+        constraint = !escrow_state.paid_through_wormhole() @ EscrowError::WormholeEscrow,
+        constraint = !escrow_state.in_ring() @ EscrowError::RingLeg,
+        constraint = !escrow_state.fills_privately() @ EscrowError::PrivateFillsOnly,
+        constraint = !escrow_state.has_terms() @ EscrowError::TermsNotAcknowledged,
+        constraint = !escrow_state.awaiting_deposit @ EscrowError::ListingNotDeposited
+    )]
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+    #[account(
+        init,
+        payer = buyer,
+        space = BnplPurchase::space(),
+        seeds = [b"bnpl".as_ref(), escrow_state.key().as_ref()],
+        bump
+    )]
+    pub bnpl_purchase: Box<Account<'info, BnplPurchase>>,
+    #[account(
+        seeds = [b"config".as_ref(), &market_seed(config.market_id)],
+        bump = config.bump,
+        constraint = config.market_id == escrow_state.market_id @ EscrowError::MarketMismatch
+    )]
+    pub config: Box<Account<'info, Config>>,
+    #[account(
+        constraint = discount_token_account.owner == buyer.key() @ EscrowError::DiscountAccountOwnerMismatch,
+        constraint = discount_token_account.mint == config.discount_mint @ EscrowError::DiscountAccountMintMismatch
+    )]
+    pub discount_token_account: Option<Box<Account<'info, TokenAccount>>>,
+    /// CHECK: This is not dangerous because it is the fee override PDA of the listing's collection, only read once initialized
+    #[account(address = collection_fee_override_address(&escrow_state.collection, config.market_id) @ EscrowError::FeeOverrideMismatch)]
+    pub collection_fee_override: AccountInfo<'info>,
+    /// CHECK: This is not dangerous because it is checked against the escrow's price feed and parsed by the Pyth SDK
+    #[account(address = escrow_state.price_feed @ EscrowError::PriceFeedMismatch)]
+    pub price_feed: Option<AccountInfo<'info>>,
+    /// CHECK: This is not dangerous because the token metadata program checks it is the mint's edition
+    pub nft_edition: AccountInfo<'info>,
+    pub token_metadata_program: Program<'info, Metadata>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct PayBnpl<'info> {
+    #[account(mut)]
+    pub buyer: Signer<'info>,
+    /// CHECK: This is not dangerous because it is checked against the escrow and only receives lamports
+    #[account(mut, address = escrow_state.initializer_key)]
+    pub initializer: AccountInfo<'info>,
+    /// CHECK: This is not dangerous because it is checked against the config and only receives lamports
+    #[account(mut, address = config.treasury)]
+    pub treasury: AccountInfo<'info>,
+    #[account(
+        seeds = [b"config".as_ref(), &market_seed(config.market_id)],
+        bump = config.bump,
+        constraint = config.market_id == escrow_state.market_id @ EscrowError::MarketMismatch
+    )]
+    pub config: Box<Account<'info, Config>>,
+    #[account(
+        constraint = mint.key() == escrow_state.initializer_deposit_mint_account @ EscrowError::EscrowMintMismatch
+    )]
+    pub mint: Box<Account<'info, Mint>>,
+    #[account(mut, address = bnpl_purchase.buyer_token_account)]
+    pub buyer_token_account: Box<Account<'info, TokenAccount>>,
+    #[account(mut)]
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+    #[account(
+        mut,
+        seeds = [b"bnpl".as_ref(), escrow_state.key().as_ref()],
+        bump = bnpl_purchase.bump,
+        constraint = bnpl_purchase.buyer == buyer.key() @ EscrowError::BnplMismatch
+    )]
+    pub bnpl_purchase: Box<Account<'info, BnplPurchase>>,
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account(
+        seeds = [b"authority".as_ref(), escrow_state.key().as_ref()],
+        bump = escrow_state.vault_authority_bump,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+    /// CHECK: This is not dangerous because the token metadata program checks it is the mint's edition
+    pub nft_edition: AccountInfo<'info>,
+    pub token_metadata_program: Program<'info, Metadata>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SeizeCollateral<'info> {
+    #[account(mut, address = escrow_state.initializer_key)]
+    pub initializer: Signer<'info>,
+    #[account(
+        constraint = mint.key() == escrow_state.initializer_deposit_mint_account @ EscrowError::EscrowMintMismatch
+    )]
+    pub mint: Box<Account<'info, Mint>>,
+    #[account(mut, address = bnpl_purchase.buyer_token_account)]
+    pub buyer_token_account: Box<Account<'info, TokenAccount>>,
+    #[account(mut, address = escrow_state.initializer_deposit_token_account)]
+    pub initializer_deposit_token_account: Box<Account<'info, TokenAccount>>,
+    #[account(mut)]
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+    #[account(
+        mut,
+        seeds = [b"bnpl".as_ref(), escrow_state.key().as_ref()],
+        bump = bnpl_purchase.bump,
+        close = initializer
+    )]
+    pub bnpl_purchase: Box<Account<'info, BnplPurchase>>,
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account(
+        seeds = [b"authority".as_ref(), escrow_state.key().as_ref()],
+        bump = escrow_state.vault_authority_bump,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+    /// CHECK: This is not dangerous because the token metadata program checks it is the mint's edition
+    pub nft_edition: AccountInfo<'info>,
+    pub token_metadata_program: Program<'info, Metadata>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct CreateSubscriptionListing<'info> {
+    #[account(mut)]
+    pub seller: Signer<'info>,
+    pub mint: Box<Account<'info, Mint>>,
+    #[account(
+        mut,
+        constraint = seller_token_account.owner == seller.key(),
+        constraint = seller_token_account.mint == mint.key() @ EscrowError::EscrowMintMismatch
+    )]
+    pub seller_token_account: Box<Account<'info, TokenAccount>>,
+    #[account(
+        init,
+        payer = seller,
+        space = SubscriptionListing::space(),
+        seeds = [b"subscription_listing".as_ref(), seller.key().as_ref(), mint.key().as_ref()],
+        bump
+    )]
+    pub subscription_listing: Box<Account<'info, SubscriptionListing>>,
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account(
+        seeds = [b"authority".as_ref(), subscription_listing.key().as_ref()],
+        bump,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+    #[account(
+        init,
+        payer = seller,
+        associated_token::mint = mint,
+        associated_token::authority = vault_authority
+    )]
+    pub vault: Box<Account<'info, TokenAccount>>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+/// Shared by `subscribe`, which creates the subscription, and
+/// `renew_subscription`.
+#[derive(Accounts)]
+pub struct Subscribe<'info> {
+    #[account(mut)]
+    pub subscriber: Signer<'info>,
+    /// CHECK: This is not dangerous because it is checked against the listing and only receives lamports
+    #[account(mut, address = subscription_listing.seller)]
+    pub seller: AccountInfo<'info>,
+    #[account(address = subscription_listing.mint @ EscrowError::EscrowMintMismatch)]
+    pub mint: Box<Account<'info, Mint>>,
+    pub subscription_listing: Box<Account<'info, SubscriptionListing>>,
+    #[account(
+        init_if_needed,
+        payer = subscriber,
+        space = Subscription::space(),
+        seeds = [b"subscription".as_ref(), subscription_listing.key().as_ref(), subscriber.key().as_ref()],
+        bump
+    )]
+    pub subscription: Box<Account<'info, Subscription>>,
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account(
+        seeds = [b"authority".as_ref(), subscription_listing.key().as_ref()],
+        bump = subscription_listing.vault_authority_bump,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = vault_authority
+    )]
+    pub vault: Box<Account<'info, TokenAccount>>,
+    #[account(
+        init_if_needed,
+        payer = subscriber,
+        associated_token::mint = mint,
+        associated_token::authority = subscriber
+    )]
+    pub subscriber_token_account: Box<Account<'info, TokenAccount>>,
+    #[account(seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+    /// CHECK: This is not dangerous because it is checked against the config and only receives lamports
+    #[account(mut, address = config.treasury)]
+    pub treasury: AccountInfo<'info>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+pub struct CancelSubscription<'info> {
+    #[account(mut, address = subscription.subscriber)]
+    pub subscriber: Signer<'info>,
+    #[account(mut, close = subscriber)]
+    pub subscription: Box<Account<'info, Subscription>>,
+}
+
+#[derive(Accounts)]
+pub struct CloseSubscriptionListing<'info> {
+    #[account(mut, address = subscription_listing.seller)]
+    pub seller: Signer<'info>,
+    #[account(address = subscription_listing.mint @ EscrowError::EscrowMintMismatch)]
+    pub mint: Box<Account<'info, Mint>>,
+    #[account(
+        mut,
+        constraint = seller_token_account.owner == seller.key(),
+        constraint = seller_token_account.mint == mint.key() @ EscrowError::EscrowMintMismatch
+    )]
+    pub seller_token_account: Box<Account<'info, TokenAccount>>,
+    #[account(mut, close = seller)]
+    pub subscription_listing: Box<Account<'info, SubscriptionListing>>,
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account(
+        seeds = [b"authority".as_ref(), subscription_listing.key().as_ref()],
+        bump = subscription_listing.vault_authority_bump,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = vault_authority
+    )]
+    pub vault: Box<Account<'info, TokenAccount>>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct CreateNftPool<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub collection_mint: Box<Account<'info, Mint>>,
+    #[account(
+        init,
+        payer = owner,
+        space = NftPool::space(),
+        seeds = [b"nft_pool".as_ref(), owner.key().as_ref(), collection_mint.key().as_ref()],
+        bump
+    )]
+    pub nft_pool: Box<Account<'info, NftPool>>,
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account(
+        seeds = [b"authority".as_ref(), nft_pool.key().as_ref()],
+        bump,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateNftPool<'info> {
+    #[account(address = nft_pool.owner)]
+    pub owner: Signer<'info>,
+    #[account(mut)]
+    pub nft_pool: Box<Account<'info, NftPool>>,
+}
+
+#[derive(Accounts)]
+pub struct DepositPoolNft<'info> {
+    #[account(mut, address = nft_pool.owner)]
+    pub owner: Signer<'info>,
+    pub mint: Box<Account<'info, Mint>>,
+    #[account(
+        constraint = nft_metadata.mint == mint.key() @ EscrowError::NftMetadataMismatch,
+        constraint = verified_collection(&nft_metadata) == Some(nft_pool.collection_mint) @ EscrowError::CollectionMismatch
+    )]
+    pub nft_metadata: Box<Account<'info, MetadataAccount>>,
+    #[account(
+        mut,
+        constraint = owner_token_account.owner == owner.key(),
+        constraint = owner_token_account.mint == mint.key() @ EscrowError::EscrowMintMismatch
+    )]
+    pub owner_token_account: Box<Account<'info, TokenAccount>>,
+    #[account(mut)]
+    pub nft_pool: Box<Account<'info, NftPool>>,
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account(
+        seeds = [b"authority".as_ref(), nft_pool.key().as_ref()],
+        bump = nft_pool.vault_authority_bump,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+    #[account(
+        init_if_needed,
+        payer = owner,
+        associated_token::mint = mint,
+        associated_token::authority = vault_authority
+    )]
+    pub vault: Box<Account<'info, TokenAccount>>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawPoolNft<'info> {
+    #[account(mut, address = nft_pool.owner)]
+    pub owner: Signer<'info>,
+    pub mint: Box<Account<'info, Mint>>,
+    #[account(
+        mut,
+        constraint = owner_token_account.owner == owner.key(),
+        constraint = owner_token_account.mint == mint.key() @ EscrowError::EscrowMintMismatch
+    )]
+    pub owner_token_account: Box<Account<'info, TokenAccount>>,
+    #[account(mut)]
+    pub nft_pool: Box<Account<'info, NftPool>>,
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account(
+        seeds = [b"authority".as_ref(), nft_pool.key().as_ref()],
+        bump = nft_pool.vault_authority_bump,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = vault_authority
+    )]
+    pub vault: Box<Account<'info, TokenAccount>>,
+    pub token_program: Program<'info, Token>,
+}
+
+/// Shared by `deposit_pool_sol` and `withdraw_pool_sol`.
+#[derive(Accounts)]
+pub struct PoolSol<'info> {
+    #[account(mut, address = nft_pool.owner)]
+    pub owner: Signer<'info>,
+    #[account(mut)]
+    pub nft_pool: Box<Account<'info, NftPool>>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct BuyFromPool<'info> {
+    #[account(mut)]
+    pub buyer: Signer<'info>,
+    pub mint: Box<Account<'info, Mint>>,
+    #[account(mut)]
+    pub nft_pool: Box<Account<'info, NftPool>>,
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account(
+        seeds = [b"authority".as_ref(), nft_pool.key().as_ref()],
+        bump = nft_pool.vault_authority_bump,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = vault_authority
+    )]
+    pub vault: Box<Account<'info, TokenAccount>>,
+    #[account(
+        init_if_needed,
+        payer = buyer,
+        associated_token::mint = mint,
+        associated_token::authority = buyer
+    )]
+    pub buyer_token_account: Box<Account<'info, TokenAccount>>,
+    #[account(seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+    /// CHECK: This is not dangerous because it is checked against the config and only receives lamports
+    #[account(mut, address = config.treasury)]
+    pub treasury: AccountInfo<'info>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+pub struct SellToPool<'info> {
+    #[account(mut)]
+    pub seller: Signer<'info>,
+    pub mint: Box<Account<'info, Mint>>,
+    #[account(
+        constraint = nft_metadata.mint == mint.key() @ EscrowError::NftMetadataMismatch,
+        constraint = verified_collection(&nft_metadata) == Some(nft_pool.collection_mint) @ EscrowError::CollectionMismatch
+    )]
+    pub nft_metadata: Box<Account<'info, MetadataAccount>>,
+    #[account(
+        mut,
+        constraint = seller_token_account.owner == seller.key(),
+        constraint = seller_token_account.mint == mint.key() @ EscrowError::EscrowMintMismatch
+    )]
+    pub seller_token_account: Box<Account<'info, TokenAccount>>,
+    #[account(mut)]
+    pub nft_pool: Box<Account<'info, NftPool>>,
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account(
+        seeds = [b"authority".as_ref(), nft_pool.key().as_ref()],
+        bump = nft_pool.vault_authority_bump,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+    #[account(
+        init_if_needed,
+        payer = seller,
+        associated_token::mint = mint,
+        associated_token::authority = vault_authority
+    )]
+    pub vault: Box<Account<'info, TokenAccount>>,
+    #[account(seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+    /// CHECK: This is not dangerous because it is checked against the config and only receives lamports
+    #[account(mut, address = config.treasury)]
+    pub treasury: AccountInfo<'info>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+pub struct CloseNftPool<'info> {
+    #[account(mut, address = nft_pool.owner)]
+    pub owner: Signer<'info>,
+    #[account(
+        mut,
+        constraint = nft_pool.nft_count == 0 @ EscrowError::PoolNotEmpty,
+        close = owner
+    )]
+    pub nft_pool: Box<Account<'info, NftPool>>,
+}
+
+#[derive(Accounts)]
+pub struct Fractionalize<'info> {
+    #[account(mut)]
+    pub depositor: Signer<'info>,
+    pub mint: Box<Account<'info, Mint>>,
+    #[account(
+        mut,
+        constraint = depositor_token_account.owner == depositor.key(),
+        constraint = depositor_token_account.mint == mint.key() @ EscrowError::EscrowMintMismatch
+    )]
+    pub depositor_token_account: Box<Account<'info, TokenAccount>>,
+    #[account(
+        init,
+        payer = depositor,
+        space = FractionVault::space(),
+        seeds = [b"fraction_vault".as_ref(), mint.key().as_ref()],
+        bump
+    )]
+    pub fraction_vault: Box<Account<'info, FractionVault>>,
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account(
+        seeds = [b"authority".as_ref(), fraction_vault.key().as_ref()],
+        bump,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+    #[account(
+        init,
+        payer = depositor,
+        associated_token::mint = mint,
+        associated_token::authority = vault_authority
+    )]
+    pub vault: Box<Account<'info, TokenAccount>>,
+    #[account(
+        init,
+        payer = depositor,
+        seeds = [b"fractions".as_ref(), fraction_vault.key().as_ref()],
+        bump,
+        mint::decimals = 0,
+        mint::authority = vault_authority
+    )]
+    pub fraction_mint: Box<Account<'info, Mint>>,
+    #[account(
+        init,
+        payer = depositor,
+        associated_token::mint = fraction_mint,
+        associated_token::authority = depositor
+    )]
+    pub depositor_fraction_account: Box<Account<'info, TokenAccount>>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+pub struct Buyout<'info> {
+    #[account(mut)]
+    pub buyer: Signer<'info>,
+    /// CHECK: This is not dangerous because it is checked against the fraction vault and only receives the vault rent
+    #[account(mut, address = fraction_vault.depositor)]
+    pub depositor: AccountInfo<'info>,
+    #[account(address = fraction_vault.mint @ EscrowError::EscrowMintMismatch)]
+    pub mint: Box<Account<'info, Mint>>,
+    #[account(
+        mut,
+        constraint = !fraction_vault.is_bought_out() @ EscrowError::AlreadyBoughtOut
+    )]
+    pub fraction_vault: Box<Account<'info, FractionVault>>,
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account(
+        seeds = [b"authority".as_ref(), fraction_vault.key().as_ref()],
+        bump = fraction_vault.vault_authority_bump,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = vault_authority
+    )]
+    pub vault: Box<Account<'info, TokenAccount>>,
+    #[account(
+        init_if_needed,
+        payer = buyer,
+        associated_token::mint = mint,
+        associated_token::authority = buyer
+    )]
+    pub buyer_token_account: Box<Account<'info, TokenAccount>>,
+    #[account(seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+    /// CHECK: This is not dangerous because it is checked against the config and only receives lamports
+    #[account(mut, address = config.treasury)]
+    pub treasury: AccountInfo<'info>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+pub struct RedeemFractions<'info> {
+    #[account(mut)]
+    pub holder: Signer<'info>,
+    /// CHECK: This is not dangerous because it is checked against the fraction vault and only receives its rent
+    #[account(mut, address = fraction_vault.depositor)]
+    pub depositor: AccountInfo<'info>,
+    #[account(
+        mut,
+        constraint = fraction_vault.is_bought_out() @ EscrowError::NotBoughtOut
+    )]
+    pub fraction_vault: Box<Account<'info, FractionVault>>,
+    #[account(mut, address = fraction_vault.fraction_mint @ EscrowError::EscrowMintMismatch)]
+    pub fraction_mint: Box<Account<'info, Mint>>,
+    #[account(
+        mut,
+        constraint = holder_fraction_account.owner == holder.key(),
+        constraint = holder_fraction_account.mint == fraction_mint.key() @ EscrowError::EscrowMintMismatch
+    )]
+    pub holder_fraction_account: Box<Account<'info, TokenAccount>>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct CreatePurchasePool<'info> {
+    #[account(mut)]
+    pub creator: Signer<'info>,
+    #[account(
+        constraint = !escrow_state.delegated @ EscrowError::GroupPurchaseDelegated,
+        constraint = !escrow_state.awaiting_deposit @ EscrowError::ListingNotDeposited
+    )]
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+    #[account(
+        init,
+        payer = creator,
+        space = PurchasePool::space(),
+        seeds = [b"purchase_pool".as_ref(), escrow_state.key().as_ref()],
+        bump
+    )]
+    pub purchase_pool: Box<Account<'info, PurchasePool>>,
+    /// CHECK: This is not dangerous because it is a data-less PDA that only holds lamports and tokens
+    #[account(
+        seeds = [b"custody".as_ref(), purchase_pool.key().as_ref()],
+        bump,
+    )]
+    pub custody: AccountInfo<'info>,
+    #[account(
+        seeds = [b"config".as_ref(), &market_seed(config.market_id)],
+        bump = config.bump,
+        constraint = config.market_id == escrow_state.market_id @ EscrowError::MarketMismatch
+    )]
+    pub config: Box<Account<'info, Config>>,
+    /// CHECK: This is not dangerous because it is the fee override PDA of the listing's collection, only read once initialized
+    #[account(address = collection_fee_override_address(&escrow_state.collection, config.market_id) @ EscrowError::FeeOverrideMismatch)]
+    pub collection_fee_override: AccountInfo<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct Contribute<'info> {
+    #[account(mut)]
+    pub contributor: Signer<'info>,
+    #[account(
+        mut,
+        constraint = !purchase_pool.executed @ EscrowError::GroupPurchaseExecuted
+    )]
+    pub purchase_pool: Box<Account<'info, PurchasePool>>,
+    /// CHECK: This is not dangerous because it is a data-less PDA that only holds lamports and tokens
+    #[account(
+        mut,
+        seeds = [b"custody".as_ref(), purchase_pool.key().as_ref()],
+        bump = purchase_pool.custody_bump,
+    )]
+    pub custody: AccountInfo<'info>,
+    #[account(
+        init_if_needed,
+        payer = contributor,
+        space = Contribution::space(),
+        seeds = [b"contribution".as_ref(), purchase_pool.key().as_ref(), contributor.key().as_ref()],
+        bump
+    )]
+    pub contribution: Box<Account<'info, Contribution>>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawContribution<'info> {
+    #[account(mut, address = contribution.contributor)]
+    pub contributor: Signer<'info>,
+    #[account(
+        mut,
+        constraint = !purchase_pool.executed @ EscrowError::GroupPurchaseExecuted
+    )]
+    pub purchase_pool: Box<Account<'info, PurchasePool>>,
+    /// CHECK: This is not dangerous because it is a data-less PDA that only holds lamports and tokens
+    #[account(
+        mut,
+        seeds = [b"custody".as_ref(), purchase_pool.key().as_ref()],
+        bump = purchase_pool.custody_bump,
+    )]
+    pub custody: AccountInfo<'info>,
+    #[account(
+        mut,
+        seeds = [b"contribution".as_ref(), purchase_pool.key().as_ref(), contributor.key().as_ref()],
+        bump = contribution.bump,
+        close = contributor
+    )]
+    pub contribution: Box<Account<'info, Contribution>>,
+    pub system_program: Program<'info, System>,
+}
+
+/// Everything the nested `exchange` needs, with the custody PDA standing in
+/// for the taker. The exchange itself checks the listing accounts.
+#[derive(Accounts)]
+pub struct ExecuteGroupPurchase<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    #[account(
+        mut,
+        constraint = !purchase_pool.executed @ EscrowError::GroupPurchaseExecuted,
+        constraint = purchase_pool.raised >= purchase_pool.target @ EscrowError::GroupPurchaseUnderfunded
+    )]
+    pub purchase_pool: Box<Account<'info, PurchasePool>>,
+    /// CHECK: This is not dangerous because it is a data-less PDA that only holds lamports and tokens
+    #[account(
+        mut,
+        seeds = [b"custody".as_ref(), purchase_pool.key().as_ref()],
+        bump = purchase_pool.custody_bump,
+    )]
+    pub custody: AccountInfo<'info>,
+    /// CHECK: This is not dangerous because it is checked against the purchase pool and validated by `exchange`
+    #[account(mut, address = purchase_pool.escrow_state)]
+    pub escrow_state: AccountInfo<'info>,
+    /// CHECK: This is not dangerous because `exchange` checks it against the escrow
+    #[account(mut)]
+    pub initializer: AccountInfo<'info>,
+    #[account(mut)]
+    pub initializer_deposit_token_mint: Box<Account<'info, Mint>>,
+    #[account(mut)]
+    pub taker_deposit_token_mint: Box<Account<'info, Mint>>,
+    #[account(
+        mut,
+        associated_token::mint = taker_deposit_token_mint,
+        associated_token::authority = custody
+    )]
+    pub custody_deposit_token_account: Box<Account<'info, TokenAccount>>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        associated_token::mint = initializer_deposit_token_mint,
+        associated_token::authority = custody
+    )]
+    pub custody_receive_token_account: Box<Account<'info, TokenAccount>>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        associated_token::mint = taker_deposit_token_mint,
+        associated_token::authority = initializer
+    )]
+    pub initializer_receive_token_account: Box<Account<'info, TokenAccount>>,
+    /// CHECK: This is not dangerous because `exchange` checks it is the escrow's vault
+    #[account(mut)]
+    pub vault: AccountInfo<'info>,
+    /// CHECK: This is not dangerous because `exchange` checks it is the escrow's vault authority
+    pub vault_authority: AccountInfo<'info>,
+    #[account(seeds = [b"config".as_ref(), &market_seed(config.market_id)], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+    /// CHECK: This is not dangerous because it is checked against the config treasury
+    #[account(mut, address = config.treasury)]
+    pub treasury: AccountInfo<'info>,
+    /// CHECK: This is not dangerous because `exchange` checks it is the fee override of the escrow's collection
+    pub collection_fee_override: AccountInfo<'info>,
+    /// CHECK: This is not dangerous because `exchange` checks it is the DAO treasury entry of the escrow's collection
+    pub collection_treasury: AccountInfo<'info>,
+    /// CHECK: This is not dangerous because `exchange` checks it against the registered DAO treasury
+    #[account(mut)]
+    pub dao_treasury: Option<AccountInfo<'info>>,
+    /// CHECK: This is not dangerous because `exchange` checks it against the escrow's donation recipient
+    #[account(mut)]
+    pub donation_recipient: Option<AccountInfo<'info>>,
+    pub escrow_program: Program<'info, crate::program::AnchorEscrow>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct OfferHtlc<'info> {
+    pub initializer: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_state.initializer_key == *initializer.key,
+        constraint = escrow_state.taker_amount == 0 && escrow_state.price_usd_cents == 0 @ EscrowError::InvalidHtlc,
+        constraint = !escrow_state.delegated @ EscrowError::HtlcDelegated,
+        constraint = !escrow_state.is_gift() @ EscrowError::GiftEscrow,
+        constraint = !escrow_state.paid_through_wormhole() @ EscrowError::WormholeEscrow,
+        constraint = !escrow_state.in_ring() @ EscrowError::RingLeg,
+        constraint = !escrow_state.is_reserved() @ EscrowError::EscrowReserved,
+        constraint = !escrow_state.is_settling() @ EscrowError::EscrowSettling,
+        constraint = !escrow_state.in_payment_plan() @ EscrowError::PaymentPlanActive,
+        constraint = !escrow_state.in_rent_to_own() @ EscrowError::RentToOwnActive,
+        constraint = !escrow_state.on_loan() @ EscrowError::EscrowOnLoan,
+        constraint = !escrow_state.is_vesting() @ EscrowError::EscrowVesting,
+        constraint = !escrow_state.in_bnpl() @ EscrowError::BnplActive,
+        constraint = !escrow_state.is_printing_editions() @ EscrowError::PrintingEditions,
+        constraint = !escrow_state.awaiting_deposit @ EscrowError::ListingNotDeposited
+    )]
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+}
+
+#[derive(Accounts)]
+pub struct ExchangeWithPreimage<'info> {
+    #[account(mut, address = escrow_state.htlc_taker @ EscrowError::NotHtlcTaker)]
+    pub taker: Signer<'info>,
+    /// CHECK: This is not dangerous because it is checked against the escrow and only receives rent
+    #[account(mut, address = escrow_state.initializer_key)]
+    pub initializer: AccountInfo<'info>,
+    #[account(address = escrow_state.initializer_deposit_mint_account @ EscrowError::EscrowMintMismatch)]
+    pub mint: Box<Account<'info, Mint>>,
+    #[account(
+        mut,
+        constraint = escrow_state.is_htlc() @ EscrowError::NotHtlc,
+        constraint = !escrow_state.is_reserved() @ EscrowError::EscrowReserved,
+        constraint = !escrow_state.is_settling() @ EscrowError::EscrowSettling,
+        constraint = !escrow_state.in_payment_plan() @ EscrowError::PaymentPlanActive,
+        constraint = !escrow_state.in_rent_to_own() @ EscrowError::RentToOwnActive,
+        constraint = !escrow_state.on_loan() @ EscrowError::EscrowOnLoan,
+        constraint = !escrow_state.is_vesting() @ EscrowError::EscrowVesting,
+        constraint = !escrow_state.in_bnpl() @ EscrowError::BnplActive,
+        constraint = !escrow_state.is_printing_editions() @ EscrowError::PrintingEditions,
+        constraint = !escrow_state.awaiting_deposit @ EscrowError::ListingNotDeposited
+    )]
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account(
+        seeds = [b"authority".as_ref(), escrow_state.key().as_ref()],
+        bump = escrow_state.vault_authority_bump,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = vault_authority
+    )]
+    pub vault: Box<Account<'info, TokenAccount>>,
+    #[account(
+        init_if_needed,
+        payer = taker,
+        associated_token::mint = mint,
+        associated_token::authority = taker
+    )]
+    pub taker_token_account: Box<Account<'info, TokenAccount>>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptWormholePayment<'info> {
+    pub initializer: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_state.initializer_key == *initializer.key,
+        constraint = escrow_state.taker_amount == 0 && escrow_state.price_usd_cents == 0 @ EscrowError::InvalidWormholeTerms,
+        constraint = !escrow_state.delegated @ EscrowError::WormholeDelegated,
+        constraint = !escrow_state.is_gift() @ EscrowError::GiftEscrow,
+        constraint = !escrow_state.is_htlc() @ EscrowError::HtlcEscrow,
+        constraint = !escrow_state.in_ring() @ EscrowError::RingLeg,
+        constraint = !escrow_state.is_reserved() @ EscrowError::EscrowReserved,
+        constraint = !escrow_state.is_settling() @ EscrowError::EscrowSettling,
+        constraint = !escrow_state.in_payment_plan() @ EscrowError::PaymentPlanActive,
+        constraint = !escrow_state.in_rent_to_own() @ EscrowError::RentToOwnActive,
+        constraint = !escrow_state.on_loan() @ EscrowError::EscrowOnLoan,
+        constraint = !escrow_state.is_vesting() @ EscrowError::EscrowVesting,
+        constraint = !escrow_state.in_bnpl() @ EscrowError::BnplActive,
+        constraint = !escrow_state.is_printing_editions() @ EscrowError::PrintingEditions,
+        constraint = !escrow_state.awaiting_deposit @ EscrowError::ListingNotDeposited
+    )]
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+}
+
+#[derive(Accounts)]
+pub struct ExchangeWithVaa<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    /// CHECK: This is not dangerous because it is checked against the payment message
+    #[account(mut)]
+    pub recipient: AccountInfo<'info>,
+    /// CHECK: This is not dangerous because it is checked against the escrow and only receives rent
+    #[account(mut, address = escrow_state.initializer_key)]
+    pub initializer: AccountInfo<'info>,
+    #[account(address = escrow_state.initializer_deposit_mint_account @ EscrowError::EscrowMintMismatch)]
+    pub mint: Box<Account<'info, Mint>>,
+    #[account(
+        mut,
+        constraint = escrow_state.paid_through_wormhole() @ EscrowError::NotWormholeEscrow,
+        constraint = !escrow_state.is_reserved() @ EscrowError::EscrowReserved,
+        constraint = !escrow_state.is_settling() @ EscrowError::EscrowSettling,
+        constraint = !escrow_state.in_payment_plan() @ EscrowError::PaymentPlanActive,
+        constraint = !escrow_state.in_rent_to_own() @ EscrowError::RentToOwnActive,
+        constraint = !escrow_state.on_loan() @ EscrowError::EscrowOnLoan,
+        constraint = !escrow_state.is_vesting() @ EscrowError::EscrowVesting,
+        constraint = !escrow_state.in_bnpl() @ EscrowError::BnplActive,
+        constraint = !escrow_state.is_printing_editions() @ EscrowError::PrintingEditions,
+        constraint = !escrow_state.awaiting_deposit @ EscrowError::ListingNotDeposited
+    )]
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account(
+        seeds = [b"authority".as_ref(), escrow_state.key().as_ref()],
+        bump = escrow_state.vault_authority_bump,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = vault_authority
+    )]
+    pub vault: Box<Account<'info, TokenAccount>>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        associated_token::mint = mint,
+        associated_token::authority = recipient
+    )]
+    pub recipient_token_account: Box<Account<'info, TokenAccount>>,
+    /// CHECK: Only the configured Wormhole program can have written this account
+    #[account(owner = escrow_state.wormhole_program @ EscrowError::InvalidVaa)]
+    pub posted_vaa: AccountInfo<'info>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+pub struct LinkRing<'info> {
+    pub initializer: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_state.initializer_key == *initializer.key,
+        constraint = escrow_state.price_usd_cents == 0 @ EscrowError::InvalidRing,
+        constraint = !escrow_state.delegated @ EscrowError::RingDelegated,
+        constraint = !escrow_state.is_gift() @ EscrowError::GiftEscrow,
+        constraint = !escrow_state.is_htlc() @ EscrowError::HtlcEscrow,
+        constraint = !escrow_state.paid_through_wormhole() @ EscrowError::WormholeEscrow,
+        constraint = !escrow_state.is_reserved() @ EscrowError::EscrowReserved,
+        constraint = !escrow_state.is_settling() @ EscrowError::EscrowSettling,
+        constraint = !escrow_state.in_payment_plan() @ EscrowError::PaymentPlanActive,
+        constraint = !escrow_state.in_rent_to_own() @ EscrowError::RentToOwnActive,
+        constraint = !escrow_state.on_loan() @ EscrowError::EscrowOnLoan,
+        constraint = !escrow_state.is_vesting() @ EscrowError::EscrowVesting,
+        constraint = !escrow_state.in_bnpl() @ EscrowError::BnplActive,
+        constraint = !escrow_state.is_printing_editions() @ EscrowError::PrintingEditions,
+        constraint = !escrow_state.awaiting_deposit @ EscrowError::ListingNotDeposited
+    )]
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+}
+
+#[derive(Accounts)]
+pub struct SettleRing<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    /// CHECK: This is not dangerous because it is checked against the escrow and only receives lamports
+    #[account(mut, address = escrow_a.initializer_key)]
+    pub initializer_a: AccountInfo<'info>,
+    /// CHECK: This is not dangerous because it is checked against the escrow and only receives lamports
+    #[account(mut, address = escrow_b.initializer_key)]
+    pub initializer_b: AccountInfo<'info>,
+    #[account(address = escrow_a.initializer_deposit_mint_account @ EscrowError::EscrowMintMismatch)]
+    pub mint_a: Box<Account<'info, Mint>>,
+    #[account(address = escrow_b.initializer_deposit_mint_account @ EscrowError::EscrowMintMismatch)]
+    pub mint_b: Box<Account<'info, Mint>>,
+    #[account(
+        mut,
+        constraint = escrow_a.ring_partner == escrow_b.key() @ EscrowError::RingMismatch,
+        constraint = escrow_a.ring_recipient == escrow_b.initializer_key @ EscrowError::RingMismatch,
+        constraint = !escrow_a.is_reserved() @ EscrowError::EscrowReserved,
+        constraint = !escrow_a.is_settling() @ EscrowError::EscrowSettling,
+        constraint = !escrow_a.in_payment_plan() @ EscrowError::PaymentPlanActive,
+        constraint = !escrow_a.in_rent_to_own() @ EscrowError::RentToOwnActive,
+        constraint = !escrow_a.on_loan() @ EscrowError::EscrowOnLoan,
+        constraint = !escrow_a.is_vesting() @ EscrowError::EscrowVesting,
+        constraint = !escrow_a.in_bnpl() @ EscrowError::BnplActive,
+        constraint = !escrow_a.is_printing_editions() @ EscrowError::PrintingEditions,
+        constraint = !escrow_a.awaiting_deposit @ EscrowError::ListingNotDeposited
+    )]
+    pub escrow_a: Box<Account<'info, EscrowState>>,
+    #[account(
+        mut,
+        constraint = escrow_b.ring_partner == escrow_a.key() @ EscrowError::RingMismatch,
+        constraint = escrow_b.ring_recipient == payer.key() @ EscrowError::RingMismatch,
+        constraint = !escrow_b.is_reserved() @ EscrowError::EscrowReserved,
+        constraint = !escrow_b.is_settling() @ EscrowError::EscrowSettling,
+        constraint = !escrow_b.in_payment_plan() @ EscrowError::PaymentPlanActive,
+        constraint = !escrow_b.in_rent_to_own() @ EscrowError::RentToOwnActive,
+        constraint = !escrow_b.on_loan() @ EscrowError::EscrowOnLoan,
+        constraint = !escrow_b.is_vesting() @ EscrowError::EscrowVesting,
+        constraint = !escrow_b.in_bnpl() @ EscrowError::BnplActive,
+        constraint = !escrow_b.is_printing_editions() @ EscrowError::PrintingEditions,
+        constraint = !escrow_b.awaiting_deposit @ EscrowError::ListingNotDeposited
+    )]
+    pub escrow_b: Box<Account<'info, EscrowState>>,
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account(
+        seeds = [b"authority".as_ref(), escrow_a.key().as_ref()],
+        bump = escrow_a.vault_authority_bump,
+    )]
+    pub vault_authority_a: AccountInfo<'info>,
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account(
+        seeds = [b"authority".as_ref(), escrow_b.key().as_ref()],
+        bump = escrow_b.vault_authority_bump,
+    )]
+    pub vault_authority_b: AccountInfo<'info>,
+    #[account(
+        mut,
+        associated_token::mint = mint_a,
+        associated_token::authority = vault_authority_a
+    )]
+    pub vault_a: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        associated_token::mint = mint_b,
+        associated_token::authority = vault_authority_b
+    )]
+    pub vault_b: Box<Account<'info, TokenAccount>>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        associated_token::mint = mint_a,
+        associated_token::authority = initializer_b
+    )]
+    pub initializer_b_token_account: Box<Account<'info, TokenAccount>>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        associated_token::mint = mint_b,
+        associated_token::authority = payer
+    )]
+    pub payer_token_account: Box<Account<'info, TokenAccount>>,
+    #[account(
+        seeds = [b"config".as_ref(), &market_seed(config.market_id)],
+        bump = config.bump,
+        constraint = config.market_id == escrow_a.market_id @ EscrowError::MarketMismatch,
+        constraint = config.market_id == escrow_b.market_id @ EscrowError::MarketMismatch
+    )]
+    pub config: Box<Account<'info, Config>>,
+    /// CHECK: This is not dangerous because it is checked against the config and only receives lamports
+    #[account(mut, address = config.treasury)]
+    pub treasury: AccountInfo<'info>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+#[instruction(swap_id: u64)]
+pub struct CreateSwap<'info> {
+    #[account(mut)]
+    pub maker: Signer<'info>,
+    #[account(
+        init,
+        payer = maker,
+        space = SwapState::space(),
+        seeds = [b"swap".as_ref(), maker.key().as_ref(), &swap_id.to_le_bytes()],
+        bump
+    )]
+    pub swap_state: Box<Account<'info, SwapState>>,
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account(
+        seeds = [b"authority".as_ref(), swap_state.key().as_ref()],
+        bump,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct DepositSwapAsset<'info> {
+    #[account(mut)]
+    pub party: Signer<'info>,
+    pub mint: Box<Account<'info, Mint>>,
+    #[account(
+        mut,
+        constraint = party_token_account.owner == party.key(),
+        constraint = party_token_account.mint == mint.key() @ EscrowError::EscrowMintMismatch
+    )]
+    pub party_token_account: Box<Account<'info, TokenAccount>>,
+    #[account(mut)]
+    pub swap_state: Box<Account<'info, SwapState>>,
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account(
+        seeds = [b"authority".as_ref(), swap_state.key().as_ref()],
+        bump = swap_state.vault_authority_bump,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+    #[account(
+        init_if_needed,
+        payer = party,
+        associated_token::mint = mint,
+        associated_token::authority = vault_authority
+    )]
+    pub vault: Box<Account<'info, TokenAccount>>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+/// Shared by `execute_swap` and `cancel_swap`, which either party may call.
+#[derive(Accounts)]
+pub struct ExecuteSwap<'info> {
+    #[account(
+        constraint = party.key() == swap_state.maker || party.key() == swap_state.taker @ EscrowError::NotSwapParty
+    )]
+    pub party: Signer<'info>,
+    /// CHECK: This is not dangerous because it is checked against the swap and only receives assets and rent
+    #[account(mut, address = swap_state.maker)]
+    pub maker: AccountInfo<'info>,
+    /// CHECK: This is not dangerous because it is checked against the swap and only receives assets and rent
+    #[account(mut, address = swap_state.taker)]
+    pub taker: AccountInfo<'info>,
+    #[account(mut, close = maker)]
+    pub swap_state: Box<Account<'info, SwapState>>,
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account(
+        seeds = [b"authority".as_ref(), swap_state.key().as_ref()],
+        bump = swap_state.vault_authority_bump,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+#[instruction(offer_id: u64)]
+pub struct PlaceBuyOffer<'info> {
+    #[account(mut)]
+    pub buyer: Signer<'info>,
+    #[account(
+        init,
+        payer = buyer,
+        space = BuyOffer::space(),
+        seeds = [b"buy_offer".as_ref(), buyer.key().as_ref(), &offer_id.to_le_bytes()],
+        bump
+    )]
+    pub buy_offer: Box<Account<'info, BuyOffer>>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CancelBuyOffer<'info> {
+    #[account(mut, address = buy_offer.buyer)]
+    pub buyer: Signer<'info>,
+    #[account(mut, close = buyer)]
+    pub buy_offer: Box<Account<'info, BuyOffer>>,
+}
+
+#[derive(Accounts)]
+pub struct SweepExpiredOffers {}
+
+#[derive(Accounts)]
+pub struct MatchEscrows<'info> {
+    #[account(mut)]
+    pub matcher: Signer<'info>,
+    /// CHECK: This is not dangerous because it is checked against the offer and only receives the NFT and lamports
+    #[account(mut, address = buy_offer.buyer)]
+    pub buyer: AccountInfo<'info>,
+    #[account(mut, close = buyer)]
+    pub buy_offer: Box<Account<'info, BuyOffer>>,
+    /// CHECK: This is not dangerous because it is checked against the escrow and only receives lamports
+    #[account(mut, address = escrow_state.initializer_key)]
+    pub initializer: AccountInfo<'info>,
+    #[account(address = escrow_state.initializer_deposit_mint_account @ EscrowError::EscrowMintMismatch)]
+    pub mint: Box<Account<'info, Mint>>,
+    #[account(
+        mut,
+        constraint = escrow_state.is_plain_listing() @ EscrowError::ListingNotMatchable
+    )]
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account(
+        seeds = [b"authority".as_ref(), escrow_state.key().as_ref()],
+        bump = escrow_state.vault_authority_bump,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = vault_authority
+    )]
+    pub vault: Box<Account<'info, TokenAccount>>,
+    #[account(
+        init_if_needed,
+        payer = matcher,
+        associated_token::mint = mint,
+        associated_token::authority = buyer
+    )]
+    pub buyer_token_account: Box<Account<'info, TokenAccount>>,
+    /// Required when the offer is for a collection.
+    pub nft_metadata: Option<Box<Account<'info, MetadataAccount>>>,
+    #[account(
+        seeds = [b"config".as_ref(), &market_seed(config.market_id)],
+        bump = config.bump,
+        constraint = config.market_id == escrow_state.market_id @ EscrowError::MarketMismatch
+    )]
+    pub config: Box<Account<'info, Config>>,
+    /// CHECK: This is not dangerous because it is the fee override PDA of the listing's collection, only read once initialized
+    #[account(address = collection_fee_override_address(&escrow_state.collection, config.market_id) @ EscrowError::FeeOverrideMismatch)]
+    pub collection_fee_override: AccountInfo<'info>,
+    /// CHECK: This is not dangerous because it is checked against the config and only receives lamports
+    #[account(mut, address = config.treasury)]
+    pub treasury: AccountInfo<'info>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+#[instruction(collection: Pubkey)]
+pub struct CreateOrderBook<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    #[account(
+        init,
+        payer = payer,
+        space = OrderBook::space(),
+        seeds = [b"order_book".as_ref(), collection.as_ref()],
+        bump
+    )]
+    pub order_book: Box<Account<'info, OrderBook>>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct PostAsk<'info> {
+    pub initializer: Signer<'info>,
+    #[account(
+        constraint = escrow_state.initializer_key == *initializer.key,
+        constraint = escrow_state.is_plain_listing() @ EscrowError::ListingNotMatchable
+    )]
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+    pub nft_metadata: Option<Box<Account<'info, MetadataAccount>>>,
+    #[account(mut)]
+    pub order_book: Box<Account<'info, OrderBook>>,
+}
+
+#[derive(Accounts)]
+pub struct PostBid<'info> {
+    #[account(address = buy_offer.buyer)]
+    pub buyer: Signer<'info>,
+    #[account(
+        constraint = buy_offer.collection == order_book.collection @ EscrowError::BuyOfferMismatch
+    )]
+    pub buy_offer: Box<Account<'info, BuyOffer>>,
+    #[account(mut)]
+    pub order_book: Box<Account<'info, OrderBook>>,
+}
+
+#[derive(Accounts)]
+pub struct CrankMatch<'info> {
+    #[account(mut)]
+    pub order_book: Box<Account<'info, OrderBook>>,
+    pub fill: MatchEscrows<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CompactOrderBook<'info> {
+    #[account(mut)]
+    pub order_book: Box<Account<'info, OrderBook>>,
+}
+
+#[derive(Accounts)]
+#[instruction(deal_id: u64)]
+pub struct CreateDealRoom<'info> {
+    #[account(mut)]
+    pub creator: Signer<'info>,
+    #[account(
+        init,
+        payer = creator,
+        space = DealRoom::space(),
+        seeds = [b"deal_room".as_ref(), creator.key().as_ref(), &deal_id.to_le_bytes()],
+        bump
+    )]
+    pub deal_room: Box<Account<'info, DealRoom>>,
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account(
+        seeds = [b"authority".as_ref(), deal_room.key().as_ref()],
+        bump,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct DepositToDeal<'info> {
+    #[account(mut)]
+    pub depositor: Signer<'info>,
+    pub mint: Box<Account<'info, Mint>>,
+    #[account(
+        mut,
+        constraint = depositor_token_account.owner == depositor.key(),
+        constraint = depositor_token_account.mint == mint.key() @ EscrowError::EscrowMintMismatch
+    )]
+    pub depositor_token_account: Box<Account<'info, TokenAccount>>,
+    #[account(mut)]
+    pub deal_room: Box<Account<'info, DealRoom>>,
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account(
+        seeds = [b"authority".as_ref(), deal_room.key().as_ref()],
+        bump = deal_room.vault_authority_bump,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+    #[account(
+        init,
+        payer = depositor,
+        associated_token::mint = mint,
+        associated_token::authority = vault_authority
+    )]
+    pub vault: Box<Account<'info, TokenAccount>>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawFromDeal<'info> {
+    #[account(mut)]
+    pub depositor: Signer<'info>,
+    pub mint: Box<Account<'info, Mint>>,
+    #[account(
+        mut,
+        constraint = depositor_token_account.owner == depositor.key(),
+        constraint = depositor_token_account.mint == mint.key() @ EscrowError::EscrowMintMismatch
+    )]
+    pub depositor_token_account: Box<Account<'info, TokenAccount>>,
+    #[account(mut)]
+    pub deal_room: Box<Account<'info, DealRoom>>,
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account(
+        seeds = [b"authority".as_ref(), deal_room.key().as_ref()],
+        bump = deal_room.vault_authority_bump,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = vault_authority
+    )]
+    pub vault: Box<Account<'info, TokenAccount>>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ApproveDeal<'info> {
+    pub party: Signer<'info>,
+    #[account(mut)]
+    pub deal_room: Box<Account<'info, DealRoom>>,
+}
+
+#[derive(Accounts)]
+pub struct ExecuteDeal<'info> {
+    /// CHECK: This is not dangerous because it is checked against the deal room and only receives its rent
+    #[account(mut, address = deal_room.creator)]
+    pub creator: AccountInfo<'info>,
+    #[account(mut, close = creator)]
+    pub deal_room: Box<Account<'info, DealRoom>>,
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account(
+        seeds = [b"authority".as_ref(), deal_room.key().as_ref()],
+        bump = deal_room.vault_authority_bump,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+#[instruction(session_key: Pubkey)]
+pub struct CreateSession<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    #[account(
+        init,
+        seeds = [b"session".as_ref(), owner.key().as_ref(), session_key.as_ref()],
+        bump,
+        payer = owner,
+        space = Session::space()
+    )]
+    pub session: Account<'info, Session>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RevokeSession<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    #[account(mut, has_one = owner, close = owner)]
+    pub session: Account<'info, Session>,
+}
+
+#[derive(Accounts)]
+pub struct ListWithSession<'info> {
+    #[account(mut)]
+    pub session_key: Signer<'info>,
+    /// CHECK: This is not dangerous because it is checked against the session
+    pub owner: AccountInfo<'info>,
+    #[account(
+        seeds = [b"session".as_ref(), owner.key().as_ref(), session_key.key().as_ref()],
+        bump = session.bump
+    )]
+    pub session: Box<Account<'info, Session>>,
+    #[account(constraint = !vault_freezable(&mint) @ EscrowError::MintCanFreezeVault)]
+    pub mint: Account<'info, Mint>,
+
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account(
+        seeds = [b"authority".as_ref(), escrow_state.key().as_ref()],
+        bump,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+
+    #[account(
+        init,
+        payer = session_key,
+        associated_token::mint = mint,
+        associated_token::authority = vault_authority
+    )]
+    pub vault: Box<Account<'info, TokenAccount>>,
+
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    pub taker_key: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        constraint = owner_deposit_token_account.owner == owner.key(),
+        constraint = owner_deposit_token_account.delegate == COption::Some(session.key()) @ EscrowError::SessionNotDelegated,
+        constraint = !owner_deposit_token_account.is_frozen() @ EscrowError::DepositAccountFrozen
+    )]
+    pub owner_deposit_token_account: Box<Account<'info, TokenAccount>>,
+
+    pub initializer_receive_mint_account: Account<'info, Mint>,
+
+    /// Config of the market to list in.
+    #[account(seeds = [b"config".as_ref(), &market_seed(config.market_id)], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    #[account(
+        init_if_needed,
+        seeds = [
+            b"listing_counter".as_ref(),
+            owner.key().as_ref(),
+            &market_seed(config.market_id)
+        ],
+        bump,
+        payer = session_key,
+        space = ListingCounter::space()
+    )]
+    pub listing_counter: Box<Account<'info, ListingCounter>>,
+
+    #[account(
+        init,
+        seeds = [
+            b"state".as_ref(),
+            owner.key().as_ref(),
+            &listing_counter.count.to_le_bytes(),
+            &market_seed(config.market_id)
+        ],
+        bump,
+        payer = session_key,
+        space = EscrowState::space()
+    )]
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+
+    /// CHECK: This is not dangerous because it is the mint's Token Metadata account, only read once it exists
+    #[account(address = metadata_address(&mint.key()) @ EscrowError::NftMetadataMismatch)]
+    pub nft_metadata: AccountInfo<'info>,
+
+    /// CHECK: This is not dangerous because `require_listable` checks it is the floor of the NFT's collection, only read once initialized
+    pub collection_floor: AccountInfo<'info>,
+
+    /// Required while the config limits how many listings a wallet makes.
+    #[account(
+        mut,
+        seeds = [
+            b"wallet_activity".as_ref(),
+            owner.key().as_ref(),
+            &market_seed(config.market_id)
+        ],
+        bump = wallet_activity.bump
+    )]
+    pub wallet_activity: Option<Box<Account<'info, WalletActivity>>>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+pub struct CancelWithSession<'info> {
+    pub session_key: Signer<'info>,
+    /// CHECK: This is not dangerous because it is checked against the session and only receives lamports
+    #[account(mut)]
+    pub owner: AccountInfo<'info>,
+    #[account(
+        seeds = [b"session".as_ref(), owner.key().as_ref(), session_key.key().as_ref()],
+        bump = session.bump
+    )]
+    pub session: Box<Account<'info, Session>>,
+    #[account(address = escrow_state.initializer_deposit_mint_account @ EscrowError::EscrowMintMismatch)]
+    pub mint: Account<'info, Mint>,
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = vault_authority
+    )]
+    pub vault: Box<Account<'info, TokenAccount>>,
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account(
+        seeds = [b"authority".as_ref(), escrow_state.key().as_ref()],
+        bump = escrow_state.vault_authority_bump,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+    #[account(mut, address = escrow_state.initializer_deposit_token_account)]
+    pub owner_deposit_token_account: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        constraint = escrow_state.initializer_key == owner.key(),
+        constraint = escrow_state.is_plain_listing() @ EscrowError::SessionListingLocked
+    )]
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+#[instruction(price: u64, expiry: i64, nonce: u64)]
+pub struct FillSignedOrder<'info> {
+    #[account(mut)]
+    pub buyer: Signer<'info>,
+    /// CHECK: This is not dangerous because the order signature is checked against it
+    #[account(mut)]
+    pub seller: AccountInfo<'info>,
+    pub mint: Box<Account<'info, Mint>>,
+    #[account(
+        mut,
+        constraint = seller_token_account.owner == seller.key(),
+        constraint = seller_token_account.mint == mint.key() @ EscrowError::EscrowMintMismatch,
+        constraint = seller_token_account.delegate == COption::Some(order_delegate.key()) @ EscrowError::OrderNotDelegated
+    )]
+    pub seller_token_account: Box<Account<'info, TokenAccount>>,
+    /// CHECK: This is not dangerous because it is a data-less PDA that only signs as token delegate
+    #[account(seeds = [b"order_delegate".as_ref()], bump)]
+    pub order_delegate: AccountInfo<'info>,
+    #[account(
+        init_if_needed,
+        payer = buyer,
+        associated_token::mint = mint,
+        associated_token::authority = buyer
+    )]
+    pub buyer_token_account: Box<Account<'info, TokenAccount>>,
+    #[account(
+        init,
+        seeds = [b"filled_order".as_ref(), seller.key().as_ref(), nonce.to_le_bytes().as_ref()],
+        bump,
+        payer = buyer,
+        space = FilledOrder::space()
+    )]
+    pub filled_order: Account<'info, FilledOrder>,
+    #[account(seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+    /// CHECK: This is not dangerous because it is checked against the config and only receives lamports
+    #[account(mut, address = config.treasury)]
+    pub treasury: AccountInfo<'info>,
+    /// CHECK: This is not dangerous because it is checked to be the instructions sysvar
+    #[account(address = instructions_sysvar::ID)]
+    pub instructions: AccountInfo<'info>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(nonce: u64)]
+pub struct CancelSignedOrder<'info> {
+    #[account(mut)]
+    pub seller: Signer<'info>,
+    #[account(
+        init,
+        seeds = [b"filled_order".as_ref(), seller.key().as_ref(), nonce.to_le_bytes().as_ref()],
+        bump,
+        payer = seller,
+        space = FilledOrder::space()
+    )]
+    pub filled_order: Account<'info, FilledOrder>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct OpenAuditTrail<'info> {
+    #[account(mut)]
+    pub initializer: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_state.initializer_key == *initializer.key
+    )]
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+    #[account(
+        init,
+        seeds = [b"audit".as_ref(), escrow_state.key().as_ref()],
+        bump,
+        payer = initializer,
+        space = AuditTrail::space()
+    )]
+    pub audit_trail: Account<'info, AuditTrail>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CloseAuditTrail<'info> {
+    #[account(mut)]
+    pub initializer: Signer<'info>,
+    /// CHECK: This is not dangerous because we only check it has been closed
+    #[account(
+        address = audit_trail.escrow_state,
+        constraint = escrow_state.data_is_empty() @ EscrowError::AuditTrailInUse
+    )]
+    pub escrow_state: AccountInfo<'info>,
+    #[account(mut, has_one = initializer, close = initializer)]
+    pub audit_trail: Account<'info, AuditTrail>,
+}
+
+#[derive(Accounts)]
+pub struct ProveReserves {}
+
+#[derive(Accounts)]
+#[instruction(template_id: u64)]
+pub struct CreateListingTemplate<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    #[account(
+        init,
+        seeds = [
+            b"listing_template".as_ref(),
+            owner.key().as_ref(),
+            &template_id.to_le_bytes()
+        ],
+        bump,
+        payer = owner,
+        space = ListingTemplate::space()
+    )]
+    pub template: Account<'info, ListingTemplate>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CloseListingTemplate<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    #[account(mut, has_one = owner, close = owner)]
+    pub template: Account<'info, ListingTemplate>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeFromTemplate<'info> {
+    #[account(mut)]
+    pub initializer: Signer<'info>,
+    /// Pays the rent of the listing's accounts, as in `initialize`.
+    #[account(mut)]
+    pub rent_payer: Signer<'info>,
+    #[account(constraint = template.owner == initializer.key())]
+    pub template: Box<Account<'info, ListingTemplate>>,
+    #[account(constraint = !vault_freezable(&mint) @ EscrowError::MintCanFreezeVault)]
+    pub mint: Account<'info, Mint>,
+
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account(
+        seeds = [b"authority".as_ref(), escrow_state.key().as_ref()],
+        bump,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+
+    #[account(
+        init,
+        payer = rent_payer,
+        associated_token::mint = mint,
+        associated_token::authority = vault_authority
+    )]
+    pub vault: Box<Account<'info, TokenAccount>>,
+
+    /// CHECK: This is not dangerous because we only record its address as the taker
+    pub taker_key: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        constraint = &initializer_deposit_token_account.owner == initializer.key,
+        constraint = !initializer_deposit_token_account.is_frozen() @ EscrowError::DepositAccountFrozen
+    )]
+    pub initializer_deposit_token_account: Account<'info, TokenAccount>,
+
+    pub initializer_receive_mint_account: Account<'info, Mint>,
+
+    #[account(seeds = [b"config".as_ref(), &market_seed(config.market_id)], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    #[account(
+        init_if_needed,
+        seeds = [
+            b"listing_counter".as_ref(),
+            initializer.key().as_ref(),
+            &market_seed(config.market_id)
+        ],
+        bump,
+        payer = rent_payer,
+        space = ListingCounter::space()
+    )]
+    pub listing_counter: Box<Account<'info, ListingCounter>>,
+
+    #[account(
+        init,
+        seeds = [
+            b"state".as_ref(),
+            initializer.key().as_ref(),
+            &listing_counter.count.to_le_bytes(),
+            &market_seed(config.market_id)
+        ],
+        bump,
+        payer = rent_payer,
+        space = EscrowState::space()
+    )]
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+
+    /// CHECK: This is not dangerous because it is the mint's Token Metadata account, only read once it exists
+    #[account(address = metadata_address(&mint.key()) @ EscrowError::NftMetadataMismatch)]
+    pub nft_metadata: AccountInfo<'info>,
+
+    /// CHECK: This is not dangerous because `require_listable` checks it is the floor of the NFT's collection, only read once initialized
+    pub collection_floor: AccountInfo<'info>,
+
+    /// Required while the config limits how many listings a wallet makes.
+    #[account(
+        mut,
+        seeds = [
+            b"wallet_activity".as_ref(),
+            initializer.key().as_ref(),
+            &market_seed(config.market_id)
+        ],
+        bump = wallet_activity.bump
+    )]
+    pub wallet_activity: Option<Box<Account<'info, WalletActivity>>>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+pub struct TransferListing<'info> {
+    pub initializer: Signer<'info>,
+    pub new_initializer: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_state.initializer_key == *initializer.key,
+        constraint = !escrow_state.delegated @ EscrowError::TransferDelegated,
+        constraint = !escrow_state.is_reserved() @ EscrowError::EscrowReserved,
+        constraint = !escrow_state.is_settling() @ EscrowError::EscrowSettling,
+        constraint = !escrow_state.in_payment_plan() @ EscrowError::PaymentPlanActive,
+        constraint = !escrow_state.in_rent_to_own() @ EscrowError::RentToOwnActive,
+        constraint = !escrow_state.on_loan() @ EscrowError::EscrowOnLoan,
+        constraint = !escrow_state.is_vesting() @ EscrowError::EscrowVesting,
+        constraint = !escrow_state.in_bnpl() @ EscrowError::BnplActive
+    )]
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+}
+
+#[derive(Accounts)]
+pub struct ReassignTaker<'info> {
+    pub initializer: Signer<'info>,
+    /// CHECK: This is not dangerous because we only record its address as the taker
+    pub new_taker: AccountInfo<'info>,
+    #[account(
+        mut,
+        constraint = escrow_state.initializer_key == *initializer.key,
+        constraint = !escrow_state.is_reserved() @ EscrowError::EscrowReserved,
+        constraint = !escrow_state.is_settling() @ EscrowError::EscrowSettling
+    )]
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+}
+
+#[derive(Accounts)]
+pub struct ExtendExpiry<'info> {
+    pub initializer: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_state.initializer_key == *initializer.key
+    )]
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+    #[account(
+        seeds = [b"config".as_ref(), &market_seed(config.market_id)],
+        bump = config.bump,
+        constraint = config.market_id == escrow_state.market_id @ EscrowError::MarketMismatch
+    )]
+    pub config: Box<Account<'info, Config>>,
+}
+
+#[derive(Accounts)]
+pub struct SetRelistDecay<'info> {
+    pub initializer: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_state.initializer_key == *initializer.key
+    )]
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+    #[account(
+        seeds = [b"config".as_ref(), &market_seed(config.market_id)],
+        bump = config.bump,
+        constraint = config.market_id == escrow_state.market_id @ EscrowError::MarketMismatch
+    )]
+    pub config: Box<Account<'info, Config>>,
+}
+
+#[derive(Accounts)]
+pub struct RelistDecayed<'info> {
+    pub keeper: Signer<'info>,
+    #[account(mut)]
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+}
+
+#[derive(Accounts)]
+pub struct CancelExpired<'info> {
+    pub keeper: Signer<'info>,
+    /// CHECK: This is not dangerous because it is checked against the escrow and only receives lamports
+    #[account(mut, address = escrow_state.initializer_key)]
+    pub initializer: AccountInfo<'info>,
+    #[account(address = escrow_state.initializer_deposit_mint_account @ EscrowError::EscrowMintMismatch)]
+    pub mint: Box<Account<'info, Mint>>,
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = vault_authority
+    )]
+    pub vault: Box<Account<'info, TokenAccount>>,
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account(
+        seeds = [b"authority".as_ref(), escrow_state.key().as_ref()],
+        bump = escrow_state.vault_authority_bump,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+    #[account(mut, address = escrow_state.initializer_deposit_token_account)]
+    pub initializer_deposit_token_account: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        constraint = !escrow_state.delegated @ EscrowError::ExpiryCancelDelegated,
+        constraint = escrow_state.relist_decay_bps == 0 @ EscrowError::ListingRelists,
+        constraint = !escrow_state.is_reserved() @ EscrowError::EscrowReserved,
+        constraint = !escrow_state.is_settling() @ EscrowError::EscrowSettling,
+        constraint = !escrow_state.in_payment_plan() @ EscrowError::PaymentPlanActive,
+        constraint = !escrow_state.in_rent_to_own() @ EscrowError::RentToOwnActive,
+        constraint = !escrow_state.on_loan() @ EscrowError::EscrowOnLoan,
+        constraint = !escrow_state.is_vesting() @ EscrowError::EscrowVesting,
+        constraint = !escrow_state.in_bnpl() @ EscrowError::BnplActive,
+        constraint = !escrow_state.paid_through_wormhole() @ EscrowError::WormholeEscrow
+    )]
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+    /// Required once the escrow has an audit trail.
+    #[account(
+        mut,
+        seeds = [b"audit".as_ref(), escrow_state.key().as_ref()],
+        bump = audit_trail.bump
+    )]
+    pub audit_trail: Option<Box<Account<'info, AuditTrail>>>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct CreateListing<'info> {
+    #[account(mut)]
+    pub initializer: Signer<'info>,
+    /// Pays the rent of the listing's accounts, as in `initialize`.
+    #[account(mut)]
+    pub rent_payer: Signer<'info>,
+    #[account(constraint = !vault_freezable(&mint) @ EscrowError::MintCanFreezeVault)]
+    pub mint: Account<'info, Mint>,
+
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account(
+        seeds = [b"authority".as_ref(), escrow_state.key().as_ref()],
+        bump,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+
+    #[account(
+        init,
+        payer = rent_payer,
+        associated_token::mint = mint,
+        associated_token::authority = vault_authority
+    )]
+    pub vault: Box<Account<'info, TokenAccount>>,
+
+    /// CHECK: This is not dangerous because we only record its address as the taker
+    pub taker_key: AccountInfo<'info>,
+
+    #[account(
+        constraint = &initializer_deposit_token_account.owner == initializer.key,
+        constraint = !initializer_deposit_token_account.is_frozen() @ EscrowError::DepositAccountFrozen
+    )]
+    pub initializer_deposit_token_account: Account<'info, TokenAccount>,
+
+    pub initializer_receive_mint_account: Account<'info, Mint>,
+
+    #[account(seeds = [b"config".as_ref(), &market_seed(config.market_id)], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    #[account(
+        init_if_needed,
+        seeds = [
+            b"listing_counter".as_ref(),
+            initializer.key().as_ref(),
+            &market_seed(config.market_id)
+        ],
+        bump,
+        payer = rent_payer,
+        space = ListingCounter::space()
+    )]
+    pub listing_counter: Box<Account<'info, ListingCounter>>,
+
+    #[account(
+        init,
+        seeds = [
+            b"state".as_ref(),
+            initializer.key().as_ref(),
+            &listing_counter.count.to_le_bytes(),
+            &market_seed(config.market_id)
+        ],
+        bump,
+        payer = rent_payer,
+        space = EscrowState::space()
+    )]
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+
+    /// CHECK: This is not dangerous because it is the mint's Token Metadata account, only read once it exists
+    #[account(address = metadata_address(&mint.key()) @ EscrowError::NftMetadataMismatch)]
+    pub nft_metadata: AccountInfo<'info>,
+
+    /// CHECK: This is not dangerous because `require_listable` checks it is the floor of the NFT's collection, only read once initialized
+    pub collection_floor: AccountInfo<'info>,
+
+    /// Required while the config limits how many listings a wallet makes.
+    #[account(
+        mut,
+        seeds = [
+            b"wallet_activity".as_ref(),
+            initializer.key().as_ref(),
+            &market_seed(config.market_id)
+        ],
+        bump = wallet_activity.bump
+    )]
+    pub wallet_activity: Option<Box<Account<'info, WalletActivity>>>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+pub struct Deposit<'info> {
+    pub initializer: Signer<'info>,
+    #[account(address = escrow_state.initializer_deposit_mint_account @ EscrowError::EscrowMintMismatch)]
+    pub mint: Account<'info, Mint>,
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account(
+        seeds = [b"authority".as_ref(), escrow_state.key().as_ref()],
+        bump = escrow_state.vault_authority_bump,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = vault_authority
+    )]
+    pub vault: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        address = escrow_state.initializer_deposit_token_account,
+        constraint = !initializer_deposit_token_account.is_frozen() @ EscrowError::DepositAccountFrozen
+    )]
+    pub initializer_deposit_token_account: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        constraint = escrow_state.initializer_key == *initializer.key,
+        constraint = escrow_state.awaiting_deposit @ EscrowError::ListingAlreadyDeposited
+    )]
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+#[instruction(merkle_root: [u8; 32])]
+pub struct CreateAirdrop<'info> {
+    #[account(mut)]
+    pub project: Signer<'info>,
+    #[account(
+        init,
+        payer = project,
+        space = Airdrop::space(),
+        seeds = [b"airdrop".as_ref(), project.key().as_ref(), merkle_root.as_ref()],
+        bump
+    )]
+    pub airdrop: Box<Account<'info, Airdrop>>,
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account(
+        seeds = [b"authority".as_ref(), airdrop.key().as_ref()],
+        bump,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct DepositAirdropNft<'info> {
+    #[account(mut, address = airdrop.project)]
+    pub project: Signer<'info>,
+    pub mint: Box<Account<'info, Mint>>,
+    #[account(
+        mut,
+        constraint = project_token_account.owner == project.key(),
+        constraint = project_token_account.mint == mint.key() @ EscrowError::EscrowMintMismatch
+    )]
+    pub project_token_account: Box<Account<'info, TokenAccount>>,
+    #[account(mut)]
+    pub airdrop: Box<Account<'info, Airdrop>>,
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account(
+        seeds = [b"authority".as_ref(), airdrop.key().as_ref()],
+        bump = airdrop.vault_authority_bump,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+    #[account(
+        init,
+        payer = project,
+        associated_token::mint = mint,
+        associated_token::authority = vault_authority
+    )]
+    pub vault: Box<Account<'info, TokenAccount>>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimAirdrop<'info> {
+    #[account(mut)]
+    pub claimant: Signer<'info>,
+    pub mint: Box<Account<'info, Mint>>,
+    #[account(mut)]
+    pub airdrop: Box<Account<'info, Airdrop>>,
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account(
+        seeds = [b"authority".as_ref(), airdrop.key().as_ref()],
+        bump = airdrop.vault_authority_bump,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = vault_authority
+    )]
+    pub vault: Box<Account<'info, TokenAccount>>,
+    #[account(
+        init_if_needed,
+        payer = claimant,
+        associated_token::mint = mint,
+        associated_token::authority = claimant
+    )]
+    pub claimant_token_account: Box<Account<'info, TokenAccount>>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+pub struct ReclaimAirdropNft<'info> {
+    #[account(mut, address = airdrop.project)]
+    pub project: Signer<'info>,
+    pub mint: Box<Account<'info, Mint>>,
+    #[account(
+        mut,
+        constraint = project_token_account.owner == project.key(),
+        constraint = project_token_account.mint == mint.key() @ EscrowError::EscrowMintMismatch
+    )]
+    pub project_token_account: Box<Account<'info, TokenAccount>>,
+    #[account(mut)]
+    pub airdrop: Box<Account<'info, Airdrop>>,
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account(
+        seeds = [b"authority".as_ref(), airdrop.key().as_ref()],
+        bump = airdrop.vault_authority_bump,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = vault_authority
+    )]
+    pub vault: Box<Account<'info, TokenAccount>>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct CloseAirdrop<'info> {
+    #[account(mut, address = airdrop.project)]
+    pub project: Signer<'info>,
+    #[account(
+        mut,
+        constraint = airdrop.nft_count == 0 @ EscrowError::AirdropNotEmpty,
+        close = project
+    )]
+    pub airdrop: Box<Account<'info, Airdrop>>,
+}
+
+#[derive(Accounts)]
+pub struct ListForRent<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub mint: Box<Account<'info, Mint>>,
+    #[account(
+        mut,
+        constraint = owner_token_account.owner == owner.key(),
+        constraint = owner_token_account.mint == mint.key() @ EscrowError::EscrowMintMismatch
+    )]
+    pub owner_token_account: Box<Account<'info, TokenAccount>>,
+    #[account(
+        init,
+        payer = owner,
+        space = RentalListing::space(),
+        seeds = [b"rental".as_ref(), owner.key().as_ref(), mint.key().as_ref()],
+        bump
+    )]
+    pub rental_listing: Box<Account<'info, RentalListing>>,
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account(
+        seeds = [b"authority".as_ref(), rental_listing.key().as_ref()],
+        bump,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+    #[account(
+        init,
+        payer = owner,
+        associated_token::mint = mint,
+        associated_token::authority = vault_authority
+    )]
+    pub vault: Box<Account<'info, TokenAccount>>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+pub struct StartRental<'info> {
+    #[account(mut)]
+    pub renter: Signer<'info>,
+    /// CHECK: This is not dangerous because it is checked against the listing and only receives lamports
+    #[account(mut, address = rental_listing.owner)]
+    pub owner: AccountInfo<'info>,
+    #[account(address = rental_listing.mint @ EscrowError::EscrowMintMismatch)]
+    pub mint: Box<Account<'info, Mint>>,
+    #[account(
+        mut,
+        constraint = !rental_listing.is_rented() @ EscrowError::AlreadyRented
+    )]
+    pub rental_listing: Box<Account<'info, RentalListing>>,
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account(
+        seeds = [b"authority".as_ref(), rental_listing.key().as_ref()],
+        bump = rental_listing.vault_authority_bump,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = vault_authority
+    )]
+    pub vault: Box<Account<'info, TokenAccount>>,
+    #[account(
+        init_if_needed,
+        payer = renter,
+        associated_token::mint = mint,
+        associated_token::authority = renter
+    )]
+    pub renter_token_account: Box<Account<'info, TokenAccount>>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+pub struct ReturnRental<'info> {
+    #[account(mut, address = rental_listing.renter @ EscrowError::NotRenter)]
+    pub renter: Signer<'info>,
+    #[account(address = rental_listing.mint @ EscrowError::EscrowMintMismatch)]
+    pub mint: Box<Account<'info, Mint>>,
+    #[account(mut, constraint = rental_listing.is_rented() @ EscrowError::NotRented)]
+    pub rental_listing: Box<Account<'info, RentalListing>>,
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account(
+        seeds = [b"authority".as_ref(), rental_listing.key().as_ref()],
+        bump = rental_listing.vault_authority_bump,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = vault_authority
+    )]
+    pub vault: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        constraint = renter_token_account.owner == renter.key(),
+        constraint = renter_token_account.mint == mint.key() @ EscrowError::EscrowMintMismatch
+    )]
+    pub renter_token_account: Box<Account<'info, TokenAccount>>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimCollateral<'info> {
+    #[account(mut, address = rental_listing.owner)]
+    pub owner: Signer<'info>,
+    #[account(
+        mut,
+        constraint = rental_listing.is_rented() @ EscrowError::NotRented,
+        close = owner
+    )]
+    pub rental_listing: Box<Account<'info, RentalListing>>,
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account(
+        seeds = [b"authority".as_ref(), rental_listing.key().as_ref()],
+        bump = rental_listing.vault_authority_bump,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+    #[account(
+        mut,
+        associated_token::mint = rental_listing.mint,
+        associated_token::authority = vault_authority
+    )]
+    pub vault: Box<Account<'info, TokenAccount>>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct DelistRental<'info> {
+    #[account(mut, address = rental_listing.owner)]
+    pub owner: Signer<'info>,
+    #[account(address = rental_listing.mint @ EscrowError::EscrowMintMismatch)]
+    pub mint: Box<Account<'info, Mint>>,
     #[account(
         mut,
-        constraint = escrow_state.initializer_key == *initializer.key,
-        constraint = escrow_state.initializer_deposit_token_account == *initializer_deposit_token_account.to_account_info().key,
-        close = initializer
+        constraint = owner_token_account.owner == owner.key(),
+        constraint = owner_token_account.mint == mint.key() @ EscrowError::EscrowMintMismatch
+    )]
+    pub owner_token_account: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        constraint = !rental_listing.is_rented() @ EscrowError::AlreadyRented,
+        close = owner
+    )]
+    pub rental_listing: Box<Account<'info, RentalListing>>,
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account(
+        seeds = [b"authority".as_ref(), rental_listing.key().as_ref()],
+        bump = rental_listing.vault_authority_bump,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = vault_authority
+    )]
+    pub vault: Box<Account<'info, TokenAccount>>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ReclaimPaymentPlan<'info> {
+    #[account(mut, address = escrow_state.initializer_key)]
+    pub initializer: Signer<'info>,
+    /// CHECK: This is not dangerous because it is checked against the payment plan and only receives lamports
+    #[account(mut, address = payment_plan.taker @ EscrowError::PaymentPlanMismatch)]
+    pub taker: AccountInfo<'info>,
+    #[account(mut)]
+    pub escrow_state: Box<Account<'info, EscrowState>>,
+    #[account(
+        mut,
+        seeds = [b"payment_plan".as_ref(), escrow_state.key().as_ref()],
+        bump = payment_plan.bump,
+        close = taker
+    )]
+    pub payment_plan: Box<Account<'info, PaymentPlan>>,
+}
+
+#[account]
+pub struct EscrowState {
+    /// Position of this escrow in the initializer's `ListingCounter`.
+    pub nonce: u64,
+    pub initializer_key: Pubkey,
+    pub taker_key: Pubkey,
+    pub initializer_deposit_token_account: Pubkey,
+    pub initializer_receive_token_account: Pubkey,
+    pub initializer_deposit_mint_account: Pubkey,
+    pub initializer_receive_mint_account: Pubkey,
+    pub initializer_amount: u64,
+    pub taker_amount: u64,
+    pub vault_authority_bump: u8,
+    pub donation_recipient: Pubkey,
+    pub donation_bps: u16,
+    /// When non-zero the taker pays this many US cents, converted to lamports
+    /// through `price_feed` at exchange time.
+    pub price_usd_cents: u64,
+    pub price_feed: Pubkey,
+    /// Rent-exempt reserve of the vault, kept apart from the lamport deposit.
+    pub vault_rent_lamports: u64,
+    /// The NFT stays frozen in the seller's wallet with the vault authority
+    /// as delegate instead of sitting in a vault.
+    pub delegated: bool,
+    /// Taker holding the listing through `reserve`, or the default key.
+    pub reserved_by: Pubkey,
+    /// Lamports the reserving taker deposited into this account.
+    pub reserved_amount: u64,
+    /// Part of the taker's deposit that is forfeited to the seller if the
+    /// reservation is not finalized by `reserved_until`.
+    pub reservation_deposit: u64,
+    /// Unix time the reservation lapses at; zero never lapses.
+    pub reserved_until: i64,
+    /// Cooling-off period between `exchange` and `settle`; zero settles
+    /// immediately.
+    pub settlement_delay_secs: i64,
+    /// Set by a delayed `exchange`: who bought, from which account, and the
+    /// seller proceeds held here until `settle_after`.
+    pub pending_taker: Pubkey,
+    pub pending_taker_deposit_token_account: Pubkey,
+    pub pending_proceeds: u64,
+    pub settle_after: i64,
+    pub disputed: bool,
+    /// Installment terms offered through `offer_payment_plan`; zero
+    /// installments means the listing cannot be bought in installments.
+    pub plan_installments: u8,
+    pub plan_interval_secs: i64,
+    pub plan_forfeit_bps: u16,
+    /// Taker paying through a `PaymentPlan`, or the default key.
+    pub plan_taker: Pubkey,
+    /// Rent-to-own terms offered through `offer_rent_to_own`; zero payment
+    /// means the listing cannot be rented to own.
+    pub rent_to_own_payment: u64,
+    pub rent_to_own_period_secs: i64,
+    pub rent_to_own_target: u64,
+    /// Renter holding the NFT through a `RentToOwn`, or the default key.
+    pub rent_to_own_renter: Pubkey,
+    /// Loan terms asked for through `request_loan`; zero principal means no
+    /// loan is requested.
+    pub loan_principal: u64,
+    pub loan_apr_bps: u16,
+    pub loan_duration_secs: i64,
+    /// Lender of the funded `LoanState`, or the default key.
+    pub loan_lender: Pubkey,
+    /// Vesting schedule set through `configure_vesting`; zero duration pays
+    /// the seller at exchange.
+    pub vesting_duration_secs: i64,
+    pub vesting_cliff_secs: i64,
+    /// Set by a vesting `exchange`: when the schedule started and the seller
+    /// proceeds held here, of which `vesting_claimed` are paid out.
+    pub vesting_start: i64,
+    pub vesting_total: u64,
+    pub vesting_claimed: u64,
+    /// Buy-now-pay-later terms offered through `offer_bnpl`; zero
+    /// installments means the listing cannot be bought this way.
+    pub bnpl_installments: u8,
+    pub bnpl_interval_secs: i64,
+    pub bnpl_collateral_bps: u16,
+    /// Buyer paying off the NFT through a `BnplPurchase`, or the default key.
+    pub bnpl_buyer: Pubkey,
+    /// Edition printing terms set through `configure_edition_printing`; zero
+    /// `edition_max_prints` sells the vaulted token itself.
+    pub edition_price: u64,
+    pub edition_max_prints: u64,
+    pub editions_printed: u64,
+    /// Bonding curve applied to `edition_price` after every print: linear
+    /// growth by `edition_price_step` lamports and exponential growth by
+    /// `edition_price_growth_bps`.
+    pub edition_price_step: u64,
+    pub edition_price_growth_bps: u16,
+    /// Member-only sales set through `set_purchase_gate`: the taker must
+    /// hold `gate_min_amount` of `gate_mint`, possibly deposited in a DAO run
+    /// by `gate_governance_program`. A default `gate_mint` is ungated.
+    pub gate_mint: Pubkey,
+    pub gate_min_amount: u64,
+    pub gate_governance_program: Pubkey,
+    /// Recipient of a gift set through `offer_gift`, or the default key, and
+    /// when an unclaimed gift may be returned; zero never lapses.
+    pub gift_recipient: Pubkey,
+    pub gift_claim_deadline: i64,
+    /// Taker of a hashed-timelock listing set through `offer_htlc`, or the
+    /// default key, the SHA-256 of its secret and when the lock lapses.
+    pub htlc_taker: Pubkey,
+    pub htlc_hash: [u8; 32],
+    pub htlc_timeout: i64,
+    /// Wormhole core bridge trusted through `accept_wormhole_payment`, or the
+    /// default key, the chain and contract whose messages pay for the NFT
+    /// and the amount they must report, in that chain's token units.
+    pub wormhole_program: Pubkey,
+    pub wormhole_emitter_chain: u16,
+    pub wormhole_emitter: [u8; 32],
+    pub wormhole_amount: u64,
+    /// Who takes the NFT in a three-way trade set through `link_ring`, or
+    /// the default key, and the leg of the ring that recipient listed.
+    pub ring_recipient: Pubkey,
+    pub ring_partner: Pubkey,
+    /// Market the escrow was listed in; only that market's config settles it.
+    pub market_id: u64,
+    /// Program that listed the escrow through `initialize_for_integrator`,
+    /// or the default key, the seed it chose, and its cut of the proceeds.
+    pub integrator: Pubkey,
+    pub integrator_namespace: [u8; 32],
+    pub integrator_fee_recipient: Pubkey,
+    pub integrator_fee_bps: u16,
+    /// Set through `set_private_fills`: `exchange` then needs an allowance
+    /// the initializer signed for the taker and price.
+    pub private_fills: bool,
+    /// When the escrow was listed; zero for escrows listed before it was kept.
+    pub created_at: i64,
+    /// Set by `open_audit_trail`; exchange and cancel then need the trail.
+    pub audited: bool,
+    /// Category code frontends filter on, zero when uncategorized. Kept
+    /// ahead of `note` so its offset does not depend on the note's length.
+    pub tag: [u8; 8],
+    /// Commitment to the document at `terms_uri`, zero without terms.
+    pub terms_hash: [u8; 32],
+    /// Unix time after which the listing no longer fills; zero never expires.
+    pub expires_at: i64,
+    /// Price cut applied by each `relist_decayed`; zero leaves an expired
+    /// listing as it is.
+    pub relist_decay_bps: u16,
+    /// How long each relisting stays up.
+    pub relist_window_secs: i64,
+    /// Who paid the listing's rent and gets it back when the listing is
+    /// cancelled or exchanged; the default key for the initializer.
+    pub rent_payer: Pubkey,
+    /// Made by `create_listing` and not yet active until `deposit`.
+    pub awaiting_deposit: bool,
+    /// Verified collection of the NFT when it was listed, or the default
+    /// key. Its fee override, if any, prices every fill.
+    pub collection: Pubkey,
+    /// Free-form context from the seller, at most `MAX_NOTE_LEN` bytes.
+    pub note: String,
+    pub terms_uri: String,
+}
+
+/// What every listing entry point records on a new escrow, whichever way
+/// it then holds the NFT.
+pub struct NewListing {
+    pub nonce: u64,
+    pub initializer: Pubkey,
+    pub taker: Pubkey,
+    pub deposit_token_account: Pubkey,
+    pub deposit_mint: Pubkey,
+    pub receive_mint: Pubkey,
+    pub taker_amount: u64,
+    pub vault_authority_bump: u8,
+    pub vault_rent_lamports: u64,
+    pub market_id: u64,
+    /// Gets the escrow's rent back when it closes.
+    pub rent_payer: Pubkey,
+    pub collection: Pubkey,
+    pub note: String,
+}
+
+impl EscrowState {
+    pub fn space() -> usize {
+        8 + 1280 + MAX_NOTE_LEN + MAX_TERMS_URI_LEN
+    }
+
+    /// Fills in a freshly created escrow from `listing`.
+    pub fn open(&mut self, listing: NewListing, now: i64) -> Result<()> {
+        require!(listing.note.len() <= MAX_NOTE_LEN, EscrowError::NoteTooLong);
+        self.nonce = listing.nonce;
+        self.initializer_key = listing.initializer;
+        self.taker_key = listing.taker;
+        self.initializer_deposit_token_account = listing.deposit_token_account;
+        self.initializer_receive_token_account =
+            get_associated_token_address(&listing.initializer, &listing.receive_mint);
+        self.initializer_deposit_mint_account = listing.deposit_mint;
+        self.initializer_receive_mint_account = listing.receive_mint;
+        self.taker_amount = listing.taker_amount;
+        self.vault_authority_bump = listing.vault_authority_bump;
+        self.vault_rent_lamports = listing.vault_rent_lamports;
+        self.market_id = listing.market_id;
+        self.rent_payer = listing.rent_payer;
+        self.collection = listing.collection;
+        self.note = listing.note;
+        self.created_at = now;
+        Ok(())
+    }
+
+    pub fn is_reserved(&self) -> bool {
+        self.reserved_by != Pubkey::default()
+    }
+
+    pub fn reservation_expired(&self, now: i64) -> bool {
+        self.reserved_until > 0 && now > self.reserved_until
+    }
+
+    /// Exchanged with a delay and waiting for `settle` or a dispute ruling.
+    pub fn is_settling(&self) -> bool {
+        self.settle_after > 0
+    }
+
+    pub fn is_settlement_party(&self, key: Pubkey) -> bool {
+        key == self.initializer_key || key == self.pending_taker
+    }
+
+    pub fn in_payment_plan(&self) -> bool {
+        self.plan_taker != Pubkey::default()
+    }
+
+    pub fn in_rent_to_own(&self) -> bool {
+        self.rent_to_own_renter != Pubkey::default()
+    }
+
+    pub fn on_loan(&self) -> bool {
+        self.loan_lender != Pubkey::default()
+    }
+
+    pub fn in_bnpl(&self) -> bool {
+        self.bnpl_buyer != Pubkey::default()
+    }
+
+    pub fn is_gift(&self) -> bool {
+        self.gift_recipient != Pubkey::default()
+    }
+
+    pub fn gift_expired(&self, now: i64) -> bool {
+        self.gift_claim_deadline > 0 && now > self.gift_claim_deadline
+    }
+
+    pub fn is_htlc(&self) -> bool {
+        self.htlc_taker != Pubkey::default()
+    }
+
+    pub fn htlc_expired(&self, now: i64) -> bool {
+        self.is_htlc() && now > self.htlc_timeout
+    }
+
+    pub fn paid_through_wormhole(&self) -> bool {
+        self.wormhole_program != Pubkey::default()
+    }
+
+    pub fn in_ring(&self) -> bool {
+        self.ring_recipient != Pubkey::default()
+    }
+
+    /// A fixed lamport-priced sale out of a vault with none of the other sale
+    /// modes, which `match_escrows` settles without `exchange`'s accounts.
+    pub fn is_plain_listing(&self) -> bool {
+        !self.delegated
+            && self.price_usd_cents == 0
+            && self.donation_bps == 0
+            && self.gate_mint == Pubkey::default()
+            && self.settlement_delay_secs == 0
+            && self.vesting_duration_secs == 0
+            && !self.is_printing_editions()
+            && !self.is_reserved()
+            && !self.is_settling()
+            && !self.in_payment_plan()
+            && !self.in_rent_to_own()
+            && !self.on_loan()
+            && !self.in_bnpl()
+            && !self.is_gift()
+            && !self.is_htlc()
+            && !self.paid_through_wormhole()
+            && !self.in_ring()
+            && !self.is_integrated()
+            && !self.fills_privately()
+            && !self.has_terms()
+            && !self.awaiting_deposit
+    }
+
+    pub fn fills_privately(&self) -> bool {
+        self.private_fills
+    }
+
+    pub fn has_terms(&self) -> bool {
+        self.terms_hash != [0; 32]
+    }
+
+    /// Whether a taker passing `terms_hash` has agreed to the listing's
+    /// terms; listings without terms need no acknowledgment.
+    pub fn terms_acknowledged(&self, terms_hash: &Option<[u8; 32]>) -> bool {
+        !self.has_terms() || terms_hash.as_ref() == Some(&self.terms_hash)
+    }
+
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expires_at != 0 && now > self.expires_at
+    }
+
+    pub fn require_listing_age(&self, config: &Config, now: i64) -> Result<()> {
+        let buyable_at = self
+            .created_at
+            .checked_add(config.min_listing_age_secs)
+            .ok_or(EscrowError::ArithmeticOverflow)?;
+        require!(now >= buyable_at, EscrowError::ListingTooNew);
+        Ok(())
+    }
+
+    pub fn is_integrated(&self) -> bool {
+        self.integrator != Pubkey::default()
+    }
+
+    pub fn is_printing_editions(&self) -> bool {
+        self.edition_max_prints > 0
+    }
+
+    /// Price of the print after the current one, following the bonding curve.
+    pub fn next_edition_price(&self) -> Result<u64> {
+        let grown = mul_div(
+            self.edition_price,
+            BPS_DENOMINATOR + self.edition_price_growth_bps as u64,
+            BPS_DENOMINATOR,
+        )?;
+        checked_add(grown, self.edition_price_step)
+    }
+
+    /// Exchanged and paying out the seller's proceeds through `claim_vested`.
+    pub fn is_vesting(&self) -> bool {
+        self.vesting_start > 0
+    }
+
+    pub fn vested_amount(&self, now: i64) -> Result<u64> {
+        let elapsed = now.saturating_sub(self.vesting_start);
+        if elapsed < self.vesting_cliff_secs {
+            return Ok(0);
+        }
+        if elapsed >= self.vesting_duration_secs {
+            return Ok(self.vesting_total);
+        }
+        mul_div(
+            self.vesting_total,
+            elapsed as u64,
+            self.vesting_duration_secs as u64,
+        )
+    }
+}
+
+/// A wallet's listings and fills in the current rate limit window.
+#[account]
+pub struct WalletActivity {
+    pub wallet: Pubkey,
+    pub window_start: i64,
+    pub listings: u16,
+    pub fills: u16,
+    pub bump: u8,
+}
+
+impl WalletActivity {
+    pub fn space() -> usize {
+        8 + 45
+    }
+
+    /// Counts one listing or fill, starting a new window once the current
+    /// one has passed.
+    pub fn record(&mut self, window_secs: i64, now: i64, listing: bool, limit: u16) -> Result<()> {
+        let window_end = self
+            .window_start
+            .checked_add(window_secs)
+            .ok_or(EscrowError::ArithmeticOverflow)?;
+        if now >= window_end {
+            self.window_start = now;
+            self.listings = 0;
+            self.fills = 0;
+        }
+        let count = if listing {
+            &mut self.listings
+        } else {
+            &mut self.fills
+        };
+        *count = count
+            .checked_add(1)
+            .ok_or(EscrowError::ArithmeticOverflow)?;
+        require!(*count <= limit, EscrowError::RateLimited);
+        Ok(())
+    }
+}
+
+/// Hands out sequential escrow nonces per initializer.
+#[account]
+pub struct ListingCounter {
+    pub initializer: Pubkey,
+    pub count: u64,
+    pub bump: u8,
+}
+
+impl ListingCounter {
+    pub fn space() -> usize {
+        8 + 41
+    }
+
+    /// Hands `initializer` the nonce of their next listing.
+    pub fn take_nonce(&mut self, initializer: Pubkey, bump: u8) -> Result<u64> {
+        self.initializer = initializer;
+        self.bump = bump;
+        let nonce = self.count;
+        self.count = checked_add(self.count, 1)?;
+        Ok(nonce)
+    }
+}
+
+#[account]
+pub struct Config {
+    pub admin: Pubkey,
+    pub treasury: Pubkey,
+    pub maker_fee_bps: u16,
+    pub taker_fee_bps: u16,
+    pub referral_share_bps: u16,
+    pub bump: u8,
+    /// Takers holding at least `discount_min_amount` of this mint pay the
+    /// discounted rates below. `Pubkey::default()` disables the tier.
+    pub discount_mint: Pubkey,
+    pub discount_min_amount: u64,
+    pub discount_maker_fee_bps: u16,
+    pub discount_taker_fee_bps: u16,
+    /// Loyalty points minted to both parties per SOL of trade value.
+    /// `Pubkey::default()` disables rewards.
+    pub rewards_mint: Pubkey,
+    pub rewards_per_sol: u64,
+    pub rewards_paused: bool,
+    pub rewards_authority_bump: u8,
+    /// Oldest and least precise oracle price accepted for USD-priced escrows.
+    pub oracle_max_age_secs: u64,
+    pub oracle_max_conf_bps: u16,
+    /// Largest allowed gap between the spot and EMA (TWAP) price; zero disables the band.
+    pub oracle_max_twap_deviation_bps: u16,
+    /// Rejects exchanges where the taker is also the initializer.
+    pub prevent_self_trade: bool,
+    /// Deposit a reserving taker puts down on top of the payment, as a share
+    /// of the price, and how long they have to finalize before losing it.
+    /// A zero window never lapses.
+    pub reservation_deposit_bps: u16,
+    pub reservation_window_secs: i64,
+    /// Treasury's share of a printed edition's price, replacing the maker
+    /// fee so the rest goes to the creator; zero keeps the maker fee.
+    pub edition_treasury_bps: u16,
+    /// Seller's share of the surplus when `match_escrows` fills a listing
+    /// from a higher offer; the buyer keeps the rest.
+    pub match_surplus_seller_bps: u16,
+    /// Share of that surplus `crank_match` tips its caller, taken first.
+    pub crank_tip_bps: u16,
+    /// Zero for the default market, else the id passed to `create_market`.
+    pub market_id: u64,
+    /// How long after `initialize` a listing first fills.
+    pub min_listing_age_secs: i64,
+    /// Listings and fills one wallet may make per window, counted in its
+    /// `WalletActivity`; zero leaves that side unlimited.
+    pub rate_limit_window_secs: i64,
+    pub max_listings_per_window: u16,
+    pub max_fills_per_window: u16,
+    /// Furthest ahead `extend_expiry` may set a listing's expiry.
+    pub max_listing_duration_secs: i64,
+    /// Oldest collection floor a listing is priced against; zero accepts
+    /// a floor of any age.
+    pub max_floor_age_secs: i64,
+}
+
+impl Config {
+    pub fn space() -> usize {
+        8 + 230
+    }
+
+    pub fn rewards_active(&self) -> bool {
+        self.rewards_mint != Pubkey::default() && !self.rewards_paused
+    }
+
+    /// Returns the `(maker, taker)` fee rates for a trade. A collection override
+    /// replaces the global rates, and discounted takers never pay more than the
+    /// discount tier.
+    pub fn fee_bps(&self, collection_fee_bps: Option<(u16, u16)>, discounted: bool) -> (u16, u16) {
+        let (maker_fee_bps, taker_fee_bps) =
+            collection_fee_bps.unwrap_or((self.maker_fee_bps, self.taker_fee_bps));
+        if discounted {
+            (
+                maker_fee_bps.min(self.discount_maker_fee_bps),
+                taker_fee_bps.min(self.discount_taker_fee_bps),
+            )
+        } else {
+            (maker_fee_bps, taker_fee_bps)
+        }
+    }
+}
+
+#[account]
+pub struct CollectionFeeOverride {
+    pub collection_mint: Pubkey,
+    pub maker_fee_bps: u16,
+    pub taker_fee_bps: u16,
+    pub bump: u8,
+}
+
+impl CollectionFeeOverride {
+    pub fn space() -> usize {
+        8 + 37
+    }
+}
+
+/// Floor price published for a collection by its floor authority.
+#[account]
+pub struct CollectionFloor {
+    pub collection_mint: Pubkey,
+    pub floor_authority: Pubkey,
+    pub floor_lamports: u64,
+    pub max_below_floor_bps: u16,
+    pub updated_at: i64,
+    pub bump: u8,
+}
+
+impl CollectionFloor {
+    pub fn space() -> usize {
+        8 + 83
+    }
+
+    /// Lowest fixed price a listing of this collection may be created at.
+    pub fn min_listing_price(&self) -> Result<u64> {
+        let floor_share_bps = BPS_DENOMINATOR as u16 - self.max_below_floor_bps;
+        bps_of(self.floor_lamports, floor_share_bps)
+    }
+}
+
+/// Registers a collection DAO's treasury to receive a share of protocol fees.
+#[account]
+pub struct CollectionTreasury {
+    pub collection_mint: Pubkey,
+    pub treasury: Pubkey,
+    pub share_bps: u16,
+    pub bump: u8,
+}
+
+impl CollectionTreasury {
+    pub fn space() -> usize {
+        8 + 67
+    }
+}
+
+/// Installments a taker has paid towards one listing, held as lamports in
+/// this account until the NFT is released or the plan is reclaimed.
+#[account]
+pub struct PaymentPlan {
+    pub escrow_state: Pubkey,
+    pub taker: Pubkey,
+    pub installments: u8,
+    pub installments_paid: u8,
+    /// Size of every installment but the last, which covers the rest of the
+    /// quote at completion.
+    pub installment_amount: u64,
+    pub paid: u64,
+    pub interval_secs: i64,
+    /// Unix time the next installment is due by.
+    pub next_due: i64,
+    pub bump: u8,
+}
+
+/// A buy-now-pay-later purchase. Holds the collateral and the installments
+/// paid as lamports until the NFT is paid off or clawed back.
+#[account]
+pub struct BnplPurchase {
+    pub escrow_state: Pubkey,
+    pub buyer: Pubkey,
+    /// Where the NFT sits frozen while it is being paid off.
+    pub buyer_token_account: Pubkey,
+    /// Quoted price plus the taker fee, fixed when the purchase started.
+    pub total: u64,
+    /// Maker and taker fee, paid to the treasury with the last installment.
+    pub fees: u64,
+    pub collateral: u64,
+    pub installments: u8,
+    pub installments_paid: u8,
+    pub installment_amount: u64,
+    pub paid: u64,
+    pub interval_secs: i64,
+    /// Unix time the next installment is due by.
+    pub next_due: i64,
+    pub bump: u8,
+}
+
+impl BnplPurchase {
+    pub fn space() -> usize {
+        8 + 155
+    }
+
+    pub fn overdue(&self, now: i64) -> bool {
+        now > self.next_due
+    }
+}
+
+/// A funded loan secured by the NFT in an escrow's vault.
+#[account]
+pub struct LoanState {
+    pub escrow_state: Pubkey,
+    pub borrower: Pubkey,
+    pub lender: Pubkey,
+    pub principal: u64,
+    /// Simple yearly interest, accrued per second from `started_at`.
+    pub apr_bps: u16,
+    pub started_at: i64,
+    /// Unix time after which the lender may liquidate.
+    pub due_at: i64,
+    pub bump: u8,
+}
+
+impl LoanState {
+    pub fn space() -> usize {
+        8 + 123
+    }
+
+    pub fn accrued_interest(&self, now: i64) -> Result<u64> {
+        let elapsed = now.saturating_sub(self.started_at).max(0) as u64;
+        mul_div(
+            self.principal,
+            (self.apr_bps as u64)
+                .checked_mul(elapsed)
+                .ok_or(EscrowError::ArithmeticOverflow)?,
+            BPS_DENOMINATOR * SECONDS_PER_YEAR,
+        )
+    }
+}
+
+/// A vault of edition copies handed out one per period to subscribers.
+#[account]
+pub struct SubscriptionListing {
+    pub seller: Pubkey,
+    pub mint: Pubkey,
+    /// Lamports per delivery, before fees.
+    pub price: u64,
+    pub period_secs: i64,
+    pub vault_authority_bump: u8,
+    pub bump: u8,
+}
+
+impl SubscriptionListing {
+    pub fn space() -> usize {
+        8 + 82
+    }
+}
+
+/// One subscriber's subscription to a `SubscriptionListing`.
+#[account]
+pub struct Subscription {
+    pub subscription_listing: Pubkey,
+    pub subscriber: Pubkey,
+    pub deliveries: u64,
+    /// Unix time the next copy can be renewed at.
+    pub next_delivery: i64,
+    pub bump: u8,
+}
+
+impl Subscription {
+    pub fn space() -> usize {
+        8 + 81
+    }
+}
+
+/// A two-sided pool of one collection's NFTs and the SOL it buys them
+/// with. The SOL is held here as lamports on top of the rent reserve.
+#[account]
+pub struct NftPool {
+    pub owner: Pubkey,
+    pub collection_mint: Pubkey,
+    pub spot_price: u64,
+    pub delta: u64,
+    pub spread_bps: u16,
+    /// Lamports available to buy NFTs with.
+    pub sol_reserve: u64,
+    pub nft_count: u64,
+    pub vault_authority_bump: u8,
+    pub bump: u8,
+}
+
+impl NftPool {
+    pub fn space() -> usize {
+        8 + 100
+    }
+
+    /// What a trader pays for the next NFT: spot moved up by `delta`, plus
+    /// the spread.
+    pub fn buy_price(&self) -> Result<u64> {
+        let price = checked_add(self.spot_price, self.delta)?;
+        checked_add(price, bps_of(price, self.spread_bps)?)
+    }
+
+    /// What the pool pays for the next NFT: spot less the spread.
+    pub fn sell_price(&self) -> Result<u64> {
+        checked_sub(self.spot_price, bps_of(self.spot_price, self.spread_bps)?)
+    }
+}
+
+/// An NFT locked against a fixed supply of fraction tokens, and the buyout
+/// price held here as lamports until holders redeem their fractions.
+#[account]
+pub struct FractionVault {
+    pub depositor: Pubkey,
+    pub mint: Pubkey,
+    pub fraction_mint: Pubkey,
+    pub fraction_supply: u64,
+    pub reserve_price: u64,
+    /// Buyer of the NFT, or the default key while it is locked.
+    pub bought_out_by: Pubkey,
+    /// Fractions not yet redeemed, and the part of the buyout price they
+    /// are owed.
+    pub fractions_outstanding: u64,
+    pub buyout_remaining: u64,
+    pub vault_authority_bump: u8,
+    pub bump: u8,
+}
+
+impl FractionVault {
+    pub fn space() -> usize {
+        8 + 162
+    }
+
+    pub fn is_bought_out(&self) -> bool {
+        self.bought_out_by != Pubkey::default()
+    }
+}
+
+/// A crowdfunded purchase of one listing. The raised SOL and, once bought,
+/// the NFT sit with the data-less custody PDA `[b"custody", purchase_pool]`.
+#[account]
+pub struct PurchasePool {
+    pub escrow_state: Pubkey,
+    /// Quoted total the contributions must cover.
+    pub target: u64,
+    pub raised: u64,
+    pub executed: bool,
+    pub custody_bump: u8,
+    pub bump: u8,
+}
+
+impl PurchasePool {
+    pub fn space() -> usize {
+        8 + 51
+    }
+}
+
+/// One contributor's stake in a `PurchasePool`, and so their share of the
+/// NFT once it is bought.
+#[account]
+pub struct Contribution {
+    pub purchase_pool: Pubkey,
+    pub contributor: Pubkey,
+    pub amount: u64,
+    pub bump: u8,
+}
+
+impl Contribution {
+    pub fn space() -> usize {
+        8 + 73
+    }
+}
+
+/// Default terms a seller lists with through `initialize_from_template`.
+#[account]
+pub struct ListingTemplate {
+    pub owner: Pubkey,
+    pub template_id: u64,
+    pub taker_amount: u64,
+    pub donation_recipient: Pubkey,
+    pub donation_bps: u16,
+    pub settlement_delay_secs: i64,
+    pub tag: [u8; 8],
+    pub note: String,
+    pub bump: u8,
+}
+
+impl ListingTemplate {
+    pub fn space() -> usize {
+        8 + 103 + MAX_NOTE_LEN
+    }
+}
+
+/// Rolling hash of everything done to an escrow since its trail was
+/// opened. Each `AuditRecorded` event carries one entry, so replaying them
+/// through `audit_entry_hash` must end at `head`.
+#[account]
+pub struct AuditTrail {
+    pub escrow_state: Pubkey,
+    pub initializer: Pubkey,
+    pub head: [u8; 32],
+    pub entries: u64,
+    pub bump: u8,
+}
+
+impl AuditTrail {
+    pub fn space() -> usize {
+        8 + 105
+    }
+
+    pub fn record(&mut self, action: u8, actor: &Pubkey, amount: u64, slot: u64) -> Result<()> {
+        self.head = audit_entry_hash(&self.head, action, actor, amount, slot);
+        self.entries = self
+            .entries
+            .checked_add(1)
+            .ok_or(EscrowError::ArithmeticOverflow)?;
+        emit!(AuditRecorded {
+            escrow_state: self.escrow_state,
+            action,
+            actor: *actor,
+            amount,
+            slot,
+            head: self.head,
+        });
+        Ok(())
+    }
+}
+
+/// An ephemeral key allowed to act for `owner` within `scope` until
+/// `expires_at`, so games can list without prompting the main wallet.
+#[account]
+pub struct Session {
+    pub owner: Pubkey,
+    pub session_key: Pubkey,
+    pub scope: u8,
+    pub expires_at: i64,
+    pub bump: u8,
+}
+
+impl Session {
+    pub fn space() -> usize {
+        8 + 74
+    }
+
+    pub fn require_scope(&self, scope: u8, now: i64) -> Result<()> {
+        require!(now < self.expires_at, EscrowError::SessionExpired);
+        require!(self.scope & scope == scope, EscrowError::SessionScopeDenied);
+        Ok(())
+    }
+}
+
+/// Marks a signed order's nonce as used, by a fill or a cancellation.
+#[account]
+pub struct FilledOrder {
+    pub bump: u8,
+}
+
+impl FilledOrder {
+    pub fn space() -> usize {
+        8 + 1
+    }
+}
+
+/// Tokens a deal room party put in, and the party they go to.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct DealDeposit {
+    pub depositor: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub recipient: Pubkey,
+}
+
+/// A multi-party OTC deal whose deposits sit in vaults owned by this
+/// account's vault authority until every party approves.
+#[account]
+pub struct DealRoom {
+    pub creator: Pubkey,
+    pub deal_id: u64,
+    pub parties: Vec<Pubkey>,
+    /// Whether each party, by position, approves the current deposits.
+    pub approved: Vec<bool>,
+    pub deposits: Vec<DealDeposit>,
+    pub vault_authority_bump: u8,
+    pub bump: u8,
+}
+
+impl DealRoom {
+    pub fn space() -> usize {
+        8 + 42 + (4 + MAX_DEAL_PARTIES * 33) + 4 + (4 + MAX_DEAL_DEPOSITS * 104)
+    }
+
+    fn party_index(&self, key: Pubkey) -> Result<usize> {
+        self.parties
+            .iter()
+            .position(|party| *party == key)
+            .ok_or_else(|| error!(EscrowError::NotDealParty))
+    }
+}
+
+/// An ask or bid in an `OrderBook`: the listing's escrow state or the buy
+/// offer, and its price when it was posted.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq)]
+pub struct BookOrder {
+    pub account: Pubkey,
+    pub price: u64,
+}
+
+/// The asks and bids posted for a collection, so clients can read its book
+/// without scanning every program account. Closed orders linger until
+/// `compact_order_book` drops them.
+#[account]
+pub struct OrderBook {
+    pub collection: Pubkey,
+    pub asks: Vec<BookOrder>,
+    pub bids: Vec<BookOrder>,
+    pub bump: u8,
+}
+
+impl OrderBook {
+    pub fn space() -> usize {
+        8 + 33 + 2 * (4 + MAX_BOOK_ORDERS * 40)
+    }
+
+    fn post(&mut self, ask: bool, order: BookOrder) -> Result<()> {
+        let orders = if ask { &mut self.asks } else { &mut self.bids };
+        require!(
+            !orders.iter().any(|posted| posted.account == order.account),
+            EscrowError::OrderAlreadyPosted
+        );
+        require!(orders.len() < MAX_BOOK_ORDERS, EscrowError::OrderBookFull);
+        orders.push(order);
+        Ok(())
+    }
+}
+
+/// A standing bid for one NFT, by mint or by verified collection, holding
+/// the bid in lamports until `match_escrows` fills it.
+#[account]
+pub struct BuyOffer {
+    pub buyer: Pubkey,
+    pub offer_id: u64,
+    /// The NFT wanted, or the default key for a collection offer.
+    pub mint: Pubkey,
+    /// The collection wanted, or the default key for a mint offer.
+    pub collection: Pubkey,
+    pub max_price: u64,
+    /// Unix time after which the offer no longer fills; zero never expires.
+    pub expires_at: i64,
+    pub bump: u8,
+}
+
+impl BuyOffer {
+    pub fn space() -> usize {
+        8 + 121
+    }
+
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expires_at != 0 && now > self.expires_at
+    }
+}
+
+/// One side's share of a bundle swap.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct SwapAsset {
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub deposited: bool,
+}
+
+/// An N-for-M swap between a maker and a taker, each side depositing its
+/// assets into vaults owned by this account's vault authority.
+#[account]
+pub struct SwapState {
+    pub maker: Pubkey,
+    pub taker: Pubkey,
+    pub swap_id: u64,
+    pub maker_assets: Vec<SwapAsset>,
+    pub taker_assets: Vec<SwapAsset>,
+    pub vault_authority_bump: u8,
+    pub bump: u8,
+}
+
+impl SwapState {
+    pub fn space() -> usize {
+        8 + 74 + 2 * (4 + MAX_SWAP_ASSETS * 41)
+    }
+}
+
+/// NFTs in program custody waiting to be claimed by the wallets committed to
+/// in `merkle_root`.
+#[account]
+pub struct Airdrop {
+    pub project: Pubkey,
+    pub merkle_root: [u8; 32],
+    /// When unclaimed NFTs may be reclaimed; zero never.
+    pub expires_at: i64,
+    pub nft_count: u64,
+    pub vault_authority_bump: u8,
+    pub bump: u8,
+}
+
+impl Airdrop {
+    pub fn space() -> usize {
+        8 + 82
+    }
+}
+
+/// An NFT deposited to be rented out, and the current rental if any. Holds
+/// the renter's collateral as lamports while the NFT is out.
+#[account]
+pub struct RentalListing {
+    pub owner: Pubkey,
+    pub mint: Pubkey,
+    pub rent_lamports: u64,
+    pub collateral_lamports: u64,
+    pub duration_secs: i64,
+    /// Current renter, or the default key while the NFT is in the vault.
+    pub renter: Pubkey,
+    /// Unix time the NFT is due back by.
+    pub rented_until: i64,
+    pub vault_authority_bump: u8,
+    pub bump: u8,
+}
+
+impl RentalListing {
+    pub fn space() -> usize {
+        8 + 130
+    }
+
+    pub fn is_rented(&self) -> bool {
+        self.renter != Pubkey::default()
+    }
+}
+
+/// A renter's progress towards owning a listing's NFT.
+#[account]
+pub struct RentToOwn {
+    pub escrow_state: Pubkey,
+    pub renter: Pubkey,
+    /// Where the NFT sits frozen while it is being paid off.
+    pub renter_token_account: Pubkey,
+    pub paid: u64,
+    /// Unix time the rent is paid up to.
+    pub paid_until: i64,
+    pub bump: u8,
+}
+
+impl RentToOwn {
+    pub fn space() -> usize {
+        8 + 113
+    }
+
+    pub fn lapsed(&self, now: i64) -> bool {
+        now > self.paid_until
+    }
+}
+
+impl PaymentPlan {
+    pub fn space() -> usize {
+        8 + 99
+    }
+
+    pub fn overdue(&self, now: i64) -> bool {
+        now > self.next_due
+    }
+}
+
+/// What `prove_reserves` checked: how many escrows, and a hash chained over
+/// each escrow, its mint and the amount found in custody.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct ReserveProof {
+    pub escrows: u32,
+    pub hash: [u8; 32],
+}
+
+/// What settling an escrow costs, as returned by `get_quote`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct Quote {
+    pub price: u64,
+    pub maker_fee: u64,
+    pub taker_fee: u64,
+    /// Total the taker pays: the price plus the taker fee.
+    pub total: u64,
+}
+
+/// Returned by `get_quote_route`. `version` comes first so a reader can
+/// check it before decoding the rest.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct QuoteRoute {
+    pub version: u8,
+    pub payment_mint: Pubkey,
+    pub price: u64,
+    /// Total the taker pays: the price plus the taker fee.
+    pub total: u64,
+    /// Who `exchange` pays out of the total; zero payouts are left out.
+    pub payouts: Vec<Payout>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct Payout {
+    pub recipient: Pubkey,
+    pub amount: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct EscrowAddresses {
+    pub escrow_state: Pubkey,
+    /// Where the listed NFT sits: the vault, or the seller's own token account
+    /// for a delegated listing.
+    pub vault: Pubkey,
+    pub nonce: u64,
+}
+
+#[event]
+pub struct EscrowExchanged {
+    pub escrow_state: Pubkey,
+    pub initializer: Pubkey,
+    pub taker: Pubkey,
+    pub initializer_amount: u64,
+    /// Gross sale price, before any fee.
+    pub taker_amount: u64,
+    pub maker_fee: u64,
+    pub taker_fee: u64,
+    /// Maker and taker fee together, including the referral and DAO shares.
+    pub protocol_fee: u64,
+    pub referrer: Option<Pubkey>,
+    pub referral_fee: u64,
+    pub donation_recipient: Pubkey,
+    pub donation: u64,
+    pub dao_treasury: Option<Pubkey>,
+    pub dao_fee: u64,
+    pub integrator_fee: u64,
+    /// What the seller keeps of the price, whether paid now or held for a
+    /// delayed settlement or vesting.
+    pub net_to_seller: u64,
+    /// Prices are in lamports, paid as SOL or wrapped SOL alike.
+    pub payment_mint: Pubkey,
+}
+
+#[event]
+pub struct AuditRecorded {
+    pub escrow_state: Pubkey,
+    pub action: u8,
+    pub actor: Pubkey,
+    pub amount: u64,
+    pub slot: u64,
+    pub head: [u8; 32],
+}
+
+/// What `cancel` actually returned to the seller: the NFT amount and the
+/// vault's rent, both zero for a vault already torn down.
+#[event]
+pub struct EscrowCancelled {
+    pub escrow_state: Pubkey,
+    pub initializer: Pubkey,
+    pub refunded_amount: u64,
+    pub refunded_rent: u64,
+}
+
+#[event]
+pub struct ListingRelisted {
+    pub escrow_state: Pubkey,
+    pub taker_amount: u64,
+    pub price_usd_cents: u64,
+    pub expires_at: i64,
+}
+
+#[error_code]
+pub enum EscrowError {
+    #[msg("Fee basis points must not exceed 10000")]
+    InvalidFeeBps,
+    #[msg("Mint does not match the escrowed mint")]
+    EscrowMintMismatch,
+    #[msg("Taker receive token account is not for the escrowed mint")]
+    TakerReceiveMintMismatch,
+    #[msg("Taker receive token account is not owned by the taker")]
+    TakerReceiveOwnerMismatch,
+    #[msg("Initializer receive token account is not for the requested mint")]
+    InitializerReceiveMintMismatch,
+    #[msg("Taker and initializer must be different wallets")]
+    SelfTrade,
+    #[msg("Arithmetic overflow")]
+    ArithmeticOverflow,
+    #[msg("Arithmetic underflow")]
+    ArithmeticUnderflow,
+    #[msg("Discount token account is not owned by the taker")]
+    DiscountAccountOwnerMismatch,
+    #[msg("Discount token account does not hold the configured discount mint")]
+    DiscountAccountMintMismatch,
+    #[msg("Collection checks require the NFT metadata account")]
+    MissingNftMetadata,
+    #[msg("Metadata account does not belong to the escrowed mint")]
+    NftMetadataMismatch,
+    #[msg("NFT is not a verified member of the collection")]
+    CollectionMismatch,
+    #[msg("A donation share requires a donation recipient")]
+    MissingDonationRecipient,
+    #[msg("Donation recipient does not match the escrow")]
+    DonationRecipientMismatch,
+    #[msg("Rewards mint does not match the config")]
+    RewardsMintMismatch,
+    #[msg("Rewards mint authority must be the program rewards PDA")]
+    RewardsMintAuthorityMismatch,
+    #[msg("Rewards are active but the rewards accounts were not provided")]
+    MissingRewardsAccounts,
+    #[msg("Rewards token account has the wrong owner or mint")]
+    RewardsAccountMismatch,
+    #[msg("A registered collection treasury requires the DAO treasury account")]
+    MissingDaoTreasury,
+    #[msg("DAO treasury does not match the registered collection treasury")]
+    DaoTreasuryMismatch,
+    #[msg("A USD price requires a price feed")]
+    MissingPriceFeed,
+    #[msg("Price feed does not match the escrow")]
+    PriceFeedMismatch,
+    #[msg("Price feed account could not be parsed")]
+    InvalidPriceFeed,
+    #[msg("Oracle price is stale")]
+    StalePrice,
+    #[msg("Oracle price confidence interval is too wide")]
+    PriceConfidenceTooWide,
+    #[msg("Oracle price cannot be converted to lamports")]
+    InvalidOraclePrice,
+    #[msg("Listing price is too far below the collection floor")]
+    PriceBelowFloor,
+    #[msg("Oracle price deviates too far from its TWAP")]
+    PriceOutsideTwapBand,
+    #[msg("wSOL account is not the taker's native-mint token account")]
+    WsolAccountMismatch,
+    #[msg("wSOL payments require the native mint account")]
+    MissingNativeMint,
+    #[msg("A payout recipient's wSOL account was not provided")]
+    MissingWsolDestination,
+    #[msg(
+        "Delegated listings require the seller's token account, edition and token metadata program"
     )]
-    pub escrow_state: Box<Account<'info, EscrowState>>,
-    /// CHECK: This is not dangerous because we don't read or write from this account
-    pub token_program: Program<'info, Token>,
+    MissingDelegatedListingAccounts,
+    #[msg("Vault listings require the vault account")]
+    MissingVault,
+    #[msg("Escrow is reserved by a taker")]
+    EscrowReserved,
+    #[msg("Escrow is not reserved")]
+    NotReserved,
+    #[msg("Escrow is reserved by a different taker")]
+    ReservationMismatch,
+    #[msg("Only the seller or the reserving taker may abort a reservation")]
+    AbortNotAllowed,
+    #[msg("Reservation window must not be negative")]
+    InvalidReservationWindow,
+    #[msg("Reservation has expired")]
+    ReservationExpired,
+    #[msg("Settlement delay must not be negative")]
+    InvalidSettlementDelay,
+    #[msg("Escrow is waiting for a delayed settlement")]
+    EscrowSettling,
+    #[msg("Escrow is not waiting for a delayed settlement")]
+    NotSettling,
+    #[msg("Delayed settlements cannot be paid in wSOL")]
+    DelayedSettlementWsol,
+    #[msg("Delayed settlements require the settlement vault")]
+    MissingSettlementVault,
+    #[msg("Only the seller or the taker may settle or dispute")]
+    NotSettlementParty,
+    #[msg("The cooling-off window has not passed yet")]
+    SettlementWindowOpen,
+    #[msg("The cooling-off window has already passed")]
+    SettlementWindowClosed,
+    #[msg("Settlement is disputed")]
+    SettlementDisputed,
+    #[msg("Settlement is not disputed")]
+    NotDisputed,
+    #[msg("Only the config admin may resolve disputes")]
+    NotDisputeResolver,
+    #[msg("Payment plans need at least two installments and a positive interval")]
+    InvalidPaymentPlan,
+    #[msg("Listing does not offer a payment plan")]
+    PaymentPlanNotOffered,
+    #[msg("Listing is being paid in installments")]
+    PaymentPlanActive,
+    #[msg("Payment plan belongs to another taker")]
+    PaymentPlanMismatch,
+    #[msg("Completing a payment plan requires the payment plan account")]
+    MissingPaymentPlan,
+    #[msg("The last installment must be paid through complete_payment_plan")]
+    FinalInstallmentRequired,
+    #[msg("Installments are still outstanding")]
+    InstallmentsOutstanding,
+    #[msg("Installment is overdue")]
+    InstallmentOverdue,
+    #[msg("Installment is not overdue yet")]
+    InstallmentNotOverdue,
+    #[msg("Rent-to-own needs a positive period and a target above one payment")]
+    InvalidRentToOwn,
+    #[msg("Delegated listings cannot be rented to own")]
+    RentToOwnDelegated,
+    #[msg("Listing does not offer rent-to-own")]
+    RentToOwnNotOffered,
+    #[msg("Listing is being rented to own")]
+    RentToOwnActive,
+    #[msg("Rent-to-own belongs to another renter")]
+    RentToOwnMismatch,
+    #[msg("Rent has lapsed")]
+    RentLapsed,
+    #[msg("Rent has not lapsed yet")]
+    RentNotLapsed,
+    #[msg("Rentals need collateral and a positive duration")]
+    InvalidRentalTerms,
+    #[msg("NFT is already rented out")]
+    AlreadyRented,
+    #[msg("NFT is not rented out")]
+    NotRented,
+    #[msg("Only the current renter may return the NFT")]
+    NotRenter,
+    #[msg("Rental is not overdue yet")]
+    RentalNotOverdue,
+    #[msg("Loans need a positive duration")]
+    InvalidLoanTerms,
+    #[msg("Delegated listings cannot secure a loan")]
+    DelegatedLoan,
+    #[msg("No loan is requested for this escrow")]
+    LoanNotRequested,
+    #[msg("Escrow is securing a loan")]
+    EscrowOnLoan,
+    #[msg("Loan is not due yet")]
+    LoanNotDue,
+    #[msg("Vesting cliff must lie within a non-negative duration")]
+    InvalidVestingSchedule,
+    #[msg("Vesting cannot be combined with a delayed settlement")]
+    VestingWithDelayedSettlement,
+    #[msg("Vesting sales cannot be paid in wSOL")]
+    VestingWsol,
+    #[msg("Escrow is paying out vested proceeds")]
+    EscrowVesting,
+    #[msg("Escrow is not paying out vested proceeds")]
+    NotVesting,
+    #[msg("Buy-now-pay-later needs two or more installments, a positive interval and collateral above the price")]
+    InvalidBnplTerms,
+    #[msg("Delegated listings cannot be bought now and paid later")]
+    BnplDelegated,
+    #[msg("Listing does not offer buy-now-pay-later")]
+    BnplNotOffered,
+    #[msg("Listing is being paid off by a buy-now-pay-later buyer")]
+    BnplActive,
+    #[msg("Purchase belongs to another buyer")]
+    BnplMismatch,
+    #[msg("Subscriptions need a price, a positive period and some stock")]
+    InvalidSubscriptionTerms,
+    #[msg("This period's copy has already been delivered")]
+    SubscriptionPeriodNotElapsed,
+    #[msg("Already subscribed; renew instead")]
+    AlreadySubscribed,
+    #[msg("Edition printing needs a price per print")]
+    InvalidEditionPrinting,
+    #[msg("Delegated listings cannot print editions")]
+    EditionPrintingDelegated,
+    #[msg("Edition printing cannot be combined with a delayed settlement or vesting")]
+    EditionPrintingConflict,
+    #[msg("Listing prints editions of its master edition")]
+    PrintingEditions,
+    #[msg("No editions are left to print")]
+    EditionSupplyExhausted,
+    #[msg("Edition printing requires the master edition and new edition accounts")]
+    MissingEditionAccounts,
+    #[msg("Pool does not hold enough SOL")]
+    InsufficientPoolLiquidity,
+    #[msg("Pool price moved past the given limit")]
+    PoolPriceMoved,
+    #[msg("Pool still holds NFTs")]
+    PoolNotEmpty,
+    #[msg("Fractionalizing needs a fraction supply and a reserve price")]
+    InvalidFractionTerms,
+    #[msg("NFT has already been bought out")]
+    AlreadyBoughtOut,
+    #[msg("NFT has not been bought out yet")]
+    NotBoughtOut,
+    #[msg("No fractions to redeem")]
+    NoFractionsHeld,
+    #[msg("Delegated listings cannot be bought by a purchase pool")]
+    GroupPurchaseDelegated,
+    #[msg("Contribution must be positive and the pool not yet funded")]
+    InvalidContribution,
+    #[msg("Purchase pool has already bought the listing")]
+    GroupPurchaseExecuted,
+    #[msg("Purchase pool has not reached its target")]
+    GroupPurchaseUnderfunded,
+    #[msg("Listing price changed since the purchase pool opened")]
+    GroupPurchasePriceChanged,
+    #[msg("Taker does not hold enough of the gate token")]
+    PurchaseGateNotMet,
+    #[msg("Gate token account must be the taker's account of the gate mint")]
+    PurchaseGateAccountMismatch,
+    #[msg("Token owner record is not the taker's record for the gate mint")]
+    InvalidTokenOwnerRecord,
+    #[msg("Gifts need a zero price, a recipient and a future or zero deadline")]
+    InvalidGift,
+    #[msg("Delegated listings cannot be gifted")]
+    GiftDelegated,
+    #[msg("Gifts can only be claimed by their recipient")]
+    GiftEscrow,
+    #[msg("Escrow is not a gift")]
+    NotGift,
+    #[msg("Signer is not the gift's recipient")]
+    NotGiftRecipient,
+    #[msg("Gift's claim deadline has passed")]
+    GiftExpired,
+    #[msg("Gift can still be claimed")]
+    GiftNotExpired,
+    #[msg("Merkle proof does not match the airdrop")]
+    InvalidMerkleProof,
+    #[msg("Airdrop can still be claimed")]
+    AirdropNotExpired,
+    #[msg("Airdrop still holds NFTs")]
+    AirdropNotEmpty,
+    #[msg("HTLC needs a taker, a future timeout and no on-chain price")]
+    InvalidHtlc,
+    #[msg("Delegated listings cannot be hashed-timelock swaps")]
+    HtlcDelegated,
+    #[msg("Listing only releases to its HTLC taker with the preimage")]
+    HtlcEscrow,
+    #[msg("Listing is not a hashed-timelock swap")]
+    NotHtlc,
+    #[msg("Only the HTLC taker can settle the swap")]
+    NotHtlcTaker,
+    #[msg("Preimage does not match the hash lock")]
+    InvalidPreimage,
+    #[msg("HTLC has timed out")]
+    HtlcExpired,
+    #[msg("HTLC cannot be cancelled before its timeout")]
+    HtlcLocked,
+    #[msg("Wormhole payment needs a bridge, an emitter, an amount and no on-chain price")]
+    InvalidWormholeTerms,
+    #[msg("Delegated listings cannot be paid through Wormhole")]
+    WormholeDelegated,
+    #[msg("Listing is paid for through Wormhole")]
+    WormholeEscrow,
+    #[msg("Listing is not paid for through Wormhole")]
+    NotWormholeEscrow,
+    #[msg("Account is not a posted VAA of the configured bridge")]
+    InvalidVaa,
+    #[msg("VAA was not emitted by the configured contract")]
+    WormholeEmitterMismatch,
+    #[msg("VAA pays for a different escrow")]
+    WormholeEscrowMismatch,
+    #[msg("Recipient does not match the VAA")]
+    WormholeRecipientMismatch,
+    #[msg("VAA reports less than the asking amount")]
+    WormholeUnderpaid,
+    #[msg("Ring leg needs a recipient and another leg as partner")]
+    InvalidRing,
+    #[msg("Delegated listings cannot join a ring")]
+    RingDelegated,
+    #[msg("Listing only settles as part of its ring")]
+    RingLeg,
+    #[msg("Escrows do not form the ring being settled")]
+    RingMismatch,
+    #[msg("Each side of a swap needs one to four distinct mints with non-zero amounts")]
+    InvalidSwapAssets,
+    #[msg("Signer is not a party to the swap")]
+    NotSwapParty,
+    #[msg("Swap asset is already deposited")]
+    SwapAssetDeposited,
+    #[msg("Swap is waiting on deposits")]
+    SwapNotFunded,
+    #[msg("Missing mint, vault or token account for a vaulted asset")]
+    MissingVaultAccount,
+    #[msg("Buy offer needs exactly one of a mint or a collection and a price")]
+    InvalidBuyOffer,
+    #[msg("Listing is not the NFT the offer is for")]
+    BuyOfferMismatch,
+    #[msg("Offer is below the listing's quoted total")]
+    BuyOfferTooLow,
+    #[msg("Only plain fixed-price listings can be matched")]
+    ListingNotMatchable,
+    #[msg("Order is already in the book")]
+    OrderAlreadyPosted,
+    #[msg("Order book is full until it is compacted")]
+    OrderBookFull,
+    #[msg("Order book has no bid or no ask")]
+    OrderBookEmpty,
+    #[msg("Listing or offer is not the best in the book")]
+    NotBestOrder,
+    #[msg("Deal room needs two to four distinct parties")]
+    InvalidDealParties,
+    #[msg("Signer or recipient is not a party to the deal")]
+    NotDealParty,
+    #[msg("Deal deposit must be a non-zero amount")]
+    InvalidDealDeposit,
+    #[msg("Another deposit in the deal room already uses this mint")]
+    DealMintTaken,
+    #[msg("Deal room holds the most deposits it can")]
+    DealRoomFull,
+    #[msg("Signer has no deposit of this mint in the deal room")]
+    NotDealDeposit,
+    #[msg("Not every party has approved the deal")]
+    DealNotApproved,
+    #[msg("Market id zero is the default market")]
+    InvalidMarket,
+    #[msg("Config belongs to a different market than the escrow")]
+    MarketMismatch,
+    #[msg("Integrator fee recipient account is missing")]
+    MissingIntegratorFeeRecipient,
+    #[msg("Signed order has expired")]
+    SignedOrderExpired,
+    #[msg("No ed25519 signature instruction precedes this one")]
+    MissingSignatureInstruction,
+    #[msg("Ed25519 instruction does not verify this message for its signer")]
+    InvalidSignatureInstruction,
+    #[msg("Seller's token account is not delegated to the order delegate")]
+    OrderNotDelegated,
+    #[msg("Session needs a known, non-empty scope and a future expiry")]
+    InvalidSession,
+    #[msg("Session has expired")]
+    SessionExpired,
+    #[msg("Session scope does not allow this action")]
+    SessionScopeDenied,
+    #[msg("Owner's token account is not delegated to the session")]
+    SessionNotDelegated,
+    #[msg("Session keys can only cancel plain listings")]
+    SessionListingLocked,
+    #[msg("Private listing needs the instructions sysvar to check the taker allowance")]
+    MissingTakerAllowance,
+    #[msg("Private listing only fills through exchange with a taker allowance")]
+    PrivateFillsOnly,
+    #[msg("Minimum listing age must not be negative")]
+    InvalidMinListingAge,
+    #[msg("Listing is too new to be bought")]
+    ListingTooNew,
+    #[msg("Rate limits need a positive window")]
+    InvalidRateLimit,
+    #[msg("Market is rate limited, so the wallet activity account is required")]
+    MissingWalletActivity,
+    #[msg("Wallet has reached the market's limit for this window")]
+    RateLimited,
+    #[msg("Escrow keeps an audit trail, so the audit trail account is required")]
+    MissingAuditTrail,
+    #[msg("Audit trail closes only once its escrow has")]
+    AuditTrailInUse,
+    #[msg("Reserve accounts come in escrow and custody pairs")]
+    InvalidReserveAccounts,
+    #[msg("Escrow custody does not hold the recorded asset")]
+    ReserveShortfall,
+    #[msg("Memo is longer than MAX_MEMO_LEN bytes")]
+    MemoTooLong,
+    #[msg("Attaching a memo needs the memo program")]
+    MissingMemoProgram,
+    #[msg("Note is longer than MAX_NOTE_LEN bytes")]
+    NoteTooLong,
+    #[msg("Terms URI is longer than MAX_TERMS_URI_LEN bytes")]
+    TermsUriTooLong,
+    #[msg("Terms hash must not be zero")]
+    InvalidTerms,
+    #[msg("Listing terms are already set")]
+    TermsAlreadySet,
+    #[msg("Taker must acknowledge the listing terms through exchange")]
+    TermsNotAcknowledged,
+    #[msg("Delegated listing stays in the seller's wallet and can't be transferred")]
+    TransferDelegated,
+    #[msg("Maximum listing duration must not be negative")]
+    InvalidListingDuration,
+    #[msg("Expiry must be in the future and later than the current one")]
+    InvalidExpiry,
+    #[msg("Expiry is further ahead than the market allows")]
+    ExpiryTooFar,
+    #[msg("Listing has expired")]
+    ListingExpired,
+    #[msg("Relist decay must be below 10000 bps with a positive window within the market's limit")]
+    InvalidRelistDecay,
+    #[msg("Listing has not opted into relisting")]
+    RelistNotEnabled,
+    #[msg("Listing has not expired")]
+    ListingNotExpired,
+    #[msg("Buy offer has expired")]
+    BuyOfferExpired,
+    #[msg("Sweep accounts must be (buy offer, buyer) pairs")]
+    InvalidSweepAccounts,
+    #[msg("Buy offer has not expired")]
+    BuyOfferNotExpired,
+    #[msg("Vault still exists and must be passed")]
+    VaultNotClosed,
+    #[msg("Delegated listings are cancelled by their seller")]
+    ExpiryCancelDelegated,
+    #[msg("Listing relists on expiry instead of being cancelled")]
+    ListingRelists,
+    #[msg("Taker cannot cover the price and fees")]
+    InsufficientTakerFunds,
+    #[msg("Account that paid the listing's rent must be passed to return it")]
+    MissingRentPayer,
+    #[msg("Listing's NFT is not the one the taker expected")]
+    UnexpectedMint,
+    #[msg("Listing price is above the one the taker expected")]
+    PriceAboveExpected,
+    #[msg("Listing's NFT has not been deposited")]
+    ListingNotDeposited,
+    #[msg("Listing's NFT has already been deposited")]
+    ListingAlreadyDeposited,
+    #[msg("Router payment must arrive in the taker's wSOL account")]
+    MissingRouterPayment,
+    #[msg("Router delivered less than the listing's total")]
+    RouterPaymentShort,
+    #[msg("Token is locked or non-transferable and cannot be escrowed")]
+    NonTransferableToken,
+    #[msg("Depositor's token account is frozen")]
+    DepositAccountFrozen,
+    #[msg("Mint's freeze authority could freeze the vault")]
+    MintCanFreezeVault,
+    #[msg("Fee override is not the one for the listing's collection")]
+    FeeOverrideMismatch,
+    #[msg("Collection treasury is not the one for the listing's collection")]
+    CollectionTreasuryMismatch,
+    #[msg("Collection floor is not the one for the NFT's collection")]
+    CollectionFloorMismatch,
+    #[msg("Collection floor is older than the market allows")]
+    StaleCollectionFloor,
+    #[msg("Floor age limit cannot be negative")]
+    InvalidFloorAge,
+    #[msg("Listing does not print editions")]
+    NotPrintingEditions,
+}
+
+fn verified_collection(metadata: &MetadataAccount) -> Option<Pubkey> {
+    metadata
+        .collection
+        .as_ref()
+        .filter(|collection| collection.verified)
+        .map(|collection| collection.key)
+}
+
+fn require_collection_member(
+    nft_metadata: &Option<Box<Account<'_, MetadataAccount>>>,
+    mint: Pubkey,
+    collection_mint: Pubkey,
+) -> Result<()> {
+    let nft_metadata = nft_metadata
+        .as_ref()
+        .ok_or(EscrowError::MissingNftMetadata)?;
+    require_keys_eq!(nft_metadata.mint, mint, EscrowError::NftMetadataMismatch);
+    require!(
+        verified_collection(nft_metadata) == Some(collection_mint),
+        EscrowError::CollectionMismatch
+    );
+    Ok(())
+}
+
+/// Returns the governing tokens `owner` has deposited for `governing_token_mint`
+/// according to an spl-governance `TokenOwnerRecord`. The record is read by
+/// offset: account type, realm, governing token mint, governing token
+/// owner, then the deposit amount.
+fn governance_deposit(
+    token_owner_record: &AccountInfo,
+    governance_program: Pubkey,
+    governing_token_mint: Pubkey,
+    owner: Pubkey,
+) -> Result<u64> {
+    require!(
+        governance_program != Pubkey::default() && *token_owner_record.owner == governance_program,
+        EscrowError::InvalidTokenOwnerRecord
+    );
+    let data = token_owner_record.try_borrow_data()?;
+    // `TokenOwnerRecordV1` and `TokenOwnerRecordV2` share the prefix read here.
+    require!(
+        data.len() >= 105 && matches!(data[0], 2 | 17),
+        EscrowError::InvalidTokenOwnerRecord
+    );
+    let record_key = |range: std::ops::Range<usize>| {
+        Pubkey::try_from(&data[range]).map_err(|_| error!(EscrowError::InvalidTokenOwnerRecord))
+    };
+    require_keys_eq!(
+        record_key(33..65)?,
+        governing_token_mint,
+        EscrowError::InvalidTokenOwnerRecord
+    );
+    require_keys_eq!(
+        record_key(65..97)?,
+        owner,
+        EscrowError::InvalidTokenOwnerRecord
+    );
+    Ok(u64::from_le_bytes(data[97..105].try_into().unwrap()))
+}
+
+/// Returns the recipient and amount of a payment for `escrow_state` reported
+/// in a Wormhole `PostedVAA`, which its bridge only writes after checking the
+/// guardian signatures. The account is read by offset: the `vaa` magic,
+/// version, consistency level, timestamps, signature set, nonce and sequence,
+/// then the emitter chain and address and the payload. The payload is the
+/// escrow state, the Solana recipient and a big-endian `u64` amount.
+fn wormhole_payment(
+    posted_vaa: &AccountInfo,
+    emitter_chain: u16,
+    emitter_address: [u8; 32],
+    escrow_state: Pubkey,
+) -> Result<(Pubkey, u64)> {
+    let data = posted_vaa.try_borrow_data()?;
+    require!(
+        data.len() >= 95 && &data[..3] == b"vaa",
+        EscrowError::InvalidVaa
+    );
+    require!(
+        u16::from_le_bytes(data[57..59].try_into().unwrap()) == emitter_chain
+            && data[59..91] == emitter_address,
+        EscrowError::WormholeEmitterMismatch
+    );
+    let payload_len = u32::from_le_bytes(data[91..95].try_into().unwrap()) as usize;
+    let payload = data
+        .get(95..95 + payload_len)
+        .ok_or(EscrowError::InvalidVaa)?;
+    require!(payload.len() == 72, EscrowError::InvalidVaa);
+    require_keys_eq!(
+        Pubkey::try_from(&payload[..32]).unwrap(),
+        escrow_state,
+        EscrowError::WormholeEscrowMismatch
+    );
+    Ok((
+        Pubkey::try_from(&payload[32..64]).unwrap(),
+        u64::from_be_bytes(payload[64..72].try_into().unwrap()),
+    ))
+}
+
+/// Moves `amount` of `mint` out of `vault_authority`'s vault to `recipient`'s
+/// token account and closes the vault to `depositor`, for the swaps and deal
+/// rooms whose vaults hold one deposit each. The mint, the vault and the
+/// recipient's token account are looked up in `remaining_accounts`.
+#[allow(clippy::too_many_arguments)]
+fn release_vault_tokens<'info>(
+    token_program: &AccountInfo<'info>,
+    vault_authority: &AccountInfo<'info>,
+    authority_seeds: &[&[u8]],
+    remaining_accounts: &[AccountInfo<'info>],
+    mint: Pubkey,
+    amount: u64,
+    recipient: &Pubkey,
+    depositor: &AccountInfo<'info>,
+) -> Result<()> {
+    let find = |key: Pubkey| {
+        remaining_accounts
+            .iter()
+            .find(|account| account.key == &key)
+            .ok_or(EscrowError::MissingVaultAccount)
+    };
+    let mint_account = find(mint)?;
+    let decimals = Account::<Mint>::try_from(mint_account)?.decimals;
+    let vault = find(get_associated_token_address(vault_authority.key, &mint))?;
+    let destination = find(get_associated_token_address(recipient, &mint))?;
+    token::transfer_checked(
+        CpiContext::new_with_signer(
+            token_program.clone(),
+            TransferChecked {
+                from: vault.clone(),
+                mint: mint_account.clone(),
+                to: destination.clone(),
+                authority: vault_authority.clone(),
+            },
+            &[authority_seeds],
+        ),
+        amount,
+        decimals,
+    )?;
+    token::close_account(CpiContext::new_with_signer(
+        token_program.clone(),
+        CloseAccount {
+            account: vault.clone(),
+            destination: depositor.clone(),
+            authority: vault_authority.clone(),
+        },
+        &[authority_seeds],
+    ))
+}
+
+/// Bytes a seller signs to offer `mint` for `price` lamports until `expiry`.
+pub fn signed_order_message(
+    seller: &Pubkey,
+    mint: &Pubkey,
+    price: u64,
+    expiry: i64,
+    nonce: u64,
+) -> Vec<u8> {
+    let mut message = Vec::with_capacity(88);
+    message.extend_from_slice(seller.as_ref());
+    message.extend_from_slice(mint.as_ref());
+    message.extend_from_slice(&price.to_le_bytes());
+    message.extend_from_slice(&expiry.to_le_bytes());
+    message.extend_from_slice(&nonce.to_le_bytes());
+    message
+}
+
+/// Bytes an initializer signs to let `taker` fill a private listing at `price`.
+pub fn taker_allowance_message(escrow_state: &Pubkey, taker: &Pubkey, price: u64) -> Vec<u8> {
+    let mut message = Vec::with_capacity(72);
+    message.extend_from_slice(escrow_state.as_ref());
+    message.extend_from_slice(taker.as_ref());
+    message.extend_from_slice(&price.to_le_bytes());
+    message
+}
+
+/// Checks the instruction before the current one is an ed25519 program
+/// instruction verifying `signer`'s signature over `message`. Every offset
+/// must point into that instruction's own data, so the key and message it
+/// verified are the ones read here.
+fn verify_ed25519_signature(
+    instructions: &AccountInfo,
+    signer: &Pubkey,
+    message: &[u8],
+) -> Result<()> {
+    let current = load_current_index_checked(instructions)?;
+    require!(current > 0, EscrowError::MissingSignatureInstruction);
+    let ix = load_instruction_at_checked(usize::from(current - 1), instructions)?;
+    require_keys_eq!(
+        ix.program_id,
+        ed25519_program::ID,
+        EscrowError::MissingSignatureInstruction
+    );
+    let data = &ix.data;
+    require!(
+        data.len() >= 16 && data[0] == 1,
+        EscrowError::InvalidSignatureInstruction
+    );
+    let read_u16 = |at: usize| u16::from_le_bytes([data[at], data[at + 1]]);
+    require!(
+        [4, 8, 14].iter().all(|&at| read_u16(at) == u16::MAX),
+        EscrowError::InvalidSignatureInstruction
+    );
+    let public_key_offset = usize::from(read_u16(6));
+    let message_offset = usize::from(read_u16(10));
+    let message_size = usize::from(read_u16(12));
+    require!(
+        data.get(public_key_offset..public_key_offset + 32) == Some(signer.as_ref()),
+        EscrowError::InvalidSignatureInstruction
+    );
+    require!(
+        data.get(message_offset..message_offset + message_size) == Some(message),
+        EscrowError::InvalidSignatureInstruction
+    );
+    Ok(())
+}
+
+/// Checks `leaf` against `root` through a proof of sorted-pair keccak hashes.
+fn verify_merkle_proof(proof: &[[u8; 32]], root: [u8; 32], leaf: [u8; 32]) -> bool {
+    let computed = proof.iter().fold(leaf, |node, sibling| {
+        if node <= *sibling {
+            keccak::hashv(&[&node, sibling]).0
+        } else {
+            keccak::hashv(&[sibling, &node]).0
+        }
+    });
+    computed == root
+}
+
+/// Whether `mint`'s freeze authority could freeze a vault holding it. A
+/// Metaplex master edition only freezes through an account's delegate, which
+/// a vault never has, so ordinary NFTs can still be listed.
+fn vault_freezable(mint: &Account<'_, Mint>) -> bool {
+    let COption::Some(freeze_authority) = mint.freeze_authority else {
+        return false;
+    };
+    freeze_authority != edition_address(&mint.key())
+}
+
+/// Master edition account of `mint`, whether or not it exists.
+pub fn edition_address(mint: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[
+            b"metadata".as_ref(),
+            Metadata::id().as_ref(),
+            mint.as_ref(),
+            b"edition".as_ref(),
+        ],
+        &Metadata::id(),
+    )
+    .0
+}
+
+/// Token Metadata account of `mint`, whether or not it exists.
+pub fn metadata_address(mint: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"metadata".as_ref(), Metadata::id().as_ref(), mint.as_ref()],
+        &Metadata::id(),
+    )
+    .0
+}
+
+/// The checks every listing entry point runs on the NFT it lists. Returns
+/// the NFT's verified collection for the listing to record, or the default
+/// key when it has none.
+fn require_listable(
+    nft_metadata: &AccountInfo,
+    collection_floor: &AccountInfo,
+    config: &Config,
+    price_usd_cents: u64,
+    taker_amount: u64,
+) -> Result<Pubkey> {
+    let nft_metadata = if nft_metadata.owner == &Metadata::id() && !nft_metadata.data_is_empty() {
+        Some(MetadataAccount::try_deserialize(
+            &mut &nft_metadata.try_borrow_data()?[..],
+        )?)
+    } else {
+        None
+    };
+    require_transferable(&nft_metadata)?;
+    let collection = nft_metadata
+        .as_ref()
+        .and_then(verified_collection)
+        .unwrap_or_default();
+    require_above_floor(
+        collection_floor,
+        config,
+        collection,
+        price_usd_cents,
+        taker_amount,
+    )?;
+    Ok(collection)
+}
+
+/// Programmable NFTs stay frozen in their holder's wallet and only move
+/// through Token Metadata's own transfer, so a plain vault deposit or release
+/// of one can never go through: refuse them rather than letting them fail
+/// partway.
+fn require_transferable(nft_metadata: &Option<MetadataAccount>) -> Result<()> {
+    let Some(nft_metadata) = nft_metadata else {
+        return Ok(());
+    };
+    require!(
+        nft_metadata.token_standard != Some(TokenStandard::ProgrammableNonFungible),
+        EscrowError::NonTransferableToken
+    );
+    Ok(())
+}
+
+/// Fixed-price listings of a collection with a registered floor may not be
+/// priced too far below it, nor against a floor older than the config
+/// allows. The floor sits at a fixed address for the collection, so a seller
+/// cannot list around it.
+fn require_above_floor(
+    collection_floor: &AccountInfo,
+    config: &Config,
+    collection: Pubkey,
+    price_usd_cents: u64,
+    taker_amount: u64,
+) -> Result<()> {
+    require_keys_eq!(
+        collection_floor.key(),
+        collection_floor_address(&collection, config.market_id),
+        EscrowError::CollectionFloorMismatch
+    );
+    if price_usd_cents > 0 || !initialized(collection_floor) {
+        return Ok(());
+    }
+    let collection_floor =
+        CollectionFloor::try_deserialize(&mut &collection_floor.try_borrow_data()?[..])?;
+    if config.max_floor_age_secs > 0 {
+        let age = Clock::get()?
+            .unix_timestamp
+            .saturating_sub(collection_floor.updated_at);
+        require!(
+            age <= config.max_floor_age_secs,
+            EscrowError::StaleCollectionFloor
+        );
+    }
+    require!(
+        taker_amount >= collection_floor.min_listing_price()?,
+        EscrowError::PriceBelowFloor
+    );
+    Ok(())
+}
+
+/// Thaws the seller's token account of a delegated listing, signing as the
+/// escrow's vault authority, which is both the token delegate and the party
+/// that froze it.
+fn thaw_delegated<'info>(
+    token_metadata_program: &Option<Program<'info, Metadata>>,
+    nft_edition: &Option<AccountInfo<'info>>,
+    vault_authority: &AccountInfo<'info>,
+    token_account: AccountInfo<'info>,
+    mint: AccountInfo<'info>,
+    token_program: AccountInfo<'info>,
+    authority_seeds: &[&[u8]],
+) -> Result<()> {
+    let (Some(token_metadata_program), Some(nft_edition)) = (token_metadata_program, nft_edition)
+    else {
+        return err!(EscrowError::MissingDelegatedListingAccounts);
+    };
+    thaw_token_account(
+        token_metadata_program,
+        nft_edition,
+        vault_authority,
+        token_account,
+        mint,
+        token_program,
+        authority_seeds,
+    )
+}
+
+/// Thaws a token account frozen with the vault authority as delegate.
+fn thaw_token_account<'info>(
+    token_metadata_program: &Program<'info, Metadata>,
+    nft_edition: &AccountInfo<'info>,
+    vault_authority: &AccountInfo<'info>,
+    token_account: AccountInfo<'info>,
+    mint: AccountInfo<'info>,
+    token_program: AccountInfo<'info>,
+    authority_seeds: &[&[u8]],
+) -> Result<()> {
+    let cpi_accounts = ThawDelegatedAccount {
+        metadata: token_metadata_program.to_account_info(),
+        delegate: vault_authority.clone(),
+        token_account,
+        edition: nft_edition.clone(),
+        mint,
+        token_program,
+    };
+    metadata::thaw_delegated_account(
+        CpiContext::new(token_metadata_program.to_account_info(), cpi_accounts)
+            .with_signer(&[authority_seeds]),
+    )
+}
+
+/// Freezes a token account the vault authority has been made delegate of.
+fn freeze_token_account<'info>(
+    token_metadata_program: &Program<'info, Metadata>,
+    nft_edition: &AccountInfo<'info>,
+    vault_authority: &AccountInfo<'info>,
+    token_account: AccountInfo<'info>,
+    mint: AccountInfo<'info>,
+    token_program: AccountInfo<'info>,
+    authority_seeds: &[&[u8]],
+) -> Result<()> {
+    let cpi_accounts = FreezeDelegatedAccount {
+        metadata: token_metadata_program.to_account_info(),
+        delegate: vault_authority.clone(),
+        token_account,
+        edition: nft_edition.clone(),
+        mint,
+        token_program,
+    };
+    metadata::freeze_delegated_account(
+        CpiContext::new(token_metadata_program.to_account_info(), cpi_accounts)
+            .with_signer(&[authority_seeds]),
+    )
+}
+
+/// Prices an escrow and its fees the same way `exchange` will settle it.
+fn quote_escrow(
+    config: &Config,
+    escrow_state: &EscrowState,
+    discount_token_account: &Option<Box<Account<'_, TokenAccount>>>,
+    collection_fee_override: &AccountInfo<'_>,
+    price_feed: &Option<AccountInfo<'_>>,
+) -> Result<Quote> {
+    // USD-priced escrows settle at the live oracle rate instead of the fixed
+    // lamport amount recorded at listing time.
+    let price = if escrow_state.is_printing_editions() {
+        escrow_state.edition_price
+    } else if escrow_state.price_usd_cents > 0 {
+        let price_feed = price_feed.as_ref().ok_or(EscrowError::MissingPriceFeed)?;
+        oracle_price_lamports(config, escrow_state.price_usd_cents, price_feed)?
+    } else {
+        escrow_state.taker_amount
+    };
+    let discounted = match discount_token_account {
+        Some(discount_token_account) => discount_token_account.amount >= config.discount_min_amount,
+        None => false,
+    };
+    // The override sits at a fixed address for the listing's collection, so
+    // the taker cannot leave it out; it applies as soon as it is set.
+    let collection_fee_bps = if initialized(collection_fee_override) {
+        let fee_override = CollectionFeeOverride::try_deserialize(
+            &mut &collection_fee_override.try_borrow_data()?[..],
+        )?;
+        Some((fee_override.maker_fee_bps, fee_override.taker_fee_bps))
+    } else {
+        None
+    };
+    let (mut maker_fee_bps, taker_fee_bps) = config.fee_bps(collection_fee_bps, discounted);
+    if escrow_state.is_printing_editions() && config.edition_treasury_bps > 0 {
+        maker_fee_bps = config.edition_treasury_bps;
+    }
+    let maker_fee = bps_of(price, maker_fee_bps)?;
+    let taker_fee = bps_of(price, taker_fee_bps)?;
+
+    Ok(Quote {
+        price,
+        maker_fee,
+        taker_fee,
+        total: checked_add(price, taker_fee)?,
+    })
+}
+
+fn oracle_price_lamports(
+    config: &Config,
+    price_usd_cents: u64,
+    price_feed: &AccountInfo,
+) -> Result<u64> {
+    let price_feed =
+        load_price_feed_from_account_info(price_feed).map_err(|_| EscrowError::InvalidPriceFeed)?;
+    let now = Clock::get()?.unix_timestamp;
+    let price = price_feed
+        .get_price_no_older_than(now, config.oracle_max_age_secs)
+        .ok_or(EscrowError::StalePrice)?;
+    require!(price.price > 0, EscrowError::InvalidOraclePrice);
+    require!(
+        price.conf as u128 * BPS_DENOMINATOR as u128
+            <= price.price as u128 * config.oracle_max_conf_bps as u128,
+        EscrowError::PriceConfidenceTooWide
+    );
+
+    // Refuse to settle while the spot price has moved too far from the
+    // feed's EMA, which is the usual signature of a manipulated print.
+    if config.oracle_max_twap_deviation_bps > 0 {
+        let ema_price = price_feed
+            .get_ema_price_no_older_than(now, config.oracle_max_age_secs)
+            .ok_or(EscrowError::StalePrice)?;
+        require!(
+            ema_price.price > 0 && ema_price.expo == price.expo,
+            EscrowError::InvalidOraclePrice
+        );
+        require!(
+            price.price.abs_diff(ema_price.price) as u128 * BPS_DENOMINATOR as u128
+                <= ema_price.price as u128 * config.oracle_max_twap_deviation_bps as u128,
+            EscrowError::PriceOutsideTwapBand
+        );
+    }
+
+    usd_cents_to_lamports(price_usd_cents, price.price, price.expo)
+        .ok_or_else(|| error!(EscrowError::InvalidOraclePrice))
+}
+
+/// Converts a USD amount in cents to lamports given an oracle SOL/USD price of
+/// `price * 10^expo`.
+fn usd_cents_to_lamports(price_usd_cents: u64, price: i64, expo: i32) -> Option<u64> {
+    if price <= 0 {
+        return None;
+    }
+    // cents * 10^-2 USD / (price * 10^expo USD/SOL) * 10^9 lamports/SOL
+    let mut numerator = (price_usd_cents as u128).checked_mul(10u128.pow(7))?;
+    let mut denominator = price as u128;
+    if expo < 0 {
+        numerator = numerator.checked_mul(10u128.checked_pow(expo.unsigned_abs())?)?;
+    } else {
+        denominator = denominator.checked_mul(10u128.checked_pow(expo as u32)?)?;
+    }
+    u64::try_from(numerator / denominator).ok()
+}
+
+/// Computes `a * b / c` through a `u128` intermediate so the product cannot
+/// overflow before the division.
+fn mul_div(a: u64, b: u64, c: u64) -> Result<u64> {
+    let value = (a as u128)
+        .checked_mul(b as u128)
+        .and_then(|product| product.checked_div(c as u128))
+        .ok_or(EscrowError::ArithmeticOverflow)?;
+    u64::try_from(value).map_err(|_| error!(EscrowError::ArithmeticOverflow))
+}
+
+/// Returns the `bps` share of `amount`.
+fn bps_of(amount: u64, bps: u16) -> Result<u64> {
+    mul_div(amount, bps as u64, BPS_DENOMINATOR)
+}
+
+fn checked_add(a: u64, b: u64) -> Result<u64> {
+    a.checked_add(b)
+        .ok_or_else(|| error!(EscrowError::ArithmeticOverflow))
+}
+
+fn checked_sub(a: u64, b: u64) -> Result<u64> {
+    a.checked_sub(b)
+        .ok_or_else(|| error!(EscrowError::ArithmeticUnderflow))
+}
+
+/// Counts a listing or a fill against the wallet's window when the config
+/// caps it; the wallet's `WalletActivity` is only needed then.
+fn record_wallet_activity(
+    wallet_activity: &mut Option<Box<Account<WalletActivity>>>,
+    config: &Config,
+    now: i64,
+    listing: bool,
+) -> Result<()> {
+    let limit = if listing {
+        config.max_listings_per_window
+    } else {
+        config.max_fills_per_window
+    };
+    if limit == 0 {
+        return Ok(());
+    }
+    wallet_activity
+        .as_mut()
+        .ok_or(EscrowError::MissingWalletActivity)?
+        .record(config.rate_limit_window_secs, now, listing, limit)
+}
+
+/// Folds one action into an audit trail's running hash.
+pub fn audit_entry_hash(
+    head: &[u8; 32],
+    action: u8,
+    actor: &Pubkey,
+    amount: u64,
+    slot: u64,
+) -> [u8; 32] {
+    keccak::hashv(&[
+        head,
+        &[action],
+        actor.as_ref(),
+        &amount.to_le_bytes(),
+        &slot.to_le_bytes(),
+    ])
+    .0
+}
+
+/// Records the action on the escrow's trail, if it keeps one.
+fn record_audit(
+    audit_trail: &mut Option<Box<Account<AuditTrail>>>,
+    escrow_state: &EscrowState,
+    action: u8,
+    actor: &Pubkey,
+    amount: u64,
+) -> Result<()> {
+    if !escrow_state.audited {
+        return Ok(());
+    }
+    audit_trail
+        .as_mut()
+        .ok_or(EscrowError::MissingAuditTrail)?
+        .record(action, actor, amount, Clock::get()?.slot)
+}
+
+/// Where a closing listing's rent goes: the seller, or the sponsor that paid
+/// it, looked up in `remaining_accounts`.
+fn rent_payer_account<'info>(
+    escrow_state: &EscrowState,
+    initializer: &AccountInfo<'info>,
+    remaining_accounts: &[AccountInfo<'info>],
+) -> Result<AccountInfo<'info>> {
+    if escrow_state.rent_payer == Pubkey::default() || escrow_state.rent_payer == initializer.key()
+    {
+        return Ok(initializer.clone());
+    }
+    remaining_accounts
+        .iter()
+        .find(|account| account.key == &escrow_state.rent_payer)
+        .cloned()
+        .ok_or_else(|| error!(EscrowError::MissingRentPayer))
+}
+
+/// Closes a finished listing. A sponsor gets back only what it paid: the
+/// listing's rent, plus `vault_rent_lamports` when the vault was closed into
+/// it. Every other lamport left here, such as the seller's deposit, is the
+/// initializer's.
+fn close_escrow<'info>(
+    escrow_state: &Account<'info, EscrowState>,
+    initializer: &AccountInfo<'info>,
+    remaining_accounts: &[AccountInfo<'info>],
+) -> Result<()> {
+    let rent_payer = rent_payer_account(escrow_state, initializer, remaining_accounts)?;
+    if rent_payer.key() != initializer.key() {
+        let escrow_info = escrow_state.to_account_info();
+        let rent = checked_add(
+            Rent::get()?.minimum_balance(escrow_info.data_len()),
+            escrow_state.vault_rent_lamports,
+        )?;
+        let surplus = escrow_info.lamports().saturating_sub(rent);
+        move_program_lamports(&escrow_info, initializer, surplus)?;
+    }
+    escrow_state.close(rent_payer)
+}
+
+/// Looks `key` up among the accounts passed after an instruction's own.
+fn remaining_account<'a, 'info>(
+    remaining_accounts: &'a [AccountInfo<'info>],
+    key: &Pubkey,
+) -> Option<&'a AccountInfo<'info>> {
+    remaining_accounts.iter().find(|account| account.key == key)
+}
+
+/// Balance of a token account passed as a remaining account.
+fn token_balance(token_account: &AccountInfo) -> Result<u64> {
+    require_keys_eq!(
+        *token_account.owner,
+        token::ID,
+        ErrorCode::AccountOwnedByWrongProgram
+    );
+    let token_account = TokenAccount::try_deserialize(&mut &token_account.try_borrow_data()?[..])?;
+    Ok(token_account.amount)
+}
+
+/// Attaches `memo` to the transaction through the memo program, for
+/// exchanges and custodians that need a compliance memo on settlements.
+fn forward_memo(memo_program: &Option<Program<Memo>>, memo: Option<String>) -> Result<()> {
+    let memo = match memo {
+        Some(memo) => memo,
+        None => return Ok(()),
+    };
+    require!(memo.len() <= MAX_MEMO_LEN, EscrowError::MemoTooLong);
+    let memo_program = memo_program
+        .as_ref()
+        .ok_or(EscrowError::MissingMemoProgram)?;
+    memo::build_memo(
+        CpiContext::new(memo_program.to_account_info(), BuildMemo {}),
+        memo.as_bytes(),
+    )
+}
+
+/// Address of the fee override for `collection` in market `market_id`,
+/// whether or not it has been set.
+pub fn collection_fee_override_address(collection: &Pubkey, market_id: u64) -> Pubkey {
+    Pubkey::find_program_address(
+        &[
+            b"fee_override".as_ref(),
+            collection.as_ref(),
+            &market_seed(market_id),
+        ],
+        &crate::ID,
+    )
+    .0
+}
+
+/// Address of the DAO treasury entry for `collection` in market `market_id`,
+/// whether or not one is registered.
+pub fn collection_treasury_address(collection: &Pubkey, market_id: u64) -> Pubkey {
+    Pubkey::find_program_address(
+        &[
+            b"collection_treasury".as_ref(),
+            collection.as_ref(),
+            &market_seed(market_id),
+        ],
+        &crate::ID,
+    )
+    .0
+}
+
+/// Address of the floor of `collection` in market `market_id`, whether or
+/// not one is registered.
+pub fn collection_floor_address(collection: &Pubkey, market_id: u64) -> Pubkey {
+    Pubkey::find_program_address(
+        &[
+            b"collection_floor".as_ref(),
+            collection.as_ref(),
+            &market_seed(market_id),
+        ],
+        &crate::ID,
+    )
+    .0
+}
+
+/// Whether `account` has been created by this program.
+fn initialized(account: &AccountInfo) -> bool {
+    account.owner == &crate::ID && !account.data_is_empty()
+}
+
+/// Extra PDA seed for accounts of market `market_id`. The default market
+/// adds none, so its addresses are the ones from before markets existed.
+pub fn market_seed(market_id: u64) -> Vec<u8> {
+    if market_id == 0 {
+        vec![]
+    } else {
+        market_id.to_le_bytes().to_vec()
+    }
+}
+
+fn require_valid_bps(values: &[u16]) -> Result<()> {
+    require!(
+        values.iter().all(|bps| *bps as u64 <= BPS_DENOMINATOR),
+        EscrowError::InvalidFeeBps
+    );
+    Ok(())
+}
+
+/// Moves lamports out of an account owned by this program, which unlike a
+/// system transfer needs no signature.
+fn move_program_lamports(from: &AccountInfo, to: &AccountInfo, amount: u64) -> Result<()> {
+    let from_lamports = checked_sub(from.lamports(), amount)?;
+    let to_lamports = checked_add(to.lamports(), amount)?;
+    **from.try_borrow_mut_lamports()? = from_lamports;
+    **to.try_borrow_mut_lamports()? = to_lamports;
+    Ok(())
+}
+
+fn transfer_lamports<'info>(
+    from: &AccountInfo<'info>,
+    to: &AccountInfo<'info>,
+    amount: u64,
+) -> Result<()> {
+    if amount == 0 {
+        return Ok(());
+    }
+    let ix = transfer(from.key, to.key, amount);
+    invoke(&ix, &[from.clone(), to.clone()])?;
+    Ok(())
+}
+
+impl<'info> Initialize<'info> {
+    fn into_transfer_to_pda_context(
+        &self,
+    ) -> CpiContext<'_, '_, '_, 'info, TransferChecked<'info>> {
+        let cpi_accounts = TransferChecked {
+            from: self.initializer_deposit_token_account.to_account_info(),
+            mint: self.mint.to_account_info(),
+            to: self.vault.to_account_info(),
+            authority: self.initializer.to_account_info(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
+}
+
+impl<'info> InitializeFromTemplate<'info> {
+    fn into_transfer_to_pda_context(
+        &self,
+    ) -> CpiContext<'_, '_, '_, 'info, TransferChecked<'info>> {
+        let cpi_accounts = TransferChecked {
+            from: self.initializer_deposit_token_account.to_account_info(),
+            mint: self.mint.to_account_info(),
+            to: self.vault.to_account_info(),
+            authority: self.initializer.to_account_info(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
+}
+
+impl<'info> Deposit<'info> {
+    fn into_transfer_to_pda_context(
+        &self,
+    ) -> CpiContext<'_, '_, '_, 'info, TransferChecked<'info>> {
+        let cpi_accounts = TransferChecked {
+            from: self.initializer_deposit_token_account.to_account_info(),
+            mint: self.mint.to_account_info(),
+            to: self.vault.to_account_info(),
+            authority: self.initializer.to_account_info(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
+}
+
+impl<'info> CancelExpired<'info> {
+    fn into_transfer_to_initializer_context(
+        &self,
+    ) -> CpiContext<'_, '_, '_, 'info, TransferChecked<'info>> {
+        let cpi_accounts = TransferChecked {
+            from: self.vault.to_account_info(),
+            mint: self.mint.to_account_info(),
+            to: self.initializer_deposit_token_account.to_account_info(),
+            authority: self.vault_authority.clone(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
+
+    fn into_close_context(&self) -> CpiContext<'_, '_, '_, 'info, CloseAccount<'info>> {
+        let cpi_accounts = CloseAccount {
+            account: self.vault.to_account_info(),
+            destination: self.escrow_state.to_account_info(),
+            authority: self.vault_authority.clone(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
+}
+
+impl<'info> InitializeForIntegrator<'info> {
+    fn into_transfer_to_pda_context(
+        &self,
+    ) -> CpiContext<'_, '_, '_, 'info, TransferChecked<'info>> {
+        let cpi_accounts = TransferChecked {
+            from: self.initializer_deposit_token_account.to_account_info(),
+            mint: self.mint.to_account_info(),
+            to: self.vault.to_account_info(),
+            authority: self.initializer.to_account_info(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
+}
+
+impl<'info> InitializeDelegated<'info> {
+    fn into_approve_context(&self) -> CpiContext<'_, '_, '_, 'info, Approve<'info>> {
+        let cpi_accounts = Approve {
+            to: self.initializer_deposit_token_account.to_account_info(),
+            delegate: self.vault_authority.clone(),
+            authority: self.initializer.to_account_info(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
+
+    fn into_freeze_context(&self) -> CpiContext<'_, '_, '_, 'info, FreezeDelegatedAccount<'info>> {
+        let cpi_accounts = FreezeDelegatedAccount {
+            metadata: self.token_metadata_program.to_account_info(),
+            delegate: self.vault_authority.clone(),
+            token_account: self.initializer_deposit_token_account.to_account_info(),
+            edition: self.nft_edition.clone(),
+            mint: self.mint.to_account_info(),
+            token_program: self.token_program.to_account_info(),
+        };
+        CpiContext::new(self.token_metadata_program.to_account_info(), cpi_accounts)
+    }
+}
+
+impl<'info> StartBnpl<'info> {
+    fn into_transfer_to_buyer_context(
+        &self,
+    ) -> CpiContext<'_, '_, '_, 'info, TransferChecked<'info>> {
+        let cpi_accounts = TransferChecked {
+            from: self.vault.to_account_info(),
+            mint: self.mint.to_account_info(),
+            to: self.buyer_token_account.to_account_info(),
+            authority: self.vault_authority.clone(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
+
+    fn into_close_context(&self) -> CpiContext<'_, '_, '_, 'info, CloseAccount<'info>> {
+        let cpi_accounts = CloseAccount {
+            account: self.vault.to_account_info(),
+            destination: self.escrow_state.to_account_info(),
+            authority: self.vault_authority.clone(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
+
+    fn into_approve_context(&self) -> CpiContext<'_, '_, '_, 'info, Approve<'info>> {
+        let cpi_accounts = Approve {
+            to: self.buyer_token_account.to_account_info(),
+            delegate: self.vault_authority.clone(),
+            authority: self.buyer.to_account_info(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
+}
+
+impl<'info> PayBnpl<'info> {
+    fn into_revoke_context(&self) -> CpiContext<'_, '_, '_, 'info, Revoke<'info>> {
+        let cpi_accounts = Revoke {
+            source: self.buyer_token_account.to_account_info(),
+            authority: self.buyer.to_account_info(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
+}
+
+impl<'info> SeizeCollateral<'info> {
+    fn into_transfer_to_initializer_context(
+        &self,
+    ) -> CpiContext<'_, '_, '_, 'info, TransferChecked<'info>> {
+        let cpi_accounts = TransferChecked {
+            from: self.buyer_token_account.to_account_info(),
+            mint: self.mint.to_account_info(),
+            to: self.initializer_deposit_token_account.to_account_info(),
+            authority: self.vault_authority.clone(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
+}
+
+impl<'info> Liquidate<'info> {
+    fn into_transfer_to_lender_context(
+        &self,
+    ) -> CpiContext<'_, '_, '_, 'info, TransferChecked<'info>> {
+        let cpi_accounts = TransferChecked {
+            from: self.vault.to_account_info(),
+            mint: self.mint.to_account_info(),
+            to: self.lender_token_account.to_account_info(),
+            authority: self.vault_authority.clone(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
+
+    fn into_close_context(&self) -> CpiContext<'_, '_, '_, 'info, CloseAccount<'info>> {
+        let cpi_accounts = CloseAccount {
+            account: self.vault.to_account_info(),
+            destination: self.escrow_state.to_account_info(),
+            authority: self.vault_authority.clone(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
+}
+
+impl<'info> CreateSubscriptionListing<'info> {
+    fn into_transfer_to_vault_context(
+        &self,
+    ) -> CpiContext<'_, '_, '_, 'info, TransferChecked<'info>> {
+        let cpi_accounts = TransferChecked {
+            from: self.seller_token_account.to_account_info(),
+            mint: self.mint.to_account_info(),
+            to: self.vault.to_account_info(),
+            authority: self.seller.to_account_info(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
+}
+
+impl<'info> Subscribe<'info> {
+    /// Charges one period and delivers one copy. The subscriber pays the
+    /// taker fee on top of the price and the maker fee comes out of the
+    /// seller's share, as with an exchange.
+    fn deliver(&mut self) -> Result<()> {
+        let price = self.subscription_listing.price;
+        let (maker_fee_bps, taker_fee_bps) = self.config.fee_bps(None, false);
+        let maker_fee = bps_of(price, maker_fee_bps)?;
+        let taker_fee = bps_of(price, taker_fee_bps)?;
+        let subscriber = self.subscriber.to_account_info();
+        transfer_lamports(&subscriber, &self.seller, checked_sub(price, maker_fee)?)?;
+        transfer_lamports(
+            &subscriber,
+            &self.treasury,
+            checked_add(maker_fee, taker_fee)?,
+        )?;
+
+        let listing_key = self.subscription_listing.key();
+        let authority_seeds = &[
+            &AUTHORITY_SEED[..],
+            listing_key.as_ref(),
+            &[self.subscription_listing.vault_authority_bump],
+        ];
+        let cpi_accounts = TransferChecked {
+            from: self.vault.to_account_info(),
+            mint: self.mint.to_account_info(),
+            to: self.subscriber_token_account.to_account_info(),
+            authority: self.vault_authority.clone(),
+        };
+        token::transfer_checked(
+            CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+                .with_signer(&[&authority_seeds[..]]),
+            1,
+            self.mint.decimals,
+        )?;
+
+        self.subscription.deliveries = checked_add(self.subscription.deliveries, 1)?;
+        self.subscription.next_delivery = Clock::get()?
+            .unix_timestamp
+            .checked_add(self.subscription_listing.period_secs)
+            .ok_or(EscrowError::ArithmeticOverflow)?;
+        Ok(())
+    }
+}
+
+impl<'info> CloseSubscriptionListing<'info> {
+    fn into_transfer_to_seller_context(
+        &self,
+    ) -> CpiContext<'_, '_, '_, 'info, TransferChecked<'info>> {
+        let cpi_accounts = TransferChecked {
+            from: self.vault.to_account_info(),
+            mint: self.mint.to_account_info(),
+            to: self.seller_token_account.to_account_info(),
+            authority: self.vault_authority.clone(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
+
+    fn into_close_context(&self) -> CpiContext<'_, '_, '_, 'info, CloseAccount<'info>> {
+        let cpi_accounts = CloseAccount {
+            account: self.vault.to_account_info(),
+            destination: self.seller.to_account_info(),
+            authority: self.vault_authority.clone(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
+}
+
+impl<'info> DepositPoolNft<'info> {
+    fn into_transfer_to_vault_context(
+        &self,
+    ) -> CpiContext<'_, '_, '_, 'info, TransferChecked<'info>> {
+        let cpi_accounts = TransferChecked {
+            from: self.owner_token_account.to_account_info(),
+            mint: self.mint.to_account_info(),
+            to: self.vault.to_account_info(),
+            authority: self.owner.to_account_info(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
+}
+
+impl<'info> WithdrawPoolNft<'info> {
+    fn into_transfer_to_owner_context(
+        &self,
+    ) -> CpiContext<'_, '_, '_, 'info, TransferChecked<'info>> {
+        let cpi_accounts = TransferChecked {
+            from: self.vault.to_account_info(),
+            mint: self.mint.to_account_info(),
+            to: self.owner_token_account.to_account_info(),
+            authority: self.vault_authority.clone(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
+
+    fn into_close_context(&self) -> CpiContext<'_, '_, '_, 'info, CloseAccount<'info>> {
+        let cpi_accounts = CloseAccount {
+            account: self.vault.to_account_info(),
+            destination: self.owner.to_account_info(),
+            authority: self.vault_authority.clone(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
+}
+
+impl<'info> BuyFromPool<'info> {
+    fn into_transfer_to_buyer_context(
+        &self,
+    ) -> CpiContext<'_, '_, '_, 'info, TransferChecked<'info>> {
+        let cpi_accounts = TransferChecked {
+            from: self.vault.to_account_info(),
+            mint: self.mint.to_account_info(),
+            to: self.buyer_token_account.to_account_info(),
+            authority: self.vault_authority.clone(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
+
+    fn into_close_context(&self) -> CpiContext<'_, '_, '_, 'info, CloseAccount<'info>> {
+        let cpi_accounts = CloseAccount {
+            account: self.vault.to_account_info(),
+            destination: self.buyer.to_account_info(),
+            authority: self.vault_authority.clone(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
+}
+
+impl<'info> SellToPool<'info> {
+    fn into_transfer_to_vault_context(
+        &self,
+    ) -> CpiContext<'_, '_, '_, 'info, TransferChecked<'info>> {
+        let cpi_accounts = TransferChecked {
+            from: self.seller_token_account.to_account_info(),
+            mint: self.mint.to_account_info(),
+            to: self.vault.to_account_info(),
+            authority: self.seller.to_account_info(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
+}
+
+impl<'info> Fractionalize<'info> {
+    fn into_transfer_to_vault_context(
+        &self,
+    ) -> CpiContext<'_, '_, '_, 'info, TransferChecked<'info>> {
+        let cpi_accounts = TransferChecked {
+            from: self.depositor_token_account.to_account_info(),
+            mint: self.mint.to_account_info(),
+            to: self.vault.to_account_info(),
+            authority: self.depositor.to_account_info(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
+
+    fn into_mint_fractions_context(&self) -> CpiContext<'_, '_, '_, 'info, MintTo<'info>> {
+        let cpi_accounts = MintTo {
+            mint: self.fraction_mint.to_account_info(),
+            to: self.depositor_fraction_account.to_account_info(),
+            authority: self.vault_authority.clone(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
+}
+
+impl<'info> Buyout<'info> {
+    fn into_transfer_to_buyer_context(
+        &self,
+    ) -> CpiContext<'_, '_, '_, 'info, TransferChecked<'info>> {
+        let cpi_accounts = TransferChecked {
+            from: self.vault.to_account_info(),
+            mint: self.mint.to_account_info(),
+            to: self.buyer_token_account.to_account_info(),
+            authority: self.vault_authority.clone(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
+
+    fn into_close_context(&self) -> CpiContext<'_, '_, '_, 'info, CloseAccount<'info>> {
+        let cpi_accounts = CloseAccount {
+            account: self.vault.to_account_info(),
+            destination: self.depositor.to_account_info(),
+            authority: self.vault_authority.clone(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
+}
+
+impl<'info> RedeemFractions<'info> {
+    fn into_burn_context(&self) -> CpiContext<'_, '_, '_, 'info, Burn<'info>> {
+        let cpi_accounts = Burn {
+            mint: self.fraction_mint.to_account_info(),
+            from: self.holder_fraction_account.to_account_info(),
+            authority: self.holder.to_account_info(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
+}
+
+impl<'info> ExecuteGroupPurchase<'info> {
+    /// Calls back into `exchange` with the custody PDA signing as taker.
+    fn exchange_for_custody(&self) -> Result<()> {
+        let pool_key = self.purchase_pool.key();
+        let custody_seeds = &[
+            b"custody".as_ref(),
+            pool_key.as_ref(),
+            &[self.purchase_pool.custody_bump],
+        ];
+        let accounts = crate::accounts::Exchange {
+            taker: self.custody.key(),
+            initializer_deposit_token_mint: self.initializer_deposit_token_mint.key(),
+            taker_deposit_token_mint: self.taker_deposit_token_mint.key(),
+            taker_deposit_token_account: self.custody_deposit_token_account.key(),
+            taker_receive_token_account: self.custody_receive_token_account.key(),
+            initializer: self.initializer.key(),
+            initializer_receive_token_account: self.initializer_receive_token_account.key(),
+            escrow_state: self.escrow_state.key(),
+            vault: Some(self.vault.key()),
+            vault_authority: self.vault_authority.key(),
+            config: self.config.key(),
+            treasury: self.treasury.key(),
+            referrer: None,
+            discount_token_account: None,
+            collection_fee_override: self.collection_fee_override.key(),
+            collection_treasury: self.collection_treasury.key(),
+            taker_activity: None,
+            audit_trail: None,
+            token_program: self.token_program.key(),
+            associated_token_program: self.associated_token_program.key(),
+            system_program: self.system_program.key(),
+        };
+        // The donation recipient and DAO treasury go after the exchange's own accounts.
+        let mut account_metas = accounts.to_account_metas(None);
+        if let Some(donation_recipient) = &self.donation_recipient {
+            account_metas.push(AccountMeta::new(donation_recipient.key(), false));
+        }
+        if let Some(dao_treasury) = &self.dao_treasury {
+            account_metas.push(AccountMeta::new(dao_treasury.key(), false));
+        }
+        let ix = Instruction {
+            program_id: crate::ID,
+            accounts: account_metas,
+            data: crate::instruction::Exchange {
+                memo: None,
+                terms_hash: None,
+                expected_price: None,
+                expected_mint: None,
+            }
+            .data(),
+        };
+
+        let mut account_infos = vec![
+            self.custody.clone(),
+            self.initializer_deposit_token_mint.to_account_info(),
+            self.taker_deposit_token_mint.to_account_info(),
+            self.custody_deposit_token_account.to_account_info(),
+            self.custody_receive_token_account.to_account_info(),
+            self.initializer.clone(),
+            self.initializer_receive_token_account.to_account_info(),
+            self.escrow_state.clone(),
+            self.vault.clone(),
+            self.vault_authority.clone(),
+            self.config.to_account_info(),
+            self.treasury.clone(),
+            self.collection_fee_override.clone(),
+            self.collection_treasury.clone(),
+            // Stands in for every optional account left out.
+            self.escrow_program.to_account_info(),
+            self.token_program.to_account_info(),
+            self.associated_token_program.to_account_info(),
+            self.system_program.to_account_info(),
+        ];
+        if let Some(donation_recipient) = &self.donation_recipient {
+            account_infos.push(donation_recipient.clone());
+        }
+        if let Some(dao_treasury) = &self.dao_treasury {
+            account_infos.push(dao_treasury.clone());
+        }
+        invoke_signed(&ix, &account_infos, &[&custody_seeds[..]])?;
+        Ok(())
+    }
+}
+
+impl<'info> ClaimGift<'info> {
+    fn into_transfer_to_recipient_context(
+        &self,
+    ) -> CpiContext<'_, '_, '_, 'info, TransferChecked<'info>> {
+        let cpi_accounts = TransferChecked {
+            from: self.vault.to_account_info(),
+            mint: self.mint.to_account_info(),
+            to: self.recipient_token_account.to_account_info(),
+            authority: self.vault_authority.clone(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
+
+    fn into_close_context(&self) -> CpiContext<'_, '_, '_, 'info, CloseAccount<'info>> {
+        let cpi_accounts = CloseAccount {
+            account: self.vault.to_account_info(),
+            destination: self.escrow_state.to_account_info(),
+            authority: self.vault_authority.clone(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
+}
+
+impl<'info> ReturnGift<'info> {
+    fn into_transfer_to_initializer_context(
+        &self,
+    ) -> CpiContext<'_, '_, '_, 'info, TransferChecked<'info>> {
+        let cpi_accounts = TransferChecked {
+            from: self.vault.to_account_info(),
+            mint: self.mint.to_account_info(),
+            to: self.initializer_deposit_token_account.to_account_info(),
+            authority: self.vault_authority.clone(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
+
+    fn into_close_context(&self) -> CpiContext<'_, '_, '_, 'info, CloseAccount<'info>> {
+        let cpi_accounts = CloseAccount {
+            account: self.vault.to_account_info(),
+            destination: self.escrow_state.to_account_info(),
+            authority: self.vault_authority.clone(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
+}
+
+impl<'info> ExchangeWithPreimage<'info> {
+    fn into_transfer_to_taker_context(
+        &self,
+    ) -> CpiContext<'_, '_, '_, 'info, TransferChecked<'info>> {
+        let cpi_accounts = TransferChecked {
+            from: self.vault.to_account_info(),
+            mint: self.mint.to_account_info(),
+            to: self.taker_token_account.to_account_info(),
+            authority: self.vault_authority.clone(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
+
+    fn into_close_context(&self) -> CpiContext<'_, '_, '_, 'info, CloseAccount<'info>> {
+        let cpi_accounts = CloseAccount {
+            account: self.vault.to_account_info(),
+            destination: self.escrow_state.to_account_info(),
+            authority: self.vault_authority.clone(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
+}
+
+impl<'info> ExchangeWithVaa<'info> {
+    fn into_transfer_to_recipient_context(
+        &self,
+    ) -> CpiContext<'_, '_, '_, 'info, TransferChecked<'info>> {
+        let cpi_accounts = TransferChecked {
+            from: self.vault.to_account_info(),
+            mint: self.mint.to_account_info(),
+            to: self.recipient_token_account.to_account_info(),
+            authority: self.vault_authority.clone(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
+
+    fn into_close_context(&self) -> CpiContext<'_, '_, '_, 'info, CloseAccount<'info>> {
+        let cpi_accounts = CloseAccount {
+            account: self.vault.to_account_info(),
+            destination: self.escrow_state.to_account_info(),
+            authority: self.vault_authority.clone(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
+}
+
+impl<'info> MatchEscrows<'info> {
+    /// Fills the listing from the offer, paying `tip_bps` of any surplus to
+    /// the matcher before the seller takes its share.
+    fn fill(&self, tip_bps: u16, remaining_accounts: &[AccountInfo<'info>]) -> Result<()> {
+        let buy_offer = &self.buy_offer;
+        let escrow_state = &self.escrow_state;
+        require!(
+            !self.config.prevent_self_trade || buy_offer.buyer != escrow_state.initializer_key,
+            EscrowError::SelfTrade
+        );
+        let now = Clock::get()?.unix_timestamp;
+        escrow_state.require_listing_age(&self.config, now)?;
+        require!(!escrow_state.is_expired(now), EscrowError::ListingExpired);
+        require!(!buy_offer.is_expired(now), EscrowError::BuyOfferExpired);
+        if buy_offer.collection != Pubkey::default() {
+            require_collection_member(
+                &self.nft_metadata,
+                escrow_state.initializer_deposit_mint_account,
+                buy_offer.collection,
+            )?;
+        } else {
+            require_keys_eq!(
+                buy_offer.mint,
+                escrow_state.initializer_deposit_mint_account,
+                EscrowError::BuyOfferMismatch
+            );
+        }
+
+        let Quote {
+            price,
+            maker_fee,
+            taker_fee,
+            total,
+        } = quote_escrow(
+            &self.config,
+            escrow_state,
+            &None,
+            &self.collection_fee_override,
+            &None,
+        )?;
+        let surplus = buy_offer
+            .max_price
+            .checked_sub(total)
+            .ok_or(EscrowError::BuyOfferTooLow)?;
+        let tip = bps_of(surplus, tip_bps)?;
+        let seller_surplus = bps_of(
+            checked_sub(surplus, tip)?,
+            self.config.match_surplus_seller_bps,
+        )?;
+        let buy_offer_info = self.buy_offer.to_account_info();
+        move_program_lamports(
+            &buy_offer_info,
+            &self.initializer,
+            checked_add(checked_sub(price, maker_fee)?, seller_surplus)?,
+        )?;
+        move_program_lamports(
+            &buy_offer_info,
+            &self.treasury,
+            checked_add(maker_fee, taker_fee)?,
+        )?;
+        move_program_lamports(&buy_offer_info, &self.matcher.to_account_info(), tip)?;
+
+        let escrow_key = escrow_state.key();
+        let authority_seeds = &[
+            &AUTHORITY_SEED[..],
+            escrow_key.as_ref(),
+            &[escrow_state.vault_authority_bump],
+        ];
+        token::transfer_checked(
+            self.into_transfer_to_buyer_context()
+                .with_signer(&[&authority_seeds[..]]),
+            1,
+            self.mint.decimals,
+        )?;
+        token::close_account(
+            self.into_close_context()
+                .with_signer(&[&authority_seeds[..]]),
+        )?;
+        move_program_lamports(
+            &self.escrow_state.to_account_info(),
+            &self.buyer,
+            self.escrow_state.initializer_amount,
+        )?;
+        close_escrow(&self.escrow_state, &self.initializer, remaining_accounts)
+    }
+
+    fn into_transfer_to_buyer_context(
+        &self,
+    ) -> CpiContext<'_, '_, '_, 'info, TransferChecked<'info>> {
+        let cpi_accounts = TransferChecked {
+            from: self.vault.to_account_info(),
+            mint: self.mint.to_account_info(),
+            to: self.buyer_token_account.to_account_info(),
+            authority: self.vault_authority.clone(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
+
+    fn into_close_context(&self) -> CpiContext<'_, '_, '_, 'info, CloseAccount<'info>> {
+        let cpi_accounts = CloseAccount {
+            account: self.vault.to_account_info(),
+            destination: self.escrow_state.to_account_info(),
+            authority: self.vault_authority.clone(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
+}
+
+impl<'info> DepositToDeal<'info> {
+    fn into_transfer_to_vault_context(
+        &self,
+    ) -> CpiContext<'_, '_, '_, 'info, TransferChecked<'info>> {
+        let cpi_accounts = TransferChecked {
+            from: self.depositor_token_account.to_account_info(),
+            mint: self.mint.to_account_info(),
+            to: self.vault.to_account_info(),
+            authority: self.depositor.to_account_info(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
+}
+
+impl<'info> WithdrawFromDeal<'info> {
+    fn into_transfer_to_depositor_context(
+        &self,
+    ) -> CpiContext<'_, '_, '_, 'info, TransferChecked<'info>> {
+        let cpi_accounts = TransferChecked {
+            from: self.vault.to_account_info(),
+            mint: self.mint.to_account_info(),
+            to: self.depositor_token_account.to_account_info(),
+            authority: self.vault_authority.clone(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
+
+    fn into_close_context(&self) -> CpiContext<'_, '_, '_, 'info, CloseAccount<'info>> {
+        let cpi_accounts = CloseAccount {
+            account: self.vault.to_account_info(),
+            destination: self.depositor.to_account_info(),
+            authority: self.vault_authority.clone(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
+}
+
+impl<'info> ListWithSession<'info> {
+    fn into_transfer_to_pda_context(
+        &self,
+    ) -> CpiContext<'_, '_, '_, 'info, TransferChecked<'info>> {
+        let cpi_accounts = TransferChecked {
+            from: self.owner_deposit_token_account.to_account_info(),
+            mint: self.mint.to_account_info(),
+            to: self.vault.to_account_info(),
+            authority: self.session.to_account_info(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
+}
+
+impl<'info> CancelWithSession<'info> {
+    fn into_transfer_to_owner_context(
+        &self,
+    ) -> CpiContext<'_, '_, '_, 'info, TransferChecked<'info>> {
+        let cpi_accounts = TransferChecked {
+            from: self.vault.to_account_info(),
+            mint: self.mint.to_account_info(),
+            to: self.owner_deposit_token_account.to_account_info(),
+            authority: self.vault_authority.clone(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
+
+    fn into_close_context(&self) -> CpiContext<'_, '_, '_, 'info, CloseAccount<'info>> {
+        let cpi_accounts = CloseAccount {
+            account: self.vault.to_account_info(),
+            destination: self.escrow_state.to_account_info(),
+            authority: self.vault_authority.clone(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
+}
+
+impl<'info> FillSignedOrder<'info> {
+    fn into_transfer_to_buyer_context(
+        &self,
+    ) -> CpiContext<'_, '_, '_, 'info, TransferChecked<'info>> {
+        let cpi_accounts = TransferChecked {
+            from: self.seller_token_account.to_account_info(),
+            mint: self.mint.to_account_info(),
+            to: self.buyer_token_account.to_account_info(),
+            authority: self.order_delegate.clone(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
+}
+
+impl<'info> DepositSwapAsset<'info> {
+    fn into_transfer_to_vault_context(
+        &self,
+    ) -> CpiContext<'_, '_, '_, 'info, TransferChecked<'info>> {
+        let cpi_accounts = TransferChecked {
+            from: self.party_token_account.to_account_info(),
+            mint: self.mint.to_account_info(),
+            to: self.vault.to_account_info(),
+            authority: self.party.to_account_info(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
+}
+
+impl<'info> DepositAirdropNft<'info> {
+    fn into_transfer_to_vault_context(
+        &self,
+    ) -> CpiContext<'_, '_, '_, 'info, TransferChecked<'info>> {
+        let cpi_accounts = TransferChecked {
+            from: self.project_token_account.to_account_info(),
+            mint: self.mint.to_account_info(),
+            to: self.vault.to_account_info(),
+            authority: self.project.to_account_info(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
+}
+
+impl<'info> ClaimAirdrop<'info> {
+    fn into_transfer_to_claimant_context(
+        &self,
+    ) -> CpiContext<'_, '_, '_, 'info, TransferChecked<'info>> {
+        let cpi_accounts = TransferChecked {
+            from: self.vault.to_account_info(),
+            mint: self.mint.to_account_info(),
+            to: self.claimant_token_account.to_account_info(),
+            authority: self.vault_authority.clone(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
+
+    fn into_close_context(&self) -> CpiContext<'_, '_, '_, 'info, CloseAccount<'info>> {
+        let cpi_accounts = CloseAccount {
+            account: self.vault.to_account_info(),
+            destination: self.claimant.to_account_info(),
+            authority: self.vault_authority.clone(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
+}
+
+impl<'info> ReclaimAirdropNft<'info> {
+    fn into_transfer_to_project_context(
+        &self,
+    ) -> CpiContext<'_, '_, '_, 'info, TransferChecked<'info>> {
+        let cpi_accounts = TransferChecked {
+            from: self.vault.to_account_info(),
+            mint: self.mint.to_account_info(),
+            to: self.project_token_account.to_account_info(),
+            authority: self.vault_authority.clone(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
+
+    fn into_close_context(&self) -> CpiContext<'_, '_, '_, 'info, CloseAccount<'info>> {
+        let cpi_accounts = CloseAccount {
+            account: self.vault.to_account_info(),
+            destination: self.project.to_account_info(),
+            authority: self.vault_authority.clone(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
+}
+
+impl<'info> ListForRent<'info> {
+    fn into_transfer_to_vault_context(
+        &self,
+    ) -> CpiContext<'_, '_, '_, 'info, TransferChecked<'info>> {
+        let cpi_accounts = TransferChecked {
+            from: self.owner_token_account.to_account_info(),
+            mint: self.mint.to_account_info(),
+            to: self.vault.to_account_info(),
+            authority: self.owner.to_account_info(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
 }
 
-#[derive(Accounts)]
-pub struct Exchange<'info> {
-    /// CHECK: This is not dangerous because we don't read or write from this account
-    #[account(mut)]
-    pub taker: Signer<'info>,
-    #[account(mut)]
-    pub initializer_deposit_token_mint: Account<'info, Mint>,
-    #[account(mut)]
-    pub taker_deposit_token_mint: Account<'info, Mint>,
-    #[account(mut, constraint = &taker_deposit_token_account.owner == taker.key)]
-    pub taker_deposit_token_account: Box<Account<'info, TokenAccount>>,
-    #[account(mut)]
-    pub taker_receive_token_account: Box<Account<'info, TokenAccount>>,
-    #[account(mut)]
-    pub initializer_deposit_token_account: Box<Account<'info, TokenAccount>>,
-    #[account(mut)]
-    pub initializer_receive_token_account: Box<Account<'info, TokenAccount>>,
-    /// CHECK: This is not dangerous because we don't read or write from this account
-    #[account(mut)]
-    pub initializer: AccountInfo<'info>,
-    #[account(
-        mut,
-        // constraint = escrow_state.taker_amount <= taker_deposit_token_account.amount,
-        constraint = escrow_state.taker_amount <= taker.lamports(),
-        constraint = escrow_state.initializer_deposit_token_account == *initializer_deposit_token_account.to_account_info().key,
-        constraint = escrow_state.initializer_receive_token_account == *initializer_receive_token_account.to_account_info().key,
-        constraint = escrow_state.initializer_key == *initializer.key,
-        close = initializer
-    )]
-    pub escrow_state: Box<Account<'info, EscrowState>>,
-    #[account(mut)]
-    pub vault: Box<Account<'info, TokenAccount>>,
-    /// CHECK: This is not dangerous because we don't read or write from this account
-    #[account(
-        seeds = [b"authority".as_ref()],
-        bump,
-    )]
-    pub vault_authority: AccountInfo<'info>,
-    /// CHECK: This is not dangerous because we don't read or write from this account
-    pub token_program: Program<'info, Token>,
-    /// CHECK: This is not dangerous because we don't read or write from this account
-    pub system_program: Program<'info, System>,
+impl<'info> StartRental<'info> {
+    fn into_transfer_to_renter_context(
+        &self,
+    ) -> CpiContext<'_, '_, '_, 'info, TransferChecked<'info>> {
+        let cpi_accounts = TransferChecked {
+            from: self.vault.to_account_info(),
+            mint: self.mint.to_account_info(),
+            to: self.renter_token_account.to_account_info(),
+            authority: self.vault_authority.clone(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
 }
 
-#[account]
-pub struct EscrowState {
-    pub random_seed: u64,
-    pub initializer_key: Pubkey,
-    pub taker_key: Pubkey,
-    pub initializer_deposit_token_account: Pubkey,
-    pub initializer_receive_token_account: Pubkey,
-    pub initializer_deposit_mint_account: Pubkey,
-    pub initializer_receive_mint_account: Pubkey,
-    pub initializer_amount: u64,
-    pub taker_amount: u64,
-    pub vault_authority_bump: u8,
+impl<'info> ReturnRental<'info> {
+    fn into_transfer_to_vault_context(
+        &self,
+    ) -> CpiContext<'_, '_, '_, 'info, TransferChecked<'info>> {
+        let cpi_accounts = TransferChecked {
+            from: self.renter_token_account.to_account_info(),
+            mint: self.mint.to_account_info(),
+            to: self.vault.to_account_info(),
+            authority: self.renter.to_account_info(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
 }
 
-impl EscrowState {
-    pub fn space() -> usize {
-        8 + 217
+impl<'info> ClaimCollateral<'info> {
+    fn into_close_context(&self) -> CpiContext<'_, '_, '_, 'info, CloseAccount<'info>> {
+        let cpi_accounts = CloseAccount {
+            account: self.vault.to_account_info(),
+            destination: self.owner.to_account_info(),
+            authority: self.vault_authority.clone(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
     }
 }
 
-impl<'info> Initialize<'info> {
-    fn into_transfer_to_pda_context(
+impl<'info> DelistRental<'info> {
+    fn into_transfer_to_owner_context(
         &self,
     ) -> CpiContext<'_, '_, '_, 'info, TransferChecked<'info>> {
         let cpi_accounts = TransferChecked {
-            from: self.initializer_deposit_token_account.to_account_info(),
+            from: self.vault.to_account_info(),
             mint: self.mint.to_account_info(),
-            to: self.vault.to_account_info(),
-            authority: self.initializer.to_account_info(),
+            to: self.owner_token_account.to_account_info(),
+            authority: self.vault_authority.clone(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
+
+    fn into_close_context(&self) -> CpiContext<'_, '_, '_, 'info, CloseAccount<'info>> {
+        let cpi_accounts = CloseAccount {
+            account: self.vault.to_account_info(),
+            destination: self.owner.to_account_info(),
+            authority: self.vault_authority.clone(),
         };
         CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
     }
 }
 
-impl<'info> Cancel<'info> {
-    fn into_transfer_to_initializer_context(
+impl<'info> StartRentToOwn<'info> {
+    fn into_transfer_to_renter_context(
         &self,
     ) -> CpiContext<'_, '_, '_, 'info, TransferChecked<'info>> {
         let cpi_accounts = TransferChecked {
             from: self.vault.to_account_info(),
             mint: self.mint.to_account_info(),
-            to: self.initializer_deposit_token_account.to_account_info(),
+            to: self.renter_token_account.to_account_info(),
             authority: self.vault_authority.clone(),
         };
         CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
@@ -373,45 +11034,785 @@ impl<'info> Cancel<'info> {
     fn into_close_context(&self) -> CpiContext<'_, '_, '_, 'info, CloseAccount<'info>> {
         let cpi_accounts = CloseAccount {
             account: self.vault.to_account_info(),
-            destination: self.initializer.to_account_info(),
+            destination: self.escrow_state.to_account_info(),
             authority: self.vault_authority.clone(),
         };
         CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
     }
+
+    fn into_approve_context(&self) -> CpiContext<'_, '_, '_, 'info, Approve<'info>> {
+        let cpi_accounts = Approve {
+            to: self.renter_token_account.to_account_info(),
+            delegate: self.vault_authority.clone(),
+            authority: self.renter.to_account_info(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
 }
 
-impl<'info> Exchange<'info> {
+impl<'info> PayRent<'info> {
+    fn into_revoke_context(&self) -> CpiContext<'_, '_, '_, 'info, Revoke<'info>> {
+        let cpi_accounts = Revoke {
+            source: self.renter_token_account.to_account_info(),
+            authority: self.renter.to_account_info(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
+}
+
+impl<'info> Repossess<'info> {
     fn into_transfer_to_initializer_context(
         &self,
     ) -> CpiContext<'_, '_, '_, 'info, TransferChecked<'info>> {
+        let cpi_accounts = TransferChecked {
+            from: self.renter_token_account.to_account_info(),
+            mint: self.mint.to_account_info(),
+            to: self.initializer_deposit_token_account.to_account_info(),
+            authority: self.vault_authority.clone(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
+}
+
+impl<'info> Cancel<'info> {
+    fn into_transfer_to_initializer_context(
+        &self,
+    ) -> Result<CpiContext<'_, '_, '_, 'info, TransferChecked<'info>>> {
+        let vault = self.vault.as_ref().ok_or(EscrowError::MissingVault)?;
+        let cpi_accounts = TransferChecked {
+            from: vault.to_account_info(),
+            mint: self.mint.to_account_info(),
+            to: self.initializer_deposit_token_account.to_account_info(),
+            authority: self.vault_authority.clone(),
+        };
+        Ok(CpiContext::new(
+            self.token_program.to_account_info(),
+            cpi_accounts,
+        ))
+    }
+
+    fn into_close_context(&self) -> Result<CpiContext<'_, '_, '_, 'info, CloseAccount<'info>>> {
+        let vault = self.vault.as_ref().ok_or(EscrowError::MissingVault)?;
+        let cpi_accounts = CloseAccount {
+            account: vault.to_account_info(),
+            destination: self.escrow_state.to_account_info(),
+            authority: self.vault_authority.clone(),
+        };
+        Ok(CpiContext::new(
+            self.token_program.to_account_info(),
+            cpi_accounts,
+        ))
+    }
+
+    fn into_revoke_context(&self) -> CpiContext<'_, '_, '_, 'info, Revoke<'info>> {
+        let cpi_accounts = Revoke {
+            source: self.initializer_deposit_token_account.to_account_info(),
+            authority: self.initializer.to_account_info(),
+        };
+        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+    }
+}
+
+impl<'info> Settle<'info> {
+    fn transfer_one(
+        &self,
+        from: &Account<'info, TokenAccount>,
+        to: &Account<'info, TokenAccount>,
+        mint: &Account<'info, Mint>,
+    ) -> Result<()> {
+        let escrow_key = self.escrow_state.key();
+        let authority_seeds = &[
+            b"authority".as_ref(),
+            escrow_key.as_ref(),
+            &[self.escrow_state.vault_authority_bump],
+        ];
+        let cpi_accounts = TransferChecked {
+            from: from.to_account_info(),
+            mint: mint.to_account_info(),
+            to: to.to_account_info(),
+            authority: self.vault_authority.clone(),
+        };
+        token::transfer_checked(
+            CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+                .with_signer(&[&authority_seeds[..]]),
+            1,
+            mint.decimals,
+        )
+    }
+
+    /// Closes both vaults: the NFT vault into the escrow state, whose rent
+    /// follows it back to whoever paid it, and the settlement vault back to the
+    /// taker who funded it.
+    fn close_vaults(&self) -> Result<()> {
+        let escrow_key = self.escrow_state.key();
+        let authority_seeds = &[
+            b"authority".as_ref(),
+            escrow_key.as_ref(),
+            &[self.escrow_state.vault_authority_bump],
+        ];
+        for (account, destination) in [
+            (&self.vault, self.escrow_state.to_account_info()),
+            (&self.settlement_vault, self.taker.clone()),
+        ] {
+            let cpi_accounts = CloseAccount {
+                account: account.to_account_info(),
+                destination,
+                authority: self.vault_authority.clone(),
+            };
+            token::close_account(
+                CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+                    .with_signer(&[&authority_seeds[..]]),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn release(&self) -> Result<()> {
+        self.transfer_one(
+            &self.vault,
+            &self.taker_receive_token_account,
+            &self.initializer_deposit_token_mint,
+        )?;
+        self.transfer_one(
+            &self.settlement_vault,
+            &self.initializer_receive_token_account,
+            &self.taker_deposit_token_mint,
+        )?;
+        self.close_vaults()?;
+        let escrow_info = self.escrow_state.to_account_info();
+        move_program_lamports(
+            &escrow_info,
+            &self.taker,
+            self.escrow_state.initializer_amount,
+        )?;
+        move_program_lamports(
+            &escrow_info,
+            &self.initializer,
+            self.escrow_state.pending_proceeds,
+        )
+    }
+
+    fn refund(&self) -> Result<()> {
+        self.transfer_one(
+            &self.vault,
+            &self.initializer_deposit_token_account,
+            &self.initializer_deposit_token_mint,
+        )?;
+        self.transfer_one(
+            &self.settlement_vault,
+            &self.taker_deposit_token_account,
+            &self.taker_deposit_token_mint,
+        )?;
+        self.close_vaults()?;
+        move_program_lamports(
+            &self.escrow_state.to_account_info(),
+            &self.taker,
+            self.escrow_state.pending_proceeds,
+        )
+    }
+}
+
+impl<'info> Exchange<'info> {
+    /// Settles a fill of the listing for `exchange` and the instructions built
+    /// on it. `print` carries the accounts of a printed edition, which every
+    /// fill of a listing printing editions needs.
+    fn fill(
+        &mut self,
+        remaining_accounts: &[AccountInfo<'info>],
+        print: Option<&PrintEdition<'info>>,
+        memo: Option<String>,
+        terms_hash: Option<[u8; 32]>,
+        expected_price: Option<u64>,
+        expected_mint: Option<Pubkey>,
+    ) -> Result<()> {
+        require!(
+            !self.escrow_state.is_reserved(),
+            EscrowError::EscrowReserved
+        );
+        require!(
+            !self.config.prevent_self_trade || self.taker.key() != self.initializer.key(),
+            EscrowError::SelfTrade
+        );
+        require!(
+            !self.escrow_state.is_settling(),
+            EscrowError::EscrowSettling
+        );
+        require!(
+            !self.escrow_state.in_payment_plan(),
+            EscrowError::PaymentPlanActive
+        );
+        require!(
+            !self.escrow_state.in_rent_to_own(),
+            EscrowError::RentToOwnActive
+        );
+        require!(!self.escrow_state.on_loan(), EscrowError::EscrowOnLoan);
+        require!(!self.escrow_state.is_vesting(), EscrowError::EscrowVesting);
+        require!(!self.escrow_state.in_bnpl(), EscrowError::BnplActive);
+        require!(!self.escrow_state.is_gift(), EscrowError::GiftEscrow);
+        require!(!self.escrow_state.is_htlc(), EscrowError::HtlcEscrow);
+        require!(
+            !self.escrow_state.paid_through_wormhole(),
+            EscrowError::WormholeEscrow
+        );
+        require!(!self.escrow_state.in_ring(), EscrowError::RingLeg);
+        require!(
+            !self.escrow_state.awaiting_deposit,
+            EscrowError::ListingNotDeposited
+        );
+        require!(
+            self.escrow_state.terms_acknowledged(&terms_hash),
+            EscrowError::TermsNotAcknowledged
+        );
+        require!(
+            expected_mint.map_or(true, |mint| {
+                mint == self.escrow_state.initializer_deposit_mint_account
+            }),
+            EscrowError::UnexpectedMint
+        );
+        let now = Clock::get()?.unix_timestamp;
+        self.escrow_state.require_listing_age(&self.config, now)?;
+        require!(
+            !self.escrow_state.is_expired(now),
+            EscrowError::ListingExpired
+        );
+        record_wallet_activity(&mut self.taker_activity, &self.config, now, false)?;
+        self.require_purchase_gate(remaining_accounts)?;
+        // Held proceeds sit in the escrow state as lamports, so neither a
+        // delayed settlement nor a vesting sale can be paid in wSOL.
+        let paying_in_wsol = self.taker_wsol_account(remaining_accounts).is_some();
+        let settlement_delay_secs = self.escrow_state.settlement_delay_secs;
+        let delayed = settlement_delay_secs > 0;
+        require!(
+            !delayed || !paying_in_wsol,
+            EscrowError::DelayedSettlementWsol
+        );
+        let vesting = self.escrow_state.vesting_duration_secs > 0;
+        require!(!vesting || !paying_in_wsol, EscrowError::VestingWsol);
+        let printing = self.escrow_state.is_printing_editions();
+
+        let escrow_key = self.escrow_state.key();
+        let authority_seeds = &[
+            &AUTHORITY_SEED[..],
+            escrow_key.as_ref(),
+            &[self.escrow_state.vault_authority_bump],
+        ];
+
+        token::transfer_checked(
+            self.into_transfer_to_initializer_context(remaining_accounts)?,
+            1 as u64,
+            self.taker_deposit_token_mint.decimals,
+        )?;
+
+        // The maker fee is deducted from the seller's proceeds and the taker fee is
+        // charged on top of the price; a referrer, when present, receives its
+        // configured share of the combined protocol fee.
+        let Quote {
+            price: taker_amount,
+            maker_fee,
+            taker_fee,
+            ..
+        } = quote_escrow(
+            &self.config,
+            &self.escrow_state,
+            &self.discount_token_account,
+            &self.collection_fee_override,
+            &remaining_account(remaining_accounts, &self.escrow_state.price_feed).cloned(),
+        )?;
+        require!(
+            expected_price.map_or(true, |price| taker_amount <= price),
+            EscrowError::PriceAboveExpected
+        );
+        // A private listing only fills for the taker and price its initializer
+        // signed, verified by the ed25519 instruction right before this one.
+        if self.escrow_state.fills_privately() {
+            let instructions = remaining_account(remaining_accounts, &instructions_sysvar::ID)
+                .ok_or(EscrowError::MissingTakerAllowance)?;
+            let message =
+                taker_allowance_message(&self.escrow_state.key(), &self.taker.key(), taker_amount);
+            verify_ed25519_signature(instructions, &self.escrow_state.initializer_key, &message)?;
+        }
+        let protocol_fee = checked_add(maker_fee, taker_fee)?;
+        let referral_fee = match self.referrer {
+            Some(_) => bps_of(protocol_fee, self.config.referral_share_bps)?,
+            None => 0,
+        };
+
+        // Everything the taker owes is collected in one go and then split out,
+        // rather than paying each recipient with its own system transfer.
+        self.collect_taker_payment(checked_add(taker_amount, taker_fee)?, remaining_accounts)?;
+
+        // The seller's donation is carved out of their proceeds after the maker fee.
+        let proceeds = checked_sub(taker_amount, maker_fee)?;
+        let donation = bps_of(proceeds, self.escrow_state.donation_bps)?;
+        if donation > 0 {
+            let donation_recipient =
+                remaining_account(remaining_accounts, &self.escrow_state.donation_recipient)
+                    .ok_or(EscrowError::MissingDonationRecipient)?;
+            self.pay(donation_recipient, donation, remaining_accounts)?;
+        }
+        // So is the fee of the program the escrow was listed through.
+        let integrator_fee = bps_of(proceeds, self.escrow_state.integrator_fee_bps)?;
+        if integrator_fee > 0 {
+            let integrator_fee_recipient = remaining_account(
+                remaining_accounts,
+                &self.escrow_state.integrator_fee_recipient,
+            )
+            .ok_or(EscrowError::MissingIntegratorFeeRecipient)?;
+            self.pay(integrator_fee_recipient, integrator_fee, remaining_accounts)?;
+        }
+        // With a delayed settlement the seller's proceeds stay in the escrow
+        // state until `settle`, and a vesting sale releases them through
+        // `claim_vested`; fees and the donation are paid right away.
+        let seller_proceeds = checked_sub(checked_sub(proceeds, donation)?, integrator_fee)?;
+        if !delayed && !vesting {
+            self.pay(&self.initializer, seller_proceeds, remaining_accounts)?;
+        }
+        // A registered collection DAO takes its slice of what is left for the
+        // protocol after the referral share. Its entry sits at a fixed
+        // address for the listing's collection, so the taker cannot skip it.
+        let protocol_share = checked_sub(protocol_fee, referral_fee)?;
+        let (dao_treasury, dao_fee) = if initialized(&self.collection_treasury) {
+            let collection_treasury = CollectionTreasury::try_deserialize(
+                &mut &self.collection_treasury.try_borrow_data()?[..],
+            )?;
+            let dao_treasury = remaining_account(remaining_accounts, &collection_treasury.treasury)
+                .ok_or(EscrowError::MissingDaoTreasury)?;
+            let dao_fee = bps_of(protocol_share, collection_treasury.share_bps)?;
+            self.pay(dao_treasury, dao_fee, remaining_accounts)?;
+            (Some(dao_treasury.key()), dao_fee)
+        } else {
+            (None, 0)
+        };
+        self.pay(
+            &self.treasury,
+            checked_sub(protocol_share, dao_fee)?,
+            remaining_accounts,
+        )?;
+        if let Some(referrer) = &self.referrer {
+            self.pay(referrer, referral_fee, remaining_accounts)?;
+        }
+
+        // The seller's lamport deposit goes with the master edition, so a
+        // printed edition leaves it in the escrow state until `cancel`.
+        let initializer_amount = if printing {
+            0
+        } else {
+            self.escrow_state.initializer_amount
+        };
+        if delayed {
+            // The NFT stays in the vault and the taker's token in the
+            // settlement vault until the cooling-off window has passed.
+            let settle_after = Clock::get()?
+                .unix_timestamp
+                .checked_add(settlement_delay_secs)
+                .ok_or(EscrowError::ArithmeticOverflow)?;
+            let escrow_state = &mut self.escrow_state;
+            escrow_state.pending_taker = self.taker.key();
+            escrow_state.pending_taker_deposit_token_account =
+                self.taker_deposit_token_account.key();
+            escrow_state.pending_proceeds = seller_proceeds;
+            escrow_state.settle_after = settle_after;
+        } else if printing {
+            // The master edition stays in the vault for the next print.
+            let print = print.ok_or(EscrowError::MissingEditionAccounts)?;
+            print.print_edition(self, &authority_seeds[..])?;
+            let escrow_state = &mut self.escrow_state;
+            escrow_state.editions_printed = checked_add(escrow_state.editions_printed, 1)?;
+            escrow_state.edition_price = escrow_state.next_edition_price()?;
+        } else {
+            if self.escrow_state.delegated {
+                let mint = self.initializer_deposit_token_mint.to_account_info();
+                let token_metadata_program = remaining_account(remaining_accounts, &Metadata::id())
+                    .map(Program::try_from)
+                    .transpose()?;
+                thaw_delegated(
+                    &token_metadata_program,
+                    &remaining_account(remaining_accounts, &edition_address(mint.key)).cloned(),
+                    &self.vault_authority,
+                    self.nft_source(remaining_accounts)?,
+                    mint,
+                    self.token_program.to_account_info(),
+                    &authority_seeds[..],
+                )?;
+            }
+            token::transfer_checked(
+                self.into_transfer_to_taker_context(remaining_accounts)?
+                    .with_signer(&[&authority_seeds[..]]),
+                1 as u64,
+                self.initializer_deposit_token_mint.decimals,
+            )?;
+
+            // The vault is closed into the escrow state so its lamports can be split:
+            // the initializer's deposit goes to the taker, while the vault rent
+            // follows the state rent back to whoever paid it when the state closes.
+            if !self.escrow_state.delegated {
+                token::close_account(
+                    self.into_close_context()?
+                        .with_signer(&[&authority_seeds[..]]),
+                )?;
+            }
+            move_program_lamports(
+                &self.escrow_state.to_account_info(),
+                &self.taker.to_account_info(),
+                initializer_amount,
+            )?;
+            if vesting {
+                let escrow_state = &mut self.escrow_state;
+                escrow_state.vesting_start = Clock::get()?.unix_timestamp;
+                escrow_state.vesting_total = seller_proceeds;
+            }
+        }
+
+        if self.config.rewards_active() {
+            let points = mul_div(taker_amount, self.config.rewards_per_sol, LAMPORTS_PER_SOL)?;
+            self.mint_rewards(points, remaining_accounts)?;
+        }
+
+        record_audit(
+            &mut self.audit_trail,
+            &self.escrow_state,
+            AUDIT_EXCHANGED,
+            &self.taker.key(),
+            taker_amount,
+        )?;
+        let memo_program = remaining_account(remaining_accounts, &Memo::id())
+            .map(Program::try_from)
+            .transpose()?;
+        forward_memo(&memo_program, memo)?;
+
+        emit!(EscrowExchanged {
+            escrow_state: self.escrow_state.key(),
+            initializer: self.initializer.key(),
+            taker: self.taker.key(),
+            initializer_amount,
+            taker_amount,
+            maker_fee,
+            taker_fee,
+            protocol_fee,
+            referrer: self.referrer.as_ref().map(|referrer| referrer.key()),
+            referral_fee,
+            donation_recipient: self.escrow_state.donation_recipient,
+            donation,
+            dao_treasury,
+            dao_fee,
+            integrator_fee,
+            net_to_seller: seller_proceeds,
+            payment_mint: NATIVE_MINT,
+        });
+
+        if !delayed && !vesting && !printing {
+            close_escrow(&self.escrow_state, &self.initializer, remaining_accounts)?;
+        }
+
+        Ok(())
+    }
+
+    /// Moves `total_due` lamports from the taker into the escrow state, or,
+    /// when paying in wSOL, tops up the taker's wSOL account with whatever
+    /// the wrapped balance is short of.
+    /// The taker's balance is checked here, when it is spent, rather than
+    /// when the listing was made.
+    fn collect_taker_payment(
+        &self,
+        total_due: u64,
+        remaining_accounts: &[AccountInfo<'info>],
+    ) -> Result<()> {
+        let Some(taker_wsol_account) = self.taker_wsol_account(remaining_accounts) else {
+            require!(
+                self.taker.lamports() >= total_due,
+                EscrowError::InsufficientTakerFunds
+            );
+            return transfer_lamports(
+                &self.taker.to_account_info(),
+                &self.escrow_state.to_account_info(),
+                total_due,
+            );
+        };
+        let wrapped = token_balance(taker_wsol_account)?;
+        if wrapped >= total_due {
+            return Ok(());
+        }
+
+        let shortfall = checked_sub(total_due, wrapped)?;
+        require!(
+            self.taker.lamports() >= shortfall,
+            EscrowError::InsufficientTakerFunds
+        );
+        transfer_lamports(&self.taker.to_account_info(), taker_wsol_account, shortfall)?;
+        token::sync_native(CpiContext::new(
+            self.token_program.to_account_info(),
+            SyncNative {
+                account: taker_wsol_account.clone(),
+            },
+        ))
+    }
+
+    /// The taker's wSOL ATA, which the taker passes to pay in wSOL instead
+    /// of lamports.
+    fn taker_wsol_account<'a>(
+        &self,
+        remaining_accounts: &'a [AccountInfo<'info>],
+    ) -> Option<&'a AccountInfo<'info>> {
+        if remaining_accounts.is_empty() {
+            return None;
+        }
+        remaining_account(
+            remaining_accounts,
+            &get_associated_token_address(self.taker.key, &NATIVE_MINT),
+        )
+    }
+
+    /// Pays `amount` of the collected taker payment to `recipient`: in
+    /// lamports out of the escrow state by default, or as wSOL into the
+    /// recipient's wSOL ATA (looked up in the remaining accounts) when the
+    /// taker pays from a wSOL account.
+    fn pay(
+        &self,
+        recipient: &AccountInfo<'info>,
+        amount: u64,
+        remaining_accounts: &[AccountInfo<'info>],
+    ) -> Result<()> {
+        let Some(taker_wsol_account) = self.taker_wsol_account(remaining_accounts) else {
+            return move_program_lamports(&self.escrow_state.to_account_info(), recipient, amount);
+        };
+        if amount == 0 {
+            return Ok(());
+        }
+
+        let native_mint = remaining_account(remaining_accounts, &NATIVE_MINT)
+            .ok_or(EscrowError::MissingNativeMint)?;
+        let destination_key = get_associated_token_address(recipient.key, &NATIVE_MINT);
+        let destination = remaining_account(remaining_accounts, &destination_key)
+            .ok_or(EscrowError::MissingWsolDestination)?;
+        let cpi_accounts = TransferChecked {
+            from: taker_wsol_account.clone(),
+            mint: native_mint.clone(),
+            to: destination.clone(),
+            authority: self.taker.to_account_info(),
+        };
+        token::transfer_checked(
+            CpiContext::new(self.token_program.to_account_info(), cpi_accounts),
+            amount,
+            NATIVE_MINT_DECIMALS,
+        )
+    }
+
+    /// Checks a member-only listing's taker holds enough of the gate mint,
+    /// in their ATA of it or deposited in governance.
+    fn require_purchase_gate(&self, remaining_accounts: &[AccountInfo<'info>]) -> Result<()> {
+        let escrow_state = &self.escrow_state;
+        if escrow_state.gate_mint == Pubkey::default() {
+            return Ok(());
+        }
+        let gate_token_account = remaining_account(
+            remaining_accounts,
+            &get_associated_token_address(self.taker.key, &escrow_state.gate_mint),
+        );
+        let token_owner_record = remaining_accounts.iter().find(|account| {
+            escrow_state.gate_governance_program != Pubkey::default()
+                && account.owner == &escrow_state.gate_governance_program
+        });
+        let held = if let Some(gate_token_account) = gate_token_account {
+            token_balance(gate_token_account)?
+        } else if let Some(token_owner_record) = token_owner_record {
+            governance_deposit(
+                token_owner_record,
+                escrow_state.gate_governance_program,
+                escrow_state.gate_mint,
+                self.taker.key(),
+            )?
+        } else {
+            0
+        };
+        require!(
+            held >= escrow_state.gate_min_amount,
+            EscrowError::PurchaseGateNotMet
+        );
+        Ok(())
+    }
+
+    /// Mints `points` of the rewards mint into the taker's and the seller's
+    /// rewards ATAs.
+    fn mint_rewards(&self, points: u64, remaining_accounts: &[AccountInfo<'info>]) -> Result<()> {
+        let market = market_seed(self.config.market_id);
+        let rewards_seeds = &[
+            b"rewards".as_ref(),
+            &market,
+            &[self.config.rewards_authority_bump],
+        ];
+        let rewards_authority_key = Pubkey::create_program_address(rewards_seeds, &crate::ID)
+            .map_err(|_| error!(EscrowError::RewardsMintAuthorityMismatch))?;
+        let rewards_mint_key = self.config.rewards_mint;
+        let (
+            Some(rewards_mint),
+            Some(rewards_authority),
+            Some(taker_rewards_token_account),
+            Some(initializer_rewards_token_account),
+        ) = (
+            remaining_account(remaining_accounts, &rewards_mint_key),
+            remaining_account(remaining_accounts, &rewards_authority_key),
+            remaining_account(
+                remaining_accounts,
+                &get_associated_token_address(self.taker.key, &rewards_mint_key),
+            ),
+            remaining_account(
+                remaining_accounts,
+                &get_associated_token_address(self.initializer.key, &rewards_mint_key),
+            ),
+        )
+        else {
+            return err!(EscrowError::MissingRewardsAccounts);
+        };
+
+        for destination in [
+            taker_rewards_token_account,
+            initializer_rewards_token_account,
+        ] {
+            let cpi_accounts = MintTo {
+                mint: rewards_mint.clone(),
+                to: destination.clone(),
+                authority: rewards_authority.clone(),
+            };
+            token::mint_to(
+                CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+                    .with_signer(&[&rewards_seeds[..]]),
+                points,
+            )?;
+        }
+        Ok(())
+    }
+
+    fn into_transfer_to_initializer_context(
+        &self,
+        remaining_accounts: &[AccountInfo<'info>],
+    ) -> Result<CpiContext<'_, '_, '_, 'info, TransferChecked<'info>>> {
+        let to = if self.escrow_state.settlement_delay_secs > 0 {
+            // Holds the taker's token during a delayed settlement.
+            let settlement_vault = remaining_account(
+                remaining_accounts,
+                &get_associated_token_address(
+                    self.vault_authority.key,
+                    &self.taker_deposit_token_mint.key(),
+                ),
+            )
+            .ok_or(EscrowError::MissingSettlementVault)?;
+            settlement_vault.clone()
+        } else {
+            self.initializer_receive_token_account.to_account_info()
+        };
         let cpi_accounts = TransferChecked {
             from: self.taker_deposit_token_account.to_account_info(),
             mint: self.taker_deposit_token_mint.to_account_info(),
-            to: self.initializer_receive_token_account.to_account_info(),
+            to,
             authority: self.taker.to_account_info(),
         };
-        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+        Ok(CpiContext::new(
+            self.token_program.to_account_info(),
+            cpi_accounts,
+        ))
+    }
+
+    /// Refunds the reserving taker's payment and deposit and clears the
+    /// reservation.
+    fn release_reservation(&mut self) -> Result<()> {
+        move_program_lamports(
+            &self.escrow_state.to_account_info(),
+            &self.taker.to_account_info(),
+            checked_add(
+                self.escrow_state.reserved_amount,
+                self.escrow_state.reservation_deposit,
+            )?,
+        )?;
+        self.escrow_state.reserved_by = Pubkey::default();
+        self.escrow_state.reserved_amount = 0;
+        self.escrow_state.reservation_deposit = 0;
+        self.escrow_state.reserved_until = 0;
+        Ok(())
+    }
+
+    /// Where the NFT is released from: the seller's own token account for a
+    /// delegated listing, otherwise the vault.
+    fn nft_source(&self, remaining_accounts: &[AccountInfo<'info>]) -> Result<AccountInfo<'info>> {
+        if self.escrow_state.delegated {
+            let source = remaining_account(
+                remaining_accounts,
+                &self.escrow_state.initializer_deposit_token_account,
+            )
+            .ok_or(EscrowError::MissingDelegatedListingAccounts)?;
+            Ok(source.clone())
+        } else {
+            let vault = self.vault.as_ref().ok_or(EscrowError::MissingVault)?;
+            Ok(vault.to_account_info())
+        }
     }
 
     fn into_transfer_to_taker_context(
         &self,
-    ) -> CpiContext<'_, '_, '_, 'info, TransferChecked<'info>> {
+        remaining_accounts: &[AccountInfo<'info>],
+    ) -> Result<CpiContext<'_, '_, '_, 'info, TransferChecked<'info>>> {
         let cpi_accounts = TransferChecked {
-            from: self.vault.to_account_info(),
+            from: self.nft_source(remaining_accounts)?,
             mint: self.initializer_deposit_token_mint.to_account_info(),
             to: self.taker_receive_token_account.to_account_info(),
             authority: self.vault_authority.clone(),
         };
-        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+        Ok(CpiContext::new(
+            self.token_program.to_account_info(),
+            cpi_accounts,
+        ))
     }
 
-    fn into_close_context(&self) -> CpiContext<'_, '_, '_, 'info, CloseAccount<'info>> {
+    fn into_close_context(&self) -> Result<CpiContext<'_, '_, '_, 'info, CloseAccount<'info>>> {
+        let vault = self.vault.as_ref().ok_or(EscrowError::MissingVault)?;
         let cpi_accounts = CloseAccount {
-            account: self.vault.to_account_info(),
-            // destination: self.initializer.clone(),
-            destination: self.taker.to_account_info().clone(),
+            account: vault.to_account_info(),
+            destination: self.escrow_state.to_account_info(),
             authority: self.vault_authority.clone(),
         };
-        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+        Ok(CpiContext::new(
+            self.token_program.to_account_info(),
+            cpi_accounts,
+        ))
+    }
+}
+
+impl<'info> PrintEdition<'info> {
+    /// Prints the next numbered edition of the vaulted master edition into
+    /// `new_edition_mint`, whose single token the taker has already minted.
+    fn print_edition(&self, exchange: &Exchange<'info>, authority_seeds: &[&[u8]]) -> Result<()> {
+        let master_edition = &self.master_edition;
+        require!(
+            exchange.escrow_state.editions_printed < exchange.escrow_state.edition_max_prints
+                && master_edition
+                    .max_supply
+                    .map_or(true, |max_supply| master_edition.supply < max_supply),
+            EscrowError::EditionSupplyExhausted
+        );
+        let vault = exchange.vault.as_ref().ok_or(EscrowError::MissingVault)?;
+        let cpi_accounts = MintNewEditionFromMasterEditionViaToken {
+            new_metadata: self.new_edition_metadata.clone(),
+            new_edition: self.new_edition.clone(),
+            master_edition: master_edition.to_account_info(),
+            new_mint: self.new_edition_mint.to_account_info(),
+            edition_mark_pda: self.edition_marker.clone(),
+            new_mint_authority: exchange.taker.to_account_info(),
+            payer: exchange.taker.to_account_info(),
+            token_account_owner: exchange.vault_authority.clone(),
+            token_account: vault.to_account_info(),
+            new_metadata_update_authority: exchange.initializer.clone(),
+            metadata: self.nft_metadata.to_account_info(),
+            token_program: exchange.token_program.to_account_info(),
+            system_program: exchange.system_program.to_account_info(),
+            rent: self.rent.to_account_info(),
+            metadata_mint: exchange.initializer_deposit_token_mint.to_account_info(),
+        };
+        metadata::mint_new_edition_from_master_edition_via_token(
+            CpiContext::new_with_signer(
+                self.token_metadata_program.to_account_info(),
+                cpi_accounts,
+                &[authority_seeds],
+            ),
+            checked_add(master_edition.supply, 1)?,
+        )
     }
 }
@@ -0,0 +1,2631 @@
+//! Helpers shared by the program-test suites.
+#![allow(dead_code)]
+
+use anchor_lang::prelude::Pubkey;
+use anchor_lang::solana_program::{
+    instruction::{AccountMeta, Instruction},
+    keccak, system_instruction, system_program,
+};
+use anchor_lang::{AnchorSerialize, InstructionData, ToAccountMetas};
+use anchor_spl::associated_token::get_associated_token_address;
+use anchor_spl::metadata::mpl_token_metadata::{
+    self,
+    state::{Collection, Data, Key, Metadata, TokenStandard, MAX_METADATA_LEN},
+};
+use anchor_spl::token::spl_token;
+use solana_nft_escrow::{accounts, instruction, market_seed, metadata_address, SwapAsset};
+use solana_program_test::{BanksClientError, ProgramTest, ProgramTestContext};
+use solana_sdk::{
+    account::Account,
+    instruction::InstructionError,
+    program_pack::Pack,
+    signature::{Keypair, Signer},
+    transaction::{Transaction, TransactionError},
+};
+use spl_associated_token_account::instruction::create_associated_token_account;
+
+pub const MAKER_FEE_BPS: u16 = 150;
+pub const TAKER_FEE_BPS: u16 = 100;
+
+pub fn funded(lamports: u64) -> Account {
+    Account::new(lamports, 0, &system_program::ID)
+}
+
+pub fn program_test() -> ProgramTest {
+    let mut program_test = ProgramTest::new("solana_nft_escrow", solana_nft_escrow::ID, None);
+    program_test.prefer_bpf(true);
+    program_test
+}
+
+pub fn pda(seeds: &[&[u8]]) -> Pubkey {
+    Pubkey::find_program_address(seeds, &solana_nft_escrow::ID).0
+}
+
+pub fn escrow_state_key(initializer: &Pubkey, nonce: u64) -> Pubkey {
+    market_escrow_state_key(initializer, nonce, 0)
+}
+
+pub fn market_escrow_state_key(initializer: &Pubkey, nonce: u64, market_id: u64) -> Pubkey {
+    pda(&[
+        b"state",
+        initializer.as_ref(),
+        &nonce.to_le_bytes(),
+        &market_seed(market_id),
+    ])
+}
+
+pub fn market_key(market_id: u64) -> Pubkey {
+    pda(&[b"config", &market_seed(market_id)])
+}
+
+pub fn vault_authority_key(escrow_state: &Pubkey) -> Pubkey {
+    pda(&[b"authority", escrow_state.as_ref()])
+}
+
+pub fn vault_key(escrow_state: &Pubkey, mint: &Pubkey) -> Pubkey {
+    get_associated_token_address(&vault_authority_key(escrow_state), mint)
+}
+
+pub async fn process(
+    ctx: &mut ProgramTestContext,
+    instructions: &[Instruction],
+    signers: &[&Keypair],
+) -> Result<(), BanksClientError> {
+    let blockhash = ctx.get_new_latest_blockhash().await.unwrap();
+    let mut all_signers = vec![&ctx.payer];
+    all_signers.extend_from_slice(signers);
+    let tx = Transaction::new_signed_with_payer(
+        instructions,
+        Some(&ctx.payer.pubkey()),
+        &all_signers,
+        blockhash,
+    );
+    ctx.banks_client.process_transaction(tx).await
+}
+
+/// Simulates `instructions` and returns the return data the program set.
+pub async fn simulate_return_data(
+    ctx: &mut ProgramTestContext,
+    instructions: &[Instruction],
+) -> Vec<u8> {
+    let blockhash = ctx.get_new_latest_blockhash().await.unwrap();
+    let tx = Transaction::new_signed_with_payer(
+        instructions,
+        Some(&ctx.payer.pubkey()),
+        &[&ctx.payer],
+        blockhash,
+    );
+    let simulation = ctx.banks_client.simulate_transaction(tx).await.unwrap();
+    simulation
+        .simulation_details
+        .unwrap()
+        .return_data
+        .unwrap()
+        .data
+}
+
+pub fn assert_error(result: Result<(), BanksClientError>, code: u32) {
+    match result.unwrap_err().unwrap() {
+        TransactionError::InstructionError(_, InstructionError::Custom(actual)) => {
+            assert_eq!(actual, code)
+        }
+        other => panic!("unexpected error {other:?}"),
+    }
+}
+
+pub async fn create_mint(ctx: &mut ProgramTestContext) -> Pubkey {
+    let mint = Keypair::new();
+    let rent = ctx.banks_client.get_rent().await.unwrap();
+    let payer = ctx.payer.pubkey();
+    let instructions = [
+        system_instruction::create_account(
+            &payer,
+            &mint.pubkey(),
+            rent.minimum_balance(spl_token::state::Mint::LEN),
+            spl_token::state::Mint::LEN as u64,
+            &spl_token::ID,
+        ),
+        spl_token::instruction::initialize_mint2(&spl_token::ID, &mint.pubkey(), &payer, None, 0)
+            .unwrap(),
+    ];
+    process(ctx, &instructions, &[&mint]).await.unwrap();
+    mint.pubkey()
+}
+
+/// Writes a Token Metadata account for `mint` as a verified member of
+/// `collection`, without loading the Token Metadata program.
+pub async fn set_collection_metadata(
+    ctx: &mut ProgramTestContext,
+    mint: &Pubkey,
+    collection: &Pubkey,
+) {
+    let metadata = Metadata {
+        key: Key::MetadataV1,
+        update_authority: ctx.payer.pubkey(),
+        mint: *mint,
+        data: Data {
+            name: String::new(),
+            symbol: String::new(),
+            uri: String::new(),
+            seller_fee_basis_points: 0,
+            creators: None,
+        },
+        primary_sale_happened: false,
+        is_mutable: true,
+        edition_nonce: None,
+        token_standard: Some(TokenStandard::NonFungible),
+        collection: Some(Collection {
+            verified: true,
+            key: *collection,
+        }),
+        uses: None,
+        collection_details: None,
+        programmable_config: None,
+    };
+    let mut data = metadata.try_to_vec().unwrap();
+    data.resize(MAX_METADATA_LEN, 0);
+    let rent = ctx.banks_client.get_rent().await.unwrap();
+    let account = Account {
+        lamports: rent.minimum_balance(data.len()),
+        data,
+        owner: mpl_token_metadata::ID,
+        executable: false,
+        rent_epoch: 0,
+    };
+    ctx.set_account(&metadata_address(mint), &account.into());
+}
+
+pub async fn mint_one_to(ctx: &mut ProgramTestContext, mint: &Pubkey, owner: &Pubkey) -> Pubkey {
+    let payer = ctx.payer.pubkey();
+    let ata = get_associated_token_address(owner, mint);
+    let instructions = [
+        create_associated_token_account(&payer, owner, mint, &spl_token::ID),
+        spl_token::instruction::mint_to(&spl_token::ID, mint, &ata, &payer, &[], 1).unwrap(),
+    ];
+    process(ctx, &instructions, &[]).await.unwrap();
+    ata
+}
+
+pub async fn token_amount(ctx: &mut ProgramTestContext, address: Pubkey) -> u64 {
+    match ctx.banks_client.get_account(address).await.unwrap() {
+        Some(account) => {
+            spl_token::state::Account::unpack(&account.data)
+                .unwrap()
+                .amount
+        }
+        None => 0,
+    }
+}
+
+pub async fn lamports(ctx: &mut ProgramTestContext, address: Pubkey) -> u64 {
+    ctx.banks_client.get_balance(address).await.unwrap()
+}
+
+pub async fn exists(ctx: &mut ProgramTestContext, address: Pubkey) -> bool {
+    ctx.banks_client
+        .get_account(address)
+        .await
+        .unwrap()
+        .is_some()
+}
+
+/// Creates the fee config with the payer as admin.
+pub async fn initialize_config(ctx: &mut ProgramTestContext, treasury: Pubkey) {
+    let ix = Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::InitializeConfig {
+            admin: ctx.payer.pubkey(),
+            config: pda(&[b"config"]),
+            treasury,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::InitializeConfig {
+            maker_fee_bps: MAKER_FEE_BPS,
+            taker_fee_bps: TAKER_FEE_BPS,
+            referral_share_bps: 0,
+        }
+        .data(),
+    };
+    process(ctx, &[ix], &[]).await.unwrap();
+}
+
+pub async fn set_reservation_params(
+    ctx: &mut ProgramTestContext,
+    reservation_deposit_bps: u16,
+    reservation_window_secs: i64,
+) {
+    let ix = Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::AdminConfig {
+            admin: ctx.payer.pubkey(),
+            config: pda(&[b"config"]),
+        }
+        .to_account_metas(None),
+        data: instruction::SetReservationParams {
+            reservation_deposit_bps,
+            reservation_window_secs,
+        }
+        .data(),
+    };
+    process(ctx, &[ix], &[]).await.unwrap();
+}
+
+pub async fn set_min_listing_age(ctx: &mut ProgramTestContext, min_listing_age_secs: i64) {
+    let ix = Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::AdminConfig {
+            admin: ctx.payer.pubkey(),
+            config: pda(&[b"config"]),
+        }
+        .to_account_metas(None),
+        data: instruction::SetMinListingAge {
+            min_listing_age_secs,
+        }
+        .data(),
+    };
+    process(ctx, &[ix], &[]).await.unwrap();
+}
+
+pub async fn set_max_listing_duration(
+    ctx: &mut ProgramTestContext,
+    max_listing_duration_secs: i64,
+) {
+    let ix = Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::AdminConfig {
+            admin: ctx.payer.pubkey(),
+            config: pda(&[b"config"]),
+        }
+        .to_account_metas(None),
+        data: instruction::SetMaxListingDuration {
+            max_listing_duration_secs,
+        }
+        .data(),
+    };
+    process(ctx, &[ix], &[]).await.unwrap();
+}
+
+pub async fn set_max_floor_age(ctx: &mut ProgramTestContext, max_floor_age_secs: i64) {
+    let ix = Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::AdminConfig {
+            admin: ctx.payer.pubkey(),
+            config: pda(&[b"config"]),
+        }
+        .to_account_metas(None),
+        data: instruction::SetMaxFloorAge { max_floor_age_secs }.data(),
+    };
+    process(ctx, &[ix], &[]).await.unwrap();
+}
+
+pub async fn set_rate_limits(
+    ctx: &mut ProgramTestContext,
+    window_secs: i64,
+    max_listings_per_window: u16,
+    max_fills_per_window: u16,
+) {
+    let ix = Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::AdminConfig {
+            admin: ctx.payer.pubkey(),
+            config: pda(&[b"config"]),
+        }
+        .to_account_metas(None),
+        data: instruction::SetRateLimits {
+            window_secs,
+            max_listings_per_window,
+            max_fills_per_window,
+        }
+        .data(),
+    };
+    process(ctx, &[ix], &[]).await.unwrap();
+}
+
+pub fn initialize_ix(
+    initializer: Pubkey,
+    taker: Pubkey,
+    mint: Pubkey,
+    receive_mint: Pubkey,
+    nonce: u64,
+    initializer_amount: u64,
+    taker_amount: u64,
+) -> Instruction {
+    delayed_initialize_ix(
+        initializer,
+        taker,
+        mint,
+        receive_mint,
+        nonce,
+        initializer_amount,
+        taker_amount,
+        0,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn delayed_initialize_ix(
+    initializer: Pubkey,
+    taker: Pubkey,
+    mint: Pubkey,
+    receive_mint: Pubkey,
+    nonce: u64,
+    initializer_amount: u64,
+    taker_amount: u64,
+    settlement_delay_secs: i64,
+) -> Instruction {
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: initialize_accounts(initializer, taker, mint, receive_mint, nonce, 0)
+            .to_account_metas(None),
+        data: instruction::Initialize {
+            initializer_amount,
+            taker_amount,
+            donation_recipient: Pubkey::default(),
+            donation_bps: 0,
+            price_usd_cents: 0,
+            price_feed: Pubkey::default(),
+            settlement_delay_secs,
+            note: String::new(),
+        }
+        .data(),
+    }
+}
+
+/// Accounts of an `initialize` listing in market `market_id`.
+pub fn initialize_accounts(
+    initializer: Pubkey,
+    taker: Pubkey,
+    mint: Pubkey,
+    receive_mint: Pubkey,
+    nonce: u64,
+    market_id: u64,
+) -> accounts::Initialize {
+    let escrow_state = market_escrow_state_key(&initializer, nonce, market_id);
+    accounts::Initialize {
+        initializer,
+        rent_payer: initializer,
+        mint,
+        vault_authority: vault_authority_key(&escrow_state),
+        vault: vault_key(&escrow_state, &mint),
+        taker_key: taker,
+        initializer_deposit_token_account: get_associated_token_address(&initializer, &mint),
+        initializer_receive_mint_account: receive_mint,
+        config: market_key(market_id),
+        listing_counter: pda(&[
+            b"listing_counter",
+            initializer.as_ref(),
+            &market_seed(market_id),
+        ]),
+        escrow_state,
+        nft_metadata: metadata_address(&mint),
+        collection_floor: collection_floor_key(&Pubkey::default(), market_id),
+        wallet_activity: None,
+        system_program: system_program::ID,
+        token_program: spl_token::ID,
+        associated_token_program: spl_associated_token_account::ID,
+    }
+}
+
+pub fn exchange_ix(
+    taker: Pubkey,
+    initializer: Pubkey,
+    mint: Pubkey,
+    receive_mint: Pubkey,
+    nonce: u64,
+    treasury: Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: exchange_accounts(taker, initializer, mint, receive_mint, nonce, treasury)
+            .to_account_metas(None),
+        data: instruction::Exchange {
+            memo: None,
+            terms_hash: None,
+            expected_price: None,
+            expected_mint: None,
+        }
+        .data(),
+    }
+}
+
+/// Accounts of a plain `exchange`, for tests to fill in the optional ones
+/// and append the remaining ones.
+pub fn exchange_accounts(
+    taker: Pubkey,
+    initializer: Pubkey,
+    mint: Pubkey,
+    receive_mint: Pubkey,
+    nonce: u64,
+    treasury: Pubkey,
+) -> accounts::Exchange {
+    let escrow_state = escrow_state_key(&initializer, nonce);
+    accounts::Exchange {
+        taker,
+        initializer_deposit_token_mint: mint,
+        taker_deposit_token_mint: receive_mint,
+        taker_deposit_token_account: get_associated_token_address(&taker, &receive_mint),
+        taker_receive_token_account: get_associated_token_address(&taker, &mint),
+        initializer,
+        initializer_receive_token_account: get_associated_token_address(
+            &initializer,
+            &receive_mint,
+        ),
+        escrow_state,
+        vault: Some(vault_key(&escrow_state, &mint)),
+        vault_authority: vault_authority_key(&escrow_state),
+        config: pda(&[b"config"]),
+        treasury,
+        referrer: None,
+        discount_token_account: None,
+        collection_fee_override: collection_fee_override_key(&Pubkey::default(), 0),
+        collection_treasury: collection_treasury_key(&Pubkey::default(), 0),
+        taker_activity: None,
+        audit_trail: None,
+        token_program: spl_token::ID,
+        associated_token_program: spl_associated_token_account::ID,
+        system_program: system_program::ID,
+    }
+}
+
+pub fn cancel_ix(initializer: Pubkey, mint: Pubkey, nonce: u64) -> Instruction {
+    let escrow_state = escrow_state_key(&initializer, nonce);
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::Cancel {
+            initializer,
+            mint,
+            vault: Some(vault_key(&escrow_state, &mint)),
+            closed_vault: None,
+            vault_authority: vault_authority_key(&escrow_state),
+            initializer_deposit_token_account: get_associated_token_address(&initializer, &mint),
+            escrow_state,
+            nft_edition: None,
+            token_metadata_program: None,
+            audit_trail: None,
+            memo_program: None,
+            token_program: spl_token::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::Cancel { memo: None }.data(),
+    }
+}
+
+pub fn reserve_ix(taker: Pubkey, initializer: Pubkey, nonce: u64) -> Instruction {
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::Reserve {
+            taker,
+            escrow_state: escrow_state_key(&initializer, nonce),
+            config: pda(&[b"config"]),
+            discount_token_account: None,
+            collection_fee_override: collection_fee_override_key(&Pubkey::default(), 0),
+            price_feed: None,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::Reserve {}.data(),
+    }
+}
+
+/// Same accounts as `exchange_ix`, for the reserving taker.
+pub fn finalize_ix(
+    taker: Pubkey,
+    initializer: Pubkey,
+    mint: Pubkey,
+    receive_mint: Pubkey,
+    nonce: u64,
+    treasury: Pubkey,
+) -> Instruction {
+    Instruction {
+        data: instruction::Finalize {
+            terms_hash: None,
+            expected_price: None,
+            expected_mint: None,
+        }
+        .data(),
+        ..exchange_ix(taker, initializer, mint, receive_mint, nonce, treasury)
+    }
+}
+
+pub fn abort_ix(signer: Pubkey, taker: Pubkey, initializer: Pubkey, nonce: u64) -> Instruction {
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::Abort {
+            signer,
+            taker,
+            initializer,
+            escrow_state: escrow_state_key(&initializer, nonce),
+        }
+        .to_account_metas(None),
+        data: instruction::Abort {}.data(),
+    }
+}
+
+/// Accounts of `settle`; `resolve_dispute` shares them.
+pub fn settle_ix(
+    authority: Pubkey,
+    initializer: Pubkey,
+    taker: Pubkey,
+    mint: Pubkey,
+    receive_mint: Pubkey,
+    nonce: u64,
+) -> Instruction {
+    let escrow_state = escrow_state_key(&initializer, nonce);
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::Settle {
+            authority,
+            initializer,
+            taker,
+            initializer_deposit_token_mint: mint,
+            taker_deposit_token_mint: receive_mint,
+            vault: vault_key(&escrow_state, &mint),
+            settlement_vault: vault_key(&escrow_state, &receive_mint),
+            taker_receive_token_account: get_associated_token_address(&taker, &mint),
+            initializer_receive_token_account: get_associated_token_address(
+                &initializer,
+                &receive_mint,
+            ),
+            initializer_deposit_token_account: get_associated_token_address(&initializer, &mint),
+            taker_deposit_token_account: get_associated_token_address(&taker, &receive_mint),
+            escrow_state,
+            vault_authority: vault_authority_key(&escrow_state),
+            config: pda(&[b"config"]),
+            token_program: spl_token::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::Settle {}.data(),
+    }
+}
+
+pub fn dispute_ix(authority: Pubkey, initializer: Pubkey, nonce: u64) -> Instruction {
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::Dispute {
+            authority,
+            escrow_state: escrow_state_key(&initializer, nonce),
+        }
+        .to_account_metas(None),
+        data: instruction::Dispute {}.data(),
+    }
+}
+
+pub fn payment_plan_key(escrow_state: &Pubkey) -> Pubkey {
+    pda(&[b"payment_plan", escrow_state.as_ref()])
+}
+
+pub fn offer_payment_plan_ix(
+    initializer: Pubkey,
+    nonce: u64,
+    installments: u8,
+    interval_secs: i64,
+    forfeit_bps: u16,
+) -> Instruction {
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::OfferPaymentPlan {
+            initializer,
+            escrow_state: escrow_state_key(&initializer, nonce),
+        }
+        .to_account_metas(None),
+        data: instruction::OfferPaymentPlan {
+            installments,
+            interval_secs,
+            forfeit_bps,
+        }
+        .data(),
+    }
+}
+
+pub fn start_payment_plan_ix(taker: Pubkey, initializer: Pubkey, nonce: u64) -> Instruction {
+    let escrow_state = escrow_state_key(&initializer, nonce);
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::StartPaymentPlan {
+            taker,
+            escrow_state,
+            payment_plan: payment_plan_key(&escrow_state),
+            config: pda(&[b"config"]),
+            discount_token_account: None,
+            collection_fee_override: collection_fee_override_key(&Pubkey::default(), 0),
+            price_feed: None,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::StartPaymentPlan {}.data(),
+    }
+}
+
+pub fn pay_installment_ix(taker: Pubkey, initializer: Pubkey, nonce: u64) -> Instruction {
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::PayInstallment {
+            taker,
+            payment_plan: payment_plan_key(&escrow_state_key(&initializer, nonce)),
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::PayInstallment {}.data(),
+    }
+}
+
+/// Same accounts as `exchange_ix` plus the taker's payment plan.
+pub fn complete_payment_plan_ix(
+    taker: Pubkey,
+    initializer: Pubkey,
+    mint: Pubkey,
+    receive_mint: Pubkey,
+    nonce: u64,
+    treasury: Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::CompletePaymentPlan {
+            exchange: exchange_accounts(taker, initializer, mint, receive_mint, nonce, treasury),
+            payment_plan: payment_plan_key(&escrow_state_key(&initializer, nonce)),
+        }
+        .to_account_metas(None),
+        data: instruction::CompletePaymentPlan {
+            terms_hash: None,
+            expected_price: None,
+            expected_mint: None,
+        }
+        .data(),
+    }
+}
+
+pub fn reclaim_payment_plan_ix(initializer: Pubkey, taker: Pubkey, nonce: u64) -> Instruction {
+    let escrow_state = escrow_state_key(&initializer, nonce);
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::ReclaimPaymentPlan {
+            initializer,
+            taker,
+            escrow_state,
+            payment_plan: payment_plan_key(&escrow_state),
+        }
+        .to_account_metas(None),
+        data: instruction::ReclaimPaymentPlan {}.data(),
+    }
+}
+
+pub fn rental_listing_key(owner: &Pubkey, mint: &Pubkey) -> Pubkey {
+    pda(&[b"rental", owner.as_ref(), mint.as_ref()])
+}
+
+pub fn list_for_rent_ix(
+    owner: Pubkey,
+    mint: Pubkey,
+    rent_lamports: u64,
+    collateral_lamports: u64,
+    duration_secs: i64,
+) -> Instruction {
+    let rental_listing = rental_listing_key(&owner, &mint);
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::ListForRent {
+            owner,
+            mint,
+            owner_token_account: get_associated_token_address(&owner, &mint),
+            rental_listing,
+            vault_authority: vault_authority_key(&rental_listing),
+            vault: vault_key(&rental_listing, &mint),
+            system_program: system_program::ID,
+            token_program: spl_token::ID,
+            associated_token_program: spl_associated_token_account::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::ListForRent {
+            rent_lamports,
+            collateral_lamports,
+            duration_secs,
+        }
+        .data(),
+    }
+}
+
+pub fn rent_ix(renter: Pubkey, owner: Pubkey, mint: Pubkey) -> Instruction {
+    let rental_listing = rental_listing_key(&owner, &mint);
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::StartRental {
+            renter,
+            owner,
+            mint,
+            rental_listing,
+            vault_authority: vault_authority_key(&rental_listing),
+            vault: vault_key(&rental_listing, &mint),
+            renter_token_account: get_associated_token_address(&renter, &mint),
+            system_program: system_program::ID,
+            token_program: spl_token::ID,
+            associated_token_program: spl_associated_token_account::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::Rent {}.data(),
+    }
+}
+
+pub fn return_rental_ix(renter: Pubkey, owner: Pubkey, mint: Pubkey) -> Instruction {
+    let rental_listing = rental_listing_key(&owner, &mint);
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::ReturnRental {
+            renter,
+            mint,
+            rental_listing,
+            vault_authority: vault_authority_key(&rental_listing),
+            vault: vault_key(&rental_listing, &mint),
+            renter_token_account: get_associated_token_address(&renter, &mint),
+            token_program: spl_token::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::ReturnRental {}.data(),
+    }
+}
+
+pub fn claim_collateral_ix(owner: Pubkey, mint: Pubkey) -> Instruction {
+    let rental_listing = rental_listing_key(&owner, &mint);
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::ClaimCollateral {
+            owner,
+            rental_listing,
+            vault_authority: vault_authority_key(&rental_listing),
+            vault: vault_key(&rental_listing, &mint),
+            token_program: spl_token::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::ClaimCollateral {}.data(),
+    }
+}
+
+pub fn delist_rental_ix(owner: Pubkey, mint: Pubkey) -> Instruction {
+    let rental_listing = rental_listing_key(&owner, &mint);
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::DelistRental {
+            owner,
+            mint,
+            owner_token_account: get_associated_token_address(&owner, &mint),
+            rental_listing,
+            vault_authority: vault_authority_key(&rental_listing),
+            vault: vault_key(&rental_listing, &mint),
+            token_program: spl_token::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::DelistRental {}.data(),
+    }
+}
+
+pub fn loan_state_key(escrow_state: &Pubkey) -> Pubkey {
+    pda(&[b"loan", escrow_state.as_ref()])
+}
+
+pub fn request_loan_ix(
+    initializer: Pubkey,
+    nonce: u64,
+    principal: u64,
+    apr_bps: u16,
+    duration_secs: i64,
+) -> Instruction {
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::RequestLoan {
+            initializer,
+            escrow_state: escrow_state_key(&initializer, nonce),
+        }
+        .to_account_metas(None),
+        data: instruction::RequestLoan {
+            principal,
+            apr_bps,
+            duration_secs,
+        }
+        .data(),
+    }
+}
+
+pub fn fund_loan_ix(lender: Pubkey, borrower: Pubkey, nonce: u64) -> Instruction {
+    let escrow_state = escrow_state_key(&borrower, nonce);
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::FundLoan {
+            lender,
+            borrower,
+            escrow_state,
+            loan_state: loan_state_key(&escrow_state),
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::FundLoan {}.data(),
+    }
+}
+
+pub fn repay_ix(borrower: Pubkey, lender: Pubkey, nonce: u64) -> Instruction {
+    let escrow_state = escrow_state_key(&borrower, nonce);
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::Repay {
+            borrower,
+            lender,
+            escrow_state,
+            loan_state: loan_state_key(&escrow_state),
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::Repay {}.data(),
+    }
+}
+
+pub fn liquidate_ix(lender: Pubkey, borrower: Pubkey, mint: Pubkey, nonce: u64) -> Instruction {
+    let escrow_state = escrow_state_key(&borrower, nonce);
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::Liquidate {
+            lender,
+            borrower,
+            mint,
+            vault: vault_key(&escrow_state, &mint),
+            vault_authority: vault_authority_key(&escrow_state),
+            lender_token_account: get_associated_token_address(&lender, &mint),
+            escrow_state,
+            loan_state: loan_state_key(&escrow_state),
+            token_program: spl_token::ID,
+            associated_token_program: spl_associated_token_account::ID,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::Liquidate {}.data(),
+    }
+}
+
+pub fn configure_vesting_ix(
+    initializer: Pubkey,
+    nonce: u64,
+    duration_secs: i64,
+    cliff_secs: i64,
+) -> Instruction {
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::ConfigureVesting {
+            initializer,
+            escrow_state: escrow_state_key(&initializer, nonce),
+        }
+        .to_account_metas(None),
+        data: instruction::ConfigureVesting {
+            duration_secs,
+            cliff_secs,
+        }
+        .data(),
+    }
+}
+
+pub fn claim_vested_ix(initializer: Pubkey, nonce: u64) -> Instruction {
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::ClaimVested {
+            initializer,
+            escrow_state: escrow_state_key(&initializer, nonce),
+        }
+        .to_account_metas(None),
+        data: instruction::ClaimVested {}.data(),
+    }
+}
+
+pub fn subscription_listing_key(seller: &Pubkey, mint: &Pubkey) -> Pubkey {
+    pda(&[b"subscription_listing", seller.as_ref(), mint.as_ref()])
+}
+
+pub fn create_subscription_listing_ix(
+    seller: Pubkey,
+    mint: Pubkey,
+    price: u64,
+    period_secs: i64,
+    stock: u64,
+) -> Instruction {
+    let subscription_listing = subscription_listing_key(&seller, &mint);
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::CreateSubscriptionListing {
+            seller,
+            mint,
+            seller_token_account: get_associated_token_address(&seller, &mint),
+            subscription_listing,
+            vault_authority: vault_authority_key(&subscription_listing),
+            vault: vault_key(&subscription_listing, &mint),
+            system_program: system_program::ID,
+            token_program: spl_token::ID,
+            associated_token_program: spl_associated_token_account::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::CreateSubscriptionListing {
+            price,
+            period_secs,
+            stock,
+        }
+        .data(),
+    }
+}
+
+/// Accounts of `subscribe`; `renew_subscription` shares them.
+pub fn subscribe_ix(
+    subscriber: Pubkey,
+    seller: Pubkey,
+    mint: Pubkey,
+    treasury: Pubkey,
+) -> Instruction {
+    let subscription_listing = subscription_listing_key(&seller, &mint);
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::Subscribe {
+            subscriber,
+            seller,
+            mint,
+            subscription_listing,
+            subscription: pda(&[
+                b"subscription",
+                subscription_listing.as_ref(),
+                subscriber.as_ref(),
+            ]),
+            vault_authority: vault_authority_key(&subscription_listing),
+            vault: vault_key(&subscription_listing, &mint),
+            subscriber_token_account: get_associated_token_address(&subscriber, &mint),
+            config: pda(&[b"config"]),
+            treasury,
+            system_program: system_program::ID,
+            token_program: spl_token::ID,
+            associated_token_program: spl_associated_token_account::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::Subscribe {}.data(),
+    }
+}
+
+pub fn fraction_vault_key(mint: &Pubkey) -> Pubkey {
+    pda(&[b"fraction_vault", mint.as_ref()])
+}
+
+pub fn fraction_mint_key(mint: &Pubkey) -> Pubkey {
+    pda(&[b"fractions", fraction_vault_key(mint).as_ref()])
+}
+
+pub fn fractionalize_ix(
+    depositor: Pubkey,
+    mint: Pubkey,
+    fraction_supply: u64,
+    reserve_price: u64,
+) -> Instruction {
+    let fraction_vault = fraction_vault_key(&mint);
+    let fraction_mint = fraction_mint_key(&mint);
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::Fractionalize {
+            depositor,
+            mint,
+            depositor_token_account: get_associated_token_address(&depositor, &mint),
+            fraction_vault,
+            vault_authority: vault_authority_key(&fraction_vault),
+            vault: vault_key(&fraction_vault, &mint),
+            fraction_mint,
+            depositor_fraction_account: get_associated_token_address(&depositor, &fraction_mint),
+            system_program: system_program::ID,
+            token_program: spl_token::ID,
+            associated_token_program: spl_associated_token_account::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::Fractionalize {
+            fraction_supply,
+            reserve_price,
+        }
+        .data(),
+    }
+}
+
+pub fn buyout_ix(buyer: Pubkey, depositor: Pubkey, mint: Pubkey, treasury: Pubkey) -> Instruction {
+    let fraction_vault = fraction_vault_key(&mint);
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::Buyout {
+            buyer,
+            depositor,
+            mint,
+            fraction_vault,
+            vault_authority: vault_authority_key(&fraction_vault),
+            vault: vault_key(&fraction_vault, &mint),
+            buyer_token_account: get_associated_token_address(&buyer, &mint),
+            config: pda(&[b"config"]),
+            treasury,
+            system_program: system_program::ID,
+            token_program: spl_token::ID,
+            associated_token_program: spl_associated_token_account::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::Buyout {}.data(),
+    }
+}
+
+pub fn redeem_fractions_ix(holder: Pubkey, depositor: Pubkey, mint: Pubkey) -> Instruction {
+    let fraction_mint = fraction_mint_key(&mint);
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::RedeemFractions {
+            holder,
+            depositor,
+            fraction_vault: fraction_vault_key(&mint),
+            fraction_mint,
+            holder_fraction_account: get_associated_token_address(&holder, &fraction_mint),
+            token_program: spl_token::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::RedeemFractions {}.data(),
+    }
+}
+
+pub fn offer_htlc_ix(
+    initializer: Pubkey,
+    nonce: u64,
+    taker: Pubkey,
+    hash_lock: [u8; 32],
+    timeout: i64,
+) -> Instruction {
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::OfferHtlc {
+            initializer,
+            escrow_state: escrow_state_key(&initializer, nonce),
+        }
+        .to_account_metas(None),
+        data: instruction::OfferHtlc {
+            taker,
+            hash_lock,
+            timeout,
+        }
+        .data(),
+    }
+}
+
+pub fn exchange_with_preimage_ix(
+    taker: Pubkey,
+    initializer: Pubkey,
+    mint: Pubkey,
+    nonce: u64,
+    preimage: Vec<u8>,
+) -> Instruction {
+    let escrow_state = escrow_state_key(&initializer, nonce);
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::ExchangeWithPreimage {
+            taker,
+            initializer,
+            mint,
+            escrow_state,
+            vault_authority: vault_authority_key(&escrow_state),
+            vault: vault_key(&escrow_state, &mint),
+            taker_token_account: get_associated_token_address(&taker, &mint),
+            system_program: system_program::ID,
+            token_program: spl_token::ID,
+            associated_token_program: spl_associated_token_account::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::ExchangeWithPreimage { preimage }.data(),
+    }
+}
+
+/// Writes a Wormhole `PostedVAA` paying `amount` for `escrow_state` as if
+/// `wormhole_program` had verified it.
+pub fn set_posted_vaa(
+    ctx: &mut ProgramTestContext,
+    wormhole_program: &Pubkey,
+    emitter_chain: u16,
+    emitter_address: [u8; 32],
+    escrow_state: &Pubkey,
+    recipient: &Pubkey,
+    amount: u64,
+) -> Pubkey {
+    let mut data = b"vaa".to_vec();
+    data.extend_from_slice(&[0; 54]);
+    data.extend_from_slice(&emitter_chain.to_le_bytes());
+    data.extend_from_slice(&emitter_address);
+    data.extend_from_slice(&72u32.to_le_bytes());
+    data.extend_from_slice(escrow_state.as_ref());
+    data.extend_from_slice(recipient.as_ref());
+    data.extend_from_slice(&amount.to_be_bytes());
+    let posted_vaa = Pubkey::new_unique();
+    let account = Account {
+        lamports: 1_000_000_000,
+        data,
+        owner: *wormhole_program,
+        executable: false,
+        rent_epoch: 0,
+    };
+    ctx.set_account(&posted_vaa, &account.into());
+    posted_vaa
+}
+
+pub fn accept_wormhole_payment_ix(
+    initializer: Pubkey,
+    nonce: u64,
+    wormhole_program: Pubkey,
+    emitter_chain: u16,
+    emitter_address: [u8; 32],
+    amount: u64,
+) -> Instruction {
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::AcceptWormholePayment {
+            initializer,
+            escrow_state: escrow_state_key(&initializer, nonce),
+        }
+        .to_account_metas(None),
+        data: instruction::AcceptWormholePayment {
+            wormhole_program,
+            emitter_chain,
+            emitter_address,
+            amount,
+        }
+        .data(),
+    }
+}
+
+pub fn exchange_with_vaa_ix(
+    payer: Pubkey,
+    recipient: Pubkey,
+    initializer: Pubkey,
+    mint: Pubkey,
+    nonce: u64,
+    posted_vaa: Pubkey,
+) -> Instruction {
+    let escrow_state = escrow_state_key(&initializer, nonce);
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::ExchangeWithVaa {
+            payer,
+            recipient,
+            initializer,
+            mint,
+            escrow_state,
+            vault_authority: vault_authority_key(&escrow_state),
+            vault: vault_key(&escrow_state, &mint),
+            recipient_token_account: get_associated_token_address(&recipient, &mint),
+            posted_vaa,
+            system_program: system_program::ID,
+            token_program: spl_token::ID,
+            associated_token_program: spl_associated_token_account::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::ExchangeWithVaa {}.data(),
+    }
+}
+
+pub fn link_ring_ix(
+    initializer: Pubkey,
+    nonce: u64,
+    recipient: Pubkey,
+    partner: Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::LinkRing {
+            initializer,
+            escrow_state: escrow_state_key(&initializer, nonce),
+        }
+        .to_account_metas(None),
+        data: instruction::LinkRing { recipient, partner }.data(),
+    }
+}
+
+pub fn settle_ring_ix(
+    payer: Pubkey,
+    initializer_a: Pubkey,
+    mint_a: Pubkey,
+    initializer_b: Pubkey,
+    mint_b: Pubkey,
+    treasury: Pubkey,
+) -> Instruction {
+    let escrow_a = escrow_state_key(&initializer_a, 0);
+    let escrow_b = escrow_state_key(&initializer_b, 0);
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::SettleRing {
+            payer,
+            initializer_a,
+            initializer_b,
+            mint_a,
+            mint_b,
+            escrow_a,
+            escrow_b,
+            vault_authority_a: vault_authority_key(&escrow_a),
+            vault_authority_b: vault_authority_key(&escrow_b),
+            vault_a: vault_key(&escrow_a, &mint_a),
+            vault_b: vault_key(&escrow_b, &mint_b),
+            initializer_b_token_account: get_associated_token_address(&initializer_b, &mint_a),
+            payer_token_account: get_associated_token_address(&payer, &mint_b),
+            config: pda(&[b"config"]),
+            treasury,
+            system_program: system_program::ID,
+            token_program: spl_token::ID,
+            associated_token_program: spl_associated_token_account::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::SettleRing {}.data(),
+    }
+}
+
+pub fn swap_state_key(maker: &Pubkey, swap_id: u64) -> Pubkey {
+    pda(&[b"swap", maker.as_ref(), &swap_id.to_le_bytes()])
+}
+
+pub fn create_swap_ix(
+    maker: Pubkey,
+    swap_id: u64,
+    taker: Pubkey,
+    maker_assets: Vec<SwapAsset>,
+    taker_assets: Vec<SwapAsset>,
+) -> Instruction {
+    let swap_state = swap_state_key(&maker, swap_id);
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::CreateSwap {
+            maker,
+            swap_state,
+            vault_authority: vault_authority_key(&swap_state),
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::CreateSwap {
+            swap_id,
+            taker,
+            maker_assets,
+            taker_assets,
+        }
+        .data(),
+    }
+}
+
+pub fn deposit_swap_asset_ix(
+    party: Pubkey,
+    swap_state: Pubkey,
+    mint: Pubkey,
+    index: u8,
+) -> Instruction {
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::DepositSwapAsset {
+            party,
+            mint,
+            party_token_account: get_associated_token_address(&party, &mint),
+            swap_state,
+            vault_authority: vault_authority_key(&swap_state),
+            vault: vault_key(&swap_state, &mint),
+            system_program: system_program::ID,
+            token_program: spl_token::ID,
+            associated_token_program: spl_associated_token_account::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::DepositSwapAsset { index }.data(),
+    }
+}
+
+/// `assets` pairs each mint with the wallet whose token account receives it.
+pub fn execute_swap_ix(
+    party: Pubkey,
+    maker: Pubkey,
+    taker: Pubkey,
+    swap_state: Pubkey,
+    assets: &[(Pubkey, Pubkey)],
+) -> Instruction {
+    let mut accounts = accounts::ExecuteSwap {
+        party,
+        maker,
+        taker,
+        swap_state,
+        vault_authority: vault_authority_key(&swap_state),
+        token_program: spl_token::ID,
+    }
+    .to_account_metas(None);
+    for (mint, recipient) in assets {
+        accounts.push(AccountMeta::new_readonly(*mint, false));
+        accounts.push(AccountMeta::new(vault_key(&swap_state, mint), false));
+        accounts.push(AccountMeta::new(
+            get_associated_token_address(recipient, mint),
+            false,
+        ));
+    }
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts,
+        data: instruction::ExecuteSwap {}.data(),
+    }
+}
+
+pub fn buy_offer_key(buyer: &Pubkey, offer_id: u64) -> Pubkey {
+    pda(&[b"buy_offer", buyer.as_ref(), &offer_id.to_le_bytes()])
+}
+
+pub fn place_buy_offer_ix(
+    buyer: Pubkey,
+    offer_id: u64,
+    mint: Pubkey,
+    max_price: u64,
+) -> Instruction {
+    place_offer_ix(buyer, offer_id, mint, Pubkey::default(), max_price, 0)
+}
+
+pub fn place_expiring_buy_offer_ix(
+    buyer: Pubkey,
+    offer_id: u64,
+    mint: Pubkey,
+    max_price: u64,
+    expires_at: i64,
+) -> Instruction {
+    place_offer_ix(
+        buyer,
+        offer_id,
+        mint,
+        Pubkey::default(),
+        max_price,
+        expires_at,
+    )
+}
+
+pub fn place_collection_offer_ix(
+    buyer: Pubkey,
+    offer_id: u64,
+    collection: Pubkey,
+    max_price: u64,
+) -> Instruction {
+    place_offer_ix(buyer, offer_id, Pubkey::default(), collection, max_price, 0)
+}
+
+fn place_offer_ix(
+    buyer: Pubkey,
+    offer_id: u64,
+    mint: Pubkey,
+    collection: Pubkey,
+    max_price: u64,
+    expires_at: i64,
+) -> Instruction {
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::PlaceBuyOffer {
+            buyer,
+            buy_offer: buy_offer_key(&buyer, offer_id),
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::PlaceBuyOffer {
+            offer_id,
+            mint,
+            collection,
+            max_price,
+            expires_at,
+        }
+        .data(),
+    }
+}
+
+pub fn cancel_buy_offer_ix(buyer: Pubkey, offer_id: u64) -> Instruction {
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::CancelBuyOffer {
+            buyer,
+            buy_offer: buy_offer_key(&buyer, offer_id),
+        }
+        .to_account_metas(None),
+        data: instruction::CancelBuyOffer {}.data(),
+    }
+}
+
+pub fn order_book_key(collection: &Pubkey) -> Pubkey {
+    pda(&[b"order_book", collection.as_ref()])
+}
+
+pub fn create_order_book_ix(payer: Pubkey, collection: Pubkey) -> Instruction {
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::CreateOrderBook {
+            payer,
+            order_book: order_book_key(&collection),
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::CreateOrderBook { collection }.data(),
+    }
+}
+
+pub fn post_bid_ix(buyer: Pubkey, offer_id: u64, collection: Pubkey) -> Instruction {
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::PostBid {
+            buyer,
+            buy_offer: buy_offer_key(&buyer, offer_id),
+            order_book: order_book_key(&collection),
+        }
+        .to_account_metas(None),
+        data: instruction::PostBid {}.data(),
+    }
+}
+
+pub fn compact_order_book_ix(collection: Pubkey, closed: &[Pubkey]) -> Instruction {
+    let mut accounts = accounts::CompactOrderBook {
+        order_book: order_book_key(&collection),
+    }
+    .to_account_metas(None);
+    accounts.extend(
+        closed
+            .iter()
+            .map(|account| AccountMeta::new_readonly(*account, false)),
+    );
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts,
+        data: instruction::CompactOrderBook {}.data(),
+    }
+}
+
+pub fn match_escrows_ix(
+    matcher: Pubkey,
+    buyer: Pubkey,
+    offer_id: u64,
+    initializer: Pubkey,
+    mint: Pubkey,
+    nonce: u64,
+    treasury: Pubkey,
+) -> Instruction {
+    let escrow_state = escrow_state_key(&initializer, nonce);
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::MatchEscrows {
+            matcher,
+            buyer,
+            buy_offer: buy_offer_key(&buyer, offer_id),
+            initializer,
+            mint,
+            escrow_state,
+            vault_authority: vault_authority_key(&escrow_state),
+            vault: vault_key(&escrow_state, &mint),
+            buyer_token_account: get_associated_token_address(&buyer, &mint),
+            nft_metadata: None,
+            config: pda(&[b"config"]),
+            collection_fee_override: collection_fee_override_key(&Pubkey::default(), 0),
+            treasury,
+            system_program: system_program::ID,
+            token_program: spl_token::ID,
+            associated_token_program: spl_associated_token_account::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::MatchEscrows {}.data(),
+    }
+}
+
+pub fn deal_room_key(creator: &Pubkey, deal_id: u64) -> Pubkey {
+    pda(&[b"deal_room", creator.as_ref(), &deal_id.to_le_bytes()])
+}
+
+pub fn create_deal_room_ix(creator: Pubkey, deal_id: u64, parties: Vec<Pubkey>) -> Instruction {
+    let deal_room = deal_room_key(&creator, deal_id);
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::CreateDealRoom {
+            creator,
+            deal_room,
+            vault_authority: vault_authority_key(&deal_room),
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::CreateDealRoom { deal_id, parties }.data(),
+    }
+}
+
+pub fn deposit_to_deal_ix(
+    depositor: Pubkey,
+    deal_room: Pubkey,
+    mint: Pubkey,
+    amount: u64,
+    recipient: Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::DepositToDeal {
+            depositor,
+            mint,
+            depositor_token_account: get_associated_token_address(&depositor, &mint),
+            deal_room,
+            vault_authority: vault_authority_key(&deal_room),
+            vault: vault_key(&deal_room, &mint),
+            system_program: system_program::ID,
+            token_program: spl_token::ID,
+            associated_token_program: spl_associated_token_account::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::DepositToDeal { amount, recipient }.data(),
+    }
+}
+
+pub fn approve_deal_ix(party: Pubkey, deal_room: Pubkey) -> Instruction {
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::ApproveDeal { party, deal_room }.to_account_metas(None),
+        data: instruction::ApproveDeal {}.data(),
+    }
+}
+
+/// `deposits` lists each deposit as (mint, depositor, recipient).
+pub fn execute_deal_ix(
+    creator: Pubkey,
+    deal_room: Pubkey,
+    deposits: &[(Pubkey, Pubkey, Pubkey)],
+) -> Instruction {
+    let mut accounts = accounts::ExecuteDeal {
+        creator,
+        deal_room,
+        vault_authority: vault_authority_key(&deal_room),
+        token_program: spl_token::ID,
+    }
+    .to_account_metas(None);
+    for (mint, depositor, recipient) in deposits {
+        accounts.push(AccountMeta::new_readonly(*mint, false));
+        accounts.push(AccountMeta::new(vault_key(&deal_room, mint), false));
+        accounts.push(AccountMeta::new(
+            get_associated_token_address(recipient, mint),
+            false,
+        ));
+        accounts.push(AccountMeta::new(*depositor, false));
+    }
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts,
+        data: instruction::ExecuteDeal {}.data(),
+    }
+}
+
+pub fn create_market_ix(admin: Pubkey, market_id: u64, treasury: Pubkey) -> Instruction {
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::CreateMarket {
+            admin,
+            config: market_key(market_id),
+            treasury,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::CreateMarket {
+            market_id,
+            maker_fee_bps: MAKER_FEE_BPS * 2,
+            taker_fee_bps: TAKER_FEE_BPS * 2,
+            referral_share_bps: 0,
+        }
+        .data(),
+    }
+}
+
+pub fn market_initialize_ix(
+    initializer: Pubkey,
+    taker: Pubkey,
+    mint: Pubkey,
+    receive_mint: Pubkey,
+    market_id: u64,
+    taker_amount: u64,
+) -> Instruction {
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: initialize_accounts(initializer, taker, mint, receive_mint, 0, market_id)
+            .to_account_metas(None),
+        data: instruction::Initialize {
+            initializer_amount: 0,
+            taker_amount,
+            donation_recipient: Pubkey::default(),
+            donation_bps: 0,
+            price_usd_cents: 0,
+            price_feed: Pubkey::default(),
+            settlement_delay_secs: 0,
+            note: String::new(),
+        }
+        .data(),
+    }
+}
+
+pub fn order_delegate_key() -> Pubkey {
+    pda(&[b"order_delegate"])
+}
+
+/// An ed25519 program instruction verifying `signer`'s signature over
+/// `message`, with the key, signature and message in its own data.
+pub fn ed25519_ix(signer: &Keypair, message: &[u8]) -> Instruction {
+    const PUBLIC_KEY_OFFSET: u16 = 16;
+    const SIGNATURE_OFFSET: u16 = PUBLIC_KEY_OFFSET + 32;
+    const MESSAGE_OFFSET: u16 = SIGNATURE_OFFSET + 64;
+    let mut data = vec![1, 0];
+    for value in [
+        SIGNATURE_OFFSET,
+        u16::MAX,
+        PUBLIC_KEY_OFFSET,
+        u16::MAX,
+        MESSAGE_OFFSET,
+        message.len() as u16,
+        u16::MAX,
+    ] {
+        data.extend_from_slice(&value.to_le_bytes());
+    }
+    data.extend_from_slice(signer.pubkey().as_ref());
+    data.extend_from_slice(signer.sign_message(message).as_ref());
+    data.extend_from_slice(message);
+    Instruction {
+        program_id: solana_sdk::ed25519_program::ID,
+        accounts: vec![],
+        data,
+    }
+}
+
+pub fn filled_order_key(seller: &Pubkey, nonce: u64) -> Pubkey {
+    pda(&[b"filled_order", seller.as_ref(), &nonce.to_le_bytes()])
+}
+
+pub fn fill_signed_order_ix(
+    buyer: Pubkey,
+    seller: Pubkey,
+    mint: Pubkey,
+    price: u64,
+    nonce: u64,
+    treasury: Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::FillSignedOrder {
+            buyer,
+            seller,
+            mint,
+            seller_token_account: get_associated_token_address(&seller, &mint),
+            order_delegate: order_delegate_key(),
+            buyer_token_account: get_associated_token_address(&buyer, &mint),
+            filled_order: filled_order_key(&seller, nonce),
+            config: pda(&[b"config"]),
+            treasury,
+            instructions: solana_sdk::sysvar::instructions::ID,
+            token_program: spl_token::ID,
+            associated_token_program: spl_associated_token_account::ID,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::FillSignedOrder {
+            price,
+            expiry: i64::MAX,
+            nonce,
+        }
+        .data(),
+    }
+}
+
+pub fn cancel_signed_order_ix(seller: Pubkey, nonce: u64) -> Instruction {
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::CancelSignedOrder {
+            seller,
+            filled_order: filled_order_key(&seller, nonce),
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::CancelSignedOrder { _nonce: nonce }.data(),
+    }
+}
+
+/// An `initialize` whose rent `relayer` pays, with no lamport deposit.
+pub fn sponsored_initialize_ix(
+    initializer: Pubkey,
+    relayer: Pubkey,
+    taker: Pubkey,
+    mint: Pubkey,
+    receive_mint: Pubkey,
+    initializer_amount: u64,
+    taker_amount: u64,
+) -> Instruction {
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::Initialize {
+            rent_payer: relayer,
+            ..initialize_accounts(initializer, taker, mint, receive_mint, 0, 0)
+        }
+        .to_account_metas(None),
+        data: instruction::Initialize {
+            initializer_amount,
+            taker_amount,
+            donation_recipient: Pubkey::default(),
+            donation_bps: 0,
+            price_usd_cents: 0,
+            price_feed: Pubkey::default(),
+            settlement_delay_secs: 0,
+            note: String::new(),
+        }
+        .data(),
+    }
+}
+
+pub fn session_key(owner: &Pubkey, session_key: &Pubkey) -> Pubkey {
+    pda(&[b"session", owner.as_ref(), session_key.as_ref()])
+}
+
+pub fn create_session_ix(owner: Pubkey, session_key: Pubkey, scope: u8) -> Instruction {
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::CreateSession {
+            owner,
+            session: self::session_key(&owner, &session_key),
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::CreateSession {
+            session_key,
+            scope,
+            expires_at: i64::MAX,
+        }
+        .data(),
+    }
+}
+
+pub fn revoke_session_ix(owner: Pubkey, session_key: Pubkey) -> Instruction {
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::RevokeSession {
+            owner,
+            session: self::session_key(&owner, &session_key),
+        }
+        .to_account_metas(None),
+        data: instruction::RevokeSession {}.data(),
+    }
+}
+
+pub fn list_with_session_ix(
+    session_key: Pubkey,
+    owner: Pubkey,
+    taker: Pubkey,
+    mint: Pubkey,
+    receive_mint: Pubkey,
+    nonce: u64,
+    taker_amount: u64,
+) -> Instruction {
+    let escrow_state = escrow_state_key(&owner, nonce);
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::ListWithSession {
+            session_key,
+            owner,
+            session: self::session_key(&owner, &session_key),
+            mint,
+            vault_authority: vault_authority_key(&escrow_state),
+            vault: vault_key(&escrow_state, &mint),
+            taker_key: taker,
+            owner_deposit_token_account: get_associated_token_address(&owner, &mint),
+            initializer_receive_mint_account: receive_mint,
+            listing_counter: pda(&[b"listing_counter", owner.as_ref()]),
+            escrow_state,
+            config: pda(&[b"config"]),
+            nft_metadata: metadata_address(&mint),
+            collection_floor: collection_floor_key(&Pubkey::default(), 0),
+            wallet_activity: None,
+            system_program: system_program::ID,
+            token_program: spl_token::ID,
+            associated_token_program: spl_associated_token_account::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::ListWithSession {
+            taker_amount,
+            note: String::new(),
+        }
+        .data(),
+    }
+}
+
+pub fn cancel_with_session_ix(
+    session_key: Pubkey,
+    owner: Pubkey,
+    mint: Pubkey,
+    nonce: u64,
+) -> Instruction {
+    let escrow_state = escrow_state_key(&owner, nonce);
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::CancelWithSession {
+            session_key,
+            owner,
+            session: self::session_key(&owner, &session_key),
+            mint,
+            vault: vault_key(&escrow_state, &mint),
+            vault_authority: vault_authority_key(&escrow_state),
+            owner_deposit_token_account: get_associated_token_address(&owner, &mint),
+            escrow_state,
+            token_program: spl_token::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::CancelWithSession {}.data(),
+    }
+}
+
+pub fn set_private_fills_ix(
+    initializer: Pubkey,
+    escrow_state: Pubkey,
+    enabled: bool,
+) -> Instruction {
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::SetPrivateFills {
+            initializer,
+            escrow_state,
+        }
+        .to_account_metas(None),
+        data: instruction::SetPrivateFills { enabled }.data(),
+    }
+}
+
+pub fn wallet_activity_key(wallet: &Pubkey) -> Pubkey {
+    pda(&[b"wallet_activity", wallet.as_ref()])
+}
+
+pub fn open_wallet_activity_ix(wallet: Pubkey) -> Instruction {
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::OpenWalletActivity {
+            wallet,
+            config: pda(&[b"config"]),
+            wallet_activity: wallet_activity_key(&wallet),
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::OpenWalletActivity {}.data(),
+    }
+}
+
+pub fn audit_trail_key(escrow_state: &Pubkey) -> Pubkey {
+    pda(&[b"audit", escrow_state.as_ref()])
+}
+
+pub fn open_audit_trail_ix(initializer: Pubkey, nonce: u64) -> Instruction {
+    let escrow_state = escrow_state_key(&initializer, nonce);
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::OpenAuditTrail {
+            initializer,
+            escrow_state,
+            audit_trail: audit_trail_key(&escrow_state),
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::OpenAuditTrail {}.data(),
+    }
+}
+
+pub fn close_audit_trail_ix(initializer: Pubkey, nonce: u64) -> Instruction {
+    let escrow_state = escrow_state_key(&initializer, nonce);
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::CloseAuditTrail {
+            initializer,
+            escrow_state,
+            audit_trail: audit_trail_key(&escrow_state),
+        }
+        .to_account_metas(None),
+        data: instruction::CloseAuditTrail {}.data(),
+    }
+}
+
+/// `prove_reserves` over `(escrow_state, custody)` pairs.
+pub fn prove_reserves_ix(escrows: &[(Pubkey, Pubkey)]) -> Instruction {
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: escrows
+            .iter()
+            .flat_map(|(escrow_state, custody)| {
+                [
+                    AccountMeta::new_readonly(*escrow_state, false),
+                    AccountMeta::new_readonly(*custody, false),
+                ]
+            })
+            .collect(),
+        data: instruction::ProveReserves {}.data(),
+    }
+}
+
+pub fn update_terms_ix(initializer: Pubkey, nonce: u64, note: &str) -> Instruction {
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::UpdateTerms {
+            initializer,
+            escrow_state: escrow_state_key(&initializer, nonce),
+        }
+        .to_account_metas(None),
+        data: instruction::UpdateTerms {
+            note: note.to_string(),
+        }
+        .data(),
+    }
+}
+
+pub fn set_listing_tag_ix(initializer: Pubkey, nonce: u64, tag: [u8; 8]) -> Instruction {
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::SetListingTag {
+            initializer,
+            escrow_state: escrow_state_key(&initializer, nonce),
+        }
+        .to_account_metas(None),
+        data: instruction::SetListingTag { tag }.data(),
+    }
+}
+
+pub fn set_terms_ix(
+    initializer: Pubkey,
+    nonce: u64,
+    terms_uri: &str,
+    terms_hash: [u8; 32],
+) -> Instruction {
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::SetTerms {
+            initializer,
+            escrow_state: escrow_state_key(&initializer, nonce),
+        }
+        .to_account_metas(None),
+        data: instruction::SetTerms {
+            terms_uri: terms_uri.to_string(),
+            terms_hash,
+        }
+        .data(),
+    }
+}
+
+pub fn listing_template_key(owner: &Pubkey, template_id: u64) -> Pubkey {
+    pda(&[
+        b"listing_template",
+        owner.as_ref(),
+        &template_id.to_le_bytes(),
+    ])
+}
+
+pub fn create_listing_template_ix(
+    owner: Pubkey,
+    template_id: u64,
+    taker_amount: u64,
+    note: &str,
+) -> Instruction {
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::CreateListingTemplate {
+            owner,
+            template: listing_template_key(&owner, template_id),
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::CreateListingTemplate {
+            template_id,
+            taker_amount,
+            donation_recipient: Pubkey::default(),
+            donation_bps: 0,
+            settlement_delay_secs: 0,
+            tag: [0; 8],
+            note: note.to_string(),
+        }
+        .data(),
+    }
+}
+
+pub fn initialize_from_template_ix(
+    initializer: Pubkey,
+    taker: Pubkey,
+    mint: Pubkey,
+    receive_mint: Pubkey,
+    nonce: u64,
+    template_id: u64,
+) -> Instruction {
+    let escrow_state = escrow_state_key(&initializer, nonce);
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::InitializeFromTemplate {
+            initializer,
+            rent_payer: initializer,
+            template: listing_template_key(&initializer, template_id),
+            mint,
+            vault_authority: vault_authority_key(&escrow_state),
+            vault: vault_key(&escrow_state, &mint),
+            taker_key: taker,
+            initializer_deposit_token_account: get_associated_token_address(&initializer, &mint),
+            initializer_receive_mint_account: receive_mint,
+            config: pda(&[b"config"]),
+            listing_counter: pda(&[b"listing_counter", initializer.as_ref()]),
+            escrow_state,
+            nft_metadata: metadata_address(&mint),
+            collection_floor: collection_floor_key(&Pubkey::default(), 0),
+            wallet_activity: None,
+            system_program: system_program::ID,
+            token_program: spl_token::ID,
+            associated_token_program: spl_associated_token_account::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::InitializeFromTemplate {}.data(),
+    }
+}
+
+pub fn transfer_listing_ix(
+    initializer: Pubkey,
+    new_initializer: Pubkey,
+    nonce: u64,
+) -> Instruction {
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::TransferListing {
+            initializer,
+            new_initializer,
+            escrow_state: escrow_state_key(&initializer, nonce),
+        }
+        .to_account_metas(None),
+        data: instruction::TransferListing {}.data(),
+    }
+}
+
+pub fn reassign_taker_ix(initializer: Pubkey, new_taker: Pubkey, nonce: u64) -> Instruction {
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::ReassignTaker {
+            initializer,
+            new_taker,
+            escrow_state: escrow_state_key(&initializer, nonce),
+        }
+        .to_account_metas(None),
+        data: instruction::ReassignTaker {}.data(),
+    }
+}
+
+pub fn extend_expiry_ix(initializer: Pubkey, nonce: u64, expires_at: i64) -> Instruction {
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::ExtendExpiry {
+            initializer,
+            escrow_state: escrow_state_key(&initializer, nonce),
+            config: pda(&[b"config"]),
+        }
+        .to_account_metas(None),
+        data: instruction::ExtendExpiry { expires_at }.data(),
+    }
+}
+
+pub fn set_relist_decay_ix(
+    initializer: Pubkey,
+    nonce: u64,
+    decay_bps: u16,
+    window_secs: i64,
+) -> Instruction {
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::SetRelistDecay {
+            initializer,
+            escrow_state: escrow_state_key(&initializer, nonce),
+            config: pda(&[b"config"]),
+        }
+        .to_account_metas(None),
+        data: instruction::SetRelistDecay {
+            decay_bps,
+            window_secs,
+        }
+        .data(),
+    }
+}
+
+pub fn relist_decayed_ix(keeper: Pubkey, escrow_state: Pubkey) -> Instruction {
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::RelistDecayed {
+            keeper,
+            escrow_state,
+        }
+        .to_account_metas(None),
+        data: instruction::RelistDecayed {}.data(),
+    }
+}
+
+/// `offers` are (buy offer, buyer) pairs.
+pub fn sweep_expired_offers_ix(offers: &[(Pubkey, Pubkey)]) -> Instruction {
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: offers
+            .iter()
+            .flat_map(|(buy_offer, buyer)| {
+                [
+                    AccountMeta::new(*buy_offer, false),
+                    AccountMeta::new(*buyer, false),
+                ]
+            })
+            .collect(),
+        data: instruction::SweepExpiredOffers {}.data(),
+    }
+}
+
+pub fn cancel_expired_ix(
+    keeper: Pubkey,
+    initializer: Pubkey,
+    mint: Pubkey,
+    nonce: u64,
+) -> Instruction {
+    let escrow_state = escrow_state_key(&initializer, nonce);
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::CancelExpired {
+            keeper,
+            initializer,
+            mint,
+            vault: vault_key(&escrow_state, &mint),
+            vault_authority: vault_authority_key(&escrow_state),
+            initializer_deposit_token_account: get_associated_token_address(&initializer, &mint),
+            escrow_state,
+            audit_trail: None,
+            token_program: spl_token::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::CancelExpired {}.data(),
+    }
+}
+
+pub fn create_listing_ix(
+    initializer: Pubkey,
+    taker: Pubkey,
+    mint: Pubkey,
+    receive_mint: Pubkey,
+    nonce: u64,
+    taker_amount: u64,
+) -> Instruction {
+    let escrow_state = escrow_state_key(&initializer, nonce);
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::CreateListing {
+            initializer,
+            rent_payer: initializer,
+            mint,
+            vault_authority: vault_authority_key(&escrow_state),
+            vault: vault_key(&escrow_state, &mint),
+            taker_key: taker,
+            initializer_deposit_token_account: get_associated_token_address(&initializer, &mint),
+            initializer_receive_mint_account: receive_mint,
+            config: pda(&[b"config"]),
+            listing_counter: pda(&[b"listing_counter", initializer.as_ref()]),
+            escrow_state,
+            nft_metadata: metadata_address(&mint),
+            collection_floor: collection_floor_key(&Pubkey::default(), 0),
+            wallet_activity: None,
+            system_program: system_program::ID,
+            token_program: spl_token::ID,
+            associated_token_program: spl_associated_token_account::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::CreateListing {
+            taker_amount,
+            note: String::new(),
+        }
+        .data(),
+    }
+}
+
+pub fn deposit_ix(initializer: Pubkey, mint: Pubkey, nonce: u64) -> Instruction {
+    let escrow_state = escrow_state_key(&initializer, nonce);
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::Deposit {
+            initializer,
+            mint,
+            vault_authority: vault_authority_key(&escrow_state),
+            vault: vault_key(&escrow_state, &mint),
+            initializer_deposit_token_account: get_associated_token_address(&initializer, &mint),
+            escrow_state,
+            token_program: spl_token::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::Deposit {}.data(),
+    }
+}
+
+pub fn get_quote_route_ix(taker: Pubkey, escrow_state: Pubkey) -> Instruction {
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::GetQuote {
+            taker,
+            escrow_state,
+            config: pda(&[b"config"]),
+            discount_token_account: None,
+            collection_fee_override: collection_fee_override_key(&Pubkey::default(), 0),
+            price_feed: None,
+        }
+        .to_account_metas(None),
+        data: instruction::GetQuoteRoute {}.data(),
+    }
+}
+
+pub fn collection_fee_override_key(collection_mint: &Pubkey, market_id: u64) -> Pubkey {
+    pda(&[
+        b"fee_override",
+        collection_mint.as_ref(),
+        &market_seed(market_id),
+    ])
+}
+
+pub fn set_collection_fee_override_ix(
+    admin: Pubkey,
+    market_id: u64,
+    collection_mint: Pubkey,
+    maker_fee_bps: u16,
+    taker_fee_bps: u16,
+) -> Instruction {
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::SetCollectionFeeOverride {
+            admin,
+            config: market_key(market_id),
+            collection_mint,
+            collection_fee_override: collection_fee_override_key(&collection_mint, market_id),
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::SetCollectionFeeOverride {
+            maker_fee_bps,
+            taker_fee_bps,
+        }
+        .data(),
+    }
+}
+
+pub fn collection_treasury_key(collection_mint: &Pubkey, market_id: u64) -> Pubkey {
+    pda(&[
+        b"collection_treasury",
+        collection_mint.as_ref(),
+        &market_seed(market_id),
+    ])
+}
+
+pub fn collection_floor_key(collection_mint: &Pubkey, market_id: u64) -> Pubkey {
+    pda(&[
+        b"collection_floor",
+        collection_mint.as_ref(),
+        &market_seed(market_id),
+    ])
+}
+
+pub fn set_collection_floor_authority_ix(
+    admin: Pubkey,
+    collection_mint: Pubkey,
+    floor_authority: Pubkey,
+    max_below_floor_bps: u16,
+) -> Instruction {
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::SetCollectionFloorAuthority {
+            admin,
+            config: pda(&[b"config"]),
+            collection_mint,
+            floor_authority,
+            collection_floor: collection_floor_key(&collection_mint, 0),
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::SetCollectionFloorAuthority {
+            max_below_floor_bps,
+        }
+        .data(),
+    }
+}
+
+pub fn update_collection_floor_ix(
+    floor_authority: Pubkey,
+    collection_mint: Pubkey,
+    floor_lamports: u64,
+) -> Instruction {
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::UpdateCollectionFloor {
+            floor_authority,
+            config: pda(&[b"config"]),
+            collection_floor: collection_floor_key(&collection_mint, 0),
+        }
+        .to_account_metas(None),
+        data: instruction::UpdateCollectionFloor { floor_lamports }.data(),
+    }
+}
+
+pub fn register_collection_treasury_ix(
+    admin: Pubkey,
+    collection_mint: Pubkey,
+    dao_treasury: Pubkey,
+    share_bps: u16,
+) -> Instruction {
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::RegisterCollectionTreasury {
+            admin,
+            config: pda(&[b"config"]),
+            collection_mint,
+            dao_treasury,
+            collection_treasury: collection_treasury_key(&collection_mint, 0),
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::RegisterCollectionTreasury { share_bps }.data(),
+    }
+}
+
+pub fn airdrop_key(project: &Pubkey, merkle_root: &[u8; 32]) -> Pubkey {
+    pda(&[b"airdrop", project.as_ref(), merkle_root.as_ref()])
+}
+
+pub fn airdrop_leaf(claimant: &Pubkey, mint: &Pubkey) -> [u8; 32] {
+    keccak::hashv(&[claimant.as_ref(), mint.as_ref()]).0
+}
+
+/// Hashes a sorted pair of nodes the way the program walks a proof.
+pub fn airdrop_node(a: [u8; 32], b: [u8; 32]) -> [u8; 32] {
+    let (low, high) = if a <= b { (a, b) } else { (b, a) };
+    keccak::hashv(&[&low, &high]).0
+}
+
+pub fn create_airdrop_ix(project: Pubkey, merkle_root: [u8; 32], expires_at: i64) -> Instruction {
+    let airdrop = airdrop_key(&project, &merkle_root);
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::CreateAirdrop {
+            project,
+            airdrop,
+            vault_authority: vault_authority_key(&airdrop),
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::CreateAirdrop {
+            merkle_root,
+            expires_at,
+        }
+        .data(),
+    }
+}
+
+pub fn deposit_airdrop_nft_ix(project: Pubkey, airdrop: Pubkey, mint: Pubkey) -> Instruction {
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::DepositAirdropNft {
+            project,
+            mint,
+            project_token_account: get_associated_token_address(&project, &mint),
+            airdrop,
+            vault_authority: vault_authority_key(&airdrop),
+            vault: vault_key(&airdrop, &mint),
+            system_program: system_program::ID,
+            token_program: spl_token::ID,
+            associated_token_program: spl_associated_token_account::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::DepositAirdropNft {}.data(),
+    }
+}
+
+pub fn claim_airdrop_ix(
+    claimant: Pubkey,
+    airdrop: Pubkey,
+    mint: Pubkey,
+    proof: Vec<[u8; 32]>,
+) -> Instruction {
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::ClaimAirdrop {
+            claimant,
+            mint,
+            airdrop,
+            vault_authority: vault_authority_key(&airdrop),
+            vault: vault_key(&airdrop, &mint),
+            claimant_token_account: get_associated_token_address(&claimant, &mint),
+            system_program: system_program::ID,
+            token_program: spl_token::ID,
+            associated_token_program: spl_associated_token_account::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::ClaimAirdrop { proof }.data(),
+    }
+}
+
+pub fn close_airdrop_ix(project: Pubkey, airdrop: Pubkey) -> Instruction {
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::CloseAirdrop { project, airdrop }.to_account_metas(None),
+        data: instruction::CloseAirdrop {}.data(),
+    }
+}
+
+pub fn purchase_pool_key(escrow_state: &Pubkey) -> Pubkey {
+    pda(&[b"purchase_pool", escrow_state.as_ref()])
+}
+
+pub fn custody_key(purchase_pool: &Pubkey) -> Pubkey {
+    pda(&[b"custody", purchase_pool.as_ref()])
+}
+
+pub fn create_purchase_pool_ix(creator: Pubkey, initializer: Pubkey, nonce: u64) -> Instruction {
+    let escrow_state = escrow_state_key(&initializer, nonce);
+    let purchase_pool = purchase_pool_key(&escrow_state);
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::CreatePurchasePool {
+            creator,
+            escrow_state,
+            purchase_pool,
+            custody: custody_key(&purchase_pool),
+            config: pda(&[b"config"]),
+            collection_fee_override: collection_fee_override_key(&Pubkey::default(), 0),
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::CreatePurchasePool {}.data(),
+    }
+}
+
+pub fn contribute_ix(
+    contributor: Pubkey,
+    initializer: Pubkey,
+    nonce: u64,
+    amount: u64,
+) -> Instruction {
+    let purchase_pool = purchase_pool_key(&escrow_state_key(&initializer, nonce));
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::Contribute {
+            contributor,
+            purchase_pool,
+            custody: custody_key(&purchase_pool),
+            contribution: pda(&[
+                b"contribution",
+                purchase_pool.as_ref(),
+                contributor.as_ref(),
+            ]),
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::Contribute { amount }.data(),
+    }
+}
+
+pub fn execute_group_purchase_ix(
+    payer: Pubkey,
+    initializer: Pubkey,
+    mint: Pubkey,
+    receive_mint: Pubkey,
+    nonce: u64,
+    treasury: Pubkey,
+) -> Instruction {
+    let escrow_state = escrow_state_key(&initializer, nonce);
+    let purchase_pool = purchase_pool_key(&escrow_state);
+    let custody = custody_key(&purchase_pool);
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::ExecuteGroupPurchase {
+            payer,
+            purchase_pool,
+            custody,
+            escrow_state,
+            initializer,
+            initializer_deposit_token_mint: mint,
+            taker_deposit_token_mint: receive_mint,
+            custody_deposit_token_account: get_associated_token_address(&custody, &receive_mint),
+            custody_receive_token_account: get_associated_token_address(&custody, &mint),
+            initializer_receive_token_account: get_associated_token_address(
+                &initializer,
+                &receive_mint,
+            ),
+            vault: vault_key(&escrow_state, &mint),
+            vault_authority: vault_authority_key(&escrow_state),
+            config: pda(&[b"config"]),
+            treasury,
+            collection_fee_override: collection_fee_override_key(&Pubkey::default(), 0),
+            collection_treasury: collection_treasury_key(&Pubkey::default(), 0),
+            dao_treasury: None,
+            donation_recipient: None,
+            escrow_program: solana_nft_escrow::ID,
+            token_program: spl_token::ID,
+            associated_token_program: spl_associated_token_account::ID,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::ExecuteGroupPurchase {}.data(),
+    }
+}
+
+pub fn set_purchase_gate_ix(
+    initializer: Pubkey,
+    nonce: u64,
+    gate_mint: Pubkey,
+    min_amount: u64,
+) -> Instruction {
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::SetPurchaseGate {
+            initializer,
+            escrow_state: escrow_state_key(&initializer, nonce),
+        }
+        .to_account_metas(None),
+        data: instruction::SetPurchaseGate {
+            gate_mint,
+            min_amount,
+            governance_program: Pubkey::default(),
+        }
+        .data(),
+    }
+}
+
+/// Same accounts as `exchange_ix` plus the taker's gate token account.
+pub fn gated_exchange_ix(
+    taker: Pubkey,
+    initializer: Pubkey,
+    mint: Pubkey,
+    receive_mint: Pubkey,
+    nonce: u64,
+    treasury: Pubkey,
+    gate_token_account: Pubkey,
+) -> Instruction {
+    let mut ix = exchange_ix(taker, initializer, mint, receive_mint, nonce, treasury);
+    ix.accounts
+        .push(AccountMeta::new_readonly(gate_token_account, false));
+    ix
+}
+
+pub fn offer_gift_ix(
+    initializer: Pubkey,
+    nonce: u64,
+    recipient: Pubkey,
+    claim_deadline: i64,
+) -> Instruction {
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::OfferGift {
+            initializer,
+            escrow_state: escrow_state_key(&initializer, nonce),
+        }
+        .to_account_metas(None),
+        data: instruction::OfferGift {
+            recipient,
+            claim_deadline,
+        }
+        .data(),
+    }
+}
+
+pub fn claim_gift_ix(
+    recipient: Pubkey,
+    initializer: Pubkey,
+    mint: Pubkey,
+    nonce: u64,
+) -> Instruction {
+    let escrow_state = escrow_state_key(&initializer, nonce);
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::ClaimGift {
+            recipient,
+            initializer,
+            mint,
+            escrow_state,
+            vault_authority: vault_authority_key(&escrow_state),
+            vault: vault_key(&escrow_state, &mint),
+            recipient_token_account: get_associated_token_address(&recipient, &mint),
+            system_program: system_program::ID,
+            token_program: spl_token::ID,
+            associated_token_program: spl_associated_token_account::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::ClaimGift {}.data(),
+    }
+}
+
+pub fn return_gift_ix(initializer: Pubkey, mint: Pubkey, nonce: u64) -> Instruction {
+    let escrow_state = escrow_state_key(&initializer, nonce);
+    Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts::ReturnGift {
+            initializer,
+            mint,
+            initializer_deposit_token_account: get_associated_token_address(&initializer, &mint),
+            escrow_state,
+            vault_authority: vault_authority_key(&escrow_state),
+            vault: vault_key(&escrow_state, &mint),
+            token_program: spl_token::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::ReturnGift {}.data(),
+    }
+}
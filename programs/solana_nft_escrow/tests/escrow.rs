@@ -0,0 +1,3813 @@
+//! End-to-end tests against the BPF build of the program.
+//!
+//! Run `anchor build` first so `target/deploy/solana_nft_escrow.so` exists.
+
+mod common;
+
+use anchor_lang::prelude::Pubkey;
+use anchor_lang::solana_program::{
+    clock::Clock, hash, instruction::Instruction, system_instruction,
+};
+use anchor_lang::{
+    AccountDeserialize, AccountSerialize, AnchorDeserialize, Id, InstructionData, ToAccountMetas,
+};
+use anchor_spl::associated_token::get_associated_token_address;
+use anchor_spl::memo::Memo;
+use anchor_spl::token::spl_token;
+use common::*;
+use solana_nft_escrow::{
+    audit_entry_hash, signed_order_message, taker_allowance_message, AuditTrail, EscrowError,
+    EscrowState, OrderBook, QuoteRoute, SwapAsset, AUDIT_EXCHANGED, AUDIT_OPENED,
+    ESCROW_TAG_OFFSET, MAX_MEMO_LEN, MAX_NOTE_LEN, QUOTE_ROUTE_VERSION, SESSION_SCOPE_CANCEL,
+    SESSION_SCOPE_LIST,
+};
+use solana_program_test::ProgramTestContext;
+use solana_sdk::{
+    account::AccountSharedData,
+    program_option::COption,
+    program_pack::Pack,
+    signature::{Keypair, Signer},
+};
+
+const INITIALIZER_AMOUNT: u64 = 20_000_000;
+const TAKER_AMOUNT: u64 = 40_000_000;
+const RESERVATION_DEPOSIT_BPS: u16 = 1_000;
+const RESERVATION_WINDOW_SECS: i64 = 3_600;
+
+struct Env {
+    ctx: ProgramTestContext,
+    initializer: Keypair,
+    taker: Keypair,
+    treasury: Pubkey,
+    mint_a: Pubkey,
+    mint_b: Pubkey,
+}
+
+impl Env {
+    fn initialize_ix(&self) -> Instruction {
+        initialize_ix(
+            self.initializer.pubkey(),
+            self.taker.pubkey(),
+            self.mint_a,
+            self.mint_b,
+            0,
+            INITIALIZER_AMOUNT,
+            TAKER_AMOUNT,
+        )
+    }
+
+    fn exchange_ix(&self, taker: &Pubkey) -> Instruction {
+        exchange_ix(
+            *taker,
+            self.initializer.pubkey(),
+            self.mint_a,
+            self.mint_b,
+            0,
+            self.treasury,
+        )
+    }
+
+    fn cancel_ix(&self) -> Instruction {
+        cancel_ix(self.initializer.pubkey(), self.mint_a, 0)
+    }
+}
+
+async fn setup() -> Env {
+    let initializer = Keypair::new();
+    let taker = Keypair::new();
+    let treasury = Pubkey::new_unique();
+
+    let mut program_test = program_test();
+    program_test.add_account(initializer.pubkey(), funded(1_000_000_000));
+    program_test.add_account(taker.pubkey(), funded(1_000_000_000));
+    let mut ctx = program_test.start_with_context().await;
+
+    let mint_a = create_mint(&mut ctx).await;
+    let mint_b = create_mint(&mut ctx).await;
+    mint_one_to(&mut ctx, &mint_a, &initializer.pubkey()).await;
+    mint_one_to(&mut ctx, &mint_b, &taker.pubkey()).await;
+    initialize_config(&mut ctx, treasury).await;
+
+    Env {
+        ctx,
+        initializer,
+        taker,
+        treasury,
+        mint_a,
+        mint_b,
+    }
+}
+
+#[tokio::test]
+async fn initialize_then_exchange() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    let taker = env.taker.insecure_clone();
+
+    let ix = env.initialize_ix();
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+
+    let escrow_state = escrow_state_key(&initializer.pubkey(), 0);
+    let account = env
+        .ctx
+        .banks_client
+        .get_account(escrow_state)
+        .await
+        .unwrap()
+        .unwrap();
+    let state = EscrowState::try_deserialize(&mut account.data.as_slice()).unwrap();
+    assert_eq!(state.initializer_key, initializer.pubkey());
+    assert_eq!(state.taker_amount, TAKER_AMOUNT);
+    assert_eq!(
+        token_amount(&mut env.ctx, vault_key(&escrow_state, &env.mint_a)).await,
+        1
+    );
+
+    let taker_before = lamports(&mut env.ctx, taker.pubkey()).await;
+    let ix = env.exchange_ix(&taker.pubkey());
+    process(&mut env.ctx, &[ix], &[&taker]).await.unwrap();
+
+    let taker_nft = get_associated_token_address(&taker.pubkey(), &env.mint_a);
+    let initializer_payment = get_associated_token_address(&initializer.pubkey(), &env.mint_b);
+    assert_eq!(token_amount(&mut env.ctx, taker_nft).await, 1);
+    assert_eq!(token_amount(&mut env.ctx, initializer_payment).await, 1);
+
+    let maker_fee = TAKER_AMOUNT * MAKER_FEE_BPS as u64 / 10_000;
+    let taker_fee = TAKER_AMOUNT * TAKER_FEE_BPS as u64 / 10_000;
+    assert_eq!(
+        lamports(&mut env.ctx, env.treasury).await,
+        maker_fee + taker_fee
+    );
+    // The taker funds both receive accounts, neither of which existed yet.
+    let ata_rent = env
+        .ctx
+        .banks_client
+        .get_rent()
+        .await
+        .unwrap()
+        .minimum_balance(spl_token::state::Account::LEN);
+    assert_eq!(
+        lamports(&mut env.ctx, taker.pubkey()).await,
+        taker_before + INITIALIZER_AMOUNT - TAKER_AMOUNT - taker_fee - 2 * ata_rent
+    );
+
+    let closed = env
+        .ctx
+        .banks_client
+        .get_account(escrow_state)
+        .await
+        .unwrap();
+    assert!(closed.is_none());
+    let vault = env
+        .ctx
+        .banks_client
+        .get_account(vault_key(&escrow_state, &env.mint_a))
+        .await
+        .unwrap();
+    assert!(vault.is_none());
+}
+
+#[tokio::test]
+async fn initialize_then_cancel() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+
+    let ix = env.initialize_ix();
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+    let ix = env.cancel_ix();
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+
+    let deposit = get_associated_token_address(&initializer.pubkey(), &env.mint_a);
+    assert_eq!(token_amount(&mut env.ctx, deposit).await, 1);
+    let escrow_state = escrow_state_key(&initializer.pubkey(), 0);
+    let closed = env
+        .ctx
+        .banks_client
+        .get_account(escrow_state)
+        .await
+        .unwrap();
+    assert!(closed.is_none());
+}
+
+#[tokio::test]
+async fn exchange_rejects_deposit_account_of_another_wallet() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    let intruder = Keypair::new();
+    let transfer =
+        system_instruction::transfer(&env.ctx.payer.pubkey(), &intruder.pubkey(), 1_000_000_000);
+    process(&mut env.ctx, &[transfer], &[]).await.unwrap();
+
+    let ix = env.initialize_ix();
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+
+    // The intruder signs but tries to pay with the real taker's token account.
+    let mut ix = env.exchange_ix(&intruder.pubkey());
+    ix.accounts[3].pubkey = get_associated_token_address(&env.taker.pubkey(), &env.mint_b);
+    let result = process(&mut env.ctx, &[ix], &[&intruder]).await;
+    assert_error(result, anchor_lang::error::ErrorCode::ConstraintRaw as u32);
+}
+
+#[tokio::test]
+async fn exchange_rejects_wrong_mint() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    let taker = env.taker.insecure_clone();
+
+    let ix = env.initialize_ix();
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+
+    let other_mint = create_mint(&mut env.ctx).await;
+    let mut ix = env.exchange_ix(&taker.pubkey());
+    ix.accounts[1].pubkey = other_mint;
+    let result = process(&mut env.ctx, &[ix], &[&taker]).await;
+    assert_error(result, EscrowError::EscrowMintMismatch.into());
+}
+
+#[tokio::test]
+async fn exchange_rejects_taker_without_funds() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    let poor_taker = Keypair::new();
+    let transfer = system_instruction::transfer(
+        &env.ctx.payer.pubkey(),
+        &poor_taker.pubkey(),
+        TAKER_AMOUNT / 4,
+    );
+    process(&mut env.ctx, &[transfer], &[]).await.unwrap();
+    let mint_b = env.mint_b;
+    mint_one_to(&mut env.ctx, &mint_b, &poor_taker.pubkey()).await;
+
+    let ix = env.initialize_ix();
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+
+    let ix = env.exchange_ix(&poor_taker.pubkey());
+    let result = process(&mut env.ctx, &[ix], &[&poor_taker]).await;
+    assert_error(result, EscrowError::InsufficientTakerFunds.into());
+
+    // The escrow is untouched and can still be cancelled.
+    let ix = env.cancel_ix();
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+}
+
+/// Swaps every occurrence of `from` in the instruction's accounts for `to`.
+fn substitute(ix: &mut Instruction, from: Pubkey, to: Pubkey) {
+    for meta in ix.accounts.iter_mut().filter(|meta| meta.pubkey == from) {
+        meta.pubkey = to;
+    }
+}
+
+/// Opens a second escrow of `mint_a` from another wallet, so two vaults of the
+/// same mint exist side by side.
+async fn open_second_escrow(env: &mut Env) -> Keypair {
+    let other = Keypair::new();
+    let transfer =
+        system_instruction::transfer(&env.ctx.payer.pubkey(), &other.pubkey(), 1_000_000_000);
+    process(&mut env.ctx, &[transfer], &[]).await.unwrap();
+    let mint_a = env.mint_a;
+    mint_one_to(&mut env.ctx, &mint_a, &other.pubkey()).await;
+
+    let ix = initialize_ix(
+        other.pubkey(),
+        env.taker.pubkey(),
+        env.mint_a,
+        env.mint_b,
+        0,
+        INITIALIZER_AMOUNT,
+        TAKER_AMOUNT,
+    );
+    process(&mut env.ctx, &[ix], &[&other]).await.unwrap();
+    other
+}
+
+#[tokio::test]
+async fn escrows_of_the_same_mint_have_separate_vaults() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    let ix = env.initialize_ix();
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+    let other = open_second_escrow(&mut env).await;
+
+    let first = escrow_state_key(&initializer.pubkey(), 0);
+    let second = escrow_state_key(&other.pubkey(), 0);
+    assert_ne!(
+        vault_key(&first, &env.mint_a),
+        vault_key(&second, &env.mint_a)
+    );
+    assert_eq!(
+        token_amount(&mut env.ctx, vault_key(&first, &env.mint_a)).await,
+        1
+    );
+    assert_eq!(
+        token_amount(&mut env.ctx, vault_key(&second, &env.mint_a)).await,
+        1
+    );
+}
+
+#[tokio::test]
+async fn cancel_cannot_drain_another_escrows_vault() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    let ix = env.initialize_ix();
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+    let other = open_second_escrow(&mut env).await;
+
+    let first = escrow_state_key(&initializer.pubkey(), 0);
+    let second = escrow_state_key(&other.pubkey(), 0);
+    let mut ix = env.cancel_ix();
+    substitute(
+        &mut ix,
+        vault_key(&first, &env.mint_a),
+        vault_key(&second, &env.mint_a),
+    );
+    substitute(
+        &mut ix,
+        vault_authority_key(&first),
+        vault_authority_key(&second),
+    );
+    let result = process(&mut env.ctx, &[ix], &[&initializer]).await;
+    assert_error(
+        result,
+        anchor_lang::error::ErrorCode::ConstraintSeeds as u32,
+    );
+
+    assert_eq!(
+        token_amount(&mut env.ctx, vault_key(&second, &env.mint_a)).await,
+        1
+    );
+    let ix = env.cancel_ix();
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+    assert_eq!(
+        token_amount(&mut env.ctx, vault_key(&second, &env.mint_a)).await,
+        1
+    );
+}
+
+#[tokio::test]
+async fn exchange_cannot_drain_another_escrows_vault() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    let taker = env.taker.insecure_clone();
+    let ix = env.initialize_ix();
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+    let other = open_second_escrow(&mut env).await;
+
+    let first = escrow_state_key(&initializer.pubkey(), 0);
+    let second = escrow_state_key(&other.pubkey(), 0);
+    let mut ix = env.exchange_ix(&taker.pubkey());
+    substitute(
+        &mut ix,
+        vault_key(&first, &env.mint_a),
+        vault_key(&second, &env.mint_a),
+    );
+    substitute(
+        &mut ix,
+        vault_authority_key(&first),
+        vault_authority_key(&second),
+    );
+    let result = process(&mut env.ctx, &[ix], &[&taker]).await;
+    assert_error(
+        result,
+        anchor_lang::error::ErrorCode::ConstraintSeeds as u32,
+    );
+
+    assert_eq!(
+        token_amount(&mut env.ctx, vault_key(&first, &env.mint_a)).await,
+        1
+    );
+    assert_eq!(
+        token_amount(&mut env.ctx, vault_key(&second, &env.mint_a)).await,
+        1
+    );
+}
+
+#[tokio::test]
+async fn reserve_then_finalize() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    let taker = env.taker.insecure_clone();
+    let ix = env.initialize_ix();
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+
+    let escrow_state = escrow_state_key(&initializer.pubkey(), 0);
+    let state_before = lamports(&mut env.ctx, escrow_state).await;
+    let ix = reserve_ix(taker.pubkey(), initializer.pubkey(), 0);
+    process(&mut env.ctx, &[ix], &[&taker]).await.unwrap();
+    let taker_fee = TAKER_AMOUNT * TAKER_FEE_BPS as u64 / 10_000;
+    assert_eq!(
+        lamports(&mut env.ctx, escrow_state).await,
+        state_before + TAKER_AMOUNT + taker_fee
+    );
+
+    // Neither a plain exchange nor a cancel can pull the listing away.
+    let ix = env.exchange_ix(&taker.pubkey());
+    let result = process(&mut env.ctx, &[ix], &[&taker]).await;
+    assert_error(result, EscrowError::EscrowReserved.into());
+    let ix = env.cancel_ix();
+    let result = process(&mut env.ctx, &[ix], &[&initializer]).await;
+    assert_error(result, EscrowError::EscrowReserved.into());
+
+    let ix = finalize_ix(
+        taker.pubkey(),
+        initializer.pubkey(),
+        env.mint_a,
+        env.mint_b,
+        0,
+        env.treasury,
+    );
+    process(&mut env.ctx, &[ix], &[&taker]).await.unwrap();
+
+    let taker_nft = get_associated_token_address(&taker.pubkey(), &env.mint_a);
+    assert_eq!(token_amount(&mut env.ctx, taker_nft).await, 1);
+    let maker_fee = TAKER_AMOUNT * MAKER_FEE_BPS as u64 / 10_000;
+    assert_eq!(
+        lamports(&mut env.ctx, env.treasury).await,
+        maker_fee + taker_fee
+    );
+    assert!(!exists(&mut env.ctx, escrow_state).await);
+}
+
+#[tokio::test]
+async fn finalize_requires_the_reserving_taker() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    let taker = env.taker.insecure_clone();
+    let ix = env.initialize_ix();
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+
+    let ix = finalize_ix(
+        taker.pubkey(),
+        initializer.pubkey(),
+        env.mint_a,
+        env.mint_b,
+        0,
+        env.treasury,
+    );
+    let result = process(&mut env.ctx, &[ix], &[&taker]).await;
+    assert_error(result, EscrowError::ReservationMismatch.into());
+}
+
+#[tokio::test]
+async fn abort_refunds_the_reservation() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    let taker = env.taker.insecure_clone();
+    let ix = env.initialize_ix();
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+
+    let taker_before = lamports(&mut env.ctx, taker.pubkey()).await;
+    let ix = reserve_ix(taker.pubkey(), initializer.pubkey(), 0);
+    process(&mut env.ctx, &[ix], &[&taker]).await.unwrap();
+
+    let bystander = Keypair::new();
+    let ix = abort_ix(bystander.pubkey(), taker.pubkey(), initializer.pubkey(), 0);
+    let result = process(&mut env.ctx, &[ix], &[&bystander]).await;
+    assert_error(result, EscrowError::AbortNotAllowed.into());
+
+    let ix = abort_ix(
+        initializer.pubkey(),
+        taker.pubkey(),
+        initializer.pubkey(),
+        0,
+    );
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+    assert_eq!(lamports(&mut env.ctx, taker.pubkey()).await, taker_before);
+
+    // The listing is back on the market.
+    let ix = env.cancel_ix();
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+}
+
+/// Opens the standard escrow and reserves it for the taker with a deposit.
+async fn reserve_with_deposit(env: &mut Env) {
+    set_reservation_params(
+        &mut env.ctx,
+        RESERVATION_DEPOSIT_BPS,
+        RESERVATION_WINDOW_SECS,
+    )
+    .await;
+    let initializer = env.initializer.insecure_clone();
+    let taker = env.taker.insecure_clone();
+    let ix = env.initialize_ix();
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+    let ix = reserve_ix(taker.pubkey(), initializer.pubkey(), 0);
+    process(&mut env.ctx, &[ix], &[&taker]).await.unwrap();
+}
+
+#[tokio::test]
+async fn taker_abort_forfeits_the_deposit() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    let taker = env.taker.insecure_clone();
+    let taker_before = lamports(&mut env.ctx, taker.pubkey()).await;
+    reserve_with_deposit(&mut env).await;
+
+    let initializer_before = lamports(&mut env.ctx, initializer.pubkey()).await;
+    let ix = abort_ix(taker.pubkey(), taker.pubkey(), initializer.pubkey(), 0);
+    process(&mut env.ctx, &[ix], &[&taker]).await.unwrap();
+
+    let deposit = TAKER_AMOUNT * RESERVATION_DEPOSIT_BPS as u64 / 10_000;
+    assert_eq!(
+        lamports(&mut env.ctx, taker.pubkey()).await,
+        taker_before - deposit
+    );
+    assert_eq!(
+        lamports(&mut env.ctx, initializer.pubkey()).await,
+        initializer_before + deposit
+    );
+}
+
+#[tokio::test]
+async fn finalize_returns_the_deposit() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    let taker = env.taker.insecure_clone();
+    reserve_with_deposit(&mut env).await;
+
+    let taker_before = lamports(&mut env.ctx, taker.pubkey()).await;
+    let ix = finalize_ix(
+        taker.pubkey(),
+        initializer.pubkey(),
+        env.mint_a,
+        env.mint_b,
+        0,
+        env.treasury,
+    );
+    process(&mut env.ctx, &[ix], &[&taker]).await.unwrap();
+
+    // The payment held since `reserve` pays for the exchange and the deposit
+    // comes back; only the two new receive accounts cost the taker rent.
+    let deposit = TAKER_AMOUNT * RESERVATION_DEPOSIT_BPS as u64 / 10_000;
+    let ata_rent = env
+        .ctx
+        .banks_client
+        .get_rent()
+        .await
+        .unwrap()
+        .minimum_balance(spl_token::state::Account::LEN);
+    assert_eq!(
+        lamports(&mut env.ctx, taker.pubkey()).await,
+        taker_before + deposit + INITIALIZER_AMOUNT - 2 * ata_rent
+    );
+}
+
+#[tokio::test]
+async fn lapsed_reservation_is_forfeited() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    let taker = env.taker.insecure_clone();
+    reserve_with_deposit(&mut env).await;
+
+    let mut clock: Clock = env.ctx.banks_client.get_sysvar().await.unwrap();
+    clock.unix_timestamp += RESERVATION_WINDOW_SECS + 1;
+    env.ctx.set_sysvar(&clock);
+
+    let ix = finalize_ix(
+        taker.pubkey(),
+        initializer.pubkey(),
+        env.mint_a,
+        env.mint_b,
+        0,
+        env.treasury,
+    );
+    let result = process(&mut env.ctx, &[ix], &[&taker]).await;
+    assert_error(result, EscrowError::ReservationExpired.into());
+
+    // Once the window has passed the seller keeps the deposit even when they
+    // are the one releasing the listing.
+    let initializer_before = lamports(&mut env.ctx, initializer.pubkey()).await;
+    let ix = abort_ix(
+        initializer.pubkey(),
+        taker.pubkey(),
+        initializer.pubkey(),
+        0,
+    );
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+    let deposit = TAKER_AMOUNT * RESERVATION_DEPOSIT_BPS as u64 / 10_000;
+    assert_eq!(
+        lamports(&mut env.ctx, initializer.pubkey()).await,
+        initializer_before + deposit
+    );
+}
+
+const SETTLEMENT_DELAY_SECS: i64 = 86_400;
+
+/// Opens a listing with a cooling-off window and exchanges it, leaving both
+/// tokens held by the escrow.
+async fn delayed_exchange(env: &mut Env) {
+    let initializer = env.initializer.insecure_clone();
+    let taker = env.taker.insecure_clone();
+    let ix = delayed_initialize_ix(
+        initializer.pubkey(),
+        taker.pubkey(),
+        env.mint_a,
+        env.mint_b,
+        0,
+        INITIALIZER_AMOUNT,
+        TAKER_AMOUNT,
+        SETTLEMENT_DELAY_SECS,
+    );
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+
+    let escrow_state = escrow_state_key(&initializer.pubkey(), 0);
+    let create_settlement_vault =
+        spl_associated_token_account::instruction::create_associated_token_account(
+            &taker.pubkey(),
+            &vault_authority_key(&escrow_state),
+            &env.mint_b,
+            &spl_token::ID,
+        );
+    let mut ix = env.exchange_ix(&taker.pubkey());
+    ix.accounts.push(solana_sdk::instruction::AccountMeta::new(
+        vault_key(&escrow_state, &env.mint_b),
+        false,
+    ));
+    process(&mut env.ctx, &[create_settlement_vault, ix], &[&taker])
+        .await
+        .unwrap();
+}
+
+async fn warp_past_settlement_window(env: &mut Env) {
+    let mut clock: Clock = env.ctx.banks_client.get_sysvar().await.unwrap();
+    clock.unix_timestamp += SETTLEMENT_DELAY_SECS;
+    env.ctx.set_sysvar(&clock);
+}
+
+impl Env {
+    fn settle_ix(&self, authority: &Pubkey) -> Instruction {
+        settle_ix(
+            *authority,
+            self.initializer.pubkey(),
+            self.taker.pubkey(),
+            self.mint_a,
+            self.mint_b,
+            0,
+        )
+    }
+}
+
+#[tokio::test]
+async fn delayed_exchange_settles_after_the_window() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    let taker = env.taker.insecure_clone();
+    delayed_exchange(&mut env).await;
+
+    let escrow_state = escrow_state_key(&initializer.pubkey(), 0);
+    assert_eq!(
+        token_amount(&mut env.ctx, vault_key(&escrow_state, &env.mint_a)).await,
+        1
+    );
+    assert_eq!(
+        token_amount(&mut env.ctx, vault_key(&escrow_state, &env.mint_b)).await,
+        1
+    );
+
+    let ix = env.settle_ix(&taker.pubkey());
+    let result = process(&mut env.ctx, &[ix], &[&taker]).await;
+    assert_error(result, EscrowError::SettlementWindowOpen.into());
+
+    warp_past_settlement_window(&mut env).await;
+    let initializer_before = lamports(&mut env.ctx, initializer.pubkey()).await;
+    let ix = env.settle_ix(&initializer.pubkey());
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+
+    let taker_nft = get_associated_token_address(&taker.pubkey(), &env.mint_a);
+    let initializer_payment = get_associated_token_address(&initializer.pubkey(), &env.mint_b);
+    assert_eq!(token_amount(&mut env.ctx, taker_nft).await, 1);
+    assert_eq!(token_amount(&mut env.ctx, initializer_payment).await, 1);
+    assert!(!exists(&mut env.ctx, escrow_state).await);
+    // The seller collects the held proceeds on top of the refunded rent.
+    let maker_fee = TAKER_AMOUNT * MAKER_FEE_BPS as u64 / 10_000;
+    assert!(
+        lamports(&mut env.ctx, initializer.pubkey()).await
+            > initializer_before + TAKER_AMOUNT - maker_fee
+    );
+}
+
+#[tokio::test]
+async fn disputed_exchange_can_be_refunded() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    let taker = env.taker.insecure_clone();
+    delayed_exchange(&mut env).await;
+
+    let ix = dispute_ix(taker.pubkey(), initializer.pubkey(), 0);
+    process(&mut env.ctx, &[ix], &[&taker]).await.unwrap();
+
+    warp_past_settlement_window(&mut env).await;
+    let ix = env.settle_ix(&initializer.pubkey());
+    let result = process(&mut env.ctx, &[ix], &[&initializer]).await;
+    assert_error(result, EscrowError::SettlementDisputed.into());
+
+    let admin = env.ctx.payer.pubkey();
+    let ix = Instruction {
+        data: solana_nft_escrow::instruction::ResolveDispute { refund: true }.data(),
+        ..env.settle_ix(&admin)
+    };
+    process(&mut env.ctx, &[ix], &[]).await.unwrap();
+
+    let initializer_nft = get_associated_token_address(&initializer.pubkey(), &env.mint_a);
+    let taker_payment = get_associated_token_address(&taker.pubkey(), &env.mint_b);
+    assert_eq!(token_amount(&mut env.ctx, initializer_nft).await, 1);
+    assert_eq!(token_amount(&mut env.ctx, taker_payment).await, 1);
+    assert!(!exists(&mut env.ctx, escrow_state_key(&initializer.pubkey(), 0)).await);
+}
+
+const PLAN_INTERVAL_SECS: i64 = 7 * 86_400;
+const PLAN_FORFEIT_BPS: u16 = 5_000;
+
+/// Opens the listing with a three-installment plan and starts it for the
+/// taker, who pays the first installment.
+async fn start_payment_plan(env: &mut Env) {
+    let initializer = env.initializer.insecure_clone();
+    let taker = env.taker.insecure_clone();
+    let ix = env.initialize_ix();
+    let offer = offer_payment_plan_ix(
+        initializer.pubkey(),
+        0,
+        3,
+        PLAN_INTERVAL_SECS,
+        PLAN_FORFEIT_BPS,
+    );
+    process(&mut env.ctx, &[ix, offer], &[&initializer])
+        .await
+        .unwrap();
+    let ix = start_payment_plan_ix(taker.pubkey(), initializer.pubkey(), 0);
+    process(&mut env.ctx, &[ix], &[&taker]).await.unwrap();
+}
+
+#[tokio::test]
+async fn payment_plan_releases_on_the_final_installment() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    let taker = env.taker.insecure_clone();
+    start_payment_plan(&mut env).await;
+
+    // The listing is held for the taker while the plan runs.
+    let ix = env.cancel_ix();
+    let result = process(&mut env.ctx, &[ix], &[&initializer]).await;
+    assert_error(result, EscrowError::PaymentPlanActive.into());
+
+    let complete = complete_payment_plan_ix(
+        taker.pubkey(),
+        initializer.pubkey(),
+        env.mint_a,
+        env.mint_b,
+        0,
+        env.treasury,
+    );
+    let result = process(&mut env.ctx, &[complete.clone()], &[&taker]).await;
+    assert_error(result, EscrowError::InstallmentsOutstanding.into());
+
+    let ix = pay_installment_ix(taker.pubkey(), initializer.pubkey(), 0);
+    process(&mut env.ctx, &[ix], &[&taker]).await.unwrap();
+    let ix = pay_installment_ix(taker.pubkey(), initializer.pubkey(), 0);
+    let result = process(&mut env.ctx, &[ix], &[&taker]).await;
+    assert_error(result, EscrowError::FinalInstallmentRequired.into());
+
+    process(&mut env.ctx, &[complete], &[&taker]).await.unwrap();
+
+    let taker_nft = get_associated_token_address(&taker.pubkey(), &env.mint_a);
+    assert_eq!(token_amount(&mut env.ctx, taker_nft).await, 1);
+    let escrow_state = escrow_state_key(&initializer.pubkey(), 0);
+    assert!(!exists(&mut env.ctx, escrow_state).await);
+    assert!(!exists(&mut env.ctx, payment_plan_key(&escrow_state)).await);
+}
+
+#[tokio::test]
+async fn withdrawn_payment_plan_cannot_be_started() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    let taker = env.taker.insecure_clone();
+    let ix = env.initialize_ix();
+    let offer = offer_payment_plan_ix(
+        initializer.pubkey(),
+        0,
+        3,
+        PLAN_INTERVAL_SECS,
+        PLAN_FORFEIT_BPS,
+    );
+    let withdraw = offer_payment_plan_ix(initializer.pubkey(), 0, 0, 0, 0);
+    process(&mut env.ctx, &[ix, offer, withdraw], &[&initializer])
+        .await
+        .unwrap();
+
+    let ix = start_payment_plan_ix(taker.pubkey(), initializer.pubkey(), 0);
+    let result = process(&mut env.ctx, &[ix], &[&taker]).await;
+    assert_error(result, EscrowError::PaymentPlanNotOffered.into());
+}
+
+#[tokio::test]
+async fn missed_installment_lets_the_seller_reclaim() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    let taker = env.taker.insecure_clone();
+    start_payment_plan(&mut env).await;
+
+    let ix = reclaim_payment_plan_ix(initializer.pubkey(), taker.pubkey(), 0);
+    let result = process(&mut env.ctx, &[ix], &[&initializer]).await;
+    assert_error(result, EscrowError::InstallmentNotOverdue.into());
+
+    let mut clock: Clock = env.ctx.banks_client.get_sysvar().await.unwrap();
+    clock.unix_timestamp += PLAN_INTERVAL_SECS + 1;
+    env.ctx.set_sysvar(&clock);
+
+    let ix = pay_installment_ix(taker.pubkey(), initializer.pubkey(), 0);
+    let result = process(&mut env.ctx, &[ix], &[&taker]).await;
+    assert_error(result, EscrowError::InstallmentOverdue.into());
+
+    let escrow_state = escrow_state_key(&initializer.pubkey(), 0);
+    let payment_plan = payment_plan_key(&escrow_state);
+    let initializer_before = lamports(&mut env.ctx, initializer.pubkey()).await;
+    let taker_before = lamports(&mut env.ctx, taker.pubkey()).await;
+    let plan_lamports = lamports(&mut env.ctx, payment_plan).await;
+
+    let ix = reclaim_payment_plan_ix(initializer.pubkey(), taker.pubkey(), 0);
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+
+    let taker_fee = TAKER_AMOUNT * TAKER_FEE_BPS as u64 / 10_000;
+    let first_installment = (TAKER_AMOUNT + taker_fee) / 3;
+    let forfeit = first_installment * PLAN_FORFEIT_BPS as u64 / 10_000;
+    assert_eq!(
+        lamports(&mut env.ctx, initializer.pubkey()).await,
+        initializer_before + forfeit
+    );
+    assert_eq!(
+        lamports(&mut env.ctx, taker.pubkey()).await,
+        taker_before + plan_lamports - forfeit
+    );
+    assert!(!exists(&mut env.ctx, payment_plan).await);
+
+    // The listing is the seller's again.
+    let ix = env.cancel_ix();
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+}
+
+const RENT_LAMPORTS: u64 = 5_000_000;
+const COLLATERAL_LAMPORTS: u64 = 100_000_000;
+const RENTAL_DURATION_SECS: i64 = 86_400;
+
+/// Lists `mint_a` for rent and rents it to the taker.
+async fn rent_out(env: &mut Env) {
+    let owner = env.initializer.insecure_clone();
+    let renter = env.taker.insecure_clone();
+    let ix = list_for_rent_ix(
+        owner.pubkey(),
+        env.mint_a,
+        RENT_LAMPORTS,
+        COLLATERAL_LAMPORTS,
+        RENTAL_DURATION_SECS,
+    );
+    process(&mut env.ctx, &[ix], &[&owner]).await.unwrap();
+    let ix = rent_ix(renter.pubkey(), owner.pubkey(), env.mint_a);
+    process(&mut env.ctx, &[ix], &[&renter]).await.unwrap();
+}
+
+#[tokio::test]
+async fn returned_rental_refunds_the_collateral() {
+    let mut env = setup().await;
+    let owner = env.initializer.insecure_clone();
+    let renter = env.taker.insecure_clone();
+    let owner_before = lamports(&mut env.ctx, owner.pubkey()).await;
+    rent_out(&mut env).await;
+
+    let renter_nft = get_associated_token_address(&renter.pubkey(), &env.mint_a);
+    assert_eq!(token_amount(&mut env.ctx, renter_nft).await, 1);
+    let ix = delist_rental_ix(owner.pubkey(), env.mint_a);
+    let result = process(&mut env.ctx, &[ix], &[&owner]).await;
+    assert_error(result, EscrowError::AlreadyRented.into());
+
+    let renter_before = lamports(&mut env.ctx, renter.pubkey()).await;
+    let ix = return_rental_ix(renter.pubkey(), owner.pubkey(), env.mint_a);
+    process(&mut env.ctx, &[ix], &[&renter]).await.unwrap();
+    assert_eq!(token_amount(&mut env.ctx, renter_nft).await, 0);
+    assert_eq!(
+        lamports(&mut env.ctx, renter.pubkey()).await,
+        renter_before + COLLATERAL_LAMPORTS
+    );
+
+    let ix = delist_rental_ix(owner.pubkey(), env.mint_a);
+    process(&mut env.ctx, &[ix], &[&owner]).await.unwrap();
+    let owner_nft = get_associated_token_address(&owner.pubkey(), &env.mint_a);
+    assert_eq!(token_amount(&mut env.ctx, owner_nft).await, 1);
+    // Listing and vault rent come back, so the owner is up by the rent.
+    assert_eq!(
+        lamports(&mut env.ctx, owner.pubkey()).await,
+        owner_before + RENT_LAMPORTS
+    );
+}
+
+#[tokio::test]
+async fn unreturned_rental_forfeits_the_collateral() {
+    let mut env = setup().await;
+    let owner = env.initializer.insecure_clone();
+    let renter = env.taker.insecure_clone();
+    rent_out(&mut env).await;
+
+    let ix = claim_collateral_ix(owner.pubkey(), env.mint_a);
+    let result = process(&mut env.ctx, &[ix], &[&owner]).await;
+    assert_error(result, EscrowError::RentalNotOverdue.into());
+
+    let mut clock: Clock = env.ctx.banks_client.get_sysvar().await.unwrap();
+    clock.unix_timestamp += RENTAL_DURATION_SECS + 1;
+    env.ctx.set_sysvar(&clock);
+
+    let owner_before = lamports(&mut env.ctx, owner.pubkey()).await;
+    let ix = claim_collateral_ix(owner.pubkey(), env.mint_a);
+    process(&mut env.ctx, &[ix], &[&owner]).await.unwrap();
+
+    assert!(lamports(&mut env.ctx, owner.pubkey()).await > owner_before + COLLATERAL_LAMPORTS);
+    let rental_listing = rental_listing_key(&owner.pubkey(), &env.mint_a);
+    assert!(!exists(&mut env.ctx, rental_listing).await);
+    let renter_nft = get_associated_token_address(&renter.pubkey(), &env.mint_a);
+    assert_eq!(token_amount(&mut env.ctx, renter_nft).await, 1);
+}
+
+const LOAN_PRINCIPAL: u64 = 200_000_000;
+const LOAN_APR_BPS: u16 = 3_650;
+const LOAN_DURATION_SECS: i64 = 30 * 86_400;
+
+/// Lists `mint_a` and has the taker fund a loan against it.
+async fn fund_loan(env: &mut Env) {
+    let borrower = env.initializer.insecure_clone();
+    let lender = env.taker.insecure_clone();
+    let ix = env.initialize_ix();
+    let request = request_loan_ix(
+        borrower.pubkey(),
+        0,
+        LOAN_PRINCIPAL,
+        LOAN_APR_BPS,
+        LOAN_DURATION_SECS,
+    );
+    process(&mut env.ctx, &[ix, request], &[&borrower])
+        .await
+        .unwrap();
+    let ix = fund_loan_ix(lender.pubkey(), borrower.pubkey(), 0);
+    process(&mut env.ctx, &[ix], &[&lender]).await.unwrap();
+}
+
+/// Lists `mint_a` at no price, as gifts, HTLCs and Wormhole sales must be,
+/// and has the taker fund a loan against it.
+async fn fund_loan_at_no_price(env: &mut Env) {
+    let borrower = env.initializer.insecure_clone();
+    let lender = env.taker.insecure_clone();
+    let ix = initialize_ix(
+        borrower.pubkey(),
+        lender.pubkey(),
+        env.mint_a,
+        env.mint_b,
+        0,
+        INITIALIZER_AMOUNT,
+        0,
+    );
+    let request = request_loan_ix(
+        borrower.pubkey(),
+        0,
+        LOAN_PRINCIPAL,
+        LOAN_APR_BPS,
+        LOAN_DURATION_SECS,
+    );
+    process(&mut env.ctx, &[ix, request], &[&borrower])
+        .await
+        .unwrap();
+    let ix = fund_loan_ix(lender.pubkey(), borrower.pubkey(), 0);
+    process(&mut env.ctx, &[ix], &[&lender]).await.unwrap();
+}
+
+#[tokio::test]
+async fn repaid_loan_pays_accrued_interest() {
+    let mut env = setup().await;
+    let borrower = env.initializer.insecure_clone();
+    let lender = env.taker.insecure_clone();
+    fund_loan(&mut env).await;
+
+    // The NFT cannot leave the vault while it secures the loan.
+    let ix = env.cancel_ix();
+    let result = process(&mut env.ctx, &[ix], &[&borrower]).await;
+    assert_error(result, EscrowError::EscrowOnLoan.into());
+
+    // Ten days at 36.5% a year is 1% of the principal.
+    let mut clock: Clock = env.ctx.banks_client.get_sysvar().await.unwrap();
+    clock.unix_timestamp += 10 * 86_400;
+    env.ctx.set_sysvar(&clock);
+
+    let lender_before = lamports(&mut env.ctx, lender.pubkey()).await;
+    let loan_state = loan_state_key(&escrow_state_key(&borrower.pubkey(), 0));
+    let loan_rent = lamports(&mut env.ctx, loan_state).await;
+    let ix = repay_ix(borrower.pubkey(), lender.pubkey(), 0);
+    process(&mut env.ctx, &[ix], &[&borrower]).await.unwrap();
+
+    let interest = LOAN_PRINCIPAL / 100;
+    assert!(
+        lamports(&mut env.ctx, lender.pubkey()).await
+            >= lender_before + LOAN_PRINCIPAL + interest + loan_rent
+    );
+    assert!(!exists(&mut env.ctx, loan_state).await);
+
+    let ix = env.cancel_ix();
+    process(&mut env.ctx, &[ix], &[&borrower]).await.unwrap();
+}
+
+#[tokio::test]
+async fn overdue_loan_can_be_liquidated() {
+    let mut env = setup().await;
+    let borrower = env.initializer.insecure_clone();
+    let lender = env.taker.insecure_clone();
+    fund_loan(&mut env).await;
+
+    let ix = liquidate_ix(lender.pubkey(), borrower.pubkey(), env.mint_a, 0);
+    let result = process(&mut env.ctx, &[ix], &[&lender]).await;
+    assert_error(result, EscrowError::LoanNotDue.into());
+
+    let mut clock: Clock = env.ctx.banks_client.get_sysvar().await.unwrap();
+    clock.unix_timestamp += LOAN_DURATION_SECS + 1;
+    env.ctx.set_sysvar(&clock);
+
+    let ix = liquidate_ix(lender.pubkey(), borrower.pubkey(), env.mint_a, 0);
+    process(&mut env.ctx, &[ix], &[&lender]).await.unwrap();
+
+    let lender_nft = get_associated_token_address(&lender.pubkey(), &env.mint_a);
+    assert_eq!(token_amount(&mut env.ctx, lender_nft).await, 1);
+    let escrow_state = escrow_state_key(&borrower.pubkey(), 0);
+    assert!(!exists(&mut env.ctx, escrow_state).await);
+    assert!(!exists(&mut env.ctx, loan_state_key(&escrow_state)).await);
+}
+
+#[tokio::test]
+async fn vesting_sale_streams_the_proceeds() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    let taker = env.taker.insecure_clone();
+    let duration_secs = 100 * 86_400;
+
+    let ix = env.initialize_ix();
+    let vesting = configure_vesting_ix(initializer.pubkey(), 0, duration_secs, 10 * 86_400);
+    process(&mut env.ctx, &[ix, vesting], &[&initializer])
+        .await
+        .unwrap();
+    let initializer_before = lamports(&mut env.ctx, initializer.pubkey()).await;
+    let ix = env.exchange_ix(&taker.pubkey());
+    process(&mut env.ctx, &[ix], &[&taker]).await.unwrap();
+
+    // Nothing is paid out before the cliff.
+    let ix = claim_vested_ix(initializer.pubkey(), 0);
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+    assert_eq!(
+        lamports(&mut env.ctx, initializer.pubkey()).await,
+        initializer_before
+    );
+
+    let mut clock: Clock = env.ctx.banks_client.get_sysvar().await.unwrap();
+    clock.unix_timestamp += duration_secs;
+    env.ctx.set_sysvar(&clock);
+
+    let ix = claim_vested_ix(initializer.pubkey(), 0);
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+    let maker_fee = TAKER_AMOUNT * MAKER_FEE_BPS as u64 / 10_000;
+    assert!(
+        lamports(&mut env.ctx, initializer.pubkey()).await
+            > initializer_before + TAKER_AMOUNT - maker_fee
+    );
+    assert!(!exists(&mut env.ctx, escrow_state_key(&initializer.pubkey(), 0)).await);
+}
+
+#[tokio::test]
+async fn subscription_delivers_one_copy_per_period() {
+    let mut env = setup().await;
+    let seller = env.initializer.insecure_clone();
+    let subscriber = env.taker.insecure_clone();
+    let period_secs = 30 * 86_400;
+
+    // The seller holds five copies of a fresh edition mint.
+    let edition = create_mint(&mut env.ctx).await;
+    let seller_copies = mint_one_to(&mut env.ctx, &edition, &seller.pubkey()).await;
+    let mint_more = spl_token::instruction::mint_to(
+        &spl_token::ID,
+        &edition,
+        &seller_copies,
+        &env.ctx.payer.pubkey(),
+        &[],
+        4,
+    )
+    .unwrap();
+    process(&mut env.ctx, &[mint_more], &[]).await.unwrap();
+
+    let ix = create_subscription_listing_ix(seller.pubkey(), edition, TAKER_AMOUNT, period_secs, 5);
+    process(&mut env.ctx, &[ix], &[&seller]).await.unwrap();
+
+    let subscribe = subscribe_ix(subscriber.pubkey(), seller.pubkey(), edition, env.treasury);
+    let renew = Instruction {
+        data: solana_nft_escrow::instruction::RenewSubscription {}.data(),
+        ..subscribe.clone()
+    };
+    process(&mut env.ctx, &[subscribe], &[&subscriber])
+        .await
+        .unwrap();
+    let result = process(&mut env.ctx, &[renew.clone()], &[&subscriber]).await;
+    assert_error(result, EscrowError::SubscriptionPeriodNotElapsed.into());
+
+    let mut clock: Clock = env.ctx.banks_client.get_sysvar().await.unwrap();
+    clock.unix_timestamp += period_secs;
+    env.ctx.set_sysvar(&clock);
+    process(&mut env.ctx, &[renew], &[&subscriber])
+        .await
+        .unwrap();
+
+    let subscriber_copies = get_associated_token_address(&subscriber.pubkey(), &edition);
+    assert_eq!(token_amount(&mut env.ctx, subscriber_copies).await, 2);
+    let maker_fee = TAKER_AMOUNT * MAKER_FEE_BPS as u64 / 10_000;
+    let taker_fee = TAKER_AMOUNT * TAKER_FEE_BPS as u64 / 10_000;
+    assert_eq!(
+        lamports(&mut env.ctx, env.treasury).await,
+        2 * (maker_fee + taker_fee)
+    );
+}
+
+#[tokio::test]
+async fn buyout_is_redeemed_pro_rata_by_fraction_holders() {
+    let mut env = setup().await;
+    let depositor = env.initializer.insecure_clone();
+    let holder = env.taker.insecure_clone();
+    let buyer = Keypair::new();
+    let fund_buyer =
+        system_instruction::transfer(&env.ctx.payer.pubkey(), &buyer.pubkey(), 1_000_000_000);
+    process(&mut env.ctx, &[fund_buyer], &[]).await.unwrap();
+
+    let ix = fractionalize_ix(depositor.pubkey(), env.mint_a, 100, TAKER_AMOUNT);
+    process(&mut env.ctx, &[ix], &[&depositor]).await.unwrap();
+
+    // A quarter of the fractions change hands before the buyout.
+    let fraction_mint = fraction_mint_key(&env.mint_a);
+    let depositor_fractions = get_associated_token_address(&depositor.pubkey(), &fraction_mint);
+    let holder_fractions = get_associated_token_address(&holder.pubkey(), &fraction_mint);
+    let create_holder_account =
+        spl_associated_token_account::instruction::create_associated_token_account(
+            &holder.pubkey(),
+            &holder.pubkey(),
+            &fraction_mint,
+            &spl_token::ID,
+        );
+    let give_fractions = spl_token::instruction::transfer(
+        &spl_token::ID,
+        &depositor_fractions,
+        &holder_fractions,
+        &depositor.pubkey(),
+        &[],
+        25,
+    )
+    .unwrap();
+    process(
+        &mut env.ctx,
+        &[create_holder_account, give_fractions],
+        &[&holder, &depositor],
+    )
+    .await
+    .unwrap();
+
+    let redeem = redeem_fractions_ix(holder.pubkey(), depositor.pubkey(), env.mint_a);
+    let result = process(&mut env.ctx, &[redeem.clone()], &[&holder]).await;
+    assert_error(result, EscrowError::NotBoughtOut.into());
+
+    let ix = buyout_ix(buyer.pubkey(), depositor.pubkey(), env.mint_a, env.treasury);
+    process(&mut env.ctx, &[ix], &[&buyer]).await.unwrap();
+    let buyer_nft = get_associated_token_address(&buyer.pubkey(), &env.mint_a);
+    assert_eq!(token_amount(&mut env.ctx, buyer_nft).await, 1);
+
+    let holder_before = lamports(&mut env.ctx, holder.pubkey()).await;
+    process(&mut env.ctx, &[redeem], &[&holder]).await.unwrap();
+    assert_eq!(
+        lamports(&mut env.ctx, holder.pubkey()).await,
+        holder_before + TAKER_AMOUNT / 4
+    );
+
+    let ix = redeem_fractions_ix(depositor.pubkey(), depositor.pubkey(), env.mint_a);
+    process(&mut env.ctx, &[ix], &[&depositor]).await.unwrap();
+    assert!(!exists(&mut env.ctx, fraction_vault_key(&env.mint_a)).await);
+}
+
+#[tokio::test]
+async fn funded_purchase_pool_buys_into_custody() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    let taker = env.taker.insecure_clone();
+    let contributor = Keypair::new();
+    let fund_contributor = system_instruction::transfer(
+        &env.ctx.payer.pubkey(),
+        &contributor.pubkey(),
+        1_000_000_000,
+    );
+    let ix = env.initialize_ix();
+    process(&mut env.ctx, &[ix, fund_contributor], &[&initializer])
+        .await
+        .unwrap();
+
+    let ix = create_purchase_pool_ix(taker.pubkey(), initializer.pubkey(), 0);
+    process(&mut env.ctx, &[ix], &[&taker]).await.unwrap();
+
+    // The taker puts in the listing's receive token and half the total.
+    let escrow_state = escrow_state_key(&initializer.pubkey(), 0);
+    let custody = custody_key(&purchase_pool_key(&escrow_state));
+    let create_custody_account =
+        spl_associated_token_account::instruction::create_associated_token_account(
+            &taker.pubkey(),
+            &custody,
+            &env.mint_b,
+            &spl_token::ID,
+        );
+    let give_receive_token = spl_token::instruction::transfer(
+        &spl_token::ID,
+        &get_associated_token_address(&taker.pubkey(), &env.mint_b),
+        &get_associated_token_address(&custody, &env.mint_b),
+        &taker.pubkey(),
+        &[],
+        1,
+    )
+    .unwrap();
+    let total = TAKER_AMOUNT + TAKER_AMOUNT * TAKER_FEE_BPS as u64 / 10_000;
+    let ix = contribute_ix(taker.pubkey(), initializer.pubkey(), 0, total / 2);
+    process(
+        &mut env.ctx,
+        &[create_custody_account, give_receive_token, ix],
+        &[&taker],
+    )
+    .await
+    .unwrap();
+
+    let execute = execute_group_purchase_ix(
+        taker.pubkey(),
+        initializer.pubkey(),
+        env.mint_a,
+        env.mint_b,
+        0,
+        env.treasury,
+    );
+    let result = process(&mut env.ctx, &[execute.clone()], &[&taker]).await;
+    assert_error(result, EscrowError::GroupPurchaseUnderfunded.into());
+
+    // Anything over the shortfall is left with the contributor.
+    let ix = contribute_ix(contributor.pubkey(), initializer.pubkey(), 0, total);
+    process(&mut env.ctx, &[ix], &[&contributor]).await.unwrap();
+    process(&mut env.ctx, &[execute], &[&taker]).await.unwrap();
+
+    let custody_nft = get_associated_token_address(&custody, &env.mint_a);
+    assert_eq!(token_amount(&mut env.ctx, custody_nft).await, 1);
+    assert!(!exists(&mut env.ctx, escrow_state).await);
+}
+
+#[tokio::test]
+async fn gated_listing_requires_the_gate_token() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    let taker = env.taker.insecure_clone();
+    let gate_mint = create_mint(&mut env.ctx).await;
+    let taker_gate_account = mint_one_to(&mut env.ctx, &gate_mint, &taker.pubkey()).await;
+
+    let ix = env.initialize_ix();
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+    let ix = set_purchase_gate_ix(initializer.pubkey(), 0, gate_mint, 1);
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+
+    let ix = env.exchange_ix(&taker.pubkey());
+    let result = process(&mut env.ctx, &[ix], &[&taker]).await;
+    assert_error(result, EscrowError::PurchaseGateNotMet.into());
+
+    let ix = gated_exchange_ix(
+        taker.pubkey(),
+        initializer.pubkey(),
+        env.mint_a,
+        env.mint_b,
+        0,
+        env.treasury,
+        taker_gate_account,
+    );
+    process(&mut env.ctx, &[ix], &[&taker]).await.unwrap();
+    let taker_nft = get_associated_token_address(&taker.pubkey(), &env.mint_a);
+    assert_eq!(token_amount(&mut env.ctx, taker_nft).await, 1);
+}
+
+async fn offer_gift(env: &mut Env, claim_deadline: i64) {
+    let initializer = env.initializer.insecure_clone();
+    let ix = initialize_ix(
+        initializer.pubkey(),
+        env.taker.pubkey(),
+        env.mint_a,
+        env.mint_b,
+        0,
+        INITIALIZER_AMOUNT,
+        0,
+    );
+    let gift = offer_gift_ix(initializer.pubkey(), 0, env.taker.pubkey(), claim_deadline);
+    process(&mut env.ctx, &[ix, gift], &[&initializer])
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn gift_is_claimed_by_its_recipient() {
+    let mut env = setup().await;
+    let taker = env.taker.insecure_clone();
+    offer_gift(&mut env, 0).await;
+
+    // A gift is not up for grabs at its zero price.
+    let ix = env.exchange_ix(&taker.pubkey());
+    let result = process(&mut env.ctx, &[ix], &[&taker]).await;
+    assert_error(result, EscrowError::GiftEscrow.into());
+
+    let taker_before = lamports(&mut env.ctx, taker.pubkey()).await;
+    let ix = claim_gift_ix(taker.pubkey(), env.initializer.pubkey(), env.mint_a, 0);
+    process(&mut env.ctx, &[ix], &[&taker]).await.unwrap();
+
+    let taker_nft = get_associated_token_address(&taker.pubkey(), &env.mint_a);
+    assert_eq!(token_amount(&mut env.ctx, taker_nft).await, 1);
+    let ata_rent = env
+        .ctx
+        .banks_client
+        .get_rent()
+        .await
+        .unwrap()
+        .minimum_balance(spl_token::state::Account::LEN);
+    assert_eq!(
+        lamports(&mut env.ctx, taker.pubkey()).await,
+        taker_before + INITIALIZER_AMOUNT - ata_rent
+    );
+}
+
+#[tokio::test]
+async fn unclaimed_gift_returns_after_the_deadline() {
+    let mut env = setup().await;
+    let claim_deadline = env
+        .ctx
+        .banks_client
+        .get_sysvar::<Clock>()
+        .await
+        .unwrap()
+        .unix_timestamp
+        + 86_400;
+    offer_gift(&mut env, claim_deadline).await;
+
+    let ix = return_gift_ix(env.initializer.pubkey(), env.mint_a, 0);
+    let result = process(&mut env.ctx, &[ix.clone()], &[]).await;
+    assert_error(result, EscrowError::GiftNotExpired.into());
+
+    let mut clock: Clock = env.ctx.banks_client.get_sysvar().await.unwrap();
+    clock.unix_timestamp = claim_deadline + 1;
+    env.ctx.set_sysvar(&clock);
+    let taker = env.taker.insecure_clone();
+    let claim = claim_gift_ix(taker.pubkey(), env.initializer.pubkey(), env.mint_a, 0);
+    let result = process(&mut env.ctx, &[claim], &[&taker]).await;
+    assert_error(result, EscrowError::GiftExpired.into());
+
+    process(&mut env.ctx, &[ix], &[]).await.unwrap();
+    let initializer_nft = get_associated_token_address(&env.initializer.pubkey(), &env.mint_a);
+    assert_eq!(token_amount(&mut env.ctx, initializer_nft).await, 1);
+    assert!(!exists(&mut env.ctx, escrow_state_key(&env.initializer.pubkey(), 0)).await);
+}
+
+#[tokio::test]
+async fn returned_sponsored_gift_splits_deposit_from_rent() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    let sponsor = Keypair::new();
+    let fund_sponsor =
+        system_instruction::transfer(&env.ctx.payer.pubkey(), &sponsor.pubkey(), 1_000_000_000);
+    let ix = sponsored_initialize_ix(
+        initializer.pubkey(),
+        sponsor.pubkey(),
+        env.taker.pubkey(),
+        env.mint_a,
+        env.mint_b,
+        INITIALIZER_AMOUNT,
+        0,
+    );
+    let claim_deadline = env
+        .ctx
+        .banks_client
+        .get_sysvar::<Clock>()
+        .await
+        .unwrap()
+        .unix_timestamp
+        + 86_400;
+    let gift = offer_gift_ix(initializer.pubkey(), 0, env.taker.pubkey(), claim_deadline);
+    process(
+        &mut env.ctx,
+        &[fund_sponsor, ix, gift],
+        &[&initializer, &sponsor],
+    )
+    .await
+    .unwrap();
+    let mut clock: Clock = env.ctx.banks_client.get_sysvar().await.unwrap();
+    clock.unix_timestamp = claim_deadline + 1;
+    env.ctx.set_sysvar(&clock);
+
+    let ix = return_gift_ix(initializer.pubkey(), env.mint_a, 0);
+    let result = process(&mut env.ctx, &[ix.clone()], &[]).await;
+    assert_error(result, EscrowError::MissingRentPayer.into());
+
+    let escrow_state = escrow_state_key(&initializer.pubkey(), 0);
+    let listing_rent = lamports(&mut env.ctx, escrow_state).await
+        + lamports(&mut env.ctx, vault_key(&escrow_state, &env.mint_a)).await
+        - INITIALIZER_AMOUNT;
+    let initializer_before = lamports(&mut env.ctx, initializer.pubkey()).await;
+    let sponsor_before = lamports(&mut env.ctx, sponsor.pubkey()).await;
+    let mut ix = ix;
+    ix.accounts.push(solana_sdk::instruction::AccountMeta::new(
+        sponsor.pubkey(),
+        false,
+    ));
+    process(&mut env.ctx, &[ix], &[]).await.unwrap();
+
+    assert_eq!(
+        lamports(&mut env.ctx, initializer.pubkey()).await,
+        initializer_before + INITIALIZER_AMOUNT
+    );
+    assert_eq!(
+        lamports(&mut env.ctx, sponsor.pubkey()).await,
+        sponsor_before + listing_rent
+    );
+}
+
+#[tokio::test]
+async fn gift_refuses_a_listing_securing_a_loan() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    fund_loan_at_no_price(&mut env).await;
+
+    let recipient = Keypair::new();
+    let ix = offer_gift_ix(initializer.pubkey(), 0, recipient.pubkey(), 0);
+    let result = process(&mut env.ctx, &[ix], &[&initializer]).await;
+    assert_error(result, EscrowError::EscrowOnLoan.into());
+    assert_eq!(
+        token_amount(
+            &mut env.ctx,
+            vault_key(&escrow_state_key(&initializer.pubkey(), 0), &env.mint_a)
+        )
+        .await,
+        1
+    );
+}
+
+#[tokio::test]
+async fn htlc_releases_only_for_the_preimage() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    let taker = env.taker.insecure_clone();
+    let secret = b"swap secret".to_vec();
+    let timeout = env
+        .ctx
+        .banks_client
+        .get_sysvar::<Clock>()
+        .await
+        .unwrap()
+        .unix_timestamp
+        + 3_600;
+    let ix = initialize_ix(
+        initializer.pubkey(),
+        taker.pubkey(),
+        env.mint_a,
+        env.mint_b,
+        0,
+        INITIALIZER_AMOUNT,
+        0,
+    );
+    let htlc = offer_htlc_ix(
+        initializer.pubkey(),
+        0,
+        taker.pubkey(),
+        hash::hash(&secret).to_bytes(),
+        timeout,
+    );
+    process(&mut env.ctx, &[ix, htlc], &[&initializer])
+        .await
+        .unwrap();
+
+    // The taker may already have paid on the other chain.
+    let ix = env.cancel_ix();
+    let result = process(&mut env.ctx, &[ix], &[&initializer]).await;
+    assert_error(result, EscrowError::HtlcLocked.into());
+
+    let ix = exchange_with_preimage_ix(
+        taker.pubkey(),
+        initializer.pubkey(),
+        env.mint_a,
+        0,
+        b"wrong".to_vec(),
+    );
+    let result = process(&mut env.ctx, &[ix], &[&taker]).await;
+    assert_error(result, EscrowError::InvalidPreimage.into());
+
+    let ix = exchange_with_preimage_ix(taker.pubkey(), initializer.pubkey(), env.mint_a, 0, secret);
+    process(&mut env.ctx, &[ix], &[&taker]).await.unwrap();
+    let taker_nft = get_associated_token_address(&taker.pubkey(), &env.mint_a);
+    assert_eq!(token_amount(&mut env.ctx, taker_nft).await, 1);
+    assert!(!exists(&mut env.ctx, escrow_state_key(&initializer.pubkey(), 0)).await);
+}
+
+#[tokio::test]
+async fn htlc_refuses_a_listing_securing_a_loan() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    fund_loan_at_no_price(&mut env).await;
+
+    let ix = offer_htlc_ix(
+        initializer.pubkey(),
+        0,
+        Keypair::new().pubkey(),
+        hash::hash(b"secret").to_bytes(),
+        i64::MAX,
+    );
+    let result = process(&mut env.ctx, &[ix], &[&initializer]).await;
+    assert_error(result, EscrowError::EscrowOnLoan.into());
+}
+
+#[tokio::test]
+async fn wormhole_payment_releases_to_the_mapped_recipient() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    let wormhole_program = Pubkey::new_unique();
+    let emitter = [7; 32];
+    let recipient = Pubkey::new_unique();
+    let escrow_state = escrow_state_key(&initializer.pubkey(), 0);
+    let ix = initialize_ix(
+        initializer.pubkey(),
+        env.taker.pubkey(),
+        env.mint_a,
+        env.mint_b,
+        0,
+        INITIALIZER_AMOUNT,
+        0,
+    );
+    let accept =
+        accept_wormhole_payment_ix(initializer.pubkey(), 0, wormhole_program, 2, emitter, 500);
+    process(&mut env.ctx, &[ix, accept], &[&initializer])
+        .await
+        .unwrap();
+
+    let underpaid = set_posted_vaa(
+        &mut env.ctx,
+        &wormhole_program,
+        2,
+        emitter,
+        &escrow_state,
+        &recipient,
+        499,
+    );
+    let payer = env.taker.insecure_clone();
+    let ix = exchange_with_vaa_ix(
+        payer.pubkey(),
+        recipient,
+        initializer.pubkey(),
+        env.mint_a,
+        0,
+        underpaid,
+    );
+    let result = process(&mut env.ctx, &[ix], &[&payer]).await;
+    assert_error(result, EscrowError::WormholeUnderpaid.into());
+
+    let paid = set_posted_vaa(
+        &mut env.ctx,
+        &wormhole_program,
+        2,
+        emitter,
+        &escrow_state,
+        &recipient,
+        500,
+    );
+    let ix = exchange_with_vaa_ix(
+        payer.pubkey(),
+        recipient,
+        initializer.pubkey(),
+        env.mint_a,
+        0,
+        paid,
+    );
+    process(&mut env.ctx, &[ix], &[&payer]).await.unwrap();
+    let recipient_nft = get_associated_token_address(&recipient, &env.mint_a);
+    assert_eq!(token_amount(&mut env.ctx, recipient_nft).await, 1);
+    assert_eq!(lamports(&mut env.ctx, recipient).await, INITIALIZER_AMOUNT);
+}
+
+#[tokio::test]
+async fn wormhole_payment_refuses_a_listing_securing_a_loan() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    fund_loan_at_no_price(&mut env).await;
+
+    let ix = accept_wormhole_payment_ix(
+        initializer.pubkey(),
+        0,
+        Pubkey::new_unique(),
+        2,
+        [7; 32],
+        500,
+    );
+    let result = process(&mut env.ctx, &[ix], &[&initializer]).await;
+    assert_error(result, EscrowError::EscrowOnLoan.into());
+}
+
+#[tokio::test]
+async fn cancel_refuses_a_listing_paid_through_wormhole() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    let ix = env.initialize_ix();
+    // A buyer may already have paid on the other chain.
+    let accept = accept_wormhole_payment_ix(
+        initializer.pubkey(),
+        0,
+        Pubkey::new_unique(),
+        2,
+        [7; 32],
+        500,
+    );
+    process(&mut env.ctx, &[ix, accept], &[&initializer])
+        .await
+        .unwrap();
+
+    let ix = env.cancel_ix();
+    let result = process(&mut env.ctx, &[ix], &[&initializer]).await;
+    assert_error(result, EscrowError::WormholeEscrow.into());
+    assert_eq!(
+        token_amount(
+            &mut env.ctx,
+            vault_key(&escrow_state_key(&initializer.pubkey(), 0), &env.mint_a)
+        )
+        .await,
+        1
+    );
+}
+
+#[tokio::test]
+async fn ring_settles_all_three_legs_at_once() {
+    let mut env = setup().await;
+    let a = env.initializer.insecure_clone();
+    let b = env.taker.insecure_clone();
+    let c = Keypair::new();
+    let fund_c = system_instruction::transfer(&env.ctx.payer.pubkey(), &c.pubkey(), 1_000_000_000);
+    process(&mut env.ctx, &[fund_c], &[]).await.unwrap();
+
+    let escrow_a = escrow_state_key(&a.pubkey(), 0);
+    let escrow_b = escrow_state_key(&b.pubkey(), 0);
+    let list_a = initialize_ix(
+        a.pubkey(),
+        b.pubkey(),
+        env.mint_a,
+        env.mint_b,
+        0,
+        INITIALIZER_AMOUNT,
+        TAKER_AMOUNT,
+    );
+    let link_a = link_ring_ix(a.pubkey(), 0, b.pubkey(), escrow_b);
+    process(&mut env.ctx, &[list_a, link_a], &[&a])
+        .await
+        .unwrap();
+    let list_b = initialize_ix(
+        b.pubkey(),
+        c.pubkey(),
+        env.mint_b,
+        env.mint_a,
+        0,
+        INITIALIZER_AMOUNT,
+        0,
+    );
+    let link_b = link_ring_ix(b.pubkey(), 0, c.pubkey(), escrow_a);
+    process(&mut env.ctx, &[list_b, link_b], &[&b])
+        .await
+        .unwrap();
+
+    // A ring leg cannot be bought on its own.
+    let ix = env.exchange_ix(&c.pubkey());
+    let result = process(&mut env.ctx, &[ix], &[&c]).await;
+    assert_error(result, EscrowError::RingLeg.into());
+
+    let a_before = lamports(&mut env.ctx, a.pubkey()).await;
+    let ix = settle_ring_ix(
+        c.pubkey(),
+        a.pubkey(),
+        env.mint_a,
+        b.pubkey(),
+        env.mint_b,
+        env.treasury,
+    );
+    process(&mut env.ctx, &[ix], &[&c]).await.unwrap();
+    let b_nft = get_associated_token_address(&b.pubkey(), &env.mint_a);
+    let c_nft = get_associated_token_address(&c.pubkey(), &env.mint_b);
+    assert_eq!(token_amount(&mut env.ctx, b_nft).await, 1);
+    assert_eq!(token_amount(&mut env.ctx, c_nft).await, 1);
+    assert!(lamports(&mut env.ctx, a.pubkey()).await >= a_before + TAKER_AMOUNT);
+    assert!(!exists(&mut env.ctx, escrow_a).await);
+    assert!(!exists(&mut env.ctx, escrow_b).await);
+}
+
+#[tokio::test]
+async fn ring_refuses_a_leg_securing_a_loan() {
+    let mut env = setup().await;
+    let a = env.initializer.insecure_clone();
+    let b = env.taker.insecure_clone();
+    let c = Keypair::new();
+    let fund_c = system_instruction::transfer(&env.ctx.payer.pubkey(), &c.pubkey(), 1_000_000_000);
+    process(&mut env.ctx, &[fund_c], &[]).await.unwrap();
+    fund_loan(&mut env).await;
+
+    let escrow_a = escrow_state_key(&a.pubkey(), 0);
+    let escrow_b = escrow_state_key(&b.pubkey(), 0);
+    let link_a = link_ring_ix(a.pubkey(), 0, b.pubkey(), escrow_b);
+    let result = process(&mut env.ctx, &[link_a], &[&a]).await;
+    assert_error(result, EscrowError::EscrowOnLoan.into());
+
+    // A leg linked before its loan was funded still cannot settle.
+    let mut account = env
+        .ctx
+        .banks_client
+        .get_account(escrow_a)
+        .await
+        .unwrap()
+        .unwrap();
+    let mut state = EscrowState::try_deserialize(&mut account.data.as_slice()).unwrap();
+    state.ring_recipient = b.pubkey();
+    state.ring_partner = escrow_b;
+    let mut data = Vec::new();
+    state.try_serialize(&mut data).unwrap();
+    account.data[..data.len()].copy_from_slice(&data);
+    env.ctx.set_account(&escrow_a, &account.into());
+    let list_b = initialize_ix(
+        b.pubkey(),
+        c.pubkey(),
+        env.mint_b,
+        env.mint_a,
+        0,
+        INITIALIZER_AMOUNT,
+        0,
+    );
+    let link_b = link_ring_ix(b.pubkey(), 0, c.pubkey(), escrow_a);
+    process(&mut env.ctx, &[list_b, link_b], &[&b])
+        .await
+        .unwrap();
+
+    let ix = settle_ring_ix(
+        c.pubkey(),
+        a.pubkey(),
+        env.mint_a,
+        b.pubkey(),
+        env.mint_b,
+        env.treasury,
+    );
+    let result = process(&mut env.ctx, &[ix], &[&c]).await;
+    assert_error(result, EscrowError::EscrowOnLoan.into());
+    assert_eq!(
+        token_amount(&mut env.ctx, vault_key(&escrow_a, &env.mint_a)).await,
+        1
+    );
+}
+
+#[tokio::test]
+async fn bundle_swap_executes_once_both_sides_deposit() {
+    let mut env = setup().await;
+    let maker = env.initializer.insecure_clone();
+    let taker = env.taker.insecure_clone();
+    let mint_c = create_mint(&mut env.ctx).await;
+    mint_one_to(&mut env.ctx, &mint_c, &maker.pubkey()).await;
+    // Receiving token accounts must exist before the swap executes.
+    for (owner, mint) in [(&taker, env.mint_a), (&taker, mint_c), (&maker, env.mint_b)] {
+        let ix = spl_associated_token_account::instruction::create_associated_token_account(
+            &owner.pubkey(),
+            &owner.pubkey(),
+            &mint,
+            &spl_token::ID,
+        );
+        process(&mut env.ctx, &[ix], &[owner]).await.unwrap();
+    }
+
+    let asset = |mint| SwapAsset {
+        mint,
+        amount: 1,
+        deposited: false,
+    };
+    let swap_state = swap_state_key(&maker.pubkey(), 0);
+    let ix = create_swap_ix(
+        maker.pubkey(),
+        0,
+        taker.pubkey(),
+        vec![asset(env.mint_a), asset(mint_c)],
+        vec![asset(env.mint_b)],
+    );
+    let deposit_a = deposit_swap_asset_ix(maker.pubkey(), swap_state, env.mint_a, 0);
+    let deposit_c = deposit_swap_asset_ix(maker.pubkey(), swap_state, mint_c, 1);
+    process(&mut env.ctx, &[ix, deposit_a, deposit_c], &[&maker])
+        .await
+        .unwrap();
+
+    let assets = [
+        (env.mint_a, taker.pubkey()),
+        (mint_c, taker.pubkey()),
+        (env.mint_b, maker.pubkey()),
+    ];
+    let execute = execute_swap_ix(
+        taker.pubkey(),
+        maker.pubkey(),
+        taker.pubkey(),
+        swap_state,
+        &assets,
+    );
+    let result = process(&mut env.ctx, &[execute.clone()], &[&taker]).await;
+    assert_error(result, EscrowError::SwapNotFunded.into());
+
+    let deposit_b = deposit_swap_asset_ix(taker.pubkey(), swap_state, env.mint_b, 0);
+    process(&mut env.ctx, &[deposit_b, execute], &[&taker])
+        .await
+        .unwrap();
+    for (mint, owner) in assets {
+        let ata = get_associated_token_address(&owner, &mint);
+        assert_eq!(token_amount(&mut env.ctx, ata).await, 1);
+    }
+    assert!(!exists(&mut env.ctx, swap_state).await);
+}
+
+#[tokio::test]
+async fn buy_offer_above_the_ask_is_matched_with_the_surplus_refunded() {
+    let mut env = setup().await;
+    let buyer = Keypair::new();
+    let fund_buyer =
+        system_instruction::transfer(&env.ctx.payer.pubkey(), &buyer.pubkey(), 1_000_000_000);
+    process(&mut env.ctx, &[fund_buyer], &[]).await.unwrap();
+    let initializer = env.initializer.insecure_clone();
+    let ix = env.initialize_ix();
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+
+    let matcher = env.taker.insecure_clone();
+    let low = place_buy_offer_ix(buyer.pubkey(), 0, env.mint_a, TAKER_AMOUNT);
+    let high = place_buy_offer_ix(buyer.pubkey(), 1, env.mint_a, 2 * TAKER_AMOUNT);
+    process(&mut env.ctx, &[low, high], &[&buyer])
+        .await
+        .unwrap();
+
+    // The taker fee comes on top of the ask, so an offer of the ask is short.
+    let ix = match_escrows_ix(
+        matcher.pubkey(),
+        buyer.pubkey(),
+        0,
+        initializer.pubkey(),
+        env.mint_a,
+        0,
+        env.treasury,
+    );
+    let result = process(&mut env.ctx, &[ix], &[&matcher]).await;
+    assert_error(result, EscrowError::BuyOfferTooLow.into());
+
+    let initializer_before = lamports(&mut env.ctx, initializer.pubkey()).await;
+    let buyer_before = lamports(&mut env.ctx, buyer.pubkey()).await;
+    let ix = match_escrows_ix(
+        matcher.pubkey(),
+        buyer.pubkey(),
+        1,
+        initializer.pubkey(),
+        env.mint_a,
+        0,
+        env.treasury,
+    );
+    process(&mut env.ctx, &[ix], &[&matcher]).await.unwrap();
+    let buyer_nft = get_associated_token_address(&buyer.pubkey(), &env.mint_a);
+    assert_eq!(token_amount(&mut env.ctx, buyer_nft).await, 1);
+    let maker_fee = TAKER_AMOUNT * MAKER_FEE_BPS as u64 / 10_000;
+    let taker_fee = TAKER_AMOUNT * TAKER_FEE_BPS as u64 / 10_000;
+    assert!(
+        lamports(&mut env.ctx, initializer.pubkey()).await
+            >= initializer_before + TAKER_AMOUNT - maker_fee
+    );
+    assert!(
+        lamports(&mut env.ctx, buyer.pubkey()).await
+            >= buyer_before + TAKER_AMOUNT - taker_fee + INITIALIZER_AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn closed_bids_are_compacted_out_of_the_order_book() {
+    let mut env = setup().await;
+    let buyer = env.taker.insecure_clone();
+    let collection = Pubkey::new_unique();
+    let order_book = order_book_key(&collection);
+    let create = create_order_book_ix(buyer.pubkey(), collection);
+    let kept = place_collection_offer_ix(buyer.pubkey(), 0, collection, TAKER_AMOUNT);
+    let closed = place_collection_offer_ix(buyer.pubkey(), 1, collection, TAKER_AMOUNT);
+    let post_kept = post_bid_ix(buyer.pubkey(), 0, collection);
+    let post_closed = post_bid_ix(buyer.pubkey(), 1, collection);
+    process(
+        &mut env.ctx,
+        &[create, kept, closed, post_kept, post_closed.clone()],
+        &[&buyer],
+    )
+    .await
+    .unwrap();
+    let result = process(&mut env.ctx, &[post_closed], &[&buyer]).await;
+    assert_error(result, EscrowError::OrderAlreadyPosted.into());
+
+    let cancel = cancel_buy_offer_ix(buyer.pubkey(), 1);
+    let compact = compact_order_book_ix(
+        collection,
+        &[
+            buy_offer_key(&buyer.pubkey(), 0),
+            buy_offer_key(&buyer.pubkey(), 1),
+        ],
+    );
+    process(&mut env.ctx, &[cancel, compact], &[&buyer])
+        .await
+        .unwrap();
+
+    let account = env
+        .ctx
+        .banks_client
+        .get_account(order_book)
+        .await
+        .unwrap()
+        .unwrap();
+    let book = OrderBook::try_deserialize(&mut account.data.as_slice()).unwrap();
+    assert_eq!(book.bids.len(), 1);
+    assert_eq!(book.bids[0].account, buy_offer_key(&buyer.pubkey(), 0));
+}
+
+#[tokio::test]
+async fn deal_room_executes_only_with_every_approval() {
+    let mut env = setup().await;
+    let alice = env.initializer.insecure_clone();
+    let bob = env.taker.insecure_clone();
+    for (owner, mint) in [(&bob, env.mint_a), (&alice, env.mint_b)] {
+        let ix = spl_associated_token_account::instruction::create_associated_token_account(
+            &owner.pubkey(),
+            &owner.pubkey(),
+            &mint,
+            &spl_token::ID,
+        );
+        process(&mut env.ctx, &[ix], &[owner]).await.unwrap();
+    }
+
+    let deal_room = deal_room_key(&alice.pubkey(), 0);
+    let create = create_deal_room_ix(alice.pubkey(), 0, vec![alice.pubkey(), bob.pubkey()]);
+    let deposit = deposit_to_deal_ix(alice.pubkey(), deal_room, env.mint_a, 1, bob.pubkey());
+    let approve = approve_deal_ix(alice.pubkey(), deal_room);
+    process(&mut env.ctx, &[create, deposit, approve], &[&alice])
+        .await
+        .unwrap();
+
+    // Bob's deposit changes the deal, so Alice has to approve it again.
+    let deposit = deposit_to_deal_ix(bob.pubkey(), deal_room, env.mint_b, 1, alice.pubkey());
+    let approve = approve_deal_ix(bob.pubkey(), deal_room);
+    process(&mut env.ctx, &[deposit, approve], &[&bob])
+        .await
+        .unwrap();
+    let deposits = [
+        (env.mint_a, alice.pubkey(), bob.pubkey()),
+        (env.mint_b, bob.pubkey(), alice.pubkey()),
+    ];
+    let execute = execute_deal_ix(alice.pubkey(), deal_room, &deposits);
+    let result = process(&mut env.ctx, &[execute.clone()], &[]).await;
+    assert_error(result, EscrowError::DealNotApproved.into());
+
+    let approve = approve_deal_ix(alice.pubkey(), deal_room);
+    process(&mut env.ctx, &[approve, execute], &[&alice])
+        .await
+        .unwrap();
+    for (mint, _, recipient) in deposits {
+        let ata = get_associated_token_address(&recipient, &mint);
+        assert_eq!(token_amount(&mut env.ctx, ata).await, 1);
+    }
+    assert!(!exists(&mut env.ctx, deal_room).await);
+}
+
+#[tokio::test]
+async fn market_admin_sets_collection_fees_for_their_market_only() {
+    let mut env = setup().await;
+    let admin = env.taker.insecure_clone();
+    let ix = create_market_ix(admin.pubkey(), 7, Pubkey::new_unique());
+    process(&mut env.ctx, &[ix], &[&admin]).await.unwrap();
+
+    let ix = set_collection_fee_override_ix(admin.pubkey(), 7, env.mint_a, 0, 0);
+    process(&mut env.ctx, &[ix], &[&admin]).await.unwrap();
+    assert!(exists(&mut env.ctx, collection_fee_override_key(&env.mint_a, 7)).await);
+    assert!(!exists(&mut env.ctx, collection_fee_override_key(&env.mint_a, 0)).await);
+
+    // The default market's config still answers to its own admin.
+    let ix = set_collection_fee_override_ix(admin.pubkey(), 0, env.mint_a, 0, 0);
+    assert!(process(&mut env.ctx, &[ix], &[&admin]).await.is_err());
+}
+
+#[tokio::test]
+async fn collection_fee_override_is_charged_whenever_it_is_set() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    let taker = env.taker.insecure_clone();
+    let ix = env.initialize_ix();
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+
+    // Record a collection on the listing, as verified metadata would.
+    let collection = env.mint_b;
+    let escrow_state = escrow_state_key(&initializer.pubkey(), 0);
+    let mut account = env
+        .ctx
+        .banks_client
+        .get_account(escrow_state)
+        .await
+        .unwrap()
+        .unwrap();
+    let mut state = EscrowState::try_deserialize(&mut account.data.as_slice()).unwrap();
+    state.collection = collection;
+    let mut data = Vec::new();
+    state.try_serialize(&mut data).unwrap();
+    account.data[..data.len()].copy_from_slice(&data);
+    env.ctx.set_account(&escrow_state, &account.into());
+
+    let admin = env.ctx.payer.insecure_clone();
+    let ix = set_collection_fee_override_ix(admin.pubkey(), 0, collection, 300, 200);
+    process(&mut env.ctx, &[ix], &[&admin]).await.unwrap();
+
+    // The taker cannot price the fill without the collection's override.
+    let mut accounts = exchange_accounts(
+        taker.pubkey(),
+        initializer.pubkey(),
+        env.mint_a,
+        env.mint_b,
+        0,
+        env.treasury,
+    );
+    let ix = |accounts: &solana_nft_escrow::accounts::Exchange| Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts.to_account_metas(None),
+        data: solana_nft_escrow::instruction::Exchange {
+            memo: None,
+            terms_hash: None,
+            expected_price: None,
+            expected_mint: None,
+        }
+        .data(),
+    };
+    let result = process(&mut env.ctx, &[ix(&accounts)], &[&taker]).await;
+    assert_error(result, EscrowError::FeeOverrideMismatch.into());
+
+    accounts.collection_fee_override = collection_fee_override_key(&collection, 0);
+    process(&mut env.ctx, &[ix(&accounts)], &[&taker])
+        .await
+        .unwrap();
+    let maker_fee = TAKER_AMOUNT * 300 / 10_000;
+    let taker_fee = TAKER_AMOUNT * 200 / 10_000;
+    assert_eq!(
+        lamports(&mut env.ctx, env.treasury).await,
+        maker_fee + taker_fee
+    );
+}
+
+#[tokio::test]
+async fn collection_dao_takes_its_share_whenever_it_is_registered() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    let taker = env.taker.insecure_clone();
+    let ix = env.initialize_ix();
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+
+    // Record a collection on the listing, as verified metadata would.
+    let collection = env.mint_b;
+    let escrow_state = escrow_state_key(&initializer.pubkey(), 0);
+    let mut account = env
+        .ctx
+        .banks_client
+        .get_account(escrow_state)
+        .await
+        .unwrap()
+        .unwrap();
+    let mut state = EscrowState::try_deserialize(&mut account.data.as_slice()).unwrap();
+    state.collection = collection;
+    let mut data = Vec::new();
+    state.try_serialize(&mut data).unwrap();
+    account.data[..data.len()].copy_from_slice(&data);
+    env.ctx.set_account(&escrow_state, &account.into());
+
+    let admin = env.ctx.payer.insecure_clone();
+    let dao_treasury = Pubkey::new_unique();
+    let ix = register_collection_treasury_ix(admin.pubkey(), collection, dao_treasury, 5_000);
+    process(&mut env.ctx, &[ix], &[&admin]).await.unwrap();
+    // Both halves of the fee are below the rent-exempt minimum on their own.
+    env.ctx
+        .set_account(&dao_treasury, &funded(1_000_000_000).into());
+    env.ctx
+        .set_account(&env.treasury, &funded(1_000_000_000).into());
+
+    let mut accounts = exchange_accounts(
+        taker.pubkey(),
+        initializer.pubkey(),
+        env.mint_a,
+        env.mint_b,
+        0,
+        env.treasury,
+    );
+    let ix = |accounts: &solana_nft_escrow::accounts::Exchange| Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts.to_account_metas(None),
+        data: solana_nft_escrow::instruction::Exchange {
+            memo: None,
+            terms_hash: None,
+            expected_price: None,
+            expected_mint: None,
+        }
+        .data(),
+    };
+    accounts.collection_fee_override = collection_fee_override_key(&collection, 0);
+    // Neither the entry nor its treasury can be left out.
+    let result = process(&mut env.ctx, &[ix(&accounts)], &[&taker]).await;
+    assert_error(result, EscrowError::CollectionTreasuryMismatch.into());
+    accounts.collection_treasury = collection_treasury_key(&collection, 0);
+    let result = process(&mut env.ctx, &[ix(&accounts)], &[&taker]).await;
+    assert_error(result, EscrowError::MissingDaoTreasury.into());
+
+    let mut ix = ix(&accounts);
+    ix.accounts.push(solana_sdk::instruction::AccountMeta::new(
+        dao_treasury,
+        false,
+    ));
+    process(&mut env.ctx, &[ix], &[&taker]).await.unwrap();
+    let maker_fee = TAKER_AMOUNT * MAKER_FEE_BPS as u64 / 10_000;
+    let taker_fee = TAKER_AMOUNT * TAKER_FEE_BPS as u64 / 10_000;
+    let protocol_fee = maker_fee + taker_fee;
+    assert_eq!(
+        lamports(&mut env.ctx, dao_treasury).await,
+        1_000_000_000 + protocol_fee / 2
+    );
+    assert_eq!(
+        lamports(&mut env.ctx, env.treasury).await,
+        1_000_000_000 + protocol_fee - protocol_fee / 2
+    );
+}
+
+#[tokio::test]
+async fn collection_listing_is_priced_against_its_fresh_floor() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    let collection = env.mint_b;
+    set_collection_metadata(&mut env.ctx, &env.mint_a, &collection).await;
+    let floor_authority = env.ctx.payer.insecure_clone();
+    let set_floor = set_collection_floor_authority_ix(
+        floor_authority.pubkey(),
+        collection,
+        floor_authority.pubkey(),
+        0,
+    );
+    let update_floor =
+        update_collection_floor_ix(floor_authority.pubkey(), collection, TAKER_AMOUNT);
+    process(&mut env.ctx, &[set_floor, update_floor.clone()], &[])
+        .await
+        .unwrap();
+    set_max_floor_age(&mut env.ctx, 3_600).await;
+
+    let list =
+        |accounts: &solana_nft_escrow::accounts::Initialize, taker_amount: u64| Instruction {
+            program_id: solana_nft_escrow::ID,
+            accounts: accounts.to_account_metas(None),
+            data: solana_nft_escrow::instruction::Initialize {
+                initializer_amount: 0,
+                taker_amount,
+                donation_recipient: Pubkey::default(),
+                donation_bps: 0,
+                price_usd_cents: 0,
+                price_feed: Pubkey::default(),
+                settlement_delay_secs: 0,
+                note: String::new(),
+            }
+            .data(),
+        };
+    let mut accounts = initialize_accounts(
+        initializer.pubkey(),
+        env.taker.pubkey(),
+        env.mint_a,
+        env.mint_b,
+        0,
+        0,
+    );
+    // The seller cannot leave out their collection's floor.
+    let result = process(
+        &mut env.ctx,
+        &[list(&accounts, TAKER_AMOUNT - 1)],
+        &[&initializer],
+    )
+    .await;
+    assert_error(result, EscrowError::CollectionFloorMismatch.into());
+
+    accounts.collection_floor = collection_floor_key(&collection, 0);
+    let result = process(
+        &mut env.ctx,
+        &[list(&accounts, TAKER_AMOUNT - 1)],
+        &[&initializer],
+    )
+    .await;
+    assert_error(result, EscrowError::PriceBelowFloor.into());
+
+    // A floor nobody has refreshed within the config's limit is refused.
+    let mut clock: Clock = env.ctx.banks_client.get_sysvar().await.unwrap();
+    clock.unix_timestamp += 3_601;
+    env.ctx.set_sysvar(&clock);
+    let result = process(
+        &mut env.ctx,
+        &[list(&accounts, TAKER_AMOUNT)],
+        &[&initializer],
+    )
+    .await;
+    assert_error(result, EscrowError::StaleCollectionFloor.into());
+
+    process(&mut env.ctx, &[update_floor], &[]).await.unwrap();
+    process(
+        &mut env.ctx,
+        &[list(&accounts, TAKER_AMOUNT)],
+        &[&initializer],
+    )
+    .await
+    .unwrap();
+    let account = env
+        .ctx
+        .banks_client
+        .get_account(escrow_state_key(&initializer.pubkey(), 0))
+        .await
+        .unwrap()
+        .unwrap();
+    let state = EscrowState::try_deserialize(&mut account.data.as_slice()).unwrap();
+    assert_eq!(state.collection, collection);
+}
+
+#[tokio::test]
+async fn market_escrows_settle_only_against_their_market() {
+    let mut env = setup().await;
+    let market_treasury = Pubkey::new_unique();
+    let admin = env.taker.insecure_clone();
+    let ix = create_market_ix(admin.pubkey(), 7, market_treasury);
+    process(&mut env.ctx, &[ix], &[&admin]).await.unwrap();
+
+    let initializer = env.initializer.insecure_clone();
+    let ix = market_initialize_ix(
+        initializer.pubkey(),
+        env.taker.pubkey(),
+        env.mint_a,
+        env.mint_b,
+        7,
+        TAKER_AMOUNT,
+    );
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+    // The default market's first listing is still free.
+    assert!(!exists(&mut env.ctx, escrow_state_key(&initializer.pubkey(), 0)).await);
+
+    let escrow_state = market_escrow_state_key(&initializer.pubkey(), 0, 7);
+    let mut accounts = exchange_accounts(
+        env.taker.pubkey(),
+        initializer.pubkey(),
+        env.mint_a,
+        env.mint_b,
+        0,
+        env.treasury,
+    );
+    accounts.escrow_state = escrow_state;
+    accounts.vault = Some(vault_key(&escrow_state, &env.mint_a));
+    accounts.vault_authority = vault_authority_key(&escrow_state);
+    let ix = |accounts: &solana_nft_escrow::accounts::Exchange| Instruction {
+        program_id: solana_nft_escrow::ID,
+        accounts: accounts.to_account_metas(None),
+        data: solana_nft_escrow::instruction::Exchange {
+            memo: None,
+            terms_hash: None,
+            expected_price: None,
+            expected_mint: None,
+        }
+        .data(),
+    };
+    let taker = env.taker.insecure_clone();
+    let result = process(&mut env.ctx, &[ix(&accounts)], &[&taker]).await;
+    assert_error(result, EscrowError::MarketMismatch.into());
+
+    accounts.config = market_key(7);
+    accounts.treasury = market_treasury;
+    accounts.collection_fee_override = collection_fee_override_key(&Pubkey::default(), 7);
+    accounts.collection_treasury = collection_treasury_key(&Pubkey::default(), 7);
+    process(&mut env.ctx, &[ix(&accounts)], &[&taker])
+        .await
+        .unwrap();
+    // The market charges twice the default market's fees.
+    let maker_fee = TAKER_AMOUNT * 2 * MAKER_FEE_BPS as u64 / 10_000;
+    let taker_fee = TAKER_AMOUNT * 2 * TAKER_FEE_BPS as u64 / 10_000;
+    assert_eq!(
+        lamports(&mut env.ctx, market_treasury).await,
+        maker_fee + taker_fee
+    );
+    assert!(!exists(&mut env.ctx, escrow_state).await);
+}
+
+#[tokio::test]
+async fn signed_order_fills_only_for_the_sellers_signature() {
+    let mut env = setup().await;
+    let seller = env.initializer.insecure_clone();
+    let buyer = env.taker.insecure_clone();
+    let seller_nft = get_associated_token_address(&seller.pubkey(), &env.mint_a);
+    let approve = spl_token::instruction::approve(
+        &spl_token::ID,
+        &seller_nft,
+        &order_delegate_key(),
+        &seller.pubkey(),
+        &[],
+        1,
+    )
+    .unwrap();
+    process(&mut env.ctx, &[approve], &[&seller]).await.unwrap();
+
+    let message = signed_order_message(&seller.pubkey(), &env.mint_a, TAKER_AMOUNT, i64::MAX, 0);
+    let fill = fill_signed_order_ix(
+        buyer.pubkey(),
+        seller.pubkey(),
+        env.mint_a,
+        TAKER_AMOUNT,
+        0,
+        env.treasury,
+    );
+    // The buyer can't sign the seller's order for them.
+    let forged = ed25519_ix(&buyer, &message);
+    let result = process(&mut env.ctx, &[forged, fill.clone()], &[&buyer]).await;
+    assert_error(result, EscrowError::InvalidSignatureInstruction.into());
+
+    // A cancelled order stays unfillable.
+    let cancel = cancel_signed_order_ix(seller.pubkey(), 1);
+    process(&mut env.ctx, &[cancel], &[&seller]).await.unwrap();
+    let cancelled_message =
+        signed_order_message(&seller.pubkey(), &env.mint_a, TAKER_AMOUNT, i64::MAX, 1);
+    let cancelled_fill = fill_signed_order_ix(
+        buyer.pubkey(),
+        seller.pubkey(),
+        env.mint_a,
+        TAKER_AMOUNT,
+        1,
+        env.treasury,
+    );
+    let signature = ed25519_ix(&seller, &cancelled_message);
+    let result = process(&mut env.ctx, &[signature, cancelled_fill], &[&buyer]).await;
+    assert!(result.is_err());
+
+    let seller_before = lamports(&mut env.ctx, seller.pubkey()).await;
+    let signature = ed25519_ix(&seller, &message);
+    process(&mut env.ctx, &[signature, fill], &[&buyer])
+        .await
+        .unwrap();
+    let buyer_nft = get_associated_token_address(&buyer.pubkey(), &env.mint_a);
+    assert_eq!(token_amount(&mut env.ctx, buyer_nft).await, 1);
+    assert_eq!(token_amount(&mut env.ctx, seller_nft).await, 0);
+    let maker_fee = TAKER_AMOUNT * MAKER_FEE_BPS as u64 / 10_000;
+    let taker_fee = TAKER_AMOUNT * TAKER_FEE_BPS as u64 / 10_000;
+    assert_eq!(
+        lamports(&mut env.ctx, seller.pubkey()).await,
+        seller_before + TAKER_AMOUNT - maker_fee
+    );
+    assert_eq!(
+        lamports(&mut env.ctx, env.treasury).await,
+        maker_fee + taker_fee
+    );
+}
+
+#[tokio::test]
+async fn relayer_pays_for_a_sponsored_listing() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    let relayer = env.ctx.payer.pubkey();
+    let ix = sponsored_initialize_ix(
+        initializer.pubkey(),
+        relayer,
+        env.taker.pubkey(),
+        env.mint_a,
+        env.mint_b,
+        0,
+        TAKER_AMOUNT,
+    );
+    let initializer_before = lamports(&mut env.ctx, initializer.pubkey()).await;
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+
+    assert_eq!(
+        lamports(&mut env.ctx, initializer.pubkey()).await,
+        initializer_before
+    );
+    let escrow_state = escrow_state_key(&initializer.pubkey(), 0);
+    assert_eq!(
+        token_amount(&mut env.ctx, vault_key(&escrow_state, &env.mint_a)).await,
+        1
+    );
+}
+
+#[tokio::test]
+async fn session_key_lists_and_cancels_within_its_scope() {
+    let mut env = setup().await;
+    let owner = env.initializer.insecure_clone();
+    let game = Keypair::new();
+    let fund_game =
+        system_instruction::transfer(&env.ctx.payer.pubkey(), &game.pubkey(), 1_000_000_000);
+    let owner_nft = get_associated_token_address(&owner.pubkey(), &env.mint_a);
+    let approve = spl_token::instruction::approve(
+        &spl_token::ID,
+        &owner_nft,
+        &session_key(&owner.pubkey(), &game.pubkey()),
+        &owner.pubkey(),
+        &[],
+        1,
+    )
+    .unwrap();
+    let create = create_session_ix(owner.pubkey(), game.pubkey(), SESSION_SCOPE_LIST);
+    process(&mut env.ctx, &[fund_game, approve, create], &[&owner])
+        .await
+        .unwrap();
+
+    let owner_before = lamports(&mut env.ctx, owner.pubkey()).await;
+    let list = list_with_session_ix(
+        game.pubkey(),
+        owner.pubkey(),
+        env.taker.pubkey(),
+        env.mint_a,
+        env.mint_b,
+        0,
+        TAKER_AMOUNT,
+    );
+    process(&mut env.ctx, &[list], &[&game]).await.unwrap();
+    assert_eq!(lamports(&mut env.ctx, owner.pubkey()).await, owner_before);
+    let escrow_state = escrow_state_key(&owner.pubkey(), 0);
+    assert_eq!(
+        token_amount(&mut env.ctx, vault_key(&escrow_state, &env.mint_a)).await,
+        1
+    );
+
+    let cancel = cancel_with_session_ix(game.pubkey(), owner.pubkey(), env.mint_a, 0);
+    let result = process(&mut env.ctx, &[cancel.clone()], &[&game]).await;
+    assert_error(result, EscrowError::SessionScopeDenied.into());
+
+    let revoke = revoke_session_ix(owner.pubkey(), game.pubkey());
+    let create = create_session_ix(
+        owner.pubkey(),
+        game.pubkey(),
+        SESSION_SCOPE_LIST | SESSION_SCOPE_CANCEL,
+    );
+    process(&mut env.ctx, &[revoke, create], &[&owner])
+        .await
+        .unwrap();
+    process(&mut env.ctx, &[cancel], &[&game]).await.unwrap();
+    assert_eq!(token_amount(&mut env.ctx, owner_nft).await, 1);
+    assert!(!exists(&mut env.ctx, escrow_state).await);
+}
+
+#[tokio::test]
+async fn private_listing_fills_only_with_a_taker_allowance() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    let taker = env.taker.insecure_clone();
+    let escrow_state = escrow_state_key(&initializer.pubkey(), 0);
+    let ix = env.initialize_ix();
+    let private = set_private_fills_ix(initializer.pubkey(), escrow_state, true);
+    process(&mut env.ctx, &[ix, private], &[&initializer])
+        .await
+        .unwrap();
+
+    let mut exchange = env.exchange_ix(&taker.pubkey());
+    exchange
+        .accounts
+        .push(solana_sdk::instruction::AccountMeta::new_readonly(
+            solana_sdk::sysvar::instructions::ID,
+            false,
+        ));
+    // An allowance for a lower price than the listing doesn't verify.
+    let lowball = taker_allowance_message(&escrow_state, &taker.pubkey(), TAKER_AMOUNT / 2);
+    let allowance = ed25519_ix(&initializer, &lowball);
+    let result = process(&mut env.ctx, &[allowance, exchange.clone()], &[&taker]).await;
+    assert_error(result, EscrowError::InvalidSignatureInstruction.into());
+
+    let message = taker_allowance_message(&escrow_state, &taker.pubkey(), TAKER_AMOUNT);
+    let allowance = ed25519_ix(&initializer, &message);
+    process(&mut env.ctx, &[allowance, exchange], &[&taker])
+        .await
+        .unwrap();
+    assert!(!exists(&mut env.ctx, escrow_state).await);
+}
+
+#[tokio::test]
+async fn new_listing_waits_out_the_minimum_age() {
+    let mut env = setup().await;
+    set_min_listing_age(&mut env.ctx, 600).await;
+    let initializer = env.initializer.insecure_clone();
+    let taker = env.taker.insecure_clone();
+    let ix = env.initialize_ix();
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+
+    let ix = env.exchange_ix(&taker.pubkey());
+    let result = process(&mut env.ctx, &[ix.clone()], &[&taker]).await;
+    assert_error(result, EscrowError::ListingTooNew.into());
+
+    let mut clock: Clock = env.ctx.banks_client.get_sysvar().await.unwrap();
+    clock.unix_timestamp += 600;
+    env.ctx.set_sysvar(&clock);
+    process(&mut env.ctx, &[ix], &[&taker]).await.unwrap();
+    let taker_nft = get_associated_token_address(&taker.pubkey(), &env.mint_a);
+    assert_eq!(token_amount(&mut env.ctx, taker_nft).await, 1);
+}
+
+#[tokio::test]
+async fn rate_limited_wallet_lists_once_per_window() {
+    let mut env = setup().await;
+    set_rate_limits(&mut env.ctx, 3600, 1, 0).await;
+    let initializer = env.initializer.insecure_clone();
+    let listing_ix = |nonce| {
+        let accounts = solana_nft_escrow::accounts::Initialize {
+            wallet_activity: Some(wallet_activity_key(&initializer.pubkey())),
+            ..initialize_accounts(
+                initializer.pubkey(),
+                env.taker.pubkey(),
+                env.mint_a,
+                env.mint_b,
+                nonce,
+                0,
+            )
+        };
+        Instruction {
+            accounts: accounts.to_account_metas(None),
+            ..initialize_ix(
+                initializer.pubkey(),
+                env.taker.pubkey(),
+                env.mint_a,
+                env.mint_b,
+                nonce,
+                INITIALIZER_AMOUNT,
+                TAKER_AMOUNT,
+            )
+        }
+    };
+    let first_listing = listing_ix(0);
+    let second_listing = listing_ix(1);
+
+    let ix = env.initialize_ix();
+    let result = process(&mut env.ctx, &[ix], &[&initializer]).await;
+    assert_error(result, EscrowError::MissingWalletActivity.into());
+
+    let open = open_wallet_activity_ix(initializer.pubkey());
+    process(&mut env.ctx, &[open, first_listing], &[&initializer])
+        .await
+        .unwrap();
+    let ix = env.cancel_ix();
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+
+    let result = process(&mut env.ctx, &[second_listing], &[&initializer]).await;
+    assert_error(result, EscrowError::RateLimited.into());
+}
+
+#[tokio::test]
+async fn audit_trail_hashes_the_escrow_history() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    let taker = env.taker.insecure_clone();
+    let escrow_state = escrow_state_key(&initializer.pubkey(), 0);
+    let audit_trail = audit_trail_key(&escrow_state);
+
+    let ix = env.initialize_ix();
+    let open = open_audit_trail_ix(initializer.pubkey(), 0);
+    process(&mut env.ctx, &[ix, open], &[&initializer])
+        .await
+        .unwrap();
+    let clock: Clock = env.ctx.banks_client.get_sysvar().await.unwrap();
+    let slot = clock.slot;
+    let mut head = audit_entry_hash(
+        &[0; 32],
+        AUDIT_OPENED,
+        &initializer.pubkey(),
+        TAKER_AMOUNT,
+        slot,
+    );
+
+    // Once the trail is open, an exchange that leaves it out is refused.
+    let ix = env.exchange_ix(&taker.pubkey());
+    let result = process(&mut env.ctx, &[ix], &[&taker]).await;
+    assert_error(result, EscrowError::MissingAuditTrail.into());
+
+    let ix = close_audit_trail_ix(initializer.pubkey(), 0);
+    let result = process(&mut env.ctx, &[ix], &[&initializer]).await;
+    assert_error(result, EscrowError::AuditTrailInUse.into());
+
+    let accounts = solana_nft_escrow::accounts::Exchange {
+        audit_trail: Some(audit_trail),
+        ..exchange_accounts(
+            taker.pubkey(),
+            initializer.pubkey(),
+            env.mint_a,
+            env.mint_b,
+            0,
+            env.treasury,
+        )
+    };
+    let ix = Instruction {
+        accounts: accounts.to_account_metas(None),
+        ..env.exchange_ix(&taker.pubkey())
+    };
+    process(&mut env.ctx, &[ix], &[&taker]).await.unwrap();
+    head = audit_entry_hash(&head, AUDIT_EXCHANGED, &taker.pubkey(), TAKER_AMOUNT, slot);
+
+    // The trail survives the escrow it describes.
+    assert!(!exists(&mut env.ctx, escrow_state).await);
+    let account = env
+        .ctx
+        .banks_client
+        .get_account(audit_trail)
+        .await
+        .unwrap()
+        .unwrap();
+    let trail = AuditTrail::try_deserialize(&mut account.data.as_slice()).unwrap();
+    assert_eq!(trail.entries, 2);
+    assert_eq!(trail.head, head);
+
+    let ix = close_audit_trail_ix(initializer.pubkey(), 0);
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+    assert!(!exists(&mut env.ctx, audit_trail).await);
+}
+
+#[tokio::test]
+async fn reserve_proof_fails_for_an_empty_custody_account() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    let ix = env.initialize_ix();
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+    let escrow_state = escrow_state_key(&initializer.pubkey(), 0);
+
+    let ix = prove_reserves_ix(&[(escrow_state, vault_key(&escrow_state, &env.mint_a))]);
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+
+    // The seller's own, now empty, account is not the escrow's custody.
+    let seller_account = get_associated_token_address(&initializer.pubkey(), &env.mint_a);
+    let ix = prove_reserves_ix(&[(escrow_state, seller_account)]);
+    let result = process(&mut env.ctx, &[ix], &[&initializer]).await;
+    assert_error(result, EscrowError::ReserveShortfall.into());
+}
+
+#[tokio::test]
+async fn state_crate_decodes_the_escrow_the_program_wrote() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    let ix = env.initialize_ix();
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+
+    let account = env
+        .ctx
+        .banks_client
+        .get_account(escrow_state_key(&initializer.pubkey(), 0))
+        .await
+        .unwrap()
+        .unwrap();
+    let state = EscrowState::try_deserialize(&mut account.data.as_slice()).unwrap();
+    let decoded = solana_nft_escrow_state::EscrowState::decode(&account.data).unwrap();
+    assert_eq!(decoded.initializer_key, state.initializer_key.to_bytes());
+    assert_eq!(decoded.taker_key, state.taker_key.to_bytes());
+    assert_eq!(
+        decoded.initializer_deposit_mint_account,
+        env.mint_a.to_bytes()
+    );
+    assert_eq!(decoded.taker_amount, TAKER_AMOUNT);
+    assert_eq!(decoded.created_at, state.created_at);
+    assert_eq!(decoded.audited, state.audited);
+    assert_eq!(decoded.tag, state.tag);
+    assert_eq!(decoded.expires_at, state.expires_at);
+    assert_eq!(decoded.relist_decay_bps, state.relist_decay_bps);
+    assert_eq!(decoded.rent_payer, state.rent_payer.to_bytes());
+    assert_eq!(decoded.awaiting_deposit, state.awaiting_deposit);
+    assert_eq!(decoded.collection, state.collection.to_bytes());
+    assert_eq!(decoded.note, state.note);
+
+    // Any other account is refused, not misread.
+    let account = env
+        .ctx
+        .banks_client
+        .get_account(pda(&[b"config"]))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        solana_nft_escrow_state::EscrowState::decode(&account.data),
+        Err(solana_nft_escrow_state::DecodeError::Discriminator)
+    );
+}
+
+#[test]
+fn state_crate_decodes_every_escrow_field() {
+    // Every field gets its own value, so a field the decoder reads out of
+    // place, or one missing from either struct, fails here.
+    let key = |n: u8| Pubkey::new_from_array([n; 32]);
+    let state = EscrowState {
+        nonce: 1,
+        initializer_key: key(2),
+        taker_key: key(3),
+        initializer_deposit_token_account: key(4),
+        initializer_receive_token_account: key(5),
+        initializer_deposit_mint_account: key(6),
+        initializer_receive_mint_account: key(7),
+        initializer_amount: 8,
+        taker_amount: 9,
+        vault_authority_bump: 10,
+        donation_recipient: key(11),
+        donation_bps: 12,
+        price_usd_cents: 13,
+        price_feed: key(14),
+        vault_rent_lamports: 15,
+        delegated: true,
+        reserved_by: key(17),
+        reserved_amount: 18,
+        reservation_deposit: 19,
+        reserved_until: -20,
+        settlement_delay_secs: -21,
+        pending_taker: key(22),
+        pending_taker_deposit_token_account: key(23),
+        pending_proceeds: 24,
+        settle_after: -25,
+        disputed: true,
+        plan_installments: 27,
+        plan_interval_secs: -28,
+        plan_forfeit_bps: 29,
+        plan_taker: key(30),
+        rent_to_own_payment: 31,
+        rent_to_own_period_secs: -32,
+        rent_to_own_target: 33,
+        rent_to_own_renter: key(34),
+        loan_principal: 35,
+        loan_apr_bps: 36,
+        loan_duration_secs: -37,
+        loan_lender: key(38),
+        vesting_duration_secs: -39,
+        vesting_cliff_secs: -40,
+        vesting_start: -41,
+        vesting_total: 42,
+        vesting_claimed: 43,
+        bnpl_installments: 44,
+        bnpl_interval_secs: -45,
+        bnpl_collateral_bps: 46,
+        bnpl_buyer: key(47),
+        edition_price: 48,
+        edition_max_prints: 49,
+        editions_printed: 50,
+        edition_price_step: 51,
+        edition_price_growth_bps: 52,
+        gate_mint: key(53),
+        gate_min_amount: 54,
+        gate_governance_program: key(55),
+        gift_recipient: key(56),
+        gift_claim_deadline: -57,
+        htlc_taker: key(58),
+        htlc_hash: [59; 32],
+        htlc_timeout: -60,
+        wormhole_program: key(61),
+        wormhole_emitter_chain: 62,
+        wormhole_emitter: [63; 32],
+        wormhole_amount: 64,
+        ring_recipient: key(65),
+        ring_partner: key(66),
+        market_id: 67,
+        integrator: key(68),
+        integrator_namespace: [69; 32],
+        integrator_fee_recipient: key(70),
+        integrator_fee_bps: 71,
+        private_fills: true,
+        created_at: -73,
+        audited: true,
+        tag: [75; 8],
+        terms_hash: [76; 32],
+        expires_at: -77,
+        relist_decay_bps: 78,
+        relist_window_secs: -79,
+        rent_payer: key(80),
+        awaiting_deposit: true,
+        collection: key(81),
+        note: "note".to_string(),
+        terms_uri: "terms_uri".to_string(),
+    };
+    let mut data = Vec::new();
+    state.try_serialize(&mut data).unwrap();
+    assert_eq!(
+        solana_nft_escrow_state::EscrowState::decode(&data).unwrap(),
+        solana_nft_escrow_state::EscrowState {
+            nonce: state.nonce,
+            initializer_key: state.initializer_key.to_bytes(),
+            taker_key: state.taker_key.to_bytes(),
+            initializer_deposit_token_account: state.initializer_deposit_token_account.to_bytes(),
+            initializer_receive_token_account: state.initializer_receive_token_account.to_bytes(),
+            initializer_deposit_mint_account: state.initializer_deposit_mint_account.to_bytes(),
+            initializer_receive_mint_account: state.initializer_receive_mint_account.to_bytes(),
+            initializer_amount: state.initializer_amount,
+            taker_amount: state.taker_amount,
+            vault_authority_bump: state.vault_authority_bump,
+            donation_recipient: state.donation_recipient.to_bytes(),
+            donation_bps: state.donation_bps,
+            price_usd_cents: state.price_usd_cents,
+            price_feed: state.price_feed.to_bytes(),
+            vault_rent_lamports: state.vault_rent_lamports,
+            delegated: state.delegated,
+            reserved_by: state.reserved_by.to_bytes(),
+            reserved_amount: state.reserved_amount,
+            reservation_deposit: state.reservation_deposit,
+            reserved_until: state.reserved_until,
+            settlement_delay_secs: state.settlement_delay_secs,
+            pending_taker: state.pending_taker.to_bytes(),
+            pending_taker_deposit_token_account: state
+                .pending_taker_deposit_token_account
+                .to_bytes(),
+            pending_proceeds: state.pending_proceeds,
+            settle_after: state.settle_after,
+            disputed: state.disputed,
+            plan_installments: state.plan_installments,
+            plan_interval_secs: state.plan_interval_secs,
+            plan_forfeit_bps: state.plan_forfeit_bps,
+            plan_taker: state.plan_taker.to_bytes(),
+            rent_to_own_payment: state.rent_to_own_payment,
+            rent_to_own_period_secs: state.rent_to_own_period_secs,
+            rent_to_own_target: state.rent_to_own_target,
+            rent_to_own_renter: state.rent_to_own_renter.to_bytes(),
+            loan_principal: state.loan_principal,
+            loan_apr_bps: state.loan_apr_bps,
+            loan_duration_secs: state.loan_duration_secs,
+            loan_lender: state.loan_lender.to_bytes(),
+            vesting_duration_secs: state.vesting_duration_secs,
+            vesting_cliff_secs: state.vesting_cliff_secs,
+            vesting_start: state.vesting_start,
+            vesting_total: state.vesting_total,
+            vesting_claimed: state.vesting_claimed,
+            bnpl_installments: state.bnpl_installments,
+            bnpl_interval_secs: state.bnpl_interval_secs,
+            bnpl_collateral_bps: state.bnpl_collateral_bps,
+            bnpl_buyer: state.bnpl_buyer.to_bytes(),
+            edition_price: state.edition_price,
+            edition_max_prints: state.edition_max_prints,
+            editions_printed: state.editions_printed,
+            edition_price_step: state.edition_price_step,
+            edition_price_growth_bps: state.edition_price_growth_bps,
+            gate_mint: state.gate_mint.to_bytes(),
+            gate_min_amount: state.gate_min_amount,
+            gate_governance_program: state.gate_governance_program.to_bytes(),
+            gift_recipient: state.gift_recipient.to_bytes(),
+            gift_claim_deadline: state.gift_claim_deadline,
+            htlc_taker: state.htlc_taker.to_bytes(),
+            htlc_hash: state.htlc_hash,
+            htlc_timeout: state.htlc_timeout,
+            wormhole_program: state.wormhole_program.to_bytes(),
+            wormhole_emitter_chain: state.wormhole_emitter_chain,
+            wormhole_emitter: state.wormhole_emitter,
+            wormhole_amount: state.wormhole_amount,
+            ring_recipient: state.ring_recipient.to_bytes(),
+            ring_partner: state.ring_partner.to_bytes(),
+            market_id: state.market_id,
+            integrator: state.integrator.to_bytes(),
+            integrator_namespace: state.integrator_namespace,
+            integrator_fee_recipient: state.integrator_fee_recipient.to_bytes(),
+            integrator_fee_bps: state.integrator_fee_bps,
+            private_fills: state.private_fills,
+            created_at: state.created_at,
+            audited: state.audited,
+            tag: state.tag,
+            terms_hash: state.terms_hash,
+            expires_at: state.expires_at,
+            relist_decay_bps: state.relist_decay_bps,
+            relist_window_secs: state.relist_window_secs,
+            rent_payer: state.rent_payer.to_bytes(),
+            awaiting_deposit: state.awaiting_deposit,
+            collection: state.collection.to_bytes(),
+            note: state.note.clone(),
+            terms_uri: state.terms_uri.clone(),
+        }
+    );
+}
+
+#[tokio::test]
+async fn exchange_forwards_its_memo_to_the_memo_program() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    let taker = env.taker.insecure_clone();
+    let ix = env.initialize_ix();
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+
+    let exchange = |memo: &str, memo_program: Option<Pubkey>| {
+        let mut accounts = exchange_accounts(
+            taker.pubkey(),
+            initializer.pubkey(),
+            env.mint_a,
+            env.mint_b,
+            0,
+            env.treasury,
+        )
+        .to_account_metas(None);
+        accounts.extend(memo_program.map(|memo_program| {
+            solana_sdk::instruction::AccountMeta::new_readonly(memo_program, false)
+        }));
+        Instruction {
+            program_id: solana_nft_escrow::ID,
+            accounts,
+            data: solana_nft_escrow::instruction::Exchange {
+                memo: Some(memo.to_string()),
+                terms_hash: None,
+                expected_price: None,
+                expected_mint: None,
+            }
+            .data(),
+        }
+    };
+
+    let ix = exchange("travel rule ref 42", None);
+    let result = process(&mut env.ctx, &[ix], &[&taker]).await;
+    assert_error(result, EscrowError::MissingMemoProgram.into());
+
+    let ix = exchange(&"x".repeat(MAX_MEMO_LEN + 1), Some(Memo::id()));
+    let result = process(&mut env.ctx, &[ix], &[&taker]).await;
+    assert_error(result, EscrowError::MemoTooLong.into());
+
+    let ix = exchange("travel rule ref 42", Some(Memo::id()));
+    process(&mut env.ctx, &[ix], &[&taker]).await.unwrap();
+    assert!(!exists(&mut env.ctx, escrow_state_key(&initializer.pubkey(), 0)).await);
+}
+
+#[tokio::test]
+async fn seller_rewrites_the_listing_note() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    let ix = env.initialize_ix();
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+
+    let ix = update_terms_ix(initializer.pubkey(), 0, &"x".repeat(MAX_NOTE_LEN + 1));
+    let result = process(&mut env.ctx, &[ix], &[&initializer]).await;
+    assert_error(result, EscrowError::NoteTooLong.into());
+
+    let ix = update_terms_ix(initializer.pubkey(), 0, "OTC deal #42");
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+    let account = env
+        .ctx
+        .banks_client
+        .get_account(escrow_state_key(&initializer.pubkey(), 0))
+        .await
+        .unwrap()
+        .unwrap();
+    let state = EscrowState::try_deserialize(&mut account.data.as_slice()).unwrap();
+    assert_eq!(state.note, "OTC deal #42");
+}
+
+#[tokio::test]
+async fn listing_tag_sits_at_its_published_offset() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    let ix = env.initialize_ix();
+    let tag = *b"gaming\0\0";
+    let set_tag = set_listing_tag_ix(initializer.pubkey(), 0, tag);
+    process(&mut env.ctx, &[ix, set_tag], &[&initializer])
+        .await
+        .unwrap();
+
+    let account = env
+        .ctx
+        .banks_client
+        .get_account(escrow_state_key(&initializer.pubkey(), 0))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(account.data[ESCROW_TAG_OFFSET..ESCROW_TAG_OFFSET + 8], tag);
+}
+
+#[tokio::test]
+async fn listing_with_terms_fills_only_for_a_taker_acknowledging_them() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    let taker = env.taker.insecure_clone();
+    let terms_hash = hash::hash(b"OTC agreement v1").to_bytes();
+    let ix = env.initialize_ix();
+    let set_terms = set_terms_ix(initializer.pubkey(), 0, "ar://terms", terms_hash);
+    process(&mut env.ctx, &[ix, set_terms], &[&initializer])
+        .await
+        .unwrap();
+
+    // Terms are fixed once listed.
+    let ix = set_terms_ix(initializer.pubkey(), 0, "ar://other", [1; 32]);
+    let result = process(&mut env.ctx, &[ix], &[&initializer]).await;
+    assert_error(result, EscrowError::TermsAlreadySet.into());
+
+    let plain_exchange = env.exchange_ix(&taker.pubkey());
+    let exchange = |terms_hash| Instruction {
+        data: solana_nft_escrow::instruction::Exchange {
+            memo: None,
+            terms_hash,
+            expected_price: None,
+            expected_mint: None,
+        }
+        .data(),
+        ..plain_exchange.clone()
+    };
+    for acknowledged in [None, Some([1; 32])] {
+        let ix = exchange(acknowledged);
+        let result = process(&mut env.ctx, &[ix], &[&taker]).await;
+        assert_error(result, EscrowError::TermsNotAcknowledged.into());
+    }
+
+    let ix = exchange(Some(terms_hash));
+    process(&mut env.ctx, &[ix], &[&taker]).await.unwrap();
+    assert!(!exists(&mut env.ctx, escrow_state_key(&initializer.pubkey(), 0)).await);
+}
+
+#[tokio::test]
+async fn reserved_listing_with_terms_finalizes_once_they_are_acknowledged() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    let taker = env.taker.insecure_clone();
+    let terms_hash = hash::hash(b"OTC agreement v1").to_bytes();
+    let ix = env.initialize_ix();
+    let set_terms = set_terms_ix(initializer.pubkey(), 0, "ar://terms", terms_hash);
+    process(&mut env.ctx, &[ix, set_terms], &[&initializer])
+        .await
+        .unwrap();
+    let ix = reserve_ix(taker.pubkey(), initializer.pubkey(), 0);
+    process(&mut env.ctx, &[ix], &[&taker]).await.unwrap();
+
+    let plain_finalize = finalize_ix(
+        taker.pubkey(),
+        initializer.pubkey(),
+        env.mint_a,
+        env.mint_b,
+        0,
+        env.treasury,
+    );
+    let result = process(&mut env.ctx, &[plain_finalize.clone()], &[&taker]).await;
+    assert_error(result, EscrowError::TermsNotAcknowledged.into());
+
+    let ix = Instruction {
+        data: solana_nft_escrow::instruction::Finalize {
+            terms_hash: Some(terms_hash),
+            expected_price: None,
+            expected_mint: None,
+        }
+        .data(),
+        ..plain_finalize
+    };
+    process(&mut env.ctx, &[ix], &[&taker]).await.unwrap();
+    assert!(!exists(&mut env.ctx, escrow_state_key(&initializer.pubkey(), 0)).await);
+}
+
+#[tokio::test]
+async fn template_listing_takes_its_terms_from_the_template() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    let taker = env.taker.insecure_clone();
+    let ix = create_listing_template_ix(initializer.pubkey(), 7, TAKER_AMOUNT, "store stock");
+    let list = initialize_from_template_ix(
+        initializer.pubkey(),
+        taker.pubkey(),
+        env.mint_a,
+        env.mint_b,
+        0,
+        7,
+    );
+    process(&mut env.ctx, &[ix, list], &[&initializer])
+        .await
+        .unwrap();
+
+    let escrow_state = escrow_state_key(&initializer.pubkey(), 0);
+    let account = env
+        .ctx
+        .banks_client
+        .get_account(escrow_state)
+        .await
+        .unwrap()
+        .unwrap();
+    let state = EscrowState::try_deserialize(&mut account.data.as_slice()).unwrap();
+    assert_eq!(state.taker_amount, TAKER_AMOUNT);
+    assert_eq!(state.note, "store stock");
+    assert_eq!(
+        token_amount(&mut env.ctx, vault_key(&escrow_state, &env.mint_a)).await,
+        1
+    );
+
+    let ix = env.exchange_ix(&taker.pubkey());
+    process(&mut env.ctx, &[ix], &[&taker]).await.unwrap();
+    assert!(!exists(&mut env.ctx, escrow_state).await);
+}
+
+#[tokio::test]
+async fn transferred_listing_pays_the_new_initializer() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    let taker = env.taker.insecure_clone();
+    let new_initializer = Keypair::new();
+    let ix = env.initialize_ix();
+    let transfer = transfer_listing_ix(initializer.pubkey(), new_initializer.pubkey(), 0);
+    process(
+        &mut env.ctx,
+        &[ix, transfer],
+        &[&initializer, &new_initializer],
+    )
+    .await
+    .unwrap();
+
+    // The old wallet no longer controls the listing, nor the rent it put up.
+    let ix = env.cancel_ix();
+    assert!(process(&mut env.ctx, &[ix], &[&initializer]).await.is_err());
+    let account = env
+        .ctx
+        .banks_client
+        .get_account(escrow_state_key(&initializer.pubkey(), 0))
+        .await
+        .unwrap()
+        .unwrap();
+    let state = EscrowState::try_deserialize(&mut account.data.as_slice()).unwrap();
+    assert_eq!(state.rent_payer, new_initializer.pubkey());
+
+    let accounts = solana_nft_escrow::accounts::Exchange {
+        initializer: new_initializer.pubkey(),
+        initializer_receive_token_account: get_associated_token_address(
+            &new_initializer.pubkey(),
+            &env.mint_b,
+        ),
+        ..exchange_accounts(
+            taker.pubkey(),
+            initializer.pubkey(),
+            env.mint_a,
+            env.mint_b,
+            0,
+            env.treasury,
+        )
+    };
+    let ix = Instruction {
+        accounts: accounts.to_account_metas(None),
+        ..env.exchange_ix(&taker.pubkey())
+    };
+    process(&mut env.ctx, &[ix], &[&taker]).await.unwrap();
+    assert_eq!(
+        token_amount(
+            &mut env.ctx,
+            get_associated_token_address(&new_initializer.pubkey(), &env.mint_b)
+        )
+        .await,
+        1
+    );
+    assert!(lamports(&mut env.ctx, new_initializer.pubkey()).await > 0);
+}
+
+#[tokio::test]
+async fn transferred_listing_keeps_its_sponsor_and_refuses_delegation() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    let new_initializer = Keypair::new();
+    let sponsor = Keypair::new();
+    let fund_sponsor =
+        system_instruction::transfer(&env.ctx.payer.pubkey(), &sponsor.pubkey(), 1_000_000_000);
+    let ix = sponsored_initialize_ix(
+        initializer.pubkey(),
+        sponsor.pubkey(),
+        env.taker.pubkey(),
+        env.mint_a,
+        env.mint_b,
+        0,
+        TAKER_AMOUNT,
+    );
+    process(&mut env.ctx, &[fund_sponsor, ix], &[&initializer, &sponsor])
+        .await
+        .unwrap();
+
+    // Mark the listing delegated, as initialize_delegated would.
+    let escrow_state = escrow_state_key(&initializer.pubkey(), 0);
+    let mut account = env
+        .ctx
+        .banks_client
+        .get_account(escrow_state)
+        .await
+        .unwrap()
+        .unwrap();
+    let mut state = EscrowState::try_deserialize(&mut account.data.as_slice()).unwrap();
+    state.delegated = true;
+    let mut data = Vec::new();
+    state.try_serialize(&mut data).unwrap();
+    account.data[..data.len()].copy_from_slice(&data);
+    env.ctx.set_account(&escrow_state, &account.clone().into());
+    let transfer = transfer_listing_ix(initializer.pubkey(), new_initializer.pubkey(), 0);
+    let result = process(
+        &mut env.ctx,
+        &[transfer.clone()],
+        &[&initializer, &new_initializer],
+    )
+    .await;
+    assert_error(result, EscrowError::TransferDelegated.into());
+
+    state.delegated = false;
+    let mut data = Vec::new();
+    state.try_serialize(&mut data).unwrap();
+    account.data[..data.len()].copy_from_slice(&data);
+    env.ctx.set_account(&escrow_state, &account.into());
+    process(&mut env.ctx, &[transfer], &[&initializer, &new_initializer])
+        .await
+        .unwrap();
+    let account = env
+        .ctx
+        .banks_client
+        .get_account(escrow_state)
+        .await
+        .unwrap()
+        .unwrap();
+    let state = EscrowState::try_deserialize(&mut account.data.as_slice()).unwrap();
+    assert_eq!(state.initializer_key, new_initializer.pubkey());
+    assert_eq!(state.rent_payer, sponsor.pubkey());
+}
+
+#[tokio::test]
+async fn initializer_reassigns_the_designated_taker() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    let new_taker = Pubkey::new_unique();
+    let ix = env.initialize_ix();
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+
+    // Only the initializer may move the listing to another taker.
+    let taker = env.taker.insecure_clone();
+    let ix = Instruction {
+        accounts: solana_nft_escrow::accounts::ReassignTaker {
+            initializer: taker.pubkey(),
+            new_taker,
+            escrow_state: escrow_state_key(&initializer.pubkey(), 0),
+        }
+        .to_account_metas(None),
+        ..reassign_taker_ix(initializer.pubkey(), new_taker, 0)
+    };
+    assert!(process(&mut env.ctx, &[ix], &[&taker]).await.is_err());
+
+    let ix = reassign_taker_ix(initializer.pubkey(), new_taker, 0);
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+    let account = env
+        .ctx
+        .banks_client
+        .get_account(escrow_state_key(&initializer.pubkey(), 0))
+        .await
+        .unwrap()
+        .unwrap();
+    let state = EscrowState::try_deserialize(&mut account.data.as_slice()).unwrap();
+    assert_eq!(state.taker_key, new_taker);
+}
+
+#[tokio::test]
+async fn extended_listing_stops_filling_once_it_expires() {
+    let mut env = setup().await;
+    set_max_listing_duration(&mut env.ctx, 3600).await;
+    let initializer = env.initializer.insecure_clone();
+    let taker = env.taker.insecure_clone();
+    let ix = env.initialize_ix();
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+
+    let clock: Clock = env.ctx.banks_client.get_sysvar().await.unwrap();
+    let now = clock.unix_timestamp;
+    let ix = extend_expiry_ix(initializer.pubkey(), 0, now + 7200);
+    let result = process(&mut env.ctx, &[ix], &[&initializer]).await;
+    assert_error(result, EscrowError::ExpiryTooFar.into());
+
+    let ix = extend_expiry_ix(initializer.pubkey(), 0, now + 600);
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+    // An expiry is only ever pushed further out.
+    let ix = extend_expiry_ix(initializer.pubkey(), 0, now + 300);
+    let result = process(&mut env.ctx, &[ix], &[&initializer]).await;
+    assert_error(result, EscrowError::InvalidExpiry.into());
+
+    let mut clock = clock;
+    clock.unix_timestamp += 601;
+    env.ctx.set_sysvar(&clock);
+    let ix = env.exchange_ix(&taker.pubkey());
+    let result = process(&mut env.ctx, &[ix], &[&taker]).await;
+    assert_error(result, EscrowError::ListingExpired.into());
+
+    // The seller can still take the NFT back.
+    let ix = env.cancel_ix();
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+    let initializer_nft = get_associated_token_address(&initializer.pubkey(), &env.mint_a);
+    assert_eq!(token_amount(&mut env.ctx, initializer_nft).await, 1);
+}
+
+#[tokio::test]
+async fn keeper_relists_an_expired_listing_at_a_discount() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    let keeper = env.taker.insecure_clone();
+    let ix = env.initialize_ix();
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+
+    let escrow_state = escrow_state_key(&initializer.pubkey(), 0);
+    let mut clock: Clock = env.ctx.banks_client.get_sysvar().await.unwrap();
+    let expiry = extend_expiry_ix(initializer.pubkey(), 0, clock.unix_timestamp + 600);
+    let opt_in = set_relist_decay_ix(initializer.pubkey(), 0, 1_000, 3600);
+    process(&mut env.ctx, &[expiry, opt_in], &[&initializer])
+        .await
+        .unwrap();
+
+    let ix = relist_decayed_ix(keeper.pubkey(), escrow_state);
+    let result = process(&mut env.ctx, &[ix.clone()], &[&keeper]).await;
+    assert_error(result, EscrowError::ListingNotExpired.into());
+
+    clock.unix_timestamp += 601;
+    env.ctx.set_sysvar(&clock);
+    process(&mut env.ctx, &[ix], &[&keeper]).await.unwrap();
+    let account = env
+        .ctx
+        .banks_client
+        .get_account(escrow_state)
+        .await
+        .unwrap()
+        .unwrap();
+    let state = EscrowState::try_deserialize(&mut account.data.as_slice()).unwrap();
+    assert_eq!(state.taker_amount, TAKER_AMOUNT - TAKER_AMOUNT / 10);
+    assert_eq!(state.expires_at, clock.unix_timestamp + 3600);
+    assert!(!state.is_expired(clock.unix_timestamp));
+}
+
+#[tokio::test]
+async fn expired_buy_offer_is_swept_back_to_the_buyer() {
+    let mut env = setup().await;
+    let buyer = env.taker.insecure_clone();
+    let mut clock: Clock = env.ctx.banks_client.get_sysvar().await.unwrap();
+    let ix = place_expiring_buy_offer_ix(
+        buyer.pubkey(),
+        0,
+        env.mint_a,
+        TAKER_AMOUNT,
+        clock.unix_timestamp + 600,
+    );
+    process(&mut env.ctx, &[ix], &[&buyer]).await.unwrap();
+
+    let buy_offer = buy_offer_key(&buyer.pubkey(), 0);
+    let ix = sweep_expired_offers_ix(&[(buy_offer, buyer.pubkey())]);
+    let result = process(&mut env.ctx, &[ix.clone()], &[]).await;
+    assert_error(result, EscrowError::BuyOfferNotExpired.into());
+
+    clock.unix_timestamp += 601;
+    env.ctx.set_sysvar(&clock);
+    let offer_lamports = lamports(&mut env.ctx, buy_offer).await;
+    let buyer_before = lamports(&mut env.ctx, buyer.pubkey()).await;
+    process(&mut env.ctx, &[ix], &[]).await.unwrap();
+    assert!(!exists(&mut env.ctx, buy_offer).await);
+    assert_eq!(
+        lamports(&mut env.ctx, buyer.pubkey()).await,
+        buyer_before + offer_lamports
+    );
+}
+
+#[tokio::test]
+async fn cancel_closes_the_listing_once_its_vault_is_gone() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    let ix = env.initialize_ix();
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+
+    let escrow_state = escrow_state_key(&initializer.pubkey(), 0);
+    let vault = vault_key(&escrow_state, &env.mint_a);
+    let mut ix = env.cancel_ix();
+    // `vault` is left out and the closed vault's address passed instead.
+    ix.accounts[2].pubkey = solana_nft_escrow::ID;
+    ix.accounts[2].is_writable = false;
+    ix.accounts[3].pubkey = vault;
+    let result = process(&mut env.ctx, &[ix.clone()], &[&initializer]).await;
+    assert_error(result, EscrowError::VaultNotClosed.into());
+
+    env.ctx.set_account(&vault, &AccountSharedData::default());
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+    assert!(!exists(&mut env.ctx, escrow_state).await);
+}
+
+#[tokio::test]
+async fn keeper_cancels_a_listing_once_it_expires() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    let keeper = env.taker.insecure_clone();
+    let ix = env.initialize_ix();
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+
+    let escrow_state = escrow_state_key(&initializer.pubkey(), 0);
+    let mut clock: Clock = env.ctx.banks_client.get_sysvar().await.unwrap();
+    let ix = extend_expiry_ix(initializer.pubkey(), 0, clock.unix_timestamp + 600);
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+
+    // Scheduled too early, the call does nothing.
+    let ix = cancel_expired_ix(keeper.pubkey(), initializer.pubkey(), env.mint_a, 0);
+    process(&mut env.ctx, &[ix.clone()], &[&keeper])
+        .await
+        .unwrap();
+    assert!(exists(&mut env.ctx, escrow_state).await);
+
+    clock.unix_timestamp += 601;
+    env.ctx.set_sysvar(&clock);
+    process(&mut env.ctx, &[ix], &[&keeper]).await.unwrap();
+    assert!(!exists(&mut env.ctx, escrow_state).await);
+    let initializer_nft = get_associated_token_address(&initializer.pubkey(), &env.mint_a);
+    assert_eq!(token_amount(&mut env.ctx, initializer_nft).await, 1);
+}
+
+#[tokio::test]
+async fn taker_balance_is_checked_when_the_listing_fills() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    let taker = env.taker.insecure_clone();
+    // Leave the taker short of the price; listing for them still works.
+    let balance = lamports(&mut env.ctx, taker.pubkey()).await;
+    let drain = system_instruction::transfer(
+        &taker.pubkey(),
+        &env.ctx.payer.pubkey(),
+        balance - TAKER_AMOUNT / 2,
+    );
+    process(&mut env.ctx, &[drain], &[&taker]).await.unwrap();
+    let ix = env.initialize_ix();
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+
+    let ix = env.exchange_ix(&taker.pubkey());
+    let result = process(&mut env.ctx, &[ix], &[&taker]).await;
+    assert_error(result, EscrowError::InsufficientTakerFunds.into());
+}
+
+#[tokio::test]
+async fn sponsored_listing_returns_its_rent_to_the_sponsor() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    let sponsor = Keypair::new();
+    let fund_sponsor =
+        system_instruction::transfer(&env.ctx.payer.pubkey(), &sponsor.pubkey(), 1_000_000_000);
+    let ix = sponsored_initialize_ix(
+        initializer.pubkey(),
+        sponsor.pubkey(),
+        env.taker.pubkey(),
+        env.mint_a,
+        env.mint_b,
+        0,
+        TAKER_AMOUNT,
+    );
+    process(&mut env.ctx, &[fund_sponsor, ix], &[&initializer, &sponsor])
+        .await
+        .unwrap();
+
+    let escrow_state = escrow_state_key(&initializer.pubkey(), 0);
+    let ix = env.cancel_ix();
+    let result = process(&mut env.ctx, &[ix.clone()], &[&initializer]).await;
+    assert_error(result, EscrowError::MissingRentPayer.into());
+
+    let listing_rent = lamports(&mut env.ctx, escrow_state).await
+        + lamports(&mut env.ctx, vault_key(&escrow_state, &env.mint_a)).await;
+    let sponsor_before = lamports(&mut env.ctx, sponsor.pubkey()).await;
+    let mut ix = ix;
+    ix.accounts.push(solana_sdk::instruction::AccountMeta::new(
+        sponsor.pubkey(),
+        false,
+    ));
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+    assert_eq!(
+        lamports(&mut env.ctx, sponsor.pubkey()).await,
+        sponsor_before + listing_rent
+    );
+}
+
+#[tokio::test]
+async fn sponsored_cancel_refunds_the_deposit_to_the_seller() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    let sponsor = Keypair::new();
+    let fund_sponsor =
+        system_instruction::transfer(&env.ctx.payer.pubkey(), &sponsor.pubkey(), 1_000_000_000);
+    let ix = sponsored_initialize_ix(
+        initializer.pubkey(),
+        sponsor.pubkey(),
+        env.taker.pubkey(),
+        env.mint_a,
+        env.mint_b,
+        INITIALIZER_AMOUNT,
+        TAKER_AMOUNT,
+    );
+    process(&mut env.ctx, &[fund_sponsor, ix], &[&initializer, &sponsor])
+        .await
+        .unwrap();
+
+    let escrow_state = escrow_state_key(&initializer.pubkey(), 0);
+    let listing_rent = lamports(&mut env.ctx, escrow_state).await
+        + lamports(&mut env.ctx, vault_key(&escrow_state, &env.mint_a)).await
+        - INITIALIZER_AMOUNT;
+    let initializer_before = lamports(&mut env.ctx, initializer.pubkey()).await;
+    let sponsor_before = lamports(&mut env.ctx, sponsor.pubkey()).await;
+    let mut ix = env.cancel_ix();
+    ix.accounts.push(solana_sdk::instruction::AccountMeta::new(
+        sponsor.pubkey(),
+        false,
+    ));
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+
+    assert_eq!(
+        lamports(&mut env.ctx, initializer.pubkey()).await,
+        initializer_before + INITIALIZER_AMOUNT
+    );
+    assert_eq!(
+        lamports(&mut env.ctx, sponsor.pubkey()).await,
+        sponsor_before + listing_rent
+    );
+}
+
+#[tokio::test]
+async fn offline_signed_fill_rejects_changed_listing_terms() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    let taker = env.taker.insecure_clone();
+    let ix = env.initialize_ix();
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+
+    let plain_exchange = env.exchange_ix(&taker.pubkey());
+    let exchange = |expected_price, expected_mint| Instruction {
+        data: solana_nft_escrow::instruction::Exchange {
+            memo: None,
+            terms_hash: None,
+            expected_price: Some(expected_price),
+            expected_mint: Some(expected_mint),
+        }
+        .data(),
+        ..plain_exchange.clone()
+    };
+    let ix = exchange(TAKER_AMOUNT, env.mint_b);
+    let result = process(&mut env.ctx, &[ix], &[&taker]).await;
+    assert_error(result, EscrowError::UnexpectedMint.into());
+    let ix = exchange(TAKER_AMOUNT - 1, env.mint_a);
+    let result = process(&mut env.ctx, &[ix], &[&taker]).await;
+    assert_error(result, EscrowError::PriceAboveExpected.into());
+
+    let ix = exchange(TAKER_AMOUNT, env.mint_a);
+    process(&mut env.ctx, &[ix], &[&taker]).await.unwrap();
+    let taker_nft = get_associated_token_address(&taker.pubkey(), &env.mint_a);
+    assert_eq!(token_amount(&mut env.ctx, taker_nft).await, 1);
+}
+
+#[tokio::test]
+async fn finalize_checks_the_takers_expected_price_and_mint() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    let taker = env.taker.insecure_clone();
+    let ix = env.initialize_ix();
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+    let ix = reserve_ix(taker.pubkey(), initializer.pubkey(), 0);
+    process(&mut env.ctx, &[ix], &[&taker]).await.unwrap();
+
+    let plain_finalize = finalize_ix(
+        taker.pubkey(),
+        initializer.pubkey(),
+        env.mint_a,
+        env.mint_b,
+        0,
+        env.treasury,
+    );
+    let finalize = |expected_price, expected_mint| Instruction {
+        data: solana_nft_escrow::instruction::Finalize {
+            terms_hash: None,
+            expected_price: Some(expected_price),
+            expected_mint: Some(expected_mint),
+        }
+        .data(),
+        ..plain_finalize.clone()
+    };
+    let ix = finalize(TAKER_AMOUNT, env.mint_b);
+    let result = process(&mut env.ctx, &[ix], &[&taker]).await;
+    assert_error(result, EscrowError::UnexpectedMint.into());
+    let ix = finalize(TAKER_AMOUNT - 1, env.mint_a);
+    let result = process(&mut env.ctx, &[ix], &[&taker]).await;
+    assert_error(result, EscrowError::PriceAboveExpected.into());
+
+    let ix = finalize(TAKER_AMOUNT, env.mint_a);
+    process(&mut env.ctx, &[ix], &[&taker]).await.unwrap();
+    let taker_nft = get_associated_token_address(&taker.pubkey(), &env.mint_a);
+    assert_eq!(token_amount(&mut env.ctx, taker_nft).await, 1);
+}
+
+#[tokio::test]
+async fn split_listing_fills_only_after_its_deposit() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    let taker = env.taker.insecure_clone();
+    let ix = create_listing_ix(
+        initializer.pubkey(),
+        taker.pubkey(),
+        env.mint_a,
+        env.mint_b,
+        0,
+        TAKER_AMOUNT,
+    );
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+
+    let exchange = env.exchange_ix(&taker.pubkey());
+    let result = process(&mut env.ctx, &[exchange.clone()], &[&taker]).await;
+    assert_error(result, EscrowError::ListingNotDeposited.into());
+    // Nor can it be reserved or borrowed against with nothing in the vault.
+    let ix = reserve_ix(taker.pubkey(), initializer.pubkey(), 0);
+    let result = process(&mut env.ctx, &[ix], &[&taker]).await;
+    assert_error(result, EscrowError::ListingNotDeposited.into());
+    let ix = request_loan_ix(
+        initializer.pubkey(),
+        0,
+        LOAN_PRINCIPAL,
+        LOAN_APR_BPS,
+        LOAN_DURATION_SECS,
+    );
+    let result = process(&mut env.ctx, &[ix], &[&initializer]).await;
+    assert_error(result, EscrowError::ListingNotDeposited.into());
+
+    let ix = deposit_ix(initializer.pubkey(), env.mint_a, 0);
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+    process(&mut env.ctx, &[exchange], &[&taker]).await.unwrap();
+    let taker_nft = get_associated_token_address(&taker.pubkey(), &env.mint_a);
+    assert_eq!(token_amount(&mut env.ctx, taker_nft).await, 1);
+}
+
+#[tokio::test]
+async fn quote_route_lists_where_the_payment_goes() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    let ix = env.initialize_ix();
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+
+    let ix = get_quote_route_ix(
+        env.taker.pubkey(),
+        escrow_state_key(&initializer.pubkey(), 0),
+    );
+    let data = simulate_return_data(&mut env.ctx, &[ix]).await;
+    let route = QuoteRoute::try_from_slice(&data).unwrap();
+    let maker_fee = TAKER_AMOUNT * MAKER_FEE_BPS as u64 / 10_000;
+    let taker_fee = TAKER_AMOUNT * TAKER_FEE_BPS as u64 / 10_000;
+    assert_eq!(route.version, QUOTE_ROUTE_VERSION);
+    assert_eq!(route.total, TAKER_AMOUNT + taker_fee);
+    let payouts: Vec<(Pubkey, u64)> = route
+        .payouts
+        .iter()
+        .map(|payout| (payout.recipient, payout.amount))
+        .collect();
+    assert_eq!(
+        payouts,
+        [
+            (initializer.pubkey(), TAKER_AMOUNT - maker_fee),
+            (env.treasury, maker_fee + taker_fee),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn router_exchange_needs_the_total_delivered_in_wsol() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    let taker = env.taker.insecure_clone();
+    let ix = env.initialize_ix();
+    process(&mut env.ctx, &[ix], &[&initializer]).await.unwrap();
+
+    let data = solana_nft_escrow::instruction::ExchangeViaRouter {
+        terms_hash: None,
+        expected_price: None,
+    }
+    .data();
+    let ix = Instruction {
+        data: data.clone(),
+        ..env.exchange_ix(&taker.pubkey())
+    };
+    let result = process(&mut env.ctx, &[ix], &[&taker]).await;
+    assert_error(result, EscrowError::MissingRouterPayment.into());
+
+    // The swap delivered nothing to the taker's wSOL account.
+    let taker_wsol = get_associated_token_address(&taker.pubkey(), &spl_token::native_mint::ID);
+    let create_wsol = spl_associated_token_account::instruction::create_associated_token_account(
+        &taker.pubkey(),
+        &taker.pubkey(),
+        &spl_token::native_mint::ID,
+        &spl_token::ID,
+    );
+    let mut ix = Instruction {
+        data,
+        ..env.exchange_ix(&taker.pubkey())
+    };
+    ix.accounts
+        .push(solana_sdk::instruction::AccountMeta::new(taker_wsol, false));
+    let result = process(&mut env.ctx, &[create_wsol, ix], &[&taker]).await;
+    assert_error(result, EscrowError::RouterPaymentShort.into());
+}
+
+#[tokio::test]
+async fn listing_refuses_a_frozen_deposit_or_a_freezable_mint() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+
+    let deposit = get_associated_token_address(&initializer.pubkey(), &env.mint_a);
+    let mut account = env
+        .ctx
+        .banks_client
+        .get_account(deposit)
+        .await
+        .unwrap()
+        .unwrap();
+    let mut token = spl_token::state::Account::unpack(&account.data).unwrap();
+    token.state = spl_token::state::AccountState::Frozen;
+    spl_token::state::Account::pack(token, &mut account.data).unwrap();
+    env.ctx.set_account(&deposit, &account.clone().into());
+    let ix = env.initialize_ix();
+    let result = process(&mut env.ctx, &[ix], &[&initializer]).await;
+    assert_error(result, EscrowError::DepositAccountFrozen.into());
+
+    // Thawed, but any wallet holding the freeze authority could lock the vault.
+    token.state = spl_token::state::AccountState::Initialized;
+    spl_token::state::Account::pack(token, &mut account.data).unwrap();
+    env.ctx.set_account(&deposit, &account.into());
+    let mut account = env
+        .ctx
+        .banks_client
+        .get_account(env.mint_a)
+        .await
+        .unwrap()
+        .unwrap();
+    let mut mint = spl_token::state::Mint::unpack(&account.data).unwrap();
+    mint.freeze_authority = COption::Some(Pubkey::new_unique());
+    spl_token::state::Mint::pack(mint, &mut account.data).unwrap();
+    env.ctx.set_account(&env.mint_a, &account.into());
+    let ix = env.initialize_ix();
+    let result = process(&mut env.ctx, &[ix], &[&initializer]).await;
+    assert_error(result, EscrowError::MintCanFreezeVault.into());
+}
+
+#[tokio::test]
+async fn template_and_session_listings_refuse_a_freezable_mint() {
+    let mut env = setup().await;
+    let initializer = env.initializer.insecure_clone();
+    let game = Keypair::new();
+    let fund_game =
+        system_instruction::transfer(&env.ctx.payer.pubkey(), &game.pubkey(), 1_000_000_000);
+    let approve = spl_token::instruction::approve(
+        &spl_token::ID,
+        &get_associated_token_address(&initializer.pubkey(), &env.mint_a),
+        &session_key(&initializer.pubkey(), &game.pubkey()),
+        &initializer.pubkey(),
+        &[],
+        1,
+    )
+    .unwrap();
+    let session = create_session_ix(initializer.pubkey(), game.pubkey(), SESSION_SCOPE_LIST);
+    let template = create_listing_template_ix(initializer.pubkey(), 7, TAKER_AMOUNT, "");
+    process(
+        &mut env.ctx,
+        &[fund_game, approve, session, template],
+        &[&initializer],
+    )
+    .await
+    .unwrap();
+
+    let mut account = env
+        .ctx
+        .banks_client
+        .get_account(env.mint_a)
+        .await
+        .unwrap()
+        .unwrap();
+    let mut mint = spl_token::state::Mint::unpack(&account.data).unwrap();
+    mint.freeze_authority = COption::Some(Pubkey::new_unique());
+    spl_token::state::Mint::pack(mint, &mut account.data).unwrap();
+    env.ctx.set_account(&env.mint_a, &account.into());
+
+    let list = initialize_from_template_ix(
+        initializer.pubkey(),
+        env.taker.pubkey(),
+        env.mint_a,
+        env.mint_b,
+        0,
+        7,
+    );
+    let result = process(&mut env.ctx, &[list], &[&initializer]).await;
+    assert_error(result, EscrowError::MintCanFreezeVault.into());
+
+    let list = list_with_session_ix(
+        game.pubkey(),
+        initializer.pubkey(),
+        env.taker.pubkey(),
+        env.mint_a,
+        env.mint_b,
+        0,
+        TAKER_AMOUNT,
+    );
+    let result = process(&mut env.ctx, &[list], &[&game]).await;
+    assert_error(result, EscrowError::MintCanFreezeVault.into());
+}
+
+#[tokio::test]
+async fn airdrop_releases_each_nft_to_its_leaf() {
+    let mut env = setup().await;
+    let project = env.initializer.insecure_clone();
+    let claimant = env.taker.insecure_clone();
+    let other_leaf = airdrop_leaf(&Pubkey::new_unique(), &Pubkey::new_unique());
+    let root = airdrop_node(airdrop_leaf(&claimant.pubkey(), &env.mint_a), other_leaf);
+    let airdrop = airdrop_key(&project.pubkey(), &root);
+
+    let ix = create_airdrop_ix(project.pubkey(), root, 0);
+    let deposit = deposit_airdrop_nft_ix(project.pubkey(), airdrop, env.mint_a);
+    process(&mut env.ctx, &[ix, deposit], &[&project])
+        .await
+        .unwrap();
+    assert_eq!(
+        token_amount(&mut env.ctx, vault_key(&airdrop, &env.mint_a)).await,
+        1
+    );
+
+    // The project is not named by the leaf, so its proof does not verify.
+    let ix = claim_airdrop_ix(project.pubkey(), airdrop, env.mint_a, vec![other_leaf]);
+    let result = process(&mut env.ctx, &[ix], &[&project]).await;
+    assert_error(result, EscrowError::InvalidMerkleProof.into());
+
+    let ix = claim_airdrop_ix(claimant.pubkey(), airdrop, env.mint_a, vec![other_leaf]);
+    process(&mut env.ctx, &[ix.clone()], &[&claimant])
+        .await
+        .unwrap();
+    let claimant_nft = get_associated_token_address(&claimant.pubkey(), &env.mint_a);
+    assert_eq!(token_amount(&mut env.ctx, claimant_nft).await, 1);
+    assert!(!exists(&mut env.ctx, vault_key(&airdrop, &env.mint_a)).await);
+    assert!(process(&mut env.ctx, &[ix], &[&claimant]).await.is_err());
+
+    let ix = close_airdrop_ix(project.pubkey(), airdrop);
+    process(&mut env.ctx, &[ix], &[&project]).await.unwrap();
+    assert!(!exists(&mut env.ctx, airdrop).await);
+}
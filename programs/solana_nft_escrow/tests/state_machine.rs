@@ -0,0 +1,291 @@
+//! Property tests that run random sequences of escrow operations against a
+//! model of who holds which NFT and check the on-chain state after every step.
+//!
+//! Run `anchor build` first so `target/deploy/solana_nft_escrow.so` exists.
+
+mod common;
+
+use std::collections::HashMap;
+
+use anchor_lang::prelude::Pubkey;
+use anchor_spl::associated_token::get_associated_token_address;
+use common::*;
+use proptest::prelude::*;
+use solana_program_test::ProgramTestContext;
+use solana_sdk::signature::{Keypair, Signer};
+
+const WALLETS: usize = 3;
+const MINTS: usize = 3;
+
+#[derive(Clone, Debug)]
+enum Op {
+    Initialize {
+        initializer: usize,
+        mint: usize,
+        receive_mint: usize,
+        taker_amount: u64,
+    },
+    Exchange {
+        taker: usize,
+        escrow: usize,
+    },
+    Cancel {
+        signer: usize,
+        escrow: usize,
+    },
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        (0..WALLETS, 0..MINTS, 0..MINTS, 1_000_000..100_000_000u64).prop_map(
+            |(initializer, mint, receive_mint, taker_amount)| Op::Initialize {
+                initializer,
+                mint,
+                receive_mint,
+                taker_amount,
+            }
+        ),
+        (0..WALLETS, any::<usize>()).prop_map(|(taker, escrow)| Op::Exchange { taker, escrow }),
+        (0..WALLETS, any::<usize>()).prop_map(|(signer, escrow)| Op::Cancel { signer, escrow }),
+    ]
+}
+
+struct OpenEscrow {
+    initializer: usize,
+    nonce: u64,
+    mint: usize,
+    receive_mint: usize,
+}
+
+struct Model {
+    /// NFTs held per (wallet, mint).
+    holdings: HashMap<(usize, usize), u64>,
+    open: Vec<OpenEscrow>,
+    nonces: [u64; WALLETS],
+}
+
+struct Harness {
+    ctx: ProgramTestContext,
+    wallets: Vec<Keypair>,
+    mints: Vec<Pubkey>,
+    treasury: Pubkey,
+    model: Model,
+}
+
+impl Harness {
+    async fn new() -> Self {
+        let wallets: Vec<Keypair> = (0..WALLETS).map(|_| Keypair::new()).collect();
+        let treasury = Pubkey::new_unique();
+        let mut program_test = program_test();
+        for wallet in &wallets {
+            program_test.add_account(wallet.pubkey(), funded(100_000_000_000));
+        }
+        let mut ctx = program_test.start_with_context().await;
+
+        let mut mints = vec![];
+        let mut holdings = HashMap::new();
+        for mint_index in 0..MINTS {
+            let mint = create_mint(&mut ctx).await;
+            for (wallet_index, wallet) in wallets.iter().enumerate() {
+                mint_one_to(&mut ctx, &mint, &wallet.pubkey()).await;
+                holdings.insert((wallet_index, mint_index), 1);
+            }
+            mints.push(mint);
+        }
+        initialize_config(&mut ctx, treasury).await;
+
+        Harness {
+            ctx,
+            wallets,
+            mints,
+            treasury,
+            model: Model {
+                holdings,
+                open: vec![],
+                nonces: [0; WALLETS],
+            },
+        }
+    }
+
+    /// Total lamports across every account the program can touch. The payer
+    /// only pays transaction fees, so it is left out.
+    async fn lamports_in_play(&mut self) -> u64 {
+        let mut keys = vec![self.treasury];
+        for wallet in &self.wallets {
+            let wallet = wallet.pubkey();
+            keys.push(wallet);
+            keys.push(pda(&[b"listing_counter", wallet.as_ref()]));
+            for mint in &self.mints {
+                keys.push(get_associated_token_address(&wallet, mint));
+            }
+        }
+        for (index, wallet) in self.wallets.iter().enumerate() {
+            for nonce in 0..self.model.nonces[index] {
+                let escrow_state = escrow_state_key(&wallet.pubkey(), nonce);
+                keys.push(escrow_state);
+                keys.extend(self.mints.iter().map(|mint| vault_key(&escrow_state, mint)));
+            }
+        }
+        let mut total = 0;
+        for key in keys {
+            total += lamports(&mut self.ctx, key).await;
+        }
+        total
+    }
+
+    async fn apply(&mut self, op: &Op) {
+        let before = self.lamports_in_play().await;
+        let (ix, signer, expected) = match *op {
+            Op::Initialize {
+                initializer,
+                mint,
+                receive_mint,
+                taker_amount,
+            } => {
+                let nonce = self.model.nonces[initializer];
+                let ix = initialize_ix(
+                    self.wallets[initializer].pubkey(),
+                    self.wallets[(initializer + 1) % WALLETS].pubkey(),
+                    self.mints[mint],
+                    self.mints[receive_mint],
+                    nonce,
+                    1_000_000,
+                    taker_amount,
+                );
+                let expected = self.model.holdings[&(initializer, mint)] > 0;
+                (ix, initializer, expected)
+            }
+            Op::Exchange { taker, escrow } => {
+                if self.model.open.is_empty() {
+                    return;
+                }
+                let escrow = &self.model.open[escrow % self.model.open.len()];
+                let ix = exchange_ix(
+                    self.wallets[taker].pubkey(),
+                    self.wallets[escrow.initializer].pubkey(),
+                    self.mints[escrow.mint],
+                    self.mints[escrow.receive_mint],
+                    escrow.nonce,
+                    self.treasury,
+                );
+                let expected = self.model.holdings[&(taker, escrow.receive_mint)] > 0;
+                (ix, taker, expected)
+            }
+            Op::Cancel { signer, escrow } => {
+                if self.model.open.is_empty() {
+                    return;
+                }
+                let escrow = &self.model.open[escrow % self.model.open.len()];
+                let ix = cancel_ix(
+                    self.wallets[signer].pubkey(),
+                    self.mints[escrow.mint],
+                    escrow.nonce,
+                );
+                (ix, signer, signer == escrow.initializer)
+            }
+        };
+
+        let signer_keypair = self.wallets[signer].insecure_clone();
+        let result = process(&mut self.ctx, &[ix], &[&signer_keypair]).await;
+        assert_eq!(result.is_ok(), expected, "{op:?} -> {result:?}");
+        if result.is_ok() {
+            self.record(op);
+        }
+
+        // Fees and rent only ever move lamports between the accounts in play.
+        let after = self.lamports_in_play().await;
+        assert_eq!(before, after, "lamports not conserved by {op:?}");
+
+        self.check_state().await;
+    }
+
+    fn record(&mut self, op: &Op) {
+        let model = &mut self.model;
+        match *op {
+            Op::Initialize {
+                initializer,
+                mint,
+                receive_mint,
+                ..
+            } => {
+                *model.holdings.get_mut(&(initializer, mint)).unwrap() -= 1;
+                model.open.push(OpenEscrow {
+                    initializer,
+                    nonce: model.nonces[initializer],
+                    mint,
+                    receive_mint,
+                });
+                model.nonces[initializer] += 1;
+            }
+            Op::Exchange { taker, escrow } => {
+                let escrow = model.open.remove(escrow % model.open.len());
+                *model
+                    .holdings
+                    .get_mut(&(taker, escrow.receive_mint))
+                    .unwrap() -= 1;
+                *model
+                    .holdings
+                    .get_mut(&(escrow.initializer, escrow.receive_mint))
+                    .unwrap() += 1;
+                *model.holdings.get_mut(&(taker, escrow.mint)).unwrap() += 1;
+            }
+            Op::Cancel { escrow, .. } => {
+                let escrow = model.open.remove(escrow % model.open.len());
+                *model
+                    .holdings
+                    .get_mut(&(escrow.initializer, escrow.mint))
+                    .unwrap() += 1;
+            }
+        }
+    }
+
+    async fn check_state(&mut self) {
+        // No NFT is ever duplicated or lost: wallets hold what the model says
+        // and every other copy sits in the vault of an open escrow.
+        for mint_index in 0..MINTS {
+            let mint = self.mints[mint_index];
+            for wallet_index in 0..WALLETS {
+                let ata = get_associated_token_address(&self.wallets[wallet_index].pubkey(), &mint);
+                assert_eq!(
+                    token_amount(&mut self.ctx, ata).await,
+                    self.model.holdings[&(wallet_index, mint_index)]
+                );
+            }
+        }
+
+        // State and vault are opened and closed together.
+        for (wallet_index, wallet) in self.wallets.iter().enumerate() {
+            for nonce in 0..self.model.nonces[wallet_index] {
+                let open = self
+                    .model
+                    .open
+                    .iter()
+                    .find(|escrow| escrow.initializer == wallet_index && escrow.nonce == nonce)
+                    .map(|escrow| escrow.mint);
+                let state = escrow_state_key(&wallet.pubkey(), nonce);
+                assert_eq!(exists(&mut self.ctx, state).await, open.is_some());
+                for mint_index in 0..MINTS {
+                    let vault = vault_key(&state, &self.mints[mint_index]);
+                    let expected = u64::from(open == Some(mint_index));
+                    assert_eq!(exists(&mut self.ctx, vault).await, expected == 1);
+                    assert_eq!(token_amount(&mut self.ctx, vault).await, expected);
+                }
+            }
+        }
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(24))]
+
+    #[test]
+    fn escrow_operations_preserve_invariants(ops in prop::collection::vec(op(), 1..16)) {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let mut harness = Harness::new().await;
+            for op in &ops {
+                harness.apply(op).await;
+            }
+        });
+    }
+}
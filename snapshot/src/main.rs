@@ -0,0 +1,144 @@
+//! Dumps the open listings and buy offers of the deployed program, for
+//! analytics and backups.
+//!
+//! From this directory: `cargo run -- [rpc_url] [json|csv]`.
+//!
+//! JSON is printed on stdout. CSV writes `listings.csv` and `offers.csv` to
+//! the current directory instead, one row per account.
+
+use std::fs;
+
+use anchor_lang::prelude::Pubkey;
+use anchor_lang::AccountDeserialize;
+use serde_json::{json, Value};
+use solana_client::rpc_client::RpcClient;
+use solana_nft_escrow::{BuyOffer, EscrowState};
+use solana_sdk::commitment_config::CommitmentConfig;
+
+const DEFAULT_RPC_URL: &str = "http://127.0.0.1:8899";
+
+const LISTING_COLUMNS: &[&str] = &[
+    "escrowState",
+    "marketId",
+    "seller",
+    "mint",
+    "receiveMint",
+    "taker",
+    "takerAmount",
+    "priceUsdCents",
+    "delegated",
+    "reservedBy",
+    "createdAt",
+    "expiresAt",
+];
+const OFFER_COLUMNS: &[&str] = &[
+    "buyOffer",
+    "buyer",
+    "offerId",
+    "mint",
+    "collection",
+    "maxPrice",
+    "expiresAt",
+    "lamports",
+];
+
+type Error = Box<dyn std::error::Error>;
+
+fn listing(address: &Pubkey, state: &EscrowState) -> Value {
+    json!({
+        "escrowState": address.to_string(),
+        "marketId": state.market_id,
+        "seller": state.initializer_key.to_string(),
+        "mint": state.initializer_deposit_mint_account.to_string(),
+        "receiveMint": state.initializer_receive_mint_account.to_string(),
+        "taker": state.taker_key.to_string(),
+        "takerAmount": state.taker_amount,
+        "priceUsdCents": state.price_usd_cents,
+        "delegated": state.delegated,
+        "reservedBy": (state.reserved_by != Pubkey::default())
+            .then(|| state.reserved_by.to_string()),
+        "createdAt": state.created_at,
+        "expiresAt": (state.expires_at != 0).then_some(state.expires_at),
+    })
+}
+
+/// `lamports` is the bid held by the offer account along with its rent.
+fn offer(address: &Pubkey, offer: &BuyOffer, lamports: u64) -> Value {
+    json!({
+        "buyOffer": address.to_string(),
+        "buyer": offer.buyer.to_string(),
+        "offerId": offer.offer_id,
+        "mint": (offer.mint != Pubkey::default()).then(|| offer.mint.to_string()),
+        "collection": (offer.collection != Pubkey::default())
+            .then(|| offer.collection.to_string()),
+        "maxPrice": offer.max_price,
+        "expiresAt": (offer.expires_at != 0).then_some(offer.expires_at),
+        "lamports": lamports,
+    })
+}
+
+fn write_csv(path: &str, columns: &[&str], rows: &[Value]) -> Result<(), Error> {
+    let mut csv = columns.join(",");
+    csv.push('\n');
+    for row in rows {
+        let cells: Vec<String> = columns
+            .iter()
+            .map(|column| match &row[*column] {
+                Value::String(value) => value.clone(),
+                Value::Null => String::new(),
+                value => value.to_string(),
+            })
+            .collect();
+        csv.push_str(&cells.join(","));
+        csv.push('\n');
+    }
+    fs::write(path, csv)?;
+    Ok(())
+}
+
+fn main() -> Result<(), Error> {
+    let mut args = std::env::args().skip(1);
+    let rpc_url = args.next().unwrap_or_else(|| DEFAULT_RPC_URL.to_string());
+    let format = args.next().unwrap_or_else(|| "json".to_string());
+    if format != "json" && format != "csv" {
+        return Err(format!("unknown format {format}, expected json or csv").into());
+    }
+
+    let rpc = RpcClient::new_with_commitment(rpc_url, CommitmentConfig::confirmed());
+    let slot = rpc.get_slot()?;
+    let mut accounts = rpc.get_program_accounts(&solana_nft_escrow::ID)?;
+    // Sorted so snapshots of the same state diff cleanly.
+    accounts.sort_by_key(|(address, _)| *address);
+
+    // Each account type starts with its own discriminator, so at most one of
+    // them decodes; everything else the program owns is left out.
+    let mut listings = vec![];
+    let mut offers = vec![];
+    for (address, account) in &accounts {
+        if let Ok(state) = EscrowState::try_deserialize(&mut account.data.as_slice()) {
+            listings.push(listing(address, &state));
+        } else if let Ok(buy_offer) = BuyOffer::try_deserialize(&mut account.data.as_slice()) {
+            offers.push(offer(address, &buy_offer, account.lamports));
+        }
+    }
+
+    if format == "csv" {
+        write_csv("listings.csv", LISTING_COLUMNS, &listings)?;
+        write_csv("offers.csv", OFFER_COLUMNS, &offers)?;
+        eprintln!(
+            "wrote {} listings and {} offers at slot {slot}",
+            listings.len(),
+            offers.len()
+        );
+        return Ok(());
+    }
+
+    let output = json!({
+        "programId": solana_nft_escrow::ID.to_string(),
+        "slot": slot,
+        "listings": listings,
+        "offers": offers,
+    });
+    println!("{}", serde_json::to_string_pretty(&output)?);
+    Ok(())
+}
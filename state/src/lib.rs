@@ -0,0 +1,336 @@
+//! Decodes the program's accounts and events from raw bytes, with no
+//! Solana or Anchor dependency, so browser apps and edge workers compiled to
+//! wasm can read listings straight from RPC account data.
+//!
+//! Kept in step with the program by a program test that decodes accounts
+//! the program itself wrote.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::string::String;
+
+/// A public key as its 32 raw bytes.
+pub type Pubkey = [u8; 32];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The data is some other account or event.
+    Discriminator,
+    /// The data ends before the last field.
+    Truncated,
+    /// A bool or option tag is neither 0 nor 1.
+    InvalidTag,
+    /// A string is not UTF-8.
+    InvalidString,
+}
+
+/// Reads Borsh-encoded fields in order, after checking the discriminator.
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8], discriminator: &[u8; 8]) -> Result<Self, DecodeError> {
+        let mut reader = Reader { data };
+        if &reader.take::<8>()? != discriminator {
+            return Err(DecodeError::Discriminator);
+        }
+        Ok(reader)
+    }
+
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], DecodeError> {
+        if self.data.len() < N {
+            return Err(DecodeError::Truncated);
+        }
+        let (head, rest) = self.data.split_at(N);
+        self.data = rest;
+        Ok(head.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, DecodeError> {
+        self.take().map(u16::from_le_bytes)
+    }
+
+    fn u64(&mut self) -> Result<u64, DecodeError> {
+        self.take().map(u64::from_le_bytes)
+    }
+
+    fn i64(&mut self) -> Result<i64, DecodeError> {
+        self.take().map(i64::from_le_bytes)
+    }
+
+    fn bool(&mut self) -> Result<bool, DecodeError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(DecodeError::InvalidTag),
+        }
+    }
+
+    fn pubkey(&mut self) -> Result<Pubkey, DecodeError> {
+        self.take()
+    }
+
+    fn bytes32(&mut self) -> Result<[u8; 32], DecodeError> {
+        self.take()
+    }
+
+    fn string(&mut self) -> Result<String, DecodeError> {
+        let len = u32::from_le_bytes(self.take()?) as usize;
+        if self.data.len() < len {
+            return Err(DecodeError::Truncated);
+        }
+        let (head, rest) = self.data.split_at(len);
+        self.data = rest;
+        String::from_utf8(head.to_vec()).map_err(|_| DecodeError::InvalidString)
+    }
+
+    fn option_pubkey(&mut self) -> Result<Option<Pubkey>, DecodeError> {
+        match self.bool()? {
+            false => Ok(None),
+            true => self.pubkey().map(Some),
+        }
+    }
+}
+
+/// An escrow listing, as stored by the program. Field meanings are
+/// documented on the program's own `EscrowState`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EscrowState {
+    pub nonce: u64,
+    pub initializer_key: Pubkey,
+    pub taker_key: Pubkey,
+    pub initializer_deposit_token_account: Pubkey,
+    pub initializer_receive_token_account: Pubkey,
+    pub initializer_deposit_mint_account: Pubkey,
+    pub initializer_receive_mint_account: Pubkey,
+    pub initializer_amount: u64,
+    pub taker_amount: u64,
+    pub vault_authority_bump: u8,
+    pub donation_recipient: Pubkey,
+    pub donation_bps: u16,
+    pub price_usd_cents: u64,
+    pub price_feed: Pubkey,
+    pub vault_rent_lamports: u64,
+    pub delegated: bool,
+    pub reserved_by: Pubkey,
+    pub reserved_amount: u64,
+    pub reservation_deposit: u64,
+    pub reserved_until: i64,
+    pub settlement_delay_secs: i64,
+    pub pending_taker: Pubkey,
+    pub pending_taker_deposit_token_account: Pubkey,
+    pub pending_proceeds: u64,
+    pub settle_after: i64,
+    pub disputed: bool,
+    pub plan_installments: u8,
+    pub plan_interval_secs: i64,
+    pub plan_forfeit_bps: u16,
+    pub plan_taker: Pubkey,
+    pub rent_to_own_payment: u64,
+    pub rent_to_own_period_secs: i64,
+    pub rent_to_own_target: u64,
+    pub rent_to_own_renter: Pubkey,
+    pub loan_principal: u64,
+    pub loan_apr_bps: u16,
+    pub loan_duration_secs: i64,
+    pub loan_lender: Pubkey,
+    pub vesting_duration_secs: i64,
+    pub vesting_cliff_secs: i64,
+    pub vesting_start: i64,
+    pub vesting_total: u64,
+    pub vesting_claimed: u64,
+    pub bnpl_installments: u8,
+    pub bnpl_interval_secs: i64,
+    pub bnpl_collateral_bps: u16,
+    pub bnpl_buyer: Pubkey,
+    pub edition_price: u64,
+    pub edition_max_prints: u64,
+    pub editions_printed: u64,
+    pub edition_price_step: u64,
+    pub edition_price_growth_bps: u16,
+    pub gate_mint: Pubkey,
+    pub gate_min_amount: u64,
+    pub gate_governance_program: Pubkey,
+    pub gift_recipient: Pubkey,
+    pub gift_claim_deadline: i64,
+    pub htlc_taker: Pubkey,
+    pub htlc_hash: [u8; 32],
+    pub htlc_timeout: i64,
+    pub wormhole_program: Pubkey,
+    pub wormhole_emitter_chain: u16,
+    pub wormhole_emitter: [u8; 32],
+    pub wormhole_amount: u64,
+    pub ring_recipient: Pubkey,
+    pub ring_partner: Pubkey,
+    pub market_id: u64,
+    pub integrator: Pubkey,
+    pub integrator_namespace: [u8; 32],
+    pub integrator_fee_recipient: Pubkey,
+    pub integrator_fee_bps: u16,
+    pub private_fills: bool,
+    pub created_at: i64,
+    pub audited: bool,
+    pub tag: [u8; 8],
+    pub terms_hash: [u8; 32],
+    pub expires_at: i64,
+    pub relist_decay_bps: u16,
+    pub relist_window_secs: i64,
+    pub rent_payer: Pubkey,
+    pub awaiting_deposit: bool,
+    pub collection: Pubkey,
+    pub note: String,
+    pub terms_uri: String,
+}
+
+impl EscrowState {
+    /// First 8 bytes of every `EscrowState` account.
+    pub const DISCRIMINATOR: [u8; 8] = [19, 90, 148, 111, 55, 130, 229, 108];
+
+    pub fn decode(data: &[u8]) -> Result<Self, DecodeError> {
+        let mut reader = Reader::new(data, &Self::DISCRIMINATOR)?;
+        Ok(EscrowState {
+            nonce: reader.u64()?,
+            initializer_key: reader.pubkey()?,
+            taker_key: reader.pubkey()?,
+            initializer_deposit_token_account: reader.pubkey()?,
+            initializer_receive_token_account: reader.pubkey()?,
+            initializer_deposit_mint_account: reader.pubkey()?,
+            initializer_receive_mint_account: reader.pubkey()?,
+            initializer_amount: reader.u64()?,
+            taker_amount: reader.u64()?,
+            vault_authority_bump: reader.u8()?,
+            donation_recipient: reader.pubkey()?,
+            donation_bps: reader.u16()?,
+            price_usd_cents: reader.u64()?,
+            price_feed: reader.pubkey()?,
+            vault_rent_lamports: reader.u64()?,
+            delegated: reader.bool()?,
+            reserved_by: reader.pubkey()?,
+            reserved_amount: reader.u64()?,
+            reservation_deposit: reader.u64()?,
+            reserved_until: reader.i64()?,
+            settlement_delay_secs: reader.i64()?,
+            pending_taker: reader.pubkey()?,
+            pending_taker_deposit_token_account: reader.pubkey()?,
+            pending_proceeds: reader.u64()?,
+            settle_after: reader.i64()?,
+            disputed: reader.bool()?,
+            plan_installments: reader.u8()?,
+            plan_interval_secs: reader.i64()?,
+            plan_forfeit_bps: reader.u16()?,
+            plan_taker: reader.pubkey()?,
+            rent_to_own_payment: reader.u64()?,
+            rent_to_own_period_secs: reader.i64()?,
+            rent_to_own_target: reader.u64()?,
+            rent_to_own_renter: reader.pubkey()?,
+            loan_principal: reader.u64()?,
+            loan_apr_bps: reader.u16()?,
+            loan_duration_secs: reader.i64()?,
+            loan_lender: reader.pubkey()?,
+            vesting_duration_secs: reader.i64()?,
+            vesting_cliff_secs: reader.i64()?,
+            vesting_start: reader.i64()?,
+            vesting_total: reader.u64()?,
+            vesting_claimed: reader.u64()?,
+            bnpl_installments: reader.u8()?,
+            bnpl_interval_secs: reader.i64()?,
+            bnpl_collateral_bps: reader.u16()?,
+            bnpl_buyer: reader.pubkey()?,
+            edition_price: reader.u64()?,
+            edition_max_prints: reader.u64()?,
+            editions_printed: reader.u64()?,
+            edition_price_step: reader.u64()?,
+            edition_price_growth_bps: reader.u16()?,
+            gate_mint: reader.pubkey()?,
+            gate_min_amount: reader.u64()?,
+            gate_governance_program: reader.pubkey()?,
+            gift_recipient: reader.pubkey()?,
+            gift_claim_deadline: reader.i64()?,
+            htlc_taker: reader.pubkey()?,
+            htlc_hash: reader.bytes32()?,
+            htlc_timeout: reader.i64()?,
+            wormhole_program: reader.pubkey()?,
+            wormhole_emitter_chain: reader.u16()?,
+            wormhole_emitter: reader.bytes32()?,
+            wormhole_amount: reader.u64()?,
+            ring_recipient: reader.pubkey()?,
+            ring_partner: reader.pubkey()?,
+            market_id: reader.u64()?,
+            integrator: reader.pubkey()?,
+            integrator_namespace: reader.bytes32()?,
+            integrator_fee_recipient: reader.pubkey()?,
+            integrator_fee_bps: reader.u16()?,
+            private_fills: reader.bool()?,
+            created_at: reader.i64()?,
+            audited: reader.bool()?,
+            tag: reader.take()?,
+            terms_hash: reader.bytes32()?,
+            expires_at: reader.i64()?,
+            relist_decay_bps: reader.u16()?,
+            relist_window_secs: reader.i64()?,
+            rent_payer: reader.pubkey()?,
+            awaiting_deposit: reader.bool()?,
+            collection: reader.pubkey()?,
+            note: reader.string()?,
+            terms_uri: reader.string()?,
+        })
+    }
+}
+
+/// The event `exchange` emits, as found base64 encoded after
+/// `Program data: ` in the transaction logs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EscrowExchanged {
+    pub escrow_state: Pubkey,
+    pub initializer: Pubkey,
+    pub taker: Pubkey,
+    pub initializer_amount: u64,
+    pub taker_amount: u64,
+    pub maker_fee: u64,
+    pub taker_fee: u64,
+    pub protocol_fee: u64,
+    pub referrer: Option<Pubkey>,
+    pub referral_fee: u64,
+    pub donation_recipient: Pubkey,
+    pub donation: u64,
+    pub dao_treasury: Option<Pubkey>,
+    pub dao_fee: u64,
+    pub integrator_fee: u64,
+    pub net_to_seller: u64,
+    pub payment_mint: Pubkey,
+}
+
+impl EscrowExchanged {
+    /// First 8 bytes of every `EscrowExchanged` event.
+    pub const DISCRIMINATOR: [u8; 8] = [227, 173, 205, 57, 1, 154, 129, 42];
+
+    pub fn decode(data: &[u8]) -> Result<Self, DecodeError> {
+        let mut reader = Reader::new(data, &Self::DISCRIMINATOR)?;
+        Ok(EscrowExchanged {
+            escrow_state: reader.pubkey()?,
+            initializer: reader.pubkey()?,
+            taker: reader.pubkey()?,
+            initializer_amount: reader.u64()?,
+            taker_amount: reader.u64()?,
+            maker_fee: reader.u64()?,
+            taker_fee: reader.u64()?,
+            protocol_fee: reader.u64()?,
+            referrer: reader.option_pubkey()?,
+            referral_fee: reader.u64()?,
+            donation_recipient: reader.pubkey()?,
+            donation: reader.u64()?,
+            dao_treasury: reader.option_pubkey()?,
+            dao_fee: reader.u64()?,
+            integrator_fee: reader.u64()?,
+            net_to_seller: reader.u64()?,
+            payment_mint: reader.pubkey()?,
+        })
+    }
+}
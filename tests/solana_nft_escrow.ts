@@ -6,6 +6,7 @@ import {
   SystemProgram,
   Connection,
   Commitment,
+  PACKET_DATA_SIZE,
   TransactionMessage,
   VersionedTransaction
 } from "@solana/web3.js";
@@ -18,6 +19,10 @@ import {
   getAccount
 } from "@solana/spl-token";
 import { assert } from "chai";
+import {
+  buildLegacyExchangeTransaction,
+  signedSize
+} from "../client/mobileExchange";
 
 describe("solana_nft_escrow", () => {
   // Use Mainnet-fork for testing
@@ -55,30 +60,97 @@ describe("solana_nft_escrow", () => {
   const mintAuthority = anchor.web3.Keypair.generate();
   const initializer = anchor.web3.Keypair.generate();
   const taker = anchor.web3.Keypair.generate();
+  const treasury = anchor.web3.Keypair.generate();
 
   // Determined Seeds
   const stateSeed = "state";
   const authoritySeed = "authority";
+  const configSeed = "config";
+  const listingCounterSeed = "listing_counter";
 
-  // Random Seed
-  const randomSeed: anchor.BN = new anchor.BN(
-    Math.floor(Math.random() * 100000000)
-  );
+  // Protocol fee settings
+  const makerFeeBps = 150;
+  const takerFeeBps = 100;
+  const referralShareBps = 2000;
+
+  // Compute units a plain SOL exchange may consume before the test fails
+  const exchangeComputeBudget = 50_000;
 
-  // Derive PDAs: escrowStateKey, vaultKey, vaultAuthorityKey
-  const escrowStateKey = PublicKey.findProgramAddressSync(
+  // Derive PDAs: listingCounterKey, escrowStateKey, vaultKey, vaultAuthorityKey
+  const listingCounterKey = PublicKey.findProgramAddressSync(
     [
-      Buffer.from(anchor.utils.bytes.utf8.encode(stateSeed)),
-      randomSeed.toArrayLike(Buffer, "le", 8)
+      Buffer.from(listingCounterSeed, "utf-8"),
+      initializer.publicKey.toBuffer()
     ],
     program.programId
   )[0];
 
-  const vaultAuthorityKey = PublicKey.findProgramAddressSync(
-    [Buffer.from(authoritySeed, "utf-8")],
+  // Escrow states are keyed by the initializer's listing nonce.
+  const escrowStateKeyFor = (nonce: number) =>
+    PublicKey.findProgramAddressSync(
+      [
+        Buffer.from(anchor.utils.bytes.utf8.encode(stateSeed)),
+        initializer.publicKey.toBuffer(),
+        new anchor.BN(nonce).toArrayLike(Buffer, "le", 8)
+      ],
+      program.programId
+    )[0];
+  let escrowStateKey = escrowStateKeyFor(0);
+
+  // Every escrow has its own vault authority and therefore its own vault.
+  const vaultAuthorityKeyFor = (escrowState: PublicKey) =>
+    PublicKey.findProgramAddressSync(
+      [Buffer.from(authoritySeed, "utf-8"), escrowState.toBuffer()],
+      program.programId
+    )[0];
+  const vaultKeyFor = (vaultAuthority: PublicKey) =>
+    PublicKey.findProgramAddressSync(
+      [
+        vaultAuthority.toBuffer(),
+        TOKEN_PROGRAM_ID.toBuffer(),
+        mintA.toBuffer()
+      ],
+      ASSOCIATED_TOKEN_PROGRAM_ID
+    )[0];
+  let vaultAuthorityKey = vaultAuthorityKeyFor(escrowStateKey);
+  let vaultKey = null as PublicKey;
+
+  const configKey = PublicKey.findProgramAddressSync(
+    [Buffer.from(configSeed, "utf-8")],
+    program.programId
+  )[0];
+
+  // The fee override of the listing's collection; listings without a
+  // verified collection point at the default key's.
+  const collectionFeeOverrideKey = PublicKey.findProgramAddressSync(
+    [Buffer.from("fee_override", "utf-8"), PublicKey.default.toBuffer()],
+    program.programId
+  )[0];
+
+  // Likewise for the collection's DAO treasury entry.
+  const collectionTreasuryKey = PublicKey.findProgramAddressSync(
+    [Buffer.from("collection_treasury", "utf-8"), PublicKey.default.toBuffer()],
+    program.programId
+  )[0];
+
+  // Listings name the NFT's metadata account and its collection's floor
+  // whether or not either exists; the test mints have no collection.
+  const tokenMetadataProgramId = new PublicKey(
+    "metaqbxxUerdq28cj1RjAWkegKJZHgjJvh7RNVEQ7LR8s"
+  );
+  const metadataKeyFor = (mint: PublicKey) =>
+    PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("metadata", "utf-8"),
+        tokenMetadataProgramId.toBuffer(),
+        mint.toBuffer()
+      ],
+      tokenMetadataProgramId
+    )[0];
+  const collectionFloorKey = PublicKey.findProgramAddressSync(
+    [Buffer.from("collection_floor", "utf-8"), PublicKey.default.toBuffer()],
     program.programId
   )[0];
-  let vaultKey = null as PublicKey;
 
   const getBalancePublicKey = async (publicKey: PublicKey) => {
     const balance = await connection.getBalance(new PublicKey(publicKey));
@@ -223,6 +295,26 @@ describe("solana_nft_escrow", () => {
     // assert.ok(initializer)
   });
 
+  it("Initialize config", async () => {
+    await program.methods
+      .initializeConfig(makerFeeBps, takerFeeBps, referralShareBps)
+      .accounts({
+        admin: payer.publicKey,
+        config: configKey,
+        treasury: treasury.publicKey,
+        systemProgram: anchor.web3.SystemProgram.programId
+      })
+      .signers([payer])
+      .rpc();
+
+    const fetchedConfig = await program.account.config.fetch(configKey);
+    assert.ok(fetchedConfig.admin.equals(payer.publicKey));
+    assert.ok(fetchedConfig.treasury.equals(treasury.publicKey));
+    assert.ok(fetchedConfig.makerFeeBps == makerFeeBps);
+    assert.ok(fetchedConfig.takerFeeBps == takerFeeBps);
+    assert.ok(fetchedConfig.referralShareBps == referralShareBps);
+  });
+
   it("Initialize escrow", async () => {
     let initializer_balance_1 = await getBalancePublicKey(
       initializer.publicKey
@@ -231,39 +323,40 @@ describe("solana_nft_escrow", () => {
     console.log("here is initializer sol(start): ", initializer_balance_1);
     console.log("here is taker sol(start): ", taker_balance_1);
     // console.log('program.account.escrowState is ', program.account.escrowState)
-    const _vaultKey = PublicKey.findProgramAddressSync(
-      [
-        vaultAuthorityKey.toBuffer(),
-        TOKEN_PROGRAM_ID.toBuffer(),
-        mintA.toBuffer()
-      ],
-      ASSOCIATED_TOKEN_PROGRAM_ID
-    )[0];
-    vaultKey = _vaultKey;
+    vaultKey = vaultKeyFor(vaultAuthorityKey);
 
     let vault_balance_1 = await getBalancePublicKey(vaultKey);
     console.log("!!!important vault sol(start):", vault_balance_1);
 
     const result = await program.methods
       .initialize(
-        randomSeed,
         // new anchor.BN(initializerAmount),
         // new anchor.BN(takerAmount)
         new anchor.BN(20000000),
-        new anchor.BN(40000000)
+        new anchor.BN(40000000),
+        PublicKey.default,
+        0,
+        new anchor.BN(0),
+        PublicKey.default,
+        new anchor.BN(0),
+        ""
       )
       .accounts({
         initializer: initializer.publicKey,
+        rentPayer: initializer.publicKey,
         takerKey: taker.publicKey,
         vaultAuthority: vaultAuthorityKey,
         vault: vaultKey,
         mint: mintA,
         initializerReceiveMintAccount: mintB,
         initializerDepositTokenAccount: initializerTokenAccountA,
-        initializerReceiveTokenAccount: initializerTokenAccountB,
+        config: configKey,
+        listingCounter: listingCounterKey,
         escrowState: escrowStateKey,
+        nftMetadata: metadataKeyFor(mintA),
+        collectionFloor: collectionFloorKey,
+        walletActivity: null,
         systemProgram: anchor.web3.SystemProgram.programId,
-        rent: anchor.web3.SYSVAR_RENT_PUBKEY,
         tokenProgram: TOKEN_PROGRAM_ID
       })
       .signers([initializer])
@@ -326,6 +419,27 @@ describe("solana_nft_escrow", () => {
     console.log("TakerTokenAccountB", fetchedTakerTokenAccountB.amount);
   });
 
+  it("Quote escrow", async () => {
+    const quote = await program.methods
+      .getQuote()
+      .accounts({
+        taker: taker.publicKey,
+        escrowState: escrowStateKey,
+        config: configKey,
+        discountTokenAccount: null,
+        collectionFeeOverride: collectionFeeOverrideKey,
+        priceFeed: null
+      })
+      .view();
+
+    const price = 40000000;
+    const takerFee = (price * takerFeeBps) / 10000;
+    assert.ok(quote.price.toNumber() == price);
+    assert.ok(quote.makerFee.toNumber() == (price * makerFeeBps) / 10000);
+    assert.ok(quote.takerFee.toNumber() == takerFee);
+    assert.ok(quote.total.toNumber() == price + takerFee);
+  });
+
   it("Exchange escrow state", async () => {
     let initializer_balance_2 = await getBalancePublicKey(
       initializer.publicKey
@@ -339,25 +453,56 @@ describe("solana_nft_escrow", () => {
 
     console.log("here is vaultkey: ", vaultKey);
 
-    const result = await program.methods
-      .exchange()
+    const exchange = program.methods
+      .exchange(null, null, null, null)
       .accounts({
         taker: taker.publicKey,
         initializerDepositTokenMint: mintA,
         takerDepositTokenMint: mintB,
         takerDepositTokenAccount: takerTokenAccountB,
         takerReceiveTokenAccount: takerTokenAccountA,
-        initializerDepositTokenAccount: initializerTokenAccountA,
         initializerReceiveTokenAccount: initializerTokenAccountB,
         initializer: initializer.publicKey,
         escrowState: escrowStateKey,
         vault: vaultKey,
         vaultAuthority: vaultAuthorityKey,
+        config: configKey,
+        treasury: treasury.publicKey,
+        referrer: null,
+        discountTokenAccount: null,
+        collectionFeeOverride: collectionFeeOverrideKey,
+        collectionTreasury: collectionTreasuryKey,
+        takerActivity: null,
+        auditTrail: null,
         tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
         systemProgram: anchor.web3.SystemProgram.programId
       })
-      .signers([taker])
-      .rpc();
+      .signers([taker]);
+
+    // Guard the compute budget so new fee legs can't silently blow it.
+    const { blockhash } = await connection.getLatestBlockhash();
+    const simulation = await connection.simulateTransaction(
+      new VersionedTransaction(
+        new TransactionMessage({
+          payerKey: taker.publicKey,
+          recentBlockhash: blockhash,
+          instructions: [await exchange.instruction()]
+        }).compileToV0Message()
+      ),
+      { sigVerify: false }
+    );
+    const unitsConsumed = simulation.value.unitsConsumed;
+    console.log("exchange compute units: ", unitsConsumed);
+    assert.ok(simulation.value.err == null);
+    assert.isDefined(unitsConsumed, "simulation did not report compute units");
+    assert.isBelow(
+      unitsConsumed,
+      exchangeComputeBudget,
+      `exchange used ${unitsConsumed} CU, over its ${exchangeComputeBudget} CU budget`
+    );
+
+    const result = await exchange.rpc();
     console.log(
       `https://solana.fm/tx/${result}?cluster=http%253A%252F%252Flocalhost%253A8899%252F`
     );
@@ -401,6 +546,59 @@ describe("solana_nft_escrow", () => {
     console.log("TakerTokenAccountB", fetchedTakerTokenAccountB.amount);
   });
 
+  it("Fits a full mobile exchange in a legacy transaction", async () => {
+    // Every fee leg a lamport fill can pay, plus the accounts a rate-limited
+    // market and an audited listing add. Only the keys matter here, so the
+    // transaction is built but never sent.
+    const referrer = anchor.web3.Keypair.generate().publicKey;
+    const discountTokenAccount = anchor.web3.Keypair.generate().publicKey;
+    const donationRecipient = anchor.web3.Keypair.generate().publicKey;
+    const integratorFeeRecipient = anchor.web3.Keypair.generate().publicKey;
+    const daoTreasury = anchor.web3.Keypair.generate().publicKey;
+    const takerActivityKey = PublicKey.findProgramAddressSync(
+      [Buffer.from("wallet_activity", "utf-8"), taker.publicKey.toBuffer()],
+      program.programId
+    )[0];
+    const auditTrailKey = PublicKey.findProgramAddressSync(
+      [Buffer.from("audit", "utf-8"), escrowStateKey.toBuffer()],
+      program.programId
+    )[0];
+
+    const transaction = await buildLegacyExchangeTransaction(
+      program,
+      {
+        taker: taker.publicKey,
+        initializerDepositTokenMint: mintA,
+        takerDepositTokenMint: mintB,
+        takerDepositTokenAccount: takerTokenAccountB,
+        takerReceiveTokenAccount: takerTokenAccountA,
+        initializerReceiveTokenAccount: initializerTokenAccountB,
+        initializer: initializer.publicKey,
+        escrowState: escrowStateKey,
+        vault: vaultKey,
+        vaultAuthority: vaultAuthorityKey,
+        config: configKey,
+        treasury: treasury.publicKey,
+        referrer,
+        discountTokenAccount,
+        collectionFeeOverride: collectionFeeOverrideKey,
+        collectionTreasury: collectionTreasuryKey,
+        takerActivity: takerActivityKey,
+        auditTrail: auditTrailKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        systemProgram: anchor.web3.SystemProgram.programId
+      },
+      [],
+      [donationRecipient, integratorFeeRecipient, daoTreasury].map(
+        (pubkey) => ({ pubkey, isSigner: false, isWritable: true })
+      )
+    );
+    const size = signedSize(transaction);
+    console.log("full legacy exchange bytes: ", size);
+    assert.isAtMost(size, PACKET_DATA_SIZE);
+  });
+
   it("Initialize escrow and cancel escrow", async () => {
     // Put back tokens into initializer token A account.
     // await mintTo(
@@ -424,20 +622,38 @@ describe("solana_nft_escrow", () => {
       taker_balance_1
     );
 
+    // The second listing by the same initializer gets the next nonce.
+    escrowStateKey = escrowStateKeyFor(1);
+    vaultAuthorityKey = vaultAuthorityKeyFor(escrowStateKey);
+    vaultKey = vaultKeyFor(vaultAuthorityKey);
+
     const initializedTx = await program.methods
-      .initialize(randomSeed, new anchor.BN(20000000), new anchor.BN(40000000))
+      .initialize(
+        new anchor.BN(20000000),
+        new anchor.BN(40000000),
+        PublicKey.default,
+        0,
+        new anchor.BN(0),
+        PublicKey.default,
+        new anchor.BN(0),
+        ""
+      )
       .accounts({
         initializer: initializer.publicKey,
+        rentPayer: initializer.publicKey,
         takerKey: taker.publicKey,
         vaultAuthority: vaultAuthorityKey,
         vault: vaultKey,
         mint: mintA,
         initializerReceiveMintAccount: mintB,
         initializerDepositTokenAccount: initializerTokenAccountA,
-        initializerReceiveTokenAccount: initializerTokenAccountB,
+        config: configKey,
+        listingCounter: listingCounterKey,
         escrowState: escrowStateKey,
+        nftMetadata: metadataKeyFor(mintA),
+        collectionFloor: collectionFloorKey,
+        walletActivity: null,
         systemProgram: anchor.web3.SystemProgram.programId,
-        rent: anchor.web3.SYSVAR_RENT_PUBKEY,
         tokenProgram: TOKEN_PROGRAM_ID
       })
       .signers([initializer])
@@ -490,14 +706,19 @@ describe("solana_nft_escrow", () => {
 
     // Cancel the escrow.
     const canceledTX = await program.methods
-      .cancel()
+      .cancel(null)
       .accounts({
         initializer: initializer.publicKey,
         mint: mintA,
         initializerDepositTokenAccount: initializerTokenAccountA,
         vault: vaultKey,
+        closedVault: null,
         vaultAuthority: vaultAuthorityKey,
         escrowState: escrowStateKey,
+        nftEdition: null,
+        tokenMetadataProgram: null,
+        auditTrail: null,
+        memoProgram: null,
         tokenProgram: TOKEN_PROGRAM_ID
       })
       .signers([initializer])